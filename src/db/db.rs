@@ -1,2 +1,9 @@
-mod sexpr;
-mod rangedb;
+#[macro_use]
+extern crate macros;
+extern crate exec;
+extern crate util;
+
+pub mod sexpr;
+pub mod rangedb;
+pub mod import;
+pub use rangedb::{RangeDB, BinaryDB, Category, CategoryNode, Annotation};
@@ -0,0 +1,518 @@
+// Names (and sometimes function bounds and comments) from other tools' symbol maps.  Addresses
+// are as the tool saw them, so they get slid to match our idea of where the segments are.
+
+use exec::{VMA, ExecBase, ExecResult, ErrorKind, err, Symbol, SymbolValue};
+use rangedb::{BinaryDB, Annotation};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use util::{ByteStr, ByteString};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapFormat {
+    // nm -n, optionally with -S
+    Nm,
+    // GNU ld -Map
+    LdMap,
+    // ld64 -map
+    Ld64Map,
+    // IDA script (File > Produce file > Dump database to IDC)
+    Idc,
+    // IDA .map (File > Produce file > Create MAP file)
+    IdaMap,
+    // Ghidra symbol table exported as CSV
+    GhidraCsv,
+}
+
+pub const ALL_MAP_FORMATS: [MapFormat; 6] = [MapFormat::Nm, MapFormat::LdMap, MapFormat::Ld64Map,
+                                             MapFormat::Idc, MapFormat::IdaMap, MapFormat::GhidraCsv];
+
+impl MapFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            MapFormat::Nm => "nm",
+            MapFormat::LdMap => "ld-map",
+            MapFormat::Ld64Map => "ld64-map",
+            MapFormat::Idc => "idc",
+            MapFormat::IdaMap => "ida-map",
+            MapFormat::GhidraCsv => "ghidra-csv",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<MapFormat> {
+        ALL_MAP_FORMATS.iter().cloned().find(|fmt| fmt.name() == name)
+    }
+    pub fn guess(text: &str) -> MapFormat {
+        let first_line = text.lines().next().unwrap_or("");
+        if text.contains("# Symbols:") {
+            MapFormat::Ld64Map
+        } else if text.contains("Linker script and memory map") {
+            MapFormat::LdMap
+        } else if text.contains("Publics by Value") {
+            MapFormat::IdaMap
+        } else if text.contains("<idc.idc>") {
+            MapFormat::Idc
+        } else if first_line.contains("\"Name\"") && first_line.contains("\"Location\"") {
+            MapFormat::GhidraCsv
+        } else {
+            MapFormat::Nm
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SymbolMap {
+    pub entries: Vec<(VMA, u64, Annotation)>,
+    // (segment, section, address) as the source saw them, for working out the slide
+    pub sections: Vec<(Option<String>, String, VMA)>,
+}
+
+impl SymbolMap {
+    // How far to move the map's addresses to match `eb`: whatever most sections known to both
+    // agree on, or else whatever most names that are also symbols in `syms` agree on, or else the
+    // difference from the source's image base, if given.  None if there's nothing to go on.
+    pub fn slide(&self, eb: &ExecBase, syms: &[Symbol], source_base: Option<VMA>) -> Option<u64> {
+        let mut votes: HashMap<u64, usize> = HashMap::new();
+        for &(ref segname, ref sectname, addr) in &self.sections {
+            let sect = eb.sections.iter().find(|sect| {
+                name_is(&sect.name, sectname) &&
+                segname.as_ref().map_or(true, |segname| {
+                    sect.seg_idx.map_or(true, |i| name_is(&eb.segments[i].name, segname))
+                })
+            });
+            if let Some(sect) = sect {
+                *votes.entry(sect.vmaddr.wrapping_sub(addr)).or_insert(0) += 1;
+            }
+        }
+        if let Some(slide) = winner(&votes) { return Some(slide); }
+        // names only count if they have a single address on both sides
+        let mut ours: HashMap<&ByteStr, Option<VMA>> = HashMap::new();
+        for sym in syms {
+            if let SymbolValue::Addr(addr) = sym.val { unique_insert(&mut ours, &sym.name, addr); }
+        }
+        let mut theirs: HashMap<&ByteStr, Option<VMA>> = HashMap::new();
+        for &(addr, _, ref ann) in &self.entries {
+            if let Annotation::Name(ref name) = *ann { unique_insert(&mut theirs, ByteStr::from_str(name), addr); }
+        }
+        for (name, addr) in theirs {
+            if let (Some(addr), Some(&Some(our_addr))) = (addr, ours.get(name)) {
+                *votes.entry(our_addr.wrapping_sub(addr)).or_insert(0) += 1;
+            }
+        }
+        if let Some(slide) = winner(&votes) { return Some(slide); }
+        match (source_base, image_base(eb)) {
+            (Some(source_base), Some(base)) => Some(base.wrapping_sub(source_base)),
+            _ => None,
+        }
+    }
+    // Returns how many entries were added.
+    pub fn import_into(&self, bdb: &mut BinaryDB, slide: u64) -> usize {
+        for &(addr, size, ref ann) in &self.entries {
+            bdb.insert(VMA(addr.0.wrapping_add(slide)), size, ann.clone());
+        }
+        self.entries.len()
+    }
+}
+
+// The slide with the most votes; ties go to no slide, then to the smallest.
+fn winner(votes: &HashMap<u64, usize>) -> Option<u64> {
+    votes.iter().max_by_key(|&(&slide, &count)| (count, slide == 0, Reverse(slide))).map(|(&slide, _)| slide)
+}
+
+fn unique_insert<'a>(map: &mut HashMap<&'a ByteStr, Option<VMA>>, name: &'a ByteStr, addr: VMA) {
+    let slot = map.entry(name).or_insert(Some(addr));
+    if *slot != Some(addr) { *slot = None; }
+}
+
+fn name_is(name: &Option<ByteString>, want: &str) -> bool {
+    name.as_ref().map_or(false, |name| *name == *want)
+}
+
+fn image_base(eb: &ExecBase) -> Option<VMA> {
+    eb.segments.iter().filter(|seg| seg.filesize != 0).map(|seg| seg.vmaddr).min()
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = if s.starts_with("0x") || s.starts_with("0X") { &s[2..] } else { s };
+    if s.is_empty() { return None; }
+    u64::from_str_radix(s, 16).ok()
+}
+
+// IDC and C-ish numbers
+fn parse_c_number(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        parse_hex(s)
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn name(s: &str) -> Annotation { Annotation::Name(s.to_owned()) }
+
+pub fn parse_map(format: MapFormat, text: &str, eb: &ExecBase) -> ExecResult<SymbolMap> {
+    let map = match format {
+        MapFormat::Nm => parse_nm(text),
+        MapFormat::LdMap => parse_ld_map(text),
+        MapFormat::Ld64Map => parse_ld64_map(text),
+        MapFormat::Idc => parse_idc(text),
+        MapFormat::IdaMap => parse_ida_map(text, eb),
+        MapFormat::GhidraCsv => parse_ghidra_csv(text)?,
+    };
+    if map.entries.is_empty() {
+        return err(ErrorKind::BadData, format!("import: no symbols found (is this really {}?)", format.name()));
+    }
+    Ok(map)
+}
+
+fn parse_nm(text: &str) -> SymbolMap {
+    let mut map = SymbolMap::default();
+    for line in text.lines() {
+        let toks: Vec<&str> = line.split_whitespace().collect();
+        // addr [size] type name; undefined (including weak undefined) symbols don't have an address
+        let (addr, size, kind, sym) = match toks.len() {
+            3 => (toks[0], None, toks[1], toks[2]),
+            4 => (toks[0], Some(toks[1]), toks[2], toks[3]),
+            _ => continue,
+        };
+        match kind {
+            "U" | "A" | "a" | "N" | "n" | "-" => continue,
+            _ => (),
+        }
+        let addr = some_or!(parse_hex(addr), continue);
+        let size = some_or!(size.map_or(Some(0), parse_hex), continue);
+        map.entries.push((VMA(addr), size, name(sym)));
+    }
+    map
+}
+
+fn parse_ld_map(text: &str) -> SymbolMap {
+    let mut map = SymbolMap::default();
+    let lines = text.lines().skip_while(|line| !line.starts_with("Linker script and memory map"));
+    // an output section whose name was too long for its address to fit on the same line
+    let mut pending_sect: Option<String> = None;
+    for line in lines {
+        let toks: Vec<&str> = line.split_whitespace().collect();
+        if !line.starts_with(' ') && line.starts_with('.') {
+            pending_sect = None;
+            match toks.len() {
+                1 => pending_sect = Some(toks[0].to_owned()),
+                n if n >= 3 => if let Some(addr) = parse_hex(toks[1]) {
+                    if addr != 0 { map.sections.push((None, toks[0].to_owned(), VMA(addr))); }
+                },
+                _ => (),
+            }
+            continue;
+        }
+        if let Some(sect) = pending_sect.take() {
+            if let Some(addr) = toks.first().and_then(|t| parse_hex(t)) {
+                if addr != 0 { map.sections.push((None, sect, VMA(addr))); }
+                continue;
+            }
+        }
+        // symbols are "<address> <name>"; assignments like ". = ALIGN (0x8)" have more
+        if line.starts_with("  ") && toks.len() == 2 && toks[0].starts_with("0x") && !toks[1].starts_with("0x") {
+            if let Some(addr) = parse_hex(toks[0]) {
+                map.entries.push((VMA(addr), 0, name(toks[1])));
+            }
+        }
+    }
+    map
+}
+
+fn parse_ld64_map(text: &str) -> SymbolMap {
+    let mut map = SymbolMap::default();
+    #[derive(PartialEq)]
+    enum Part { Other, Sections, Symbols }
+    let mut part = Part::Other;
+    for line in text.lines() {
+        if line.starts_with('#') {
+            part = if line.starts_with("# Sections:") {
+                Part::Sections
+            } else if line.starts_with("# Symbols:") {
+                Part::Symbols
+            } else if line.starts_with("# Address") {
+                part
+            } else {
+                Part::Other
+            };
+            continue;
+        }
+        let mut toks = line.split('\t');
+        let addr = some_or!(toks.next().and_then(|t| parse_hex(t.trim())), continue);
+        let size = some_or!(toks.next().and_then(|t| parse_hex(t.trim())), continue);
+        match part {
+            Part::Sections => {
+                let toks: Vec<&str> = toks.map(|t| t.trim()).collect();
+                if toks.len() == 2 {
+                    map.sections.push((Some(toks[0].to_owned()), toks[1].to_owned(), VMA(addr)));
+                }
+            },
+            Part::Symbols => {
+                // "[  1] _main"
+                let rest = some_or!(toks.next(), continue);
+                let sym = match rest.find("] ") { Some(i) => &rest[i + 2..], None => rest };
+                if !sym.is_empty() {
+                    map.entries.push((VMA(addr), size, name(sym)));
+                }
+            },
+            Part::Other => (),
+        }
+    }
+    map
+}
+
+#[derive(Debug)]
+enum IdcArg {
+    Num(u64),
+    Str(String),
+    Other,
+}
+
+// Every `ident(args...)` call with simple arguments; anything fancier is skipped.
+fn idc_calls(text: &str) -> Vec<(String, Vec<IdcArg>)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) { i += 1; }
+            i += 2;
+        } else if c == '"' {
+            // a string that isn't an argument
+            i = idc_string(&chars, i).1;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+            let ident: String = chars[start..i].iter().cloned().collect();
+            while i < chars.len() && chars[i] == ' ' { i += 1; }
+            if chars.get(i) != Some(&'(') { continue; }
+            i += 1;
+            let mut args = Vec::new();
+            loop {
+                while i < chars.len() && chars[i].is_whitespace() { i += 1; }
+                let c = some_or!(chars.get(i), break);
+                if *c == ')' { i += 1; break; }
+                if *c == ',' { i += 1; continue; }
+                if *c == '"' {
+                    let (s, next) = idc_string(&chars, i);
+                    args.push(IdcArg::Str(s));
+                    i = next;
+                    continue;
+                }
+                let start = i;
+                while i < chars.len() && !",)\"(;\n".contains(chars[i]) { i += 1; }
+                if i == start || chars.get(i).map_or(true, |&c| c == '(' || c == ';' || c == '\n') {
+                    // a nested call or something; forget this one
+                    args.clear();
+                    break;
+                }
+                let tok: String = chars[start..i].iter().cloned().collect();
+                args.push(match parse_c_number(tok.trim()) {
+                    Some(num) => IdcArg::Num(num),
+                    None => IdcArg::Other,
+                });
+            }
+            if !args.is_empty() { out.push((ident, args)); }
+        } else {
+            i += 1;
+        }
+    }
+    out
+}
+
+// (contents, index after the closing quote)
+fn idc_string(chars: &[char], start: usize) -> (String, usize) {
+    let mut s = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return (s, i + 1),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                s.push(match chars[i] {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    c => c,
+                });
+            },
+            c => s.push(c),
+        }
+        i += 1;
+    }
+    (s, i)
+}
+
+fn parse_idc(text: &str) -> SymbolMap {
+    let mut map = SymbolMap::default();
+    for (func, args) in idc_calls(text) {
+        match (&*func, args.first(), args.get(1)) {
+            ("MakeName", Some(&IdcArg::Num(addr)), Some(&IdcArg::Str(ref sym))) |
+            ("MakeNameEx", Some(&IdcArg::Num(addr)), Some(&IdcArg::Str(ref sym))) |
+            ("set_name", Some(&IdcArg::Num(addr)), Some(&IdcArg::Str(ref sym))) if !sym.is_empty() => {
+                map.entries.push((VMA(addr), 0, name(sym)));
+            },
+            ("MakeFunction", Some(&IdcArg::Num(start)), Some(&IdcArg::Num(end))) |
+            // BADADDR for the end means IDA should work it out
+            ("add_func", Some(&IdcArg::Num(start)), Some(&IdcArg::Num(end)))
+            if end > start && end != !0 && end != 0xffff_ffff => {
+                map.entries.push((VMA(start), end - start, Annotation::Function { thumb: false }));
+            },
+            ("MakeComm", Some(&IdcArg::Num(addr)), Some(&IdcArg::Str(ref text))) |
+            ("MakeRptCmt", Some(&IdcArg::Num(addr)), Some(&IdcArg::Str(ref text))) |
+            ("set_cmt", Some(&IdcArg::Num(addr)), Some(&IdcArg::Str(ref text))) => {
+                map.entries.push((VMA(addr), 0, Annotation::Comment(text.clone())));
+            },
+            _ => (),
+        }
+    }
+    map
+}
+
+fn parse_ida_map(text: &str, eb: &ExecBase) -> SymbolMap {
+    let mut map = SymbolMap::default();
+    // segment number -> name, from the table at the top
+    let mut segs: HashMap<u64, String> = HashMap::new();
+    let mut in_publics = false;
+    for line in text.lines() {
+        if line.contains("Publics by Value") { in_publics = true; continue; }
+        let toks: Vec<&str> = line.split_whitespace().collect();
+        let (seg, off) = some_or!(toks.first().and_then(|t| {
+            let colon = t.find(':')?;
+            Some((parse_hex(&t[..colon])?, parse_hex(&t[colon + 1..])?))
+        }), continue);
+        if !in_publics {
+            // " 0001:00000000 000001A2H .text  CODE"
+            if toks.len() >= 3 && toks[1].ends_with('H') { segs.insert(seg, toks[2].to_owned()); }
+            continue;
+        }
+        if toks.len() < 2 { continue; }
+        let segname = some_or!(segs.get(&seg), continue);
+        // segment-relative, so it's resolved against our own sections and never needs a slide
+        let sect = some_or!(eb.sections.iter().find(|sect| name_is(&sect.name, segname)), continue);
+        if !map.sections.iter().any(|s| &s.1 == segname) {
+            map.sections.push((None, segname.clone(), sect.vmaddr));
+        }
+        map.entries.push((sect.vmaddr + off, 0, name(&toks[1..].join(" "))));
+    }
+    map
+}
+
+// Ghidra quotes every field, doubling quotes inside.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut it = line.chars().peekable();
+    while let Some(c) = it.next() {
+        match c {
+            '"' if quoted && it.peek() == Some(&'"') => { it.next(); cur.push('"'); },
+            '"' => quoted = !quoted,
+            ',' if !quoted => out.push(::std::mem::replace(&mut cur, String::new())),
+            _ => cur.push(c),
+        }
+    }
+    out.push(cur);
+    out
+}
+
+fn parse_ghidra_csv(text: &str) -> ExecResult<SymbolMap> {
+    let mut map = SymbolMap::default();
+    let mut lines = text.lines();
+    let header = split_csv_line(lines.next().unwrap_or(""));
+    let col = |want: &str| header.iter().position(|h| h == want);
+    let (name_col, loc_col) = match (col("Name"), col("Location")) {
+        (Some(n), Some(l)) => (n, l),
+        _ => return err(ErrorKind::BadData, "import: Ghidra CSV needs Name and Location columns"),
+    };
+    let ns_col = col("Namespace");
+    for line in lines {
+        let fields = split_csv_line(line);
+        let sym = some_or!(fields.get(name_col), continue);
+        let loc = some_or!(fields.get(loc_col), continue);
+        // "00101139", or with an address space like "ram:00101139"; externals have none
+        let loc = match loc.rfind(':') { Some(i) => &loc[i + 1..], None => &loc[..] };
+        let addr = some_or!(parse_hex(loc), continue);
+        let full = match ns_col.and_then(|c| fields.get(c)) {
+            Some(ns) if !ns.is_empty() && ns != "Global" => format!("{}::{}", ns, sym),
+            _ => sym.clone(),
+        };
+        map.entries.push((VMA(addr), 0, Annotation::Name(full)));
+    }
+    Ok(map)
+}
+
+#[test]
+fn test_parse_nm() {
+    let map = parse_nm("                 U abort\n0000000000001040 T main\n0000000000001150 0000000000000002 t die\n                 w __gmon_start__\n");
+    assert_eq!(map.entries, vec![(VMA(0x1040), 0, name("main")), (VMA(0x1150), 2, name("die"))]);
+}
+
+#[test]
+fn test_parse_ld64_map() {
+    let map = parse_ld64_map("# Path: a.out\n# Sections:\n# Address\tSize    \tSegment\tSection\n\
+                              0x100003F70\t0x00000020\t__TEXT\t__text\n# Symbols:\n# Address\tSize    \tFile  Name\n\
+                              0x100003F70\t0x00000010\t[  1] _main\n0x100003F80\t0x00000010\t[  1] literal string: a b\n\
+                              # Dead Stripped Symbols:\n<<dead>> \t0x00000008\t[  2] _gone\n");
+    assert_eq!(map.sections, vec![(Some("__TEXT".to_owned()), "__text".to_owned(), VMA(0x100003f70))]);
+    assert_eq!(map.entries, vec![(VMA(0x100003f70), 0x10, name("_main")),
+                                 (VMA(0x100003f80), 0x10, name("literal string: a b"))]);
+}
+
+#[test]
+fn test_parse_idc() {
+    let map = parse_idc("static main(void) {\n  // MakeName(0x1, \"no\");\n  MakeName(0X1150, \"spin\");\n  \
+                         set_name(0x1160, \"a\\\"b\", SN_NOWARN);\n  add_func(0x1170, 0x1184);\n  \
+                         MakeFunction(0x1190, BADADDR);\n  set_cmt(0x117f, \"hi\", 0);\n}\n");
+    assert_eq!(map.entries, vec![(VMA(0x1150), 0, name("spin")), (VMA(0x1160), 0, name("a\"b")),
+                                 (VMA(0x1170), 0x14, Annotation::Function { thumb: false }),
+                                 (VMA(0x117f), 0, Annotation::Comment("hi".to_owned()))]);
+}
+
+#[test]
+fn test_parse_ghidra_csv() {
+    let map = parse_ghidra_csv("\"Name\",\"Location\",\"Type\",\"Namespace\"\n\"main\",\"00101040\",\"Function\",\"Global\"\n\
+                                \"bar\",\"ram:00101190\",\"Function\",\"Foo\"\n\"printf\",\"External[00000000]\",\"Function\",\"<EXTERNAL>\"\n").unwrap();
+    assert_eq!(map.entries, vec![(VMA(0x101040), 0, name("main")), (VMA(0x101190), 0, name("Foo::bar"))]);
+}
+
+#[cfg(test)]
+fn exec_base(sections: &[(&str, &str, u64)]) -> ExecBase {
+    let mut eb = ExecBase::default();
+    for &(segname, sectname, addr) in sections {
+        eb.segments.push(::exec::Segment { vmaddr: VMA(addr & !0xfff), vmsize: 0x1000, filesize: 0x1000,
+                                           name: Some(ByteString::from_str(segname)), ..Default::default() });
+        eb.sections.push(::exec::Segment { vmaddr: VMA(addr), name: Some(ByteString::from_str(sectname)),
+                                           seg_idx: Some(eb.segments.len() - 1), ..Default::default() });
+    }
+    eb
+}
+
+#[cfg(test)]
+fn sym(name: &str, addr: u64) -> Symbol<'_> {
+    Symbol { name: ::std::borrow::Cow::Borrowed(ByteStr::from_str(name)), is_public: true, is_weak: false,
+             val: SymbolValue::Addr(VMA(addr)), size: None, kind: ::exec::SymbolKind::Func, attrs: 0, private: 0 }
+}
+
+#[test]
+fn test_slide() {
+    let eb = exec_base(&[("__TEXT", "__text", 0x100007f70)]);
+    // sections win over symbols
+    let map = parse_ld64_map("# Sections:\n0x100003F70\t0x00000020\t__TEXT\t__text\n# Symbols:\n0x100003F70\t0x00000010\t[  1] _main\n");
+    assert_eq!(map.slide(&eb, &[sym("_main", 0x100003f70)], None), Some(0x4000));
+    // nm has no sections, so it goes by names that have one address on each side
+    let map = parse_nm("0000000100003f70 T _main\n0000000100003f80 t _a\n0000000100003f90 t _a\n0000000100003fa0 t _b\n");
+    let syms = [sym("_main", 0x100007f70), sym("_a", 0x100009f80), sym("_b", 0x10000bfa0), sym("_b", 0x1000)];
+    assert_eq!(map.slide(&eb, &syms, Some(VMA(0x100000000))), Some(0x4000));
+    // ties go to no slide, then the smallest
+    let map = parse_nm("0000000000001000 T one\n0000000000002000 T two\n0000000000003000 T three\n");
+    let syms = [sym("one", 0x9000), sym("two", 0x6000), sym("three", 0x3000)];
+    assert_eq!(map.slide(&eb, &syms, None), Some(0));
+    let syms = [sym("one", 0x9000), sym("two", 0x6000)];
+    assert_eq!(map.slide(&eb, &syms, None), Some(0x4000));
+    // nothing in common
+    assert_eq!(map.slide(&eb, &[], Some(VMA(0x100003000))), Some(0x4000));
+    assert_eq!(map.slide(&eb, &[sym("four", 0x4000)], None), None);
+}
@@ -1,17 +1,326 @@
-// we can have multiple categories
-trait Category {
-    fn parse_sexpr(Sexpr) -> Box<Blob>;
+// Annotations over address ranges, per binary, saved as s-expressions:
+//
+// ; comments before an entry stay with it
+// (binary "/bin/ls"
+//   (function #x4a10 #x5c)
+//   (name #x4a10 #x5c main)
+//   (data-type #x21000 8 "char *"))
+
+use exec::{VMA, ExecResult, ErrorKind, err};
+use sexpr::{Sexpr, read_sexprs, write_sexpr, read_number, AllowComments};
+use std::collections::{BTreeMap, HashMap};
+use std::cmp::max;
+use std::mem::replace;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    Function,
+    Name,
+    Comment,
+    DataType,
 }
-trait CategoryNodeInner : Any {
+
+pub const ALL_CATEGORIES: [Category; 4] = [Category::Function, Category::Name, Category::Comment, Category::DataType];
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Function => "function",
+            Category::Name => "name",
+            Category::Comment => "comment",
+            Category::DataType => "data-type",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Category> {
+        ALL_CATEGORIES.iter().cloned().find(|cat| cat.name() == name)
+    }
 }
-struct CategoryNode {
-    size: u64,
-    inner: Box<CategoryNodeInner>,
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Annotation {
+    Function { thumb: bool },
+    Name(String),
+    Comment(String),
+    DataType(String),
 }
+
+impl Annotation {
+    pub fn category(&self) -> Category {
+        match *self {
+            Annotation::Function { .. } => Category::Function,
+            Annotation::Name(_) => Category::Name,
+            Annotation::Comment(_) => Category::Comment,
+            Annotation::DataType(_) => Category::DataType,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CategoryNode {
+    pub size: u64,
+    pub ann: Annotation,
+    // comments that preceded (or were inside) the entry in the file
+    pub comments: Vec<Sexpr>,
+}
+
+#[derive(Default)]
 struct CategoryData {
-    cat: Category,
-    BTreeMap<VMA, CategoryNode>
+    nodes: BTreeMap<VMA, Vec<CategoryNode>>,
+    // bounds how far before a query a node can start and still overlap it
+    max_size: u64,
+}
+
+#[derive(Default)]
+pub struct BinaryDB {
+    category_data: HashMap<Category, CategoryData>,
+    pub comments: Vec<Sexpr>,
+    trailing_comments: Vec<Sexpr>,
+}
+
+impl BinaryDB {
+    // Replaces any node in the same category with the same range (keeping its comments).
+    pub fn insert(&mut self, start: VMA, size: u64, ann: Annotation) {
+        self.insert_node(start, CategoryNode { size: size, ann: ann, comments: Vec::new() });
+    }
+    fn insert_node(&mut self, start: VMA, mut node: CategoryNode) {
+        let data = self.category_data.entry(node.ann.category()).or_default();
+        data.max_size = max(data.max_size, node.size);
+        let nodes = data.nodes.entry(start).or_default();
+        if let Some(old) = nodes.iter_mut().find(|old| old.size == node.size) {
+            if node.comments.is_empty() {
+                node.comments = old.comments.clone();
+            }
+            *old = node;
+            return;
+        }
+        nodes.push(node);
+    }
+    pub fn remove(&mut self, cat: Category, start: VMA) -> Vec<CategoryNode> {
+        let data = some_or!(self.category_data.get_mut(&cat), { return Vec::new(); });
+        data.nodes.remove(&start).unwrap_or_default()
+    }
+    pub fn clear(&mut self, cat: Category) {
+        self.category_data.remove(&cat);
+    }
+    pub fn all(&self, cat: Category) -> Vec<(VMA, &CategoryNode)> {
+        let data = some_or!(self.category_data.get(&cat), { return Vec::new(); });
+        data.nodes.iter().flat_map(|(&start, nodes)| nodes.iter().map(move |node| (start, node))).collect()
+    }
+    // Nodes whose range intersects [start, start+size); empty ranges count as one byte.
+    pub fn overlapping(&self, cat: Category, start: VMA, size: u64) -> Vec<(VMA, &CategoryNode)> {
+        let data = some_or!(self.category_data.get(&cat), { return Vec::new(); });
+        let end = VMA(start.0.saturating_add(max(size, 1)));
+        let lo = VMA(start.0.saturating_sub(max(data.max_size, 1) - 1));
+        let mut out = Vec::new();
+        for (&node_start, nodes) in data.nodes.range(lo..end) {
+            for node in nodes {
+                if node_start.0.saturating_add(max(node.size, 1)) > start.0 {
+                    out.push((node_start, node));
+                }
+            }
+        }
+        out
+    }
+    pub fn at(&self, cat: Category, addr: VMA) -> Vec<(VMA, &CategoryNode)> {
+        self.overlapping(cat, addr, 1)
+    }
+
+    fn parse_entry(&mut self, items: Vec<Sexpr>, mut comments: Vec<Sexpr>) -> ExecResult<()> {
+        let mut strs = Vec::new();
+        for item in items {
+            match item {
+                Sexpr::Str(s) => strs.push(s),
+                Sexpr::LineComment(_) | Sexpr::BlockComment(_) => comments.push(item),
+                Sexpr::List(_) => return err(ErrorKind::BadData, "rangedb: unexpected list in entry"),
+            }
+        }
+        if strs.len() < 3 {
+            return err(ErrorKind::BadData, format!("rangedb: entry too short: {:?}", strs));
+        }
+        let cat = some_or!(Category::from_name(&strs[0]), {
+            return err(ErrorKind::BadData, format!("rangedb: unknown category '{}'", strs[0]));
+        });
+        let start = parse_number(&strs[1])?;
+        let size = parse_number(&strs[2])?;
+        let rest = &strs[3..];
+        let ann = match (cat, rest.len()) {
+            (Category::Function, 0) => Annotation::Function { thumb: false },
+            (Category::Function, 1) if rest[0] == "thumb" => Annotation::Function { thumb: true },
+            (Category::Name, 1) => Annotation::Name(rest[0].clone()),
+            (Category::Comment, 1) => Annotation::Comment(rest[0].clone()),
+            (Category::DataType, 1) => Annotation::DataType(rest[0].clone()),
+            _ => return err(ErrorKind::BadData, format!("rangedb: bad {} entry at {:#x}", cat.name(), start)),
+        };
+        self.insert_node(VMA(start), CategoryNode { size: size, ann: ann, comments: comments });
+        Ok(())
+    }
+
+    fn write<W: Write>(&self, w: &mut W, key: &str) -> io::Result<()> {
+        write_comments(w, &self.comments, "")?;
+        write!(w, "(binary ")?;
+        write_sexpr(&mut *w, &Sexpr::Str(key.to_owned()))?;
+        let mut entries: Vec<(VMA, Category, &CategoryNode)> = Vec::new();
+        for (&cat, data) in &self.category_data {
+            for (&start, nodes) in &data.nodes {
+                entries.extend(nodes.iter().map(|node| (start, cat, node)));
+            }
+        }
+        entries.sort_by_key(|&(start, cat, node)| (start, cat, node.size));
+        for (start, cat, node) in entries {
+            writeln!(w)?;
+            write_comments(w, &node.comments, "  ")?;
+            let mut items = vec![Sexpr::Str(cat.name().to_owned()),
+                                 Sexpr::Str(format!("#x{:x}", start.0)),
+                                 Sexpr::Str(format!("#x{:x}", node.size))];
+            match node.ann {
+                Annotation::Function { thumb: false } => (),
+                Annotation::Function { thumb: true } => items.push(Sexpr::Str("thumb".to_owned())),
+                Annotation::Name(ref s) | Annotation::Comment(ref s) | Annotation::DataType(ref s) =>
+                    items.push(Sexpr::Str(s.clone())),
+            }
+            write!(w, "  ")?;
+            write_sexpr(&mut *w, &Sexpr::List(items))?;
+        }
+        if !self.trailing_comments.is_empty() {
+            writeln!(w)?;
+            write_comments(w, &self.trailing_comments, "  ")?;
+        }
+        writeln!(w, ")")
+    }
+}
+
+fn parse_number(s: &str) -> ExecResult<u64> {
+    read_number(s).or_else(|_| err(ErrorKind::BadData, format!("rangedb: bad number '{}'", s)))
+}
+
+// each on its own line(s), followed by a newline
+fn write_comments<W: Write>(w: &mut W, comments: &[Sexpr], indent: &str) -> io::Result<()> {
+    for comment in comments {
+        write!(w, "{}", indent)?;
+        write_sexpr(&mut *w, comment)?;
+        if let Sexpr::BlockComment(_) = *comment {
+            writeln!(w)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct RangeDB {
+    binaries: BTreeMap<String, BinaryDB>,
+    trailing_comments: Vec<Sexpr>,
+}
+
+impl RangeDB {
+    pub fn new() -> Self { Default::default() }
+    pub fn binary_keys(&self) -> Vec<&str> {
+        self.binaries.keys().map(|k| &**k).collect()
+    }
+    pub fn binary(&self, key: &str) -> Option<&BinaryDB> {
+        self.binaries.get(key)
+    }
+    pub fn binary_mut(&mut self, key: &str) -> &mut BinaryDB {
+        self.binaries.entry(key.to_owned()).or_default()
+    }
+
+    pub fn read<R: BufRead>(r: R) -> ExecResult<RangeDB> {
+        let sxs = read_sexprs(r, AllowComments).or_else(|e| {
+            err(ErrorKind::BadData, format!("rangedb: couldn't read: {:?}", e))
+        })?;
+        let mut db = RangeDB::new();
+        let mut comments = Vec::new();
+        for sx in sxs {
+            let items = match sx {
+                Sexpr::List(items) => items,
+                Sexpr::LineComment(_) | Sexpr::BlockComment(_) => { comments.push(sx); continue; },
+                Sexpr::Str(s) => return err(ErrorKind::BadData, format!("rangedb: unexpected '{}' at top level", s)),
+            };
+            let mut items = items.into_iter();
+            let mut head = Vec::new();
+            while head.len() < 2 {
+                match items.next() {
+                    Some(item @ Sexpr::LineComment(_)) | Some(item @ Sexpr::BlockComment(_)) => comments.push(item),
+                    Some(item) => head.push(item),
+                    None => break,
+                }
+            }
+            match (head.first(), head.get(1)) {
+                (Some(Sexpr::Str(tag)), Some(Sexpr::Str(key))) if tag == "binary" => {
+                    let bin = db.binary_mut(key);
+                    bin.comments.append(&mut comments);
+                    for item in items {
+                        match item {
+                            Sexpr::List(entry) => bin.parse_entry(entry, replace(&mut comments, Vec::new()))?,
+                            Sexpr::LineComment(_) | Sexpr::BlockComment(_) => comments.push(item),
+                            Sexpr::Str(s) => return err(ErrorKind::BadData, format!("rangedb: unexpected '{}' in binary", s)),
+                        }
+                    }
+                    bin.trailing_comments.append(&mut comments);
+                },
+                _ => return err(ErrorKind::BadData, "rangedb: expected (binary \"key\" ...)"),
+            }
+        }
+        db.trailing_comments = comments;
+        Ok(db)
+    }
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for (key, bin) in &self.binaries {
+            bin.write(&mut w, key)?;
+        }
+        write_comments(&mut w, &self.trailing_comments, "")
+    }
+
+    pub fn load(path: &Path) -> ExecResult<RangeDB> {
+        let fp = fs::File::open(path).or_else(|e| {
+            err(ErrorKind::Other, format!("rangedb: couldn't open {}: {}", path.display(), e))
+        })?;
+        RangeDB::read(io::BufReader::new(fp))
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        let mut fp = fs::File::create(path)?;
+        fp.write_all(&buf)
+    }
+}
+
+#[test]
+fn test_rangedb_round_trip() {
+    let sin = "; header\n(binary \"/bin/foo\"\n  ; main\n  (function #x1000 #x20)\n  (name #x1000 #x20 main)\n  \
+               (comment #x1008 1 \"hi there\")\n  #|block|#\n  (data-type #x2000 #x8 \"char *\")\n  ; end\n)\n; bye\n";
+    let db = RangeDB::read(io::Cursor::new(sin)).unwrap();
+    let bin = db.binary("/bin/foo").unwrap();
+    assert_eq!(bin.all(Category::Function).len(), 1);
+    assert_eq!(bin.at(Category::Name, VMA(0x101f))[0].1.ann, Annotation::Name("main".to_owned()));
+    assert!(bin.at(Category::Name, VMA(0x1020)).is_empty());
+    assert_eq!(bin.overlapping(Category::Comment, VMA(0x1000), 9).len(), 1);
+    assert!(bin.overlapping(Category::Comment, VMA(0x1000), 8).is_empty());
+    let mut out = Vec::new();
+    db.write(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out, "; header\n(binary /bin/foo\n  ; main\n  (function #x1000 #x20)\n  (name #x1000 #x20 main)\n  \
+                     (comment #x1008 #x1 \"hi there\")\n  #|block|#\n  (data-type #x2000 #x8 \"char *\")\n  ; end\n)\n; bye\n");
+    let db2 = RangeDB::read(io::Cursor::new(&out[..])).unwrap();
+    let mut out2 = Vec::new();
+    db2.write(&mut out2).unwrap();
+    assert_eq!(String::from_utf8(out2).unwrap(), out);
 }
-struct RangeDB {
-    category_data: HashMap<String, CategoryData>,
+
+#[test]
+fn test_rangedb_insert() {
+    let mut db = RangeDB::new();
+    {
+        let bin = db.binary_mut("x");
+        bin.insert(VMA(0x10), 4, Annotation::Name("a".to_owned()));
+        bin.insert(VMA(0x10), 4, Annotation::Name("b".to_owned()));
+        bin.insert(VMA(0x10), 8, Annotation::Name("c".to_owned()));
+        bin.insert(VMA(0), 0x100, Annotation::Function { thumb: true });
+    }
+    let bin = db.binary("x").unwrap();
+    let names: Vec<_> = bin.at(Category::Name, VMA(0x12)).into_iter().map(|(_, n)| n.ann.clone()).collect();
+    assert_eq!(names, vec![Annotation::Name("b".to_owned()), Annotation::Name("c".to_owned())]);
+    assert_eq!(bin.at(Category::Function, VMA(0xff)).len(), 1);
 }
@@ -1,14 +1,12 @@
 // super basic
 
-#![feature(io)]
-#[macro_use]
-extern crate macros;
-
-use std::io::{BufRead, CharsError, Write};
+use std;
+use std::io::{self, BufRead, Read, Write};
+use std::iter::Peekable;
 #[cfg(test)]
 use std::io::Cursor;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Sexpr {
     Str(String),
     List(Vec<Sexpr>),
@@ -16,6 +14,12 @@ pub enum Sexpr {
     BlockComment(String),
 }
 
+#[derive(Debug)]
+pub enum CharsError {
+    NotUtf8,
+    Other(io::Error),
+}
+
 #[derive(Debug)]
 pub enum ReadError {
     ParseError(&'static str),
@@ -24,11 +28,47 @@ pub enum ReadError {
 
 pub type ReadResult = Result<Box<Sexpr>, ReadError>;
 
-fn parse_error() -> ReadResult {
+// std's Read::chars went away; this reads one byte at a time, so it never consumes past the end
+// of the sexpr (other than the one char peeked after an unquoted symbol)
+pub struct Chars<R> {
+    bytes: io::Bytes<R>,
+}
+
+impl<R: Read> Iterator for Chars<R> {
+    type Item = Result<char, CharsError>;
+    fn next(&mut self) -> Option<Result<char, CharsError>> {
+        let first = match self.bytes.next() {
+            None => return None,
+            Some(Ok(b)) => b,
+            Some(Err(e)) => return Some(Err(CharsError::Other(e))),
+        };
+        let len = match first {
+            0..=0x7f => return Some(Ok(first as char)),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Some(Err(CharsError::NotUtf8)),
+        };
+        let mut buf = [first, 0, 0, 0];
+        for b in &mut buf[1..len] {
+            *b = match self.bytes.next() {
+                None => return Some(Err(CharsError::NotUtf8)),
+                Some(Ok(b)) => b,
+                Some(Err(e)) => return Some(Err(CharsError::Other(e))),
+            };
+        }
+        match std::str::from_utf8(&buf[..len]) {
+            Ok(s) => Some(Ok(s.chars().next().unwrap())),
+            Err(_) => Some(Err(CharsError::NotUtf8)),
+        }
+    }
+}
+
+fn parse_error<T>() -> Result<T, ReadError> {
     Err(ReadError::ParseError("parse error"))
 }
 
-fn comments_error() -> ReadResult {
+fn comments_error<T>() -> Result<T, ReadError> {
     Err(ReadError::ParseError("inner comments not allowed (because they will be stripped when it's written back out)"))
 }
 
@@ -37,7 +77,7 @@ macro_rules! insert { ($stack:expr, $e:expr) => { {
     if let Some(last) = $stack.last_mut() {
         last.push(e);
     } else {
-        return Ok(Box::new(e));
+        return Ok(Some(Box::new(e)));
     }
 } } }
 
@@ -57,15 +97,33 @@ macro_rules! intry_peek { ($it:expr) => {
     }
 } }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum CommentBehavior {
     AllowComments,
     BanInnerComments,
 }
-pub use CommentBehavior::*;
+pub use self::CommentBehavior::*;
 
 pub fn read_sexpr<R: BufRead>(r: R, cb: CommentBehavior) -> ReadResult {
-    let mut it = r.chars().peekable();
+    let mut it = Chars { bytes: r.bytes() }.peekable();
+    match read_one(&mut it, cb)? {
+        Some(sx) => Ok(sx),
+        None => parse_error(),
+    }
+}
+
+// Everything up to the end of the input, including top-level comments.
+pub fn read_sexprs<R: BufRead>(r: R, cb: CommentBehavior) -> Result<Vec<Sexpr>, ReadError> {
+    let mut it = Chars { bytes: r.bytes() }.peekable();
+    let mut out = Vec::new();
+    while let Some(sx) = read_one(&mut it, cb)? {
+        out.push(*sx);
+    }
+    Ok(out)
+}
+
+// None if we ran out of chars before starting anything
+fn read_one<R: Read>(it: &mut Peekable<Chars<R>>, cb: CommentBehavior) -> Result<Option<Box<Sexpr>>, ReadError> {
     let mut stack: Vec<Vec<Sexpr>> = vec![];
     while let Some(ch) = intry!(it.next()) {
         if ch == '#' {
@@ -195,7 +253,7 @@ pub fn read_sexpr<R: BufRead>(r: R, cb: CommentBehavior) -> ReadResult {
         }
     }
     // out of chars
-    parse_error()
+    if stack.is_empty() { Ok(None) } else { parse_error() }
 }
 
 // based on R6Rs
@@ -218,12 +276,12 @@ fn niceish_char(_c: char) -> bool {
     true // TODO (anything but " and \ can go in quotes but only pretty things should)
 }
 #[derive(Debug, PartialEq, Eq)]
-enum ReadNumberError {
+pub enum ReadNumberError {
     ParseError,
     OutOfRange,
 }
 const PE: Result<u64, ReadNumberError> = Err(ReadNumberError::ParseError);
-fn read_number(s: &str) -> Result<u64, ReadNumberError> {
+pub fn read_number(s: &str) -> Result<u64, ReadNumberError> {
     let mut radix: Option<u8> = None;
     let mut have_eness = false;
     let mut it = s.chars();
@@ -395,7 +453,7 @@ fn test_read_sexpr() {
     let sin = "(foo (foo bar(#| 1 #| 2 |# |#baz)\"boo\\x23;\\r\") ; comment\n)";
     let mut cursor = Cursor::new(sin);
     //println!("{}", sin);
-    use Sexpr::*;
+    use self::Sexpr::*;
     let res = read_sexpr(&mut cursor, AllowComments).unwrap();
     assert_eq!(cursor.position(), sin.len() as u64);
     assert_eq!(*res,
@@ -426,7 +484,7 @@ fn test_read_number() {
 
 #[test]
 fn test_write_sexpr() {
-    use Sexpr::*;
+    use self::Sexpr::*;
     let foo =
         List(vec![s("foo"),
                   List(vec![s("foo"),
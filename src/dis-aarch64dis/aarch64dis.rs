@@ -0,0 +1,1999 @@
+// A64 disassembler that doesn't need LLVM.  Output follows LLVM's syntax (including its choice
+// of aliases), except that branch and literal targets are printed as absolute addresses.
+// Covers the base instruction set plus LSE, pointer authentication, the crypto extensions and
+// the commonly used parts of Advanced SIMD; anything else disassembles as None.
+
+extern crate dis;
+extern crate exec;
+extern crate util;
+
+use std::fmt::Write;
+use exec::VMA;
+use exec::arch::{ArchAndOptions, OtherMode};
+use dis::{Disassembler, DisassemblerStatics, DisassemblerInput, TrawlLead, TrawlLeadKind, CreateDisError};
+use util::{LittleEndian, copy_from_slice};
+
+pub struct AArch64Disassembler { arch: ArchAndOptions }
+
+impl Disassembler for AArch64Disassembler {
+    fn arch(&self) -> &ArchAndOptions { &self.arch }
+    fn can_disassemble_to_str(&self) -> bool { true }
+    fn disassemble_insn_to_str(&self, input: &DisassemblerInput) -> Option<(Option<String>, u32)> {
+        let insn = get_insn(input)?;
+        disassemble(insn, input.pc.0).map(|s| (Some(s), 4))
+    }
+
+    fn can_trawl(&self) -> bool { true }
+    fn trawl(&self, input: &DisassemblerInput, leads: &mut Vec<TrawlLead>) -> Option<()> {
+        let insn = get_insn(input)?;
+        let (next, target) = match flow(insn, input.pc.0) {
+            Flow::Normal => (true, None),
+            Flow::Jump(addr) => (false, Some((addr, TrawlLeadKind::JumpRef { mode: OtherMode }))),
+            Flow::CondJumpOrCall(addr) => (true, Some((addr, TrawlLeadKind::JumpRef { mode: OtherMode }))),
+            Flow::DataRef(addr) => (true, Some((addr, TrawlLeadKind::OtherRef))),
+            Flow::Stop => (false, None),
+        };
+        if let Some((addr, kind)) = target {
+            leads.push(TrawlLead { addr: VMA(addr), kind });
+        }
+        if next {
+            leads.push(TrawlLead { addr: input.pc + 4, kind: TrawlLeadKind::NextInsn });
+        }
+        Some(())
+    }
+}
+
+impl DisassemblerStatics for AArch64Disassembler {
+    fn new_with_args(arch: ArchAndOptions, args: &[String]) -> Result<Self, CreateDisError> {
+        if !args.is_empty() {
+            return Err(CreateDisError::InvalidArgs("aarch64: no supported args".to_owned()));
+        }
+        if let ArchAndOptions::AArch64(_) = arch {} else {
+            return Err(CreateDisError::InvalidArgs(format!("aarch64: can't disassemble {:?}", arch)));
+        }
+        Ok(AArch64Disassembler { arch })
+    }
+    fn name() -> &'static str { "aarch64" }
+}
+
+// A64 instructions are little endian even on big-endian systems
+fn get_insn(input: &DisassemblerInput) -> Option<u32> {
+    if input.data.len() < 4 { return None; }
+    Some(copy_from_slice(&input.data[..4], LittleEndian))
+}
+
+enum Flow {
+    Normal,
+    Jump(u64),
+    CondJumpOrCall(u64),
+    DataRef(u64),
+    Stop,
+}
+
+fn flow(i: u32, pc: u64) -> Flow {
+    if i & 0x7c000000 == 0x14000000 {
+        let target = pc.wrapping_add((sext(b(i, 25, 0), 26) << 2) as u64);
+        if bit(i, 31) { Flow::CondJumpOrCall(target) } else { Flow::Jump(target) }
+    } else if i & 0xff000010 == 0x54000000 {
+        let target = pc.wrapping_add((sext(b(i, 23, 5), 19) << 2) as u64);
+        if b(i, 3, 0) >= 14 { Flow::Jump(target) } else { Flow::CondJumpOrCall(target) }
+    } else if i & 0x7c000000 == 0x34000000 {
+        // cbz/cbnz, tbz/tbnz
+        let off = if bit(i, 25) { sext(b(i, 18, 5), 14) } else { sext(b(i, 23, 5), 19) };
+        Flow::CondJumpOrCall(pc.wrapping_add((off << 2) as u64))
+    } else if i & 0xfe000000 == 0xd6000000 {
+        // blr* are the only indirect branches that come back
+        if b(i, 24, 21) & 0b0111 == 0b0001 { Flow::Normal } else { Flow::Stop }
+    } else if i & 0x1f000000 == 0x10000000 {
+        let imm = sext((b(i, 23, 5) << 2) | b(i, 30, 29), 21);
+        Flow::DataRef(if bit(i, 31) {
+            (pc & !0xfff).wrapping_add((imm << 12) as u64)
+        } else {
+            pc.wrapping_add(imm as u64)
+        })
+    } else if i & 0x3b000000 == 0x18000000 {
+        Flow::DataRef(pc.wrapping_add((sext(b(i, 23, 5), 19) << 2) as u64))
+    } else if i & 0xffff0000 == 0 || i & 0xffe0001f == 0xd4200000 {
+        // udf, brk
+        Flow::Stop
+    } else {
+        Flow::Normal
+    }
+}
+
+pub fn disassemble(i: u32, pc: u64) -> Option<String> {
+    match b(i, 28, 25) {
+        0b0000 => if b(i, 31, 16) == 0 { Some(format!("udf #{}", b(i, 15, 0))) } else { None },
+        0b1000 | 0b1001 => dp_imm(i, pc),
+        0b1010 | 0b1011 => branch_sys(i, pc),
+        0b0100 | 0b0110 | 0b1100 | 0b1110 => ldst(i, pc),
+        0b0101 | 0b1101 => dp_reg(i),
+        0b0111 | 0b1111 => simd_fp(i),
+        _ => None,
+    }
+}
+
+// bits hi..lo, inclusive
+#[inline]
+fn b(i: u32, hi: u32, lo: u32) -> u32 {
+    (i >> lo) & (!0u32 >> (31 - (hi - lo)))
+}
+#[inline]
+fn bit(i: u32, n: u32) -> bool {
+    (i >> n) & 1 != 0
+}
+fn sext(val: u32, len: u32) -> i64 {
+    ((val as i64) << (64 - len)) >> (64 - len)
+}
+
+fn insn<S: AsRef<str>>(mnem: &str, ops: &[S]) -> Option<String> {
+    let mut out = mnem.to_owned();
+    for (n, op) in ops.iter().enumerate() {
+        out.push_str(if n == 0 { " " } else { ", " });
+        out.push_str(op.as_ref());
+    }
+    Some(out)
+}
+
+fn gpr(n: u32, sf: bool) -> String {
+    match (n, sf) {
+        (31, true) => "xzr".to_owned(),
+        (31, false) => "wzr".to_owned(),
+        (n, true) => format!("x{}", n),
+        (n, false) => format!("w{}", n),
+    }
+}
+fn gpr_sp(n: u32, sf: bool) -> String {
+    match (n, sf) {
+        (31, true) => "sp".to_owned(),
+        (31, false) => "wsp".to_owned(),
+        _ => gpr(n, sf),
+    }
+}
+fn x(n: u32) -> String { gpr(n, true) }
+fn w(n: u32) -> String { gpr(n, false) }
+fn xsp(n: u32) -> String { gpr_sp(n, true) }
+
+// size is log2 of the byte size: b h s d q
+fn fpr(n: u32, size: u32) -> String {
+    format!("{}{}", ["b", "h", "s", "d", "q"][size as usize], n)
+}
+// scalar FP register for a ptype field
+fn fpr_ptype(n: u32, ptype: u32) -> Option<String> {
+    match ptype {
+        0 => Some(fpr(n, 2)),
+        1 => Some(fpr(n, 3)),
+        3 => Some(fpr(n, 1)),
+        _ => None,
+    }
+}
+fn arr(size: u32, q: bool) -> &'static str {
+    ["8b", "16b", "4h", "8h", "2s", "4s", "1d", "2d"][(size * 2 + q as u32) as usize]
+}
+fn vreg(n: u32, arrangement: &str) -> String {
+    format!("v{}.{}", n, arrangement)
+}
+fn velem(n: u32, size: u32, index: u32) -> String {
+    format!("v{}.{}[{}]", n, ["b", "h", "s", "d"][size as usize], index)
+}
+fn reglist(first: u32, count: u32, suffix: &str) -> String {
+    let mut out = "{ ".to_owned();
+    for n in 0..count {
+        if n != 0 { out.push_str(", "); }
+        write!(&mut out, "v{}.{}", (first + n) % 32, suffix).unwrap();
+    }
+    out.push_str(" }");
+    out
+}
+
+fn hex(addr: u64) -> String {
+    format!("0x{:x}", addr)
+}
+fn imm(val: i64) -> String {
+    format!("#{}", val)
+}
+
+static CONDS: [&str; 16] = ["eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc",
+                            "hi", "ls", "ge", "lt", "gt", "le", "al", "nv"];
+fn cond(c: u32) -> &'static str { CONDS[c as usize] }
+
+fn shift_suffix(shift: u32, amount: u32) -> String {
+    if shift == 0 && amount == 0 { return String::new(); }
+    format!(", {} #{}", ["lsl", "lsr", "asr", "ror"][shift as usize], amount)
+}
+
+fn mem_off(rn: u32, off: i64) -> String {
+    if off == 0 { format!("[{}]", xsp(rn)) } else { format!("[{}, #{}]", xsp(rn), off) }
+}
+
+// Data processing (immediate)
+
+fn decode_bit_masks(n: bool, imms: u32, immr: u32, sf: bool) -> Option<u64> {
+    let combined = ((n as u32) << 6) | (!imms & 0x3f);
+    if combined == 0 { return None; }
+    let len = 31 - combined.leading_zeros();
+    if len < 1 { return None; }
+    let size = 1u32 << len;
+    let levels = size - 1;
+    let s = imms & levels;
+    let r = immr & levels;
+    if s == levels { return None; }
+    let emask = if size == 64 { !0u64 } else { (1u64 << size) - 1 };
+    let welem = (1u64 << (s + 1)) - 1;
+    let mut elem = if r == 0 { welem } else { ((welem >> r) | (welem << (size - r))) & emask };
+    let mut width = size;
+    while width < 64 {
+        elem |= elem << width;
+        width *= 2;
+    }
+    Some(if sf { elem } else { elem & 0xffffffff })
+}
+
+// LLVM only prefers mov over orr when a single movz or movn can't do it
+fn is_move_wide_imm(val: u64, sf: bool) -> bool {
+    let mask = if sf { !0u64 } else { 0xffffffff };
+    let (val, inv) = (val & mask, !val & mask);
+    (0..if sf { 4 } else { 2 }).any(|hw| {
+        let chunk = 0xffffu64 << (hw * 16);
+        val & !chunk == 0 || inv & !chunk == 0
+    })
+}
+
+fn dp_imm(i: u32, pc: u64) -> Option<String> {
+    let sf = bit(i, 31);
+    let rd = b(i, 4, 0);
+    let rn = b(i, 9, 5);
+    match b(i, 25, 23) {
+        0b000 | 0b001 => {
+            let off = sext((b(i, 23, 5) << 2) | b(i, 30, 29), 21);
+            if sf {
+                insn("adrp", &[x(rd), hex((pc & !0xfff).wrapping_add((off << 12) as u64))])
+            } else {
+                insn("adr", &[x(rd), hex(pc.wrapping_add(off as u64))])
+            }
+        },
+        0b010 => {
+            let (op, s, sh) = (bit(i, 30), bit(i, 29), bit(i, 22));
+            let val = b(i, 21, 10);
+            let mut val_s = imm(val as i64);
+            if sh { val_s.push_str(", lsl #12"); }
+            if !op && !s && !sh && val == 0 && (rd == 31 || rn == 31) {
+                insn("mov", &[gpr_sp(rd, sf), gpr_sp(rn, sf)])
+            } else if s && rd == 31 {
+                insn(if op { "cmp" } else { "cmn" }, &[gpr_sp(rn, sf), val_s])
+            } else {
+                let name = ["add", "adds", "sub", "subs"][(op as usize) * 2 + s as usize];
+                insn(name, &[if s { gpr(rd, sf) } else { gpr_sp(rd, sf) }, gpr_sp(rn, sf), val_s])
+            }
+        },
+        0b100 => {
+            let opc = b(i, 30, 29);
+            let n = bit(i, 22);
+            let (immr, imms) = (b(i, 21, 16), b(i, 15, 10));
+            if !sf && n { return None; }
+            let val = decode_bit_masks(n, imms, immr, sf)?;
+            let val_s = format!("#{}", hex(val));
+            if opc == 1 && rn == 31 && !is_move_wide_imm(val, sf) {
+                let val = if sf { val as i64 } else { val as u32 as i32 as i64 };
+                insn("mov", &[gpr_sp(rd, sf), imm(val)])
+            } else if opc == 3 && rd == 31 {
+                insn("tst", &[gpr(rn, sf), val_s])
+            } else {
+                let rd_s = if opc == 3 { gpr(rd, sf) } else { gpr_sp(rd, sf) };
+                insn(["and", "orr", "eor", "ands"][opc as usize], &[rd_s, gpr(rn, sf), val_s])
+            }
+        },
+        0b101 => {
+            let opc = b(i, 30, 29);
+            let hw = b(i, 22, 21);
+            let val = b(i, 20, 5) as u64;
+            if opc == 1 || (!sf && hw >= 2) { return None; }
+            let shift = hw * 16;
+            let shift_s = if hw != 0 { format!(", lsl #{}", shift) } else { String::new() };
+            let as_mov = !(val == 0 && hw != 0);
+            let signed = |v: u64| if sf { v as i64 } else { v as u32 as i32 as i64 };
+            match opc {
+                0 if as_mov && (sf || val != 0xffff) => insn("mov", &[gpr(rd, sf), imm(signed(!(val << shift)))]),
+                2 if as_mov => insn("mov", &[gpr(rd, sf), imm(signed(val << shift))]),
+                _ => insn(["movn", "", "movz", "movk"][opc as usize], &[gpr(rd, sf), format!("#{}{}", val, shift_s)]),
+            }
+        },
+        0b110 => {
+            let opc = b(i, 30, 29);
+            let (immr, imms) = (b(i, 21, 16), b(i, 15, 10));
+            if opc == 3 || bit(i, 22) != sf || (!sf && (immr >= 32 || imms >= 32)) { return None; }
+            let ds = if sf { 64 } else { 32 };
+            let (rd_s, rn_s) = (gpr(rd, sf), gpr(rn, sf));
+            let lsb_width = |name: &str, lsb: u32, width: u32| insn(name, &[rd_s.clone(), rn_s.clone(), imm(lsb as i64), imm(width as i64)]);
+            match opc {
+                0 => if imms == ds - 1 {
+                    insn("asr", &[rd_s.clone(), rn_s.clone(), imm(immr as i64)])
+                } else if imms < immr {
+                    lsb_width("sbfiz", ds - immr, imms + 1)
+                } else if immr == 0 && (imms == 7 || imms == 15 || (sf && imms == 31)) {
+                    insn(match imms { 7 => "sxtb", 15 => "sxth", _ => "sxtw" }, &[rd_s.clone(), w(rn)])
+                } else {
+                    lsb_width("sbfx", immr, imms - immr + 1)
+                },
+                1 => if rn == 31 && (imms < immr || immr == 0) {
+                    insn("bfc", &[rd_s.clone(), imm(((ds - immr) % ds) as i64), imm((imms + 1) as i64)])
+                } else if imms < immr {
+                    lsb_width("bfi", ds - immr, imms + 1)
+                } else {
+                    lsb_width("bfxil", immr, imms - immr + 1)
+                },
+                _ => if imms != ds - 1 && imms + 1 == immr {
+                    insn("lsl", &[rd_s.clone(), rn_s.clone(), imm((ds - 1 - imms) as i64)])
+                } else if imms == ds - 1 {
+                    insn("lsr", &[rd_s.clone(), rn_s.clone(), imm(immr as i64)])
+                } else if imms < immr {
+                    lsb_width("ubfiz", ds - immr, imms + 1)
+                } else if immr == 0 && !sf && (imms == 7 || imms == 15) {
+                    insn(if imms == 7 { "uxtb" } else { "uxth" }, &[rd_s.clone(), rn_s.clone()])
+                } else {
+                    lsb_width("ubfx", immr, imms - immr + 1)
+                },
+            }
+        },
+        0b111 => {
+            let rm = b(i, 20, 16);
+            let imms = b(i, 15, 10);
+            if b(i, 30, 29) != 0 || bit(i, 21) || bit(i, 22) != sf || (!sf && imms >= 32) { return None; }
+            if rn == rm {
+                insn("ror", &[gpr(rd, sf), gpr(rn, sf), imm(imms as i64)])
+            } else {
+                insn("extr", &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf), imm(imms as i64)])
+            }
+        },
+        _ => None,
+    }
+}
+
+// Branches, exception generation and system instructions
+
+fn branch_sys(i: u32, pc: u64) -> Option<String> {
+    let rt = b(i, 4, 0);
+    if i & 0x7c000000 == 0x14000000 {
+        let target = pc.wrapping_add((sext(b(i, 25, 0), 26) << 2) as u64);
+        insn(if bit(i, 31) { "bl" } else { "b" }, &[hex(target)])
+    } else if i & 0xff000010 == 0x54000000 {
+        let target = pc.wrapping_add((sext(b(i, 23, 5), 19) << 2) as u64);
+        insn(&format!("b.{}", cond(b(i, 3, 0))), &[hex(target)])
+    } else if i & 0x7e000000 == 0x34000000 {
+        let target = pc.wrapping_add((sext(b(i, 23, 5), 19) << 2) as u64);
+        insn(if bit(i, 24) { "cbnz" } else { "cbz" }, &[gpr(rt, bit(i, 31)), hex(target)])
+    } else if i & 0x7e000000 == 0x36000000 {
+        let target = pc.wrapping_add((sext(b(i, 18, 5), 14) << 2) as u64);
+        let bitno = (b(i, 31, 31) << 5) | b(i, 23, 19);
+        insn(if bit(i, 24) { "tbnz" } else { "tbz" }, &[gpr(rt, bitno >= 32), imm(bitno as i64), hex(target)])
+    } else if i & 0xff000000 == 0xd4000000 {
+        let val = b(i, 20, 5);
+        let name = match (b(i, 23, 21), b(i, 4, 0)) {
+            (0, 1) => "svc",
+            (0, 2) => "hvc",
+            (0, 3) => "smc",
+            (1, 0) => "brk",
+            (2, 0) => "hlt",
+            (5, 1) | (5, 2) | (5, 3) => {
+                let name = ["", "dcps1", "dcps2", "dcps3"][b(i, 1, 0) as usize];
+                return if val == 0 { insn::<String>(name, &[]) } else { insn(name, &[format!("#{}", hex(val as u64))]) };
+            },
+            _ => return None,
+        };
+        // LLVM prints a zero immediate here in decimal
+        insn(name, &[if val == 0 { "#0".to_owned() } else { format!("#{}", hex(val as u64)) }])
+    } else if i & 0xffc00000 == 0xd5000000 {
+        system(i)
+    } else if i & 0xfe000000 == 0xd6000000 {
+        branch_reg(i)
+    } else {
+        None
+    }
+}
+
+fn system(i: u32) -> Option<String> {
+    let l = bit(i, 21);
+    let op0 = b(i, 20, 19);
+    let op1 = b(i, 18, 16);
+    let crn = b(i, 15, 12);
+    let crm = b(i, 11, 8);
+    let op2 = b(i, 7, 5);
+    let rt = b(i, 4, 0);
+    if !l && op0 == 0 && rt == 31 {
+        if let Some(s) = pstate_hint_barrier(op1, crn, crm, op2) { return Some(s); }
+    }
+    if op0 == 1 {
+        if l {
+            return insn("sysl", &[x(rt), imm(op1 as i64), format!("c{}", crn), format!("c{}", crm), imm(op2 as i64)]);
+        }
+        if let Some((kind, op, needs_reg)) = sys_alias(op1, crn, crm, op2) {
+            // like LLVM, drop Rt for operations that don't take one
+            return if needs_reg { insn(kind, &[op.to_owned(), x(rt)]) } else { insn(kind, &[op]) };
+        }
+        let mut ops = vec![imm(op1 as i64), format!("c{}", crn), format!("c{}", crm), imm(op2 as i64)];
+        if rt != 31 { ops.push(x(rt)); }
+        return insn("sys", &ops);
+    }
+    let reg = sysreg_name(b(i, 20, 5)).map(|s| s.to_owned())
+        .unwrap_or_else(|| format!("S{}_{}_C{}_C{}_{}", op0, op1, crn, crm, op2));
+    if l { insn("mrs", &[x(rt), reg]) } else { insn("msr", &[reg, x(rt)]) }
+}
+
+// msr to PSTATE fields, hints and barriers
+fn pstate_hint_barrier(op1: u32, crn: u32, crm: u32, op2: u32) -> Option<String> {
+    if crn == 4 {
+        let field = match (op1, op2) {
+            (0, 3) => "UAO",
+            (0, 4) => "PAN",
+            (0, 5) => "SPSel",
+            (3, 1) => "SSBS",
+            (3, 2) => "DIT",
+            (3, 4) => "TCO",
+            (3, 6) => "DAIFSet",
+            (3, 7) => "DAIFClr",
+            _ => return None,
+        };
+        insn("msr", &[field.to_owned(), imm(crm as i64)])
+    } else if op1 == 3 && crn == 2 {
+        let hint = (crm << 3) | op2;
+        let name = match hint {
+            0 => "nop", 1 => "yield", 2 => "wfe", 3 => "wfi", 4 => "sev", 5 => "sevl", 6 => "dgh",
+            7 => "xpaclri", 8 => "pacia1716", 10 => "pacib1716", 12 => "autia1716", 14 => "autib1716",
+            16 => "esb", 17 => "psb csync", 18 => "tsb csync", 20 => "csdb",
+            24 => "paciaz", 25 => "paciasp", 26 => "pacibz", 27 => "pacibsp",
+            28 => "autiaz", 29 => "autiasp", 30 => "autibz", 31 => "autibsp",
+            32 => "bti", 34 => "bti c", 36 => "bti j", 38 => "bti jc",
+            _ => return insn("hint", &[imm(hint as i64)]),
+        };
+        insn::<String>(name, &[])
+    } else if op1 == 3 && crn == 3 {
+        let opt = || match crm {
+            1 => "oshld".to_owned(), 2 => "oshst".to_owned(), 3 => "osh".to_owned(),
+            5 => "nshld".to_owned(), 6 => "nshst".to_owned(), 7 => "nsh".to_owned(),
+            9 => "ishld".to_owned(), 10 => "ishst".to_owned(), 11 => "ish".to_owned(),
+            13 => "ld".to_owned(), 14 => "st".to_owned(), 15 => "sy".to_owned(),
+            _ => imm(crm as i64),
+        };
+        match op2 {
+            2 => if crm == 15 { insn::<String>("clrex", &[]) } else { insn("clrex", &[imm(crm as i64)]) },
+            4 => match crm {
+                0 => insn::<String>("ssbb", &[]),
+                4 => insn::<String>("pssbb", &[]),
+                _ => insn("dsb", &[opt()]),
+            },
+            5 => insn("dmb", &[opt()]),
+            6 => if crm == 15 { insn::<String>("isb", &[]) } else { insn("isb", &[imm(crm as i64)]) },
+            7 if crm == 0 => insn::<String>("sb", &[]),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+
+// (kind, operation, takes a register)
+fn sys_alias(op1: u32, crn: u32, crm: u32, op2: u32) -> Option<(&'static str, &'static str, bool)> {
+    Some(match (op1, crn, crm, op2) {
+        (0, 7, 1, 0) => ("ic", "ialluis", false),
+        (0, 7, 5, 0) => ("ic", "iallu", false),
+        (3, 7, 5, 1) => ("ic", "ivau", true),
+        (0, 7, 6, 1) => ("dc", "ivac", true),
+        (0, 7, 6, 2) => ("dc", "isw", true),
+        (0, 7, 10, 2) => ("dc", "csw", true),
+        (0, 7, 14, 2) => ("dc", "cisw", true),
+        (3, 7, 4, 1) => ("dc", "zva", true),
+        (3, 7, 10, 1) => ("dc", "cvac", true),
+        (3, 7, 11, 1) => ("dc", "cvau", true),
+        (3, 7, 12, 1) => ("dc", "cvap", true),
+        (3, 7, 13, 1) => ("dc", "cvadp", true),
+        (3, 7, 14, 1) => ("dc", "civac", true),
+        (0, 7, 8, 0) => ("at", "s1e1r", true),
+        (0, 7, 8, 1) => ("at", "s1e1w", true),
+        (0, 7, 8, 2) => ("at", "s1e0r", true),
+        (0, 7, 8, 3) => ("at", "s1e0w", true),
+        (4, 7, 8, 0) => ("at", "s1e2r", true),
+        (4, 7, 8, 1) => ("at", "s1e2w", true),
+        (4, 7, 8, 4) => ("at", "s12e1r", true),
+        (4, 7, 8, 5) => ("at", "s12e1w", true),
+        (4, 7, 8, 6) => ("at", "s12e0r", true),
+        (4, 7, 8, 7) => ("at", "s12e0w", true),
+        (6, 7, 8, 0) => ("at", "s1e3r", true),
+        (6, 7, 8, 1) => ("at", "s1e3w", true),
+        (0, 8, 3, 0) => ("tlbi", "vmalle1is", false),
+        (0, 8, 7, 0) => ("tlbi", "vmalle1", false),
+        (0, 8, 3, 1) => ("tlbi", "vae1is", true),
+        (0, 8, 7, 1) => ("tlbi", "vae1", true),
+        (0, 8, 3, 2) => ("tlbi", "aside1is", true),
+        (0, 8, 7, 2) => ("tlbi", "aside1", true),
+        (0, 8, 3, 3) => ("tlbi", "vaae1is", true),
+        (0, 8, 7, 3) => ("tlbi", "vaae1", true),
+        (0, 8, 3, 5) => ("tlbi", "vale1is", true),
+        (0, 8, 7, 5) => ("tlbi", "vale1", true),
+        (0, 8, 3, 7) => ("tlbi", "vaale1is", true),
+        (0, 8, 7, 7) => ("tlbi", "vaale1", true),
+        (4, 8, 0, 1) => ("tlbi", "ipas2e1is", true),
+        (4, 8, 4, 1) => ("tlbi", "ipas2e1", true),
+        (4, 8, 3, 0) => ("tlbi", "alle2is", false),
+        (4, 8, 7, 0) => ("tlbi", "alle2", false),
+        (4, 8, 0, 5) => ("tlbi", "ipas2le1is", true),
+        (4, 8, 4, 5) => ("tlbi", "ipas2le1", true),
+        (4, 8, 3, 1) => ("tlbi", "vae2is", true),
+        (4, 8, 7, 1) => ("tlbi", "vae2", true),
+        (4, 8, 3, 5) => ("tlbi", "vale2is", true),
+        (4, 8, 7, 5) => ("tlbi", "vale2", true),
+        (4, 8, 3, 4) => ("tlbi", "alle1is", false),
+        (4, 8, 7, 4) => ("tlbi", "alle1", false),
+        (4, 8, 3, 6) => ("tlbi", "vmalls12e1is", false),
+        (4, 8, 7, 6) => ("tlbi", "vmalls12e1", false),
+        (6, 8, 3, 0) => ("tlbi", "alle3is", false),
+        (6, 8, 7, 0) => ("tlbi", "alle3", false),
+        (6, 8, 3, 1) => ("tlbi", "vae3is", true),
+        (6, 8, 7, 1) => ("tlbi", "vae3", true),
+        (6, 8, 3, 5) => ("tlbi", "vale3is", true),
+        (6, 8, 7, 5) => ("tlbi", "vale3", true),
+        _ => return None,
+    })
+}
+
+// op0:op1:CRn:CRm:op2, as in the instruction
+fn sysreg_name(enc: u32) -> Option<&'static str> {
+    macro_rules! sr { ($op0:expr, $op1:expr, $crn:expr, $crm:expr, $op2:expr) => {
+        ($op0 << 14) | ($op1 << 11) | ($crn << 7) | ($crm << 3) | $op2
+    } }
+    static REGS: &[(u32, &str)] = &[
+        (sr!(2, 0, 0, 2, 2), "MDSCR_EL1"),
+        (sr!(2, 0, 1, 0, 4), "OSLAR_EL1"),
+        (sr!(2, 0, 1, 3, 4), "OSDLR_EL1"),
+        (sr!(3, 0, 0, 0, 0), "MIDR_EL1"),
+        (sr!(3, 0, 0, 0, 5), "MPIDR_EL1"),
+        (sr!(3, 0, 0, 0, 6), "REVIDR_EL1"),
+        (sr!(3, 0, 0, 4, 0), "ID_AA64PFR0_EL1"),
+        (sr!(3, 0, 0, 4, 1), "ID_AA64PFR1_EL1"),
+        (sr!(3, 0, 0, 5, 0), "ID_AA64DFR0_EL1"),
+        (sr!(3, 0, 0, 6, 0), "ID_AA64ISAR0_EL1"),
+        (sr!(3, 0, 0, 6, 1), "ID_AA64ISAR1_EL1"),
+        (sr!(3, 0, 0, 7, 0), "ID_AA64MMFR0_EL1"),
+        (sr!(3, 0, 0, 7, 1), "ID_AA64MMFR1_EL1"),
+        (sr!(3, 0, 0, 7, 2), "ID_AA64MMFR2_EL1"),
+        (sr!(3, 0, 1, 0, 0), "SCTLR_EL1"),
+        (sr!(3, 0, 1, 0, 1), "ACTLR_EL1"),
+        (sr!(3, 0, 1, 0, 2), "CPACR_EL1"),
+        (sr!(3, 0, 2, 0, 0), "TTBR0_EL1"),
+        (sr!(3, 0, 2, 0, 1), "TTBR1_EL1"),
+        (sr!(3, 0, 2, 0, 2), "TCR_EL1"),
+        (sr!(3, 0, 4, 0, 0), "SPSR_EL1"),
+        (sr!(3, 0, 4, 0, 1), "ELR_EL1"),
+        (sr!(3, 0, 4, 1, 0), "SP_EL0"),
+        (sr!(3, 0, 4, 2, 0), "SPSel"),
+        (sr!(3, 0, 4, 2, 2), "CurrentEL"),
+        (sr!(3, 0, 4, 2, 3), "PAN"),
+        (sr!(3, 0, 4, 2, 4), "UAO"),
+        (sr!(3, 0, 5, 2, 0), "ESR_EL1"),
+        (sr!(3, 0, 6, 0, 0), "FAR_EL1"),
+        (sr!(3, 0, 7, 4, 0), "PAR_EL1"),
+        (sr!(3, 0, 10, 2, 0), "MAIR_EL1"),
+        (sr!(3, 0, 10, 3, 0), "AMAIR_EL1"),
+        (sr!(3, 0, 12, 0, 0), "VBAR_EL1"),
+        (sr!(3, 0, 13, 0, 1), "CONTEXTIDR_EL1"),
+        (sr!(3, 0, 13, 0, 4), "TPIDR_EL1"),
+        (sr!(3, 0, 14, 1, 0), "CNTKCTL_EL1"),
+        (sr!(3, 3, 0, 0, 1), "CTR_EL0"),
+        (sr!(3, 3, 0, 0, 7), "DCZID_EL0"),
+        (sr!(3, 3, 2, 4, 0), "RNDR"),
+        (sr!(3, 3, 2, 4, 1), "RNDRRS"),
+        (sr!(3, 3, 4, 2, 0), "NZCV"),
+        (sr!(3, 3, 4, 2, 1), "DAIF"),
+        (sr!(3, 3, 4, 2, 5), "DIT"),
+        (sr!(3, 3, 4, 2, 6), "SSBS"),
+        (sr!(3, 3, 4, 2, 7), "TCO"),
+        (sr!(3, 3, 4, 4, 0), "FPCR"),
+        (sr!(3, 3, 4, 4, 1), "FPSR"),
+        (sr!(3, 3, 9, 12, 0), "PMCR_EL0"),
+        (sr!(3, 3, 9, 13, 0), "PMCCNTR_EL0"),
+        (sr!(3, 3, 9, 14, 0), "PMUSERENR_EL0"),
+        (sr!(3, 3, 13, 0, 2), "TPIDR_EL0"),
+        (sr!(3, 3, 13, 0, 3), "TPIDRRO_EL0"),
+        (sr!(3, 3, 14, 0, 0), "CNTFRQ_EL0"),
+        (sr!(3, 3, 14, 0, 1), "CNTPCT_EL0"),
+        (sr!(3, 3, 14, 0, 2), "CNTVCT_EL0"),
+        (sr!(3, 3, 14, 2, 0), "CNTP_TVAL_EL0"),
+        (sr!(3, 3, 14, 2, 1), "CNTP_CTL_EL0"),
+        (sr!(3, 3, 14, 2, 2), "CNTP_CVAL_EL0"),
+        (sr!(3, 3, 14, 3, 0), "CNTV_TVAL_EL0"),
+        (sr!(3, 3, 14, 3, 1), "CNTV_CTL_EL0"),
+        (sr!(3, 3, 14, 3, 2), "CNTV_CVAL_EL0"),
+        (sr!(3, 4, 1, 0, 0), "SCTLR_EL2"),
+        (sr!(3, 4, 1, 1, 0), "HCR_EL2"),
+        (sr!(3, 4, 4, 0, 0), "SPSR_EL2"),
+        (sr!(3, 4, 4, 0, 1), "ELR_EL2"),
+        (sr!(3, 4, 4, 1, 0), "SP_EL1"),
+        (sr!(3, 4, 5, 2, 0), "ESR_EL2"),
+        (sr!(3, 4, 6, 0, 0), "FAR_EL2"),
+        (sr!(3, 4, 12, 0, 0), "VBAR_EL2"),
+        (sr!(3, 4, 13, 0, 2), "TPIDR_EL2"),
+        (sr!(3, 6, 1, 0, 0), "SCTLR_EL3"),
+        (sr!(3, 6, 1, 1, 0), "SCR_EL3"),
+        (sr!(3, 6, 4, 0, 0), "SPSR_EL3"),
+        (sr!(3, 6, 4, 0, 1), "ELR_EL3"),
+        (sr!(3, 6, 5, 2, 0), "ESR_EL3"),
+        (sr!(3, 6, 12, 0, 0), "VBAR_EL3"),
+    ];
+    REGS.iter().find(|&&(e, _)| e == enc).map(|&(_, name)| name)
+}
+
+fn branch_reg(i: u32) -> Option<String> {
+    let opc = b(i, 24, 21);
+    let op3 = b(i, 15, 10);
+    let rn = b(i, 9, 5);
+    let op4 = b(i, 4, 0);
+    if b(i, 20, 16) != 31 { return None; }
+    let none: &[String] = &[];
+    match (opc, op3) {
+        (0, 0) if op4 == 0 => insn("br", &[x(rn)]),
+        (1, 0) if op4 == 0 => insn("blr", &[x(rn)]),
+        (2, 0) if op4 == 0 => if rn == 30 { insn("ret", none) } else { insn("ret", &[x(rn)]) },
+        (4, 0) if op4 == 0 && rn == 31 => insn("eret", none),
+        (5, 0) if op4 == 0 && rn == 31 => insn("drps", none),
+        (0, 2) | (0, 3) if op4 == 31 => insn(if op3 == 2 { "braaz" } else { "brabz" }, &[x(rn)]),
+        (1, 2) | (1, 3) if op4 == 31 => insn(if op3 == 2 { "blraaz" } else { "blrabz" }, &[x(rn)]),
+        (2, 2) | (2, 3) if rn == 31 && op4 == 31 => insn(if op3 == 2 { "retaa" } else { "retab" }, none),
+        (4, 2) | (4, 3) if rn == 31 && op4 == 31 => insn(if op3 == 2 { "eretaa" } else { "eretab" }, none),
+        (8, 2) | (8, 3) => insn(if op3 == 2 { "braa" } else { "brab" }, &[x(rn), xsp(op4)]),
+        (9, 2) | (9, 3) => insn(if op3 == 2 { "blraa" } else { "blrab" }, &[x(rn), xsp(op4)]),
+        _ => None,
+    }
+}
+
+// Loads and stores
+
+fn ldst(i: u32, pc: u64) -> Option<String> {
+    if i & 0xbfbf0000 == 0x0c000000 || i & 0xbfa00000 == 0x0c800000 {
+        simd_ldst_multiple(i)
+    } else if i & 0xbf9f0000 == 0x0d000000 || i & 0xbf800000 == 0x0d800000 {
+        simd_ldst_single(i)
+    } else if i & 0x3f000000 == 0x08000000 {
+        ldst_exclusive(i)
+    } else if i & 0x3b000000 == 0x18000000 {
+        ldr_literal(i, pc)
+    } else if i & 0x3a000000 == 0x28000000 {
+        ldst_pair(i)
+    } else if i & 0x3a000000 == 0x38000000 {
+        ldst_reg(i)
+    } else if i & 0x3f200c00 == 0x19000000 {
+        // ldapur/stlur
+        let (kind, sfx, reg, _) = ldst_reg_kind(b(i, 31, 30), false, b(i, 23, 22), b(i, 4, 0))?;
+        let name = match kind {
+            LdstKind::Load => format!("ldapur{}", sfx),
+            LdstKind::Store => format!("stlur{}", sfx),
+            LdstKind::Prefetch => return None,
+        };
+        insn(&name, &[reg, mem_off(b(i, 9, 5), sext(b(i, 20, 12), 9))])
+    } else {
+        None
+    }
+}
+
+fn simd_ldst_multiple(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let (l, post) = (bit(i, 22), bit(i, 23));
+    let rm = b(i, 20, 16);
+    let size = b(i, 11, 10);
+    let (n, nregs) = match b(i, 15, 12) {
+        0 => (4, 4),
+        2 => (1, 4),
+        4 => (3, 3),
+        6 => (1, 3),
+        7 => (1, 1),
+        8 => (2, 2),
+        10 => (1, 2),
+        _ => return None,
+    };
+    if size == 3 && !q && n != 1 { return None; }
+    let mut ops = vec![reglist(b(i, 4, 0), nregs, arr(size, q)), format!("[{}]", xsp(b(i, 9, 5)))];
+    if post {
+        ops.push(if rm == 31 { imm((nregs * if q { 16 } else { 8 }) as i64) } else { x(rm) });
+    }
+    insn(&format!("{}{}", if l { "ld" } else { "st" }, n), &ops)
+}
+
+fn simd_ldst_single(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let (l, r, post) = (bit(i, 22), bit(i, 21), bit(i, 23));
+    let rm = b(i, 20, 16);
+    let opcode = b(i, 15, 13);
+    let s = b(i, 12, 12);
+    let size = b(i, 11, 10);
+    let q_ = q as u32;
+    let selem = (((opcode & 1) << 1) | r as u32) + 1;
+    let (list, bytes, suffix) = match opcode >> 1 {
+        3 => {
+            if !l || s != 0 { return None; }
+            (reglist(b(i, 4, 0), selem, arr(size, q)), selem << size, "r")
+        },
+        scale => {
+            let (esize, index) = match scale {
+                0 => (0, (q_ << 3) | (s << 2) | size),
+                1 if size & 1 == 0 => (1, (q_ << 2) | (s << 1) | (size >> 1)),
+                2 if size == 0 => (2, (q_ << 1) | s),
+                2 if size == 1 && s == 0 => (3, q_),
+                _ => return None,
+            };
+            let list = format!("{}[{}]", reglist(b(i, 4, 0), selem, ["b", "h", "s", "d"][esize as usize]), index);
+            (list, selem << esize, "")
+        },
+    };
+    let mut ops = vec![list, format!("[{}]", xsp(b(i, 9, 5)))];
+    if post {
+        ops.push(if rm == 31 { imm(bytes as i64) } else { x(rm) });
+    }
+    insn(&format!("{}{}{}", if l { "ld" } else { "st" }, selem, suffix), &ops)
+}
+
+fn ldst_exclusive(i: u32) -> Option<String> {
+    let size = b(i, 31, 30);
+    let (o2, l, o1, o0) = (bit(i, 23), bit(i, 22), bit(i, 21), bit(i, 15));
+    let rs = b(i, 20, 16);
+    let rt2 = b(i, 14, 10);
+    let rt = b(i, 4, 0);
+    let addr = format!("[{}]", xsp(b(i, 9, 5)));
+    let sfx = ["b", "h", "", ""][size as usize];
+    let sf = size == 3;
+    let al = |base: &str| format!("{}{}{}{}", base, if l { "a" } else { "" }, if o0 { "l" } else { "" }, sfx);
+    match (o2, o1) {
+        (false, false) => if l {
+            insn(&format!("{}{}", if o0 { "ldaxr" } else { "ldxr" }, sfx), &[gpr(rt, sf), addr])
+        } else {
+            insn(&format!("{}{}", if o0 { "stlxr" } else { "stxr" }, sfx), &[w(rs), gpr(rt, sf), addr])
+        },
+        (false, true) => if size >= 2 {
+            if l {
+                insn(if o0 { "ldaxp" } else { "ldxp" }, &[gpr(rt, sf), gpr(rt2, sf), addr])
+            } else {
+                insn(if o0 { "stlxp" } else { "stxp" }, &[w(rs), gpr(rt, sf), gpr(rt2, sf), addr])
+            }
+        } else {
+            if rt2 != 31 || rs & 1 != 0 || rt & 1 != 0 { return None; }
+            let sf = size == 1;
+            let name = format!("casp{}{}", if l { "a" } else { "" }, if o0 { "l" } else { "" });
+            insn(&name, &[gpr(rs, sf), gpr(rs + 1, sf), gpr(rt, sf), gpr(rt + 1, sf), addr])
+        },
+        (true, false) => {
+            let name = match (l, o0) {
+                (true, true) => "ldar",
+                (true, false) => "ldlar",
+                (false, true) => "stlr",
+                (false, false) => "stllr",
+            };
+            insn(&format!("{}{}", name, sfx), &[gpr(rt, sf), addr])
+        },
+        (true, true) if rt2 == 31 => insn(&al("cas"), &[gpr(rs, sf), gpr(rt, sf), addr]),
+        _ => None,
+    }
+}
+
+fn prfop(rt: u32) -> String {
+    let (typ, target, strm) = (rt >> 3, (rt >> 1) & 3, rt & 1 != 0);
+    if typ == 3 || target == 3 { return imm(rt as i64); }
+    format!("{}l{}{}", ["pld", "pli", "pst"][typ as usize], target + 1, if strm { "strm" } else { "keep" })
+}
+
+fn ldr_literal(i: u32, pc: u64) -> Option<String> {
+    let opc = b(i, 31, 30);
+    let rt = b(i, 4, 0);
+    let target = hex(pc.wrapping_add((sext(b(i, 23, 5), 19) << 2) as u64));
+    if bit(i, 26) {
+        if opc == 3 { return None; }
+        insn("ldr", &[fpr(rt, opc + 2), target])
+    } else {
+        match opc {
+            0 => insn("ldr", &[w(rt), target]),
+            1 => insn("ldr", &[x(rt), target]),
+            2 => insn("ldrsw", &[x(rt), target]),
+            _ => insn("prfm", &[prfop(rt), target]),
+        }
+    }
+}
+
+fn ldst_pair(i: u32) -> Option<String> {
+    let opc = b(i, 31, 30);
+    let mode = b(i, 24, 23);
+    let l = bit(i, 22);
+    let (rt, rt2, rn) = (b(i, 4, 0), b(i, 14, 10), b(i, 9, 5));
+    let (name, reg, scale): (&str, fn(u32) -> String, u32) = if bit(i, 26) {
+        match opc {
+            0 => ("p", |n| fpr(n, 2), 2),
+            1 => ("p", |n| fpr(n, 3), 3),
+            2 => ("p", |n| fpr(n, 4), 4),
+            _ => return None,
+        }
+    } else {
+        match (opc, l) {
+            (0, _) => ("p", w, 2),
+            (1, true) if mode != 0 => ("psw", x, 2),
+            (2, _) => ("p", x, 3),
+            _ => return None,
+        }
+    };
+    let name = format!("{}{}{}", if l { "ld" } else { "st" }, if mode == 0 { "n" } else { "" }, name);
+    let off = sext(b(i, 21, 15), 7) << scale;
+    let addr = match mode {
+        1 => format!("[{}], #{}", xsp(rn), off),
+        3 => format!("[{}, #{}]!", xsp(rn), off),
+        _ => mem_off(rn, off),
+    };
+    insn(&name, &[reg(rt), reg(rt2), addr])
+}
+
+enum LdstKind {
+    Load,
+    Store,
+    Prefetch,
+}
+
+// (kind, mnemonic suffix, register, log2 access size)
+fn ldst_reg_kind(size: u32, v: bool, opc: u32, rt: u32) -> Option<(LdstKind, &'static str, String, u32)> {
+    let sfx = ["b", "h", "", ""][size as usize];
+    Some(if v {
+        match (opc, size) {
+            (0, _) => (LdstKind::Store, "", fpr(rt, size), size),
+            (1, _) => (LdstKind::Load, "", fpr(rt, size), size),
+            (2, 0) => (LdstKind::Store, "", fpr(rt, 4), 4),
+            (3, 0) => (LdstKind::Load, "", fpr(rt, 4), 4),
+            _ => return None,
+        }
+    } else {
+        match (opc, size) {
+            (0, _) => (LdstKind::Store, sfx, gpr(rt, size == 3), size),
+            (1, _) => (LdstKind::Load, sfx, gpr(rt, size == 3), size),
+            (2, 3) => (LdstKind::Prefetch, "", prfop(rt), 3),
+            (2, _) => (LdstKind::Load, ["sb", "sh", "sw", ""][size as usize], x(rt), size),
+            (3, 0) | (3, 1) => (LdstKind::Load, ["sb", "sh"][size as usize], w(rt), size),
+            _ => return None,
+        }
+    })
+}
+
+fn ldst_reg(i: u32) -> Option<String> {
+    let size = b(i, 31, 30);
+    let v = bit(i, 26);
+    let opc = b(i, 23, 22);
+    let (rt, rn) = (b(i, 4, 0), b(i, 9, 5));
+    if !bit(i, 24) && bit(i, 21) {
+        match b(i, 11, 10) {
+            0b10 => (),
+            0b00 => return if v { None } else { atomic(i) },
+            _ => {
+                // ldraa/ldrab
+                if size != 3 || v { return None; }
+                let off = sext((b(i, 22, 22) << 9) | b(i, 20, 12), 10) << 3;
+                let name = if bit(i, 23) { "ldrab" } else { "ldraa" };
+                let addr = if bit(i, 11) { format!("[{}, #{}]!", xsp(rn), off) } else { mem_off(rn, off) };
+                return insn(name, &[x(rt), addr]);
+            },
+        }
+    }
+    let (kind, sfx, reg, scale) = ldst_reg_kind(size, v, opc, rt)?;
+    let name = |infix: &str| match kind {
+        LdstKind::Load => format!("ld{}{}", infix, sfx),
+        LdstKind::Store => format!("st{}{}", infix, sfx),
+        LdstKind::Prefetch => format!("prf{}", if infix == "r" { "m" } else { "um" }),
+    };
+    let is_prefetch = matches!(kind, LdstKind::Prefetch);
+    if bit(i, 24) {
+        return insn(&name("r"), &[reg, mem_off(rn, (b(i, 21, 10) as i64) << scale)]);
+    }
+    if bit(i, 21) {
+        // register offset
+        let option = b(i, 15, 13);
+        let s = bit(i, 12);
+        let rm = b(i, 20, 16);
+        if option & 2 == 0 { return None; }
+        let rm_s = gpr(rm, option & 1 != 0);
+        let ext = ["", "", "uxtw", "lsl", "", "", "sxtw", "sxtx"][option as usize];
+        let addr = if option == 3 && !s {
+            format!("[{}, {}]", xsp(rn), rm_s)
+        } else if s {
+            format!("[{}, {}, {} #{}]", xsp(rn), rm_s, ext, scale)
+        } else {
+            format!("[{}, {}, {}]", xsp(rn), rm_s, ext)
+        };
+        return insn(&name("r"), &[reg, addr]);
+    }
+    let off = sext(b(i, 20, 12), 9);
+    match b(i, 11, 10) {
+        0 => insn(&name("ur"), &[reg, mem_off(rn, off)]),
+        1 if !is_prefetch => insn(&name("r"), &[reg, format!("[{}], #{}", xsp(rn), off)]),
+        2 if !is_prefetch && !v => insn(&name("tr"), &[reg, mem_off(rn, off)]),
+        3 if !is_prefetch => insn(&name("r"), &[reg, format!("[{}, #{}]!", xsp(rn), off)]),
+        _ => None,
+    }
+}
+
+fn atomic(i: u32) -> Option<String> {
+    let size = b(i, 31, 30);
+    let (a, r, o3) = (bit(i, 23), bit(i, 22), bit(i, 15));
+    let opc = b(i, 14, 12);
+    let rs = b(i, 20, 16);
+    let rt = b(i, 4, 0);
+    let sf = size == 3;
+    let sfx = ["b", "h", "", ""][size as usize];
+    let addr = format!("[{}]", xsp(b(i, 9, 5)));
+    let al = format!("{}{}", if a { "a" } else { "" }, if r { "l" } else { "" });
+    if o3 {
+        match opc {
+            0 => insn(&format!("swp{}{}", al, sfx), &[gpr(rs, sf), gpr(rt, sf), addr]),
+            4 if a && !r && rs == 31 => insn(&format!("ldapr{}", sfx), &[gpr(rt, sf), addr]),
+            _ => None,
+        }
+    } else {
+        let op = ["add", "clr", "eor", "set", "smax", "smin", "umax", "umin"][opc as usize];
+        if rt == 31 && !a {
+            insn(&format!("st{}{}{}", op, al, sfx), &[gpr(rs, sf), addr])
+        } else {
+            insn(&format!("ld{}{}{}", op, al, sfx), &[gpr(rs, sf), gpr(rt, sf), addr])
+        }
+    }
+}
+
+// Data processing (register)
+
+fn dp_reg(i: u32) -> Option<String> {
+    let sf = bit(i, 31);
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    let op = bit(i, 30);
+    let s = bit(i, 29);
+    let op2 = b(i, 24, 21);
+    let op3 = b(i, 15, 10);
+    if !bit(i, 28) {
+        let shift = b(i, 23, 22);
+        let amount = b(i, 15, 10);
+        if op2 & 8 == 0 {
+            // logical (shifted register)
+            if !sf && amount >= 32 { return None; }
+            let opc = b(i, 30, 29);
+            let n = bit(i, 21);
+            let sh = shift_suffix(shift, amount);
+            return if opc == 1 && !n && rn == 31 && shift == 0 && amount == 0 {
+                insn("mov", &[gpr(rd, sf), gpr(rm, sf)])
+            } else if opc == 1 && n && rn == 31 {
+                insn("mvn", &[gpr(rd, sf), gpr(rm, sf) + &sh])
+            } else if opc == 3 && !n && rd == 31 {
+                insn("tst", &[gpr(rn, sf), gpr(rm, sf) + &sh])
+            } else {
+                let name = [["and", "bic"], ["orr", "orn"], ["eor", "eon"], ["ands", "bics"]][opc as usize][n as usize];
+                insn(name, &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf) + &sh])
+            };
+        }
+        let name = ["add", "adds", "sub", "subs"][(op as usize) * 2 + s as usize];
+        if op2 & 1 == 0 {
+            // add/subtract (shifted register)
+            if shift == 3 || (!sf && amount >= 32) { return None; }
+            let rm_s = gpr(rm, sf) + &shift_suffix(shift, amount);
+            return if s && rd == 31 {
+                insn(if op { "cmp" } else { "cmn" }, &[gpr(rn, sf), rm_s])
+            } else if op && rn == 31 {
+                insn(if s { "negs" } else { "neg" }, &[gpr(rd, sf), rm_s])
+            } else {
+                insn(name, &[gpr(rd, sf), gpr(rn, sf), rm_s])
+            };
+        }
+        // add/subtract (extended register)
+        let option = b(i, 15, 13);
+        let amount = b(i, 12, 10);
+        if shift != 0 || amount > 4 { return None; }
+        let mut rm_s = gpr(rm, sf && option & 3 == 3);
+        if (rn == 31 || (!s && rd == 31)) && option == if sf { 3 } else { 2 } {
+            if amount != 0 { write!(&mut rm_s, ", lsl #{}", amount).unwrap(); }
+        } else {
+            let ext = ["uxtb", "uxth", "uxtw", "uxtx", "sxtb", "sxth", "sxtw", "sxtx"][option as usize];
+            write!(&mut rm_s, ", {}", ext).unwrap();
+            if amount != 0 { write!(&mut rm_s, " #{}", amount).unwrap(); }
+        }
+        return if s && rd == 31 {
+            insn(if op { "cmp" } else { "cmn" }, &[gpr_sp(rn, sf), rm_s])
+        } else {
+            insn(name, &[if s { gpr(rd, sf) } else { gpr_sp(rd, sf) }, gpr_sp(rn, sf), rm_s])
+        };
+    }
+    match op2 {
+        0 => {
+            if op3 != 0 { return None; }
+            if op && rn == 31 {
+                insn(if s { "ngcs" } else { "ngc" }, &[gpr(rd, sf), gpr(rm, sf)])
+            } else {
+                insn(["adc", "adcs", "sbc", "sbcs"][(op as usize) * 2 + s as usize], &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf)])
+            }
+        },
+        2 => {
+            // conditional compare
+            if !s || bit(i, 10) || bit(i, 4) { return None; }
+            let rm_s = if bit(i, 11) { imm(rm as i64) } else { gpr(rm, sf) };
+            insn(if op { "ccmp" } else { "ccmn" }, &[gpr(rn, sf), rm_s, imm(b(i, 3, 0) as i64), cond(b(i, 15, 12)).to_owned()])
+        },
+        4 => {
+            // conditional select
+            if s || bit(i, 11) { return None; }
+            let c = b(i, 15, 12);
+            let inv = cond(c ^ 1).to_owned();
+            let o2 = bit(i, 10);
+            if (o2 || op) && rm == rn && c < 14 {
+                if rn == 31 && !(op && o2) {
+                    return insn(if op { "csetm" } else { "cset" }, &[gpr(rd, sf), inv]);
+                }
+                let name = match (op, o2) { (false, _) => "cinc", (true, false) => "cinv", (true, true) => "cneg" };
+                return insn(name, &[gpr(rd, sf), gpr(rn, sf), inv]);
+            }
+            let name = [["csel", "csinc"], ["csinv", "csneg"]][op as usize][o2 as usize];
+            insn(name, &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf), cond(c).to_owned()])
+        },
+        6 if !op => {
+            // data processing (2 source)
+            if s { return None; }
+            let name = match op3 {
+                2 => "udiv", 3 => "sdiv", 8 => "lsl", 9 => "lsr", 10 => "asr", 11 => "ror",
+                12 if sf => return insn("pacga", &[x(rd), x(rn), xsp(rm)]),
+                16..=23 => {
+                    let sz = op3 & 3;
+                    if (sz == 3) != sf { return None; }
+                    let name = format!("crc32{}{}", if op3 & 4 != 0 { "c" } else { "" }, ["b", "h", "w", "x"][sz as usize]);
+                    return insn(&name, &[w(rd), w(rn), gpr(rm, sz == 3)]);
+                },
+                _ => return None,
+            };
+            insn(name, &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf)])
+        },
+        6 => {
+            // data processing (1 source)
+            if s { return None; }
+            match rm {
+                0 => {
+                    let name = match (op3, sf) {
+                        (0, _) => "rbit", (1, _) => "rev16", (2, true) => "rev32", (2, false) => "rev",
+                        (3, true) => "rev", (4, _) => "clz", (5, _) => "cls",
+                        _ => return None,
+                    };
+                    insn(name, &[gpr(rd, sf), gpr(rn, sf)])
+                },
+                1 if sf => match op3 {
+                    0..=7 => {
+                        let name = ["pacia", "pacib", "pacda", "pacdb", "autia", "autib", "autda", "autdb"][op3 as usize];
+                        insn(name, &[x(rd), xsp(rn)])
+                    },
+                    8..=15 if rn == 31 => {
+                        let name = ["paciza", "pacizb", "pacdza", "pacdzb", "autiza", "autizb", "autdza", "autdzb"][op3 as usize - 8];
+                        insn(name, &[x(rd)])
+                    },
+                    16 if rn == 31 => insn("xpaci", &[x(rd)]),
+                    17 if rn == 31 => insn("xpacd", &[x(rd)]),
+                    _ => None,
+                },
+                _ => None,
+            }
+        },
+        8..=15 => {
+            // data processing (3 source)
+            if op || s { return None; }
+            let ra = b(i, 14, 10);
+            let o0 = bit(i, 15);
+            let op31 = b(i, 23, 21);
+            if op31 == 0 {
+                return if ra == 31 {
+                    insn(if o0 { "mneg" } else { "mul" }, &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf)])
+                } else {
+                    insn(if o0 { "msub" } else { "madd" }, &[gpr(rd, sf), gpr(rn, sf), gpr(rm, sf), gpr(ra, sf)])
+                };
+            }
+            if !sf { return None; }
+            let (long, short) = match (op31, o0) {
+                (1, false) => ("smaddl", "smull"),
+                (1, true) => ("smsubl", "smnegl"),
+                (5, false) => ("umaddl", "umull"),
+                (5, true) => ("umsubl", "umnegl"),
+                (2, false) => return insn("smulh", &[x(rd), x(rn), x(rm)]),
+                (6, false) => return insn("umulh", &[x(rd), x(rn), x(rm)]),
+                _ => return None,
+            };
+            if ra == 31 {
+                insn(short, &[x(rd), w(rn), w(rm)])
+            } else {
+                insn(long, &[x(rd), w(rn), w(rm), x(ra)])
+            }
+        },
+        _ => None,
+    }
+}
+
+// SIMD and floating point
+
+fn simd_fp(i: u32) -> Option<String> {
+    if i & 0xff3e0c00 == 0x4e280800 {
+        crypto_aes(i)
+    } else if i & 0xffe08c00 == 0x5e000000 {
+        crypto_sha3(i)
+    } else if i & 0xff3e0c00 == 0x5e280800 {
+        crypto_sha2(i)
+    } else if !bit(i, 30) && bit(i, 28) {
+        fp(i)
+    } else if !bit(i, 31) && !bit(i, 28) {
+        simd_vector(i)
+    } else if i & 0xd0000000 == 0x50000000 {
+        simd_scalar(i)
+    } else {
+        None
+    }
+}
+
+fn crypto_aes(i: u32) -> Option<String> {
+    if b(i, 23, 22) != 0 { return None; }
+    let name = match b(i, 16, 12) {
+        4 => "aese", 5 => "aesd", 6 => "aesmc", 7 => "aesimc",
+        _ => return None,
+    };
+    insn(name, &[vreg(b(i, 4, 0), "16b"), vreg(b(i, 9, 5), "16b")])
+}
+
+fn crypto_sha3(i: u32) -> Option<String> {
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    match b(i, 14, 12) {
+        0 => insn("sha1c", &[fpr(rd, 4), fpr(rn, 2), vreg(rm, "4s")]),
+        1 => insn("sha1p", &[fpr(rd, 4), fpr(rn, 2), vreg(rm, "4s")]),
+        2 => insn("sha1m", &[fpr(rd, 4), fpr(rn, 2), vreg(rm, "4s")]),
+        3 => insn("sha1su0", &[vreg(rd, "4s"), vreg(rn, "4s"), vreg(rm, "4s")]),
+        4 => insn("sha256h", &[fpr(rd, 4), fpr(rn, 4), vreg(rm, "4s")]),
+        5 => insn("sha256h2", &[fpr(rd, 4), fpr(rn, 4), vreg(rm, "4s")]),
+        6 => insn("sha256su1", &[vreg(rd, "4s"), vreg(rn, "4s"), vreg(rm, "4s")]),
+        _ => None,
+    }
+}
+
+fn crypto_sha2(i: u32) -> Option<String> {
+    if b(i, 23, 22) != 0 { return None; }
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    match b(i, 16, 12) {
+        0 => insn("sha1h", &[fpr(rd, 2), fpr(rn, 2)]),
+        1 => insn("sha1su1", &[vreg(rd, "4s"), vreg(rn, "4s")]),
+        2 => insn("sha256su0", &[vreg(rd, "4s"), vreg(rn, "4s")]),
+        _ => None,
+    }
+}
+
+// VFPExpandImm
+fn fp_imm(imm8: u32) -> String {
+    let exp = ((imm8 >> 4) & 7) as i32 ^ 4;
+    let val = (1.0 + (imm8 & 0xf) as f64 / 16.0) * 2f64.powi(exp - 3);
+    format!("#{:.8}", if imm8 & 0x80 != 0 { -val } else { val })
+}
+
+fn fp(i: u32) -> Option<String> {
+    let sf = bit(i, 31);
+    let ptype = b(i, 23, 22);
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    if bit(i, 29) { return None; }
+    let f = |n| fpr_ptype(n, ptype);
+    if bit(i, 24) {
+        if sf { return None; }
+        let name = ["fmadd", "fmsub", "fnmadd", "fnmsub"][(b(i, 21, 21) * 2 + b(i, 15, 15)) as usize];
+        return insn(name, &[f(rd)?, f(rn)?, f(rm)?, f(b(i, 14, 10))?]);
+    }
+    if !bit(i, 21) {
+        // conversion between floating-point and fixed-point
+        let scale = b(i, 15, 10);
+        if !sf && scale < 32 { return None; }
+        let fbits = imm(64 - scale as i64);
+        return match (b(i, 20, 19), b(i, 18, 16)) {
+            (0, 2) => insn("scvtf", &[f(rd)?, gpr(rn, sf), fbits]),
+            (0, 3) => insn("ucvtf", &[f(rd)?, gpr(rn, sf), fbits]),
+            (3, 0) => insn("fcvtzs", &[gpr(rd, sf), f(rn)?, fbits]),
+            (3, 1) => insn("fcvtzu", &[gpr(rd, sf), f(rn)?, fbits]),
+            _ => None,
+        };
+    }
+    if sf && b(i, 15, 10) != 0 { return None; }
+    match b(i, 11, 10) {
+        1 => {
+            let name = if bit(i, 4) { "fccmpe" } else { "fccmp" };
+            return insn(name, &[f(rn)?, f(rm)?, imm(b(i, 3, 0) as i64), cond(b(i, 15, 12)).to_owned()]);
+        },
+        2 => {
+            let name = match b(i, 15, 12) {
+                0 => "fmul", 1 => "fdiv", 2 => "fadd", 3 => "fsub", 4 => "fmax", 5 => "fmin",
+                6 => "fmaxnm", 7 => "fminnm", 8 => "fnmul",
+                _ => return None,
+            };
+            return insn(name, &[f(rd)?, f(rn)?, f(rm)?]);
+        },
+        3 => return insn("fcsel", &[f(rd)?, f(rn)?, f(rm)?, cond(b(i, 15, 12)).to_owned()]),
+        _ => (),
+    }
+    if bit(i, 12) {
+        if b(i, 9, 5) != 0 { return None; }
+        return insn("fmov", &[f(rd)?, fp_imm(b(i, 20, 13))]);
+    }
+    if b(i, 13, 10) == 0b1000 {
+        if b(i, 15, 14) != 0 { return None; }
+        let (name, zero) = match b(i, 4, 0) {
+            0 => ("fcmp", false), 8 => ("fcmp", true), 16 => ("fcmpe", false), 24 => ("fcmpe", true),
+            _ => return None,
+        };
+        return insn(name, &[f(rn)?, if zero { "#0.0".to_owned() } else { f(rm)? }]);
+    }
+    if b(i, 14, 10) == 0b10000 {
+        let opcode = b(i, 20, 15);
+        let name = match opcode {
+            0 => "fmov", 1 => "fabs", 2 => "fneg", 3 => "fsqrt",
+            4..=7 => {
+                let to = opcode & 3;
+                if to == 2 || to == ptype { return None; }
+                return insn("fcvt", &[fpr_ptype(rd, to)?, f(rn)?]);
+            },
+            8 => "frintn", 9 => "frintp", 10 => "frintm", 11 => "frintz", 12 => "frinta",
+            14 => "frintx", 15 => "frinti",
+            16 if ptype < 2 => "frint32z", 17 if ptype < 2 => "frint32x",
+            18 if ptype < 2 => "frint64z", 19 if ptype < 2 => "frint64x",
+            _ => return None,
+        };
+        return insn(name, &[f(rd)?, f(rn)?]);
+    }
+    if b(i, 15, 10) != 0 { return None; }
+    // conversion between floating-point and integer
+    let (rmode, opcode) = (b(i, 20, 19), b(i, 18, 16));
+    if ptype == 2 {
+        return match (sf, rmode, opcode) {
+            (true, 1, 6) => insn("fmov", &[x(rd), format!("v{}.d[1]", rn)]),
+            (true, 1, 7) => insn("fmov", &[format!("v{}.d[1]", rd), x(rn)]),
+            _ => None,
+        };
+    }
+    match (rmode, opcode) {
+        (_, 0) | (_, 1) => {
+            let name = format!("fcvt{}{}", ["n", "p", "m", "z"][rmode as usize], if opcode == 1 { "u" } else { "s" });
+            insn(&name, &[gpr(rd, sf), f(rn)?])
+        },
+        (0, 2) => insn("scvtf", &[f(rd)?, gpr(rn, sf)]),
+        (0, 3) => insn("ucvtf", &[f(rd)?, gpr(rn, sf)]),
+        (0, 4) => insn("fcvtas", &[gpr(rd, sf), f(rn)?]),
+        (0, 5) => insn("fcvtau", &[gpr(rd, sf), f(rn)?]),
+        (0, 6) | (0, 7) => {
+            if ptype != 3 && (ptype == 1) != sf { return None; }
+            if opcode == 6 {
+                insn("fmov", &[gpr(rd, sf), f(rn)?])
+            } else {
+                insn("fmov", &[f(rd)?, gpr(rn, sf)])
+            }
+        },
+        (3, 6) if !sf && ptype == 1 => insn("fjcvtzs", &[w(rd), f(rn)?]),
+        _ => None,
+    }
+}
+
+fn simd_vector(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    if i & 0x9f200400 == 0x0e200400 {
+        return simd_three_same(i);
+    }
+    if i & 0x9f200c00 == 0x0e200000 {
+        return simd_three_different(i);
+    }
+    if i & 0x9f3e0c00 == 0x0e200800 {
+        return simd_two_reg_misc(i);
+    }
+    if i & 0x9f3e0c00 == 0x0e300800 {
+        // across lanes
+        if size == 3 || (size == 2 && !q) { return None; }
+        let (name, long) = match (u, b(i, 16, 12)) {
+            (false, 3) => ("saddlv", true), (true, 3) => ("uaddlv", true),
+            (false, 10) => ("smaxv", false), (true, 10) => ("umaxv", false),
+            (false, 26) => ("sminv", false), (true, 26) => ("uminv", false),
+            (false, 27) => ("addv", false),
+            _ => return None,
+        };
+        return insn(name, &[fpr(rd, size + long as u32), vreg(rn, arr(size, q))]);
+    }
+    if i & 0x9f208400 == 0x0e008400 {
+        // three same (extra): rounding doubling multiply-accumulate and dot product
+        let name = match (u, b(i, 14, 11)) {
+            (true, 0) if size == 1 || size == 2 => "sqrdmlah",
+            (true, 1) if size == 1 || size == 2 => "sqrdmlsh",
+            (false, 2) if size == 2 => return insn("sdot", &[vreg(rd, arr(2, q)), vreg(rn, arr(0, q)), vreg(rm, arr(0, q))]),
+            (true, 2) if size == 2 => return insn("udot", &[vreg(rd, arr(2, q)), vreg(rn, arr(0, q)), vreg(rm, arr(0, q))]),
+            _ => return None,
+        };
+        let a = arr(size, q);
+        return insn(name, &[vreg(rd, a), vreg(rn, a), vreg(rm, a)]);
+    }
+    if i & 0x9fe08400 == 0x0e000400 {
+        return simd_copy(i);
+    }
+    if i & 0x9ff80400 == 0x0f000400 {
+        return simd_modified_imm(i);
+    }
+    if i & 0x9f800400 == 0x0f000400 {
+        return simd_shift_imm(i);
+    }
+    if i & 0x9f000400 == 0x0f000000 {
+        return simd_by_element(i);
+    }
+    if i & 0xbfe08c00 == 0x0e000000 {
+        let len = b(i, 14, 13) + 1;
+        let a = if q { "16b" } else { "8b" };
+        return insn(if bit(i, 12) { "tbx" } else { "tbl" }, &[vreg(rd, a), reglist(rn, len, "16b"), vreg(rm, a)]);
+    }
+    if i & 0xbf208c00 == 0x0e000800 {
+        if size == 3 && !q { return None; }
+        let name = match b(i, 14, 12) {
+            1 => "uzp1", 2 => "trn1", 3 => "zip1", 5 => "uzp2", 6 => "trn2", 7 => "zip2",
+            _ => return None,
+        };
+        let a = arr(size, q);
+        return insn(name, &[vreg(rd, a), vreg(rn, a), vreg(rm, a)]);
+    }
+    if i & 0xbfe08400 == 0x2e000000 {
+        let index = b(i, 14, 11);
+        if !q && index >= 8 { return None; }
+        let a = if q { "16b" } else { "8b" };
+        return insn("ext", &[vreg(rd, a), vreg(rn, a), vreg(rm, a), imm(index as i64)]);
+    }
+    None
+}
+
+fn simd_three_same(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    let opcode = b(i, 15, 11);
+    if opcode == 3 {
+        let a = if q { "16b" } else { "8b" };
+        if !u && size == 2 && rm == rn {
+            return insn("mov", &[vreg(rd, a), vreg(rn, a)]);
+        }
+        let name = [["and", "bic", "orr", "orn"], ["eor", "bsl", "bit", "bif"]][u as usize][size as usize];
+        return insn(name, &[vreg(rd, a), vreg(rn, a), vreg(rm, a)]);
+    }
+    if opcode >= 0x18 {
+        let sz = size & 1;
+        if sz == 1 && !q { return None; }
+        let name = match (u, size >> 1, opcode) {
+            (false, 0, 0x18) => "fmaxnm", (false, 0, 0x19) => "fmla", (false, 0, 0x1a) => "fadd",
+            (false, 0, 0x1b) => "fmulx", (false, 0, 0x1c) => "fcmeq", (false, 0, 0x1e) => "fmax",
+            (false, 0, 0x1f) => "frecps",
+            (false, 1, 0x18) => "fminnm", (false, 1, 0x19) => "fmls", (false, 1, 0x1a) => "fsub",
+            (false, 1, 0x1e) => "fmin", (false, 1, 0x1f) => "frsqrts",
+            (true, 0, 0x18) => "fmaxnmp", (true, 0, 0x1a) => "faddp", (true, 0, 0x1b) => "fmul",
+            (true, 0, 0x1c) => "fcmge", (true, 0, 0x1d) => "facge", (true, 0, 0x1e) => "fmaxp",
+            (true, 0, 0x1f) => "fdiv",
+            (true, 1, 0x18) => "fminnmp", (true, 1, 0x1a) => "fabd", (true, 1, 0x1c) => "fcmgt",
+            (true, 1, 0x1d) => "facgt", (true, 1, 0x1e) => "fminp",
+            _ => return None,
+        };
+        let a = arr(sz + 2, q);
+        return insn(name, &[vreg(rd, a), vreg(rn, a), vreg(rm, a)]);
+    }
+    // (name, allows 64-bit lanes)
+    let (name, d_ok) = match (u, opcode) {
+        (false, 0x00) => ("shadd", false), (true, 0x00) => ("uhadd", false),
+        (false, 0x01) => ("sqadd", true), (true, 0x01) => ("uqadd", true),
+        (false, 0x02) => ("srhadd", false), (true, 0x02) => ("urhadd", false),
+        (false, 0x04) => ("shsub", false), (true, 0x04) => ("uhsub", false),
+        (false, 0x05) => ("sqsub", true), (true, 0x05) => ("uqsub", true),
+        (false, 0x06) => ("cmgt", true), (true, 0x06) => ("cmhi", true),
+        (false, 0x07) => ("cmge", true), (true, 0x07) => ("cmhs", true),
+        (false, 0x08) => ("sshl", true), (true, 0x08) => ("ushl", true),
+        (false, 0x09) => ("sqshl", true), (true, 0x09) => ("uqshl", true),
+        (false, 0x0a) => ("srshl", true), (true, 0x0a) => ("urshl", true),
+        (false, 0x0b) => ("sqrshl", true), (true, 0x0b) => ("uqrshl", true),
+        (false, 0x0c) => ("smax", false), (true, 0x0c) => ("umax", false),
+        (false, 0x0d) => ("smin", false), (true, 0x0d) => ("umin", false),
+        (false, 0x0e) => ("sabd", false), (true, 0x0e) => ("uabd", false),
+        (false, 0x0f) => ("saba", false), (true, 0x0f) => ("uaba", false),
+        (false, 0x10) => ("add", true), (true, 0x10) => ("sub", true),
+        (false, 0x11) => ("cmtst", true), (true, 0x11) => ("cmeq", true),
+        (false, 0x12) => ("mla", false), (true, 0x12) => ("mls", false),
+        (false, 0x13) => ("mul", false),
+        (true, 0x13) if size == 0 => ("pmul", false),
+        (false, 0x14) => ("smaxp", false), (true, 0x14) => ("umaxp", false),
+        (false, 0x15) => ("sminp", false), (true, 0x15) => ("uminp", false),
+        (false, 0x16) | (true, 0x16) if size == 1 || size == 2 => (if u { "sqrdmulh" } else { "sqdmulh" }, false),
+        (false, 0x17) => ("addp", true),
+        _ => return None,
+    };
+    if size == 3 && (!d_ok || !q) { return None; }
+    let a = arr(size, q);
+    insn(name, &[vreg(rd, a), vreg(rn, a), vreg(rm, a)])
+}
+
+fn simd_three_different(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    let opcode = b(i, 15, 12);
+    if opcode == 14 && !u && (size == 0 || size == 3) {
+        let (wide, narrow) = if size == 0 { ("8h", arr(0, q)) } else { ("1q", if q { "2d" } else { "1d" }) };
+        return insn(if q { "pmull2" } else { "pmull" }, &[vreg(rd, wide), vreg(rn, narrow), vreg(rm, narrow)]);
+    }
+    if size == 3 { return None; }
+    // 'l': wide <- narrow, narrow; 'w': wide <- wide, narrow; 'n': narrow <- wide, wide
+    let (name, form) = match (u, opcode) {
+        (false, 0) => ("saddl", 'l'), (true, 0) => ("uaddl", 'l'),
+        (false, 1) => ("saddw", 'w'), (true, 1) => ("uaddw", 'w'),
+        (false, 2) => ("ssubl", 'l'), (true, 2) => ("usubl", 'l'),
+        (false, 3) => ("ssubw", 'w'), (true, 3) => ("usubw", 'w'),
+        (false, 4) => ("addhn", 'n'), (true, 4) => ("raddhn", 'n'),
+        (false, 5) => ("sabal", 'l'), (true, 5) => ("uabal", 'l'),
+        (false, 6) => ("subhn", 'n'), (true, 6) => ("rsubhn", 'n'),
+        (false, 7) => ("sabdl", 'l'), (true, 7) => ("uabdl", 'l'),
+        (false, 8) => ("smlal", 'l'), (true, 8) => ("umlal", 'l'),
+        (false, 9) if size != 0 => ("sqdmlal", 'l'),
+        (false, 10) => ("smlsl", 'l'), (true, 10) => ("umlsl", 'l'),
+        (false, 11) if size != 0 => ("sqdmlsl", 'l'),
+        (false, 12) => ("smull", 'l'), (true, 12) => ("umull", 'l'),
+        (false, 13) if size != 0 => ("sqdmull", 'l'),
+        _ => return None,
+    };
+    let (wide, narrow) = (arr(size + 1, true), arr(size, q));
+    let (d, n, m) = match form {
+        'l' => (wide, narrow, narrow),
+        'w' => (wide, wide, narrow),
+        _ => (narrow, wide, wide),
+    };
+    insn(&format!("{}{}", name, if q { "2" } else { "" }), &[vreg(rd, d), vreg(rn, n), vreg(rm, m)])
+}
+
+fn simd_two_reg_misc(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    let opcode = b(i, 16, 12);
+    let a = arr(size, q);
+    if opcode >= 0xc && opcode != 0x12 && opcode != 0x13 && opcode != 0x14 {
+        // floating point
+        let sz = size & 1;
+        let sfx = if q { "2" } else { "" };
+        match (u, size >> 1, opcode) {
+            (false, 0, 0x16) => return insn(&format!("fcvtn{}", sfx), &[vreg(rd, arr(sz + 1, q)), vreg(rn, arr(sz + 2, true))]),
+            (false, 0, 0x17) => return insn(&format!("fcvtl{}", sfx), &[vreg(rd, arr(sz + 2, true)), vreg(rn, arr(sz + 1, q))]),
+            (true, 0, 0x16) if sz == 1 => return insn(&format!("fcvtxn{}", sfx), &[vreg(rd, arr(2, q)), vreg(rn, "2d")]),
+            _ => (),
+        }
+        if sz == 1 && !q { return None; }
+        let a = arr(sz + 2, q);
+        let zero = match (u, size >> 1, opcode) {
+            (false, 1, 0x0c) => Some("fcmgt"), (false, 1, 0x0d) => Some("fcmeq"), (false, 1, 0x0e) => Some("fcmlt"),
+            (true, 1, 0x0c) => Some("fcmge"), (true, 1, 0x0d) => Some("fcmle"),
+            _ => None,
+        };
+        if let Some(name) = zero {
+            return insn(name, &[vreg(rd, a), vreg(rn, a), "#0.0".to_owned()]);
+        }
+        let name = match (u, size >> 1, opcode) {
+            (false, 0, 0x18) => "frintn", (false, 0, 0x19) => "frintm", (false, 0, 0x1a) => "fcvtns",
+            (false, 0, 0x1b) => "fcvtms", (false, 0, 0x1c) => "fcvtas", (false, 0, 0x1d) => "scvtf",
+            (false, 1, 0x0f) => "fabs", (false, 1, 0x18) => "frintp", (false, 1, 0x19) => "frintz",
+            (false, 1, 0x1a) => "fcvtps", (false, 1, 0x1b) => "fcvtzs", (false, 1, 0x1d) => "frecpe",
+            (true, 0, 0x18) => "frinta", (true, 0, 0x19) => "frintx", (true, 0, 0x1a) => "fcvtnu",
+            (true, 0, 0x1b) => "fcvtmu", (true, 0, 0x1c) => "fcvtau", (true, 0, 0x1d) => "ucvtf",
+            (true, 1, 0x0f) => "fneg", (true, 1, 0x19) => "frinti", (true, 1, 0x1a) => "fcvtpu",
+            (true, 1, 0x1b) => "fcvtzu", (true, 1, 0x1d) => "frsqrte", (true, 1, 0x1f) => "fsqrt",
+            _ => return None,
+        };
+        return insn(name, &[vreg(rd, a), vreg(rn, a)]);
+    }
+    let zero = || vec![vreg(rd, a), vreg(rn, a), "#0".to_owned()];
+    match (u, opcode) {
+        (false, 0x00) | (true, 0x00) | (false, 0x01) => {
+            // rev64, rev32, rev16: the element has to be smaller than the reversed chunk
+            let chunk = match (u, opcode) { (false, 0) => 3, (true, 0) => 2, _ => 1 };
+            if size >= chunk { return None; }
+            insn(["rev64", "rev32", "rev16"][(3 - chunk) as usize], &[vreg(rd, a), vreg(rn, a)])
+        },
+        (false, 0x02) | (true, 0x02) | (false, 0x06) | (true, 0x06) => {
+            if size == 3 { return None; }
+            let name = match (u, opcode) { (false, 2) => "saddlp", (true, 2) => "uaddlp", (false, _) => "sadalp", (true, _) => "uadalp" };
+            insn(name, &[vreg(rd, arr(size + 1, q)), vreg(rn, a)])
+        },
+        (false, 0x05) if size == 0 => insn("cnt", &[vreg(rd, a), vreg(rn, a)]),
+        (true, 0x05) if size == 0 => insn("mvn", &[vreg(rd, a), vreg(rn, a)]),
+        (true, 0x05) if size == 1 => insn("rbit", &[vreg(rd, arr(0, q)), vreg(rn, arr(0, q))]),
+        (false, 0x04) | (true, 0x04) | (false, 0x03) | (true, 0x03) | (false, 0x07) | (true, 0x07) |
+        (false, 0x0b) | (true, 0x0b) => {
+            if size == 3 && !q { return None; }
+            let name = match (u, opcode) {
+                (false, 0x04) => "cls", (true, 0x04) => "clz",
+                (false, 0x03) => "suqadd", (true, 0x03) => "usqadd",
+                (false, 0x07) => "sqabs", (true, 0x07) => "sqneg",
+                (false, _) => "abs", (true, _) => "neg",
+            };
+            if opcode == 4 && size == 3 { return None; }
+            insn(name, &[vreg(rd, a), vreg(rn, a)])
+        },
+        (_, 0x08) | (_, 0x09) | (false, 0x0a) => {
+            if size == 3 && !q { return None; }
+            let name = match (u, opcode) {
+                (false, 0x08) => "cmgt", (true, 0x08) => "cmge",
+                (false, 0x09) => "cmeq", (true, 0x09) => "cmle",
+                _ => "cmlt",
+            };
+            insn(name, &zero())
+        },
+        (_, 0x12) | (_, 0x14) => {
+            if size == 3 { return None; }
+            let name = match (u, opcode) {
+                (false, 0x12) => "xtn", (true, 0x12) => "sqxtun",
+                (false, _) => "sqxtn", (true, _) => "uqxtn",
+            };
+            insn(&format!("{}{}", name, if q { "2" } else { "" }), &[vreg(rd, a), vreg(rn, arr(size + 1, true))])
+        },
+        (true, 0x13) => {
+            if size == 3 { return None; }
+            insn(if q { "shll2" } else { "shll" }, &[vreg(rd, arr(size + 1, true)), vreg(rn, a), imm(8 << size)])
+        },
+        _ => None,
+    }
+}
+
+fn simd_copy(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let op = bit(i, 29);
+    let imm5 = b(i, 20, 16);
+    let imm4 = b(i, 14, 11);
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    let size = imm5.trailing_zeros();
+    if size > 3 { return None; }
+    let index = imm5 >> (size + 1);
+    if op {
+        if !q { return None; }
+        return insn("mov", &[velem(rd, size, index), velem(rn, size, imm4 >> size)]);
+    }
+    match imm4 {
+        0 => {
+            if size == 3 && !q { return None; }
+            insn("dup", &[vreg(rd, arr(size, q)), velem(rn, size, index)])
+        },
+        1 => {
+            if size == 3 && !q { return None; }
+            insn("dup", &[vreg(rd, arr(size, q)), gpr(rn, size == 3)])
+        },
+        3 if q => insn("mov", &[velem(rd, size, index), gpr(rn, size == 3)]),
+        5 => {
+            if size == 3 || (size == 2 && !q) { return None; }
+            insn("smov", &[gpr(rd, q), velem(rn, size, index)])
+        },
+        7 => {
+            if (size == 3) != q { return None; }
+            insn(if size >= 2 { "mov" } else { "umov" }, &[gpr(rd, q), velem(rn, size, index)])
+        },
+        _ => None,
+    }
+}
+
+fn simd_modified_imm(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let op = bit(i, 29);
+    let cmode = b(i, 15, 12);
+    let imm8 = (b(i, 18, 16) << 5) | b(i, 9, 5);
+    let rd = b(i, 4, 0);
+    if bit(i, 11) { return None; }
+    let shifted = |name: &str, a: &str, shift: u32, msl: bool| {
+        let mut val = imm(imm8 as i64);
+        if msl || shift != 0 {
+            write!(&mut val, ", {} #{}", if msl { "msl" } else { "lsl" }, shift).unwrap();
+        }
+        insn(name, &[vreg(rd, a), val])
+    };
+    match cmode {
+        0 | 2 | 4 | 6 => shifted(if op { "mvni" } else { "movi" }, arr(2, q), (cmode >> 1) * 8, false),
+        1 | 3 | 5 | 7 => shifted(if op { "bic" } else { "orr" }, arr(2, q), (cmode >> 1) * 8, false),
+        8 | 10 => shifted(if op { "mvni" } else { "movi" }, arr(1, q), (cmode >> 1 & 1) * 8, false),
+        9 | 11 => shifted(if op { "bic" } else { "orr" }, arr(1, q), (cmode >> 1 & 1) * 8, false),
+        12 | 13 => shifted(if op { "mvni" } else { "movi" }, arr(2, q), if cmode & 1 != 0 { 16 } else { 8 }, true),
+        14 if !op => insn("movi", &[vreg(rd, arr(0, q)), imm(imm8 as i64)]),
+        14 => {
+            let mut val = 0u64;
+            for n in 0..8 {
+                if imm8 & (1 << n) != 0 { val |= 0xff << (n * 8); }
+            }
+            // the same format LLVM uses, which is printf's %#016llx
+            let val_s = if val == 0 { "#0000000000000000".to_owned() } else { format!("#0x{:014x}", val) };
+            if q { insn("movi", &[vreg(rd, "2d"), val_s]) } else { insn("movi", &[fpr(rd, 3), val_s]) }
+        },
+        _ => {
+            if op && !q { return None; }
+            insn("fmov", &[vreg(rd, if op { "2d" } else { arr(2, q) }), fp_imm(imm8)])
+        },
+    }
+}
+
+fn simd_shift_imm(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let u = bit(i, 29);
+    let immh = b(i, 22, 19);
+    let immhb = b(i, 22, 16);
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    let size = 31 - immh.leading_zeros();
+    let esize = 8 << size;
+    let right = imm((2 * esize - immhb) as i64);
+    let left = imm((immhb - esize) as i64);
+    let a = arr(size, q);
+    let same = |name: &str, amount: String| {
+        if size == 3 && !q { return None; }
+        insn(name, &[vreg(rd, a), vreg(rn, a), amount])
+    };
+    let suffix2 = if q { "2" } else { "" };
+    let narrow = |name: &str| {
+        if size == 3 { return None; }
+        insn(&format!("{}{}", name, suffix2), &[vreg(rd, a), vreg(rn, arr(size + 1, true)), right.clone()])
+    };
+    match (u, b(i, 15, 11)) {
+        (false, 0x00) => same("sshr", right.clone()), (true, 0x00) => same("ushr", right.clone()),
+        (false, 0x02) => same("ssra", right.clone()), (true, 0x02) => same("usra", right.clone()),
+        (false, 0x04) => same("srshr", right.clone()), (true, 0x04) => same("urshr", right.clone()),
+        (false, 0x06) => same("srsra", right.clone()), (true, 0x06) => same("ursra", right.clone()),
+        (true, 0x08) => same("sri", right.clone()),
+        (false, 0x0a) => same("shl", left.clone()), (true, 0x0a) => same("sli", left.clone()),
+        (true, 0x0c) => same("sqshlu", left.clone()),
+        (false, 0x0e) => same("sqshl", left.clone()), (true, 0x0e) => same("uqshl", left.clone()),
+        (false, 0x1c) | (true, 0x1c) | (false, 0x1f) | (true, 0x1f) if size >= 2 => {
+            let name = match (u, b(i, 15, 11)) {
+                (false, 0x1c) => "scvtf", (true, 0x1c) => "ucvtf", (false, _) => "fcvtzs", (true, _) => "fcvtzu",
+            };
+            same(name, right.clone())
+        },
+        (false, 0x10) => narrow("shrn"), (true, 0x10) => narrow("sqshrun"),
+        (false, 0x11) => narrow("rshrn"), (true, 0x11) => narrow("sqrshrun"),
+        (false, 0x12) => narrow("sqshrn"), (true, 0x12) => narrow("uqshrn"),
+        (false, 0x13) => narrow("sqrshrn"), (true, 0x13) => narrow("uqrshrn"),
+        (_, 0x14) => {
+            if size == 3 { return None; }
+            insn(&format!("{}{}", if u { "ushll" } else { "sshll" }, suffix2), &[vreg(rd, arr(size + 1, true)), vreg(rn, a), left.clone()])
+        },
+        _ => None,
+    }
+}
+
+fn simd_by_element(i: u32) -> Option<String> {
+    let q = bit(i, 30);
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (l, m, h) = (b(i, 21, 21), b(i, 20, 20), b(i, 11, 11));
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    let opcode = b(i, 15, 12);
+    let fp_op = match (u, opcode) {
+        (false, 1) => Some("fmla"), (false, 5) => Some("fmls"), (false, 9) => Some("fmul"), (true, 9) => Some("fmulx"),
+        _ => None,
+    };
+    if let Some(name) = fp_op {
+        let (esize, index) = match size {
+            2 => (2, (h << 1) | l),
+            3 if l == 0 && q => (3, h),
+            _ => return None,
+        };
+        let a = arr(esize, q);
+        return insn(name, &[vreg(rd, a), vreg(rn, a), velem(b(i, 20, 16), esize, index)]);
+    }
+    if opcode == 14 && size == 2 {
+        let elem = format!("v{}.4b[{}]", b(i, 20, 16), (h << 1) | l);
+        return insn(if u { "udot" } else { "sdot" }, &[vreg(rd, arr(2, q)), vreg(rn, arr(0, q)), elem]);
+    }
+    let (rm, index) = match size {
+        1 => (b(i, 19, 16), (h << 2) | (l << 1) | m),
+        2 => (b(i, 20, 16), (h << 1) | l),
+        _ => return None,
+    };
+    let elem = velem(rm, size, index);
+    let (name, long) = match (u, opcode) {
+        (false, 8) => ("mul", false), (true, 0) => ("mla", false), (true, 4) => ("mls", false),
+        (false, 12) => ("sqdmulh", false), (false, 13) => ("sqrdmulh", false),
+        (true, 13) => ("sqrdmlah", false), (true, 15) => ("sqrdmlsh", false),
+        (false, 2) => ("smlal", true), (true, 2) => ("umlal", true),
+        (false, 6) => ("smlsl", true), (true, 6) => ("umlsl", true),
+        (false, 10) => ("smull", true), (true, 10) => ("umull", true),
+        (false, 3) => ("sqdmlal", true), (false, 7) => ("sqdmlsl", true), (false, 11) => ("sqdmull", true),
+        _ => return None,
+    };
+    if long {
+        insn(&format!("{}{}", name, if q { "2" } else { "" }), &[vreg(rd, arr(size + 1, true)), vreg(rn, arr(size, q)), elem])
+    } else {
+        let a = arr(size, q);
+        insn(name, &[vreg(rd, a), vreg(rn, a), elem])
+    }
+}
+
+fn simd_scalar(i: u32) -> Option<String> {
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (rd, rn, rm) = (b(i, 4, 0), b(i, 9, 5), b(i, 20, 16));
+    if i & 0xdfe08400 == 0x5e000400 {
+        if u || b(i, 14, 11) != 0 { return None; }
+        let imm5 = b(i, 20, 16);
+        let esize = imm5.trailing_zeros();
+        if esize > 3 { return None; }
+        return insn("mov", &[fpr(rd, esize), velem(rn, esize, imm5 >> (esize + 1))]);
+    }
+    if i & 0xdf3e0c00 == 0x5e300800 {
+        // pairwise
+        let name = match (u, size >> 1, b(i, 16, 12)) {
+            (false, _, 0x1b) if size == 3 => return insn("addp", &[fpr(rd, 3), vreg(rn, "2d")]),
+            (true, 0, 0x0c) => "fmaxnmp", (true, 0, 0x0d) => "faddp", (true, 0, 0x0f) => "fmaxp",
+            (true, 1, 0x0c) => "fminnmp", (true, 1, 0x0f) => "fminp",
+            _ => return None,
+        };
+        let sz = size & 1;
+        return insn(name, &[fpr(rd, sz + 2), vreg(rn, if sz == 1 { "2d" } else { "2s" })]);
+    }
+    if i & 0xdf200400 == 0x5e200400 {
+        let opcode = b(i, 15, 11);
+        if opcode >= 0x18 {
+            let name = match (u, size >> 1, opcode) {
+                (false, 0, 0x1b) => "fmulx", (false, 0, 0x1c) => "fcmeq", (false, 0, 0x1f) => "frecps",
+                (false, 1, 0x1f) => "frsqrts",
+                (true, 0, 0x1c) => "fcmge", (true, 0, 0x1d) => "facge",
+                (true, 1, 0x1a) => "fabd", (true, 1, 0x1c) => "fcmgt", (true, 1, 0x1d) => "facgt",
+                _ => return None,
+            };
+            let sz = (size & 1) + 2;
+            return insn(name, &[fpr(rd, sz), fpr(rn, sz), fpr(rm, sz)]);
+        }
+        // (name, sizes allowed as a bitmask)
+        let (name, sizes) = match (u, opcode) {
+            (false, 0x01) => ("sqadd", 0xf), (true, 0x01) => ("uqadd", 0xf),
+            (false, 0x05) => ("sqsub", 0xf), (true, 0x05) => ("uqsub", 0xf),
+            (false, 0x06) => ("cmgt", 8), (true, 0x06) => ("cmhi", 8),
+            (false, 0x07) => ("cmge", 8), (true, 0x07) => ("cmhs", 8),
+            (false, 0x08) => ("sshl", 8), (true, 0x08) => ("ushl", 8),
+            (false, 0x09) => ("sqshl", 0xf), (true, 0x09) => ("uqshl", 0xf),
+            (false, 0x0a) => ("srshl", 8), (true, 0x0a) => ("urshl", 8),
+            (false, 0x0b) => ("sqrshl", 0xf), (true, 0x0b) => ("uqrshl", 0xf),
+            (false, 0x10) => ("add", 8), (true, 0x10) => ("sub", 8),
+            (false, 0x11) => ("cmtst", 8), (true, 0x11) => ("cmeq", 8),
+            (false, 0x16) => ("sqdmulh", 6), (true, 0x16) => ("sqrdmulh", 6),
+            _ => return None,
+        };
+        if sizes & (1 << size) == 0 { return None; }
+        return insn(name, &[fpr(rd, size), fpr(rn, size), fpr(rm, size)]);
+    }
+    if i & 0xdf208400 == 0x5e008400 {
+        let name = match (u, b(i, 14, 11)) {
+            (true, 0) => "sqrdmlah", (true, 1) => "sqrdmlsh",
+            _ => return None,
+        };
+        if size != 1 && size != 2 { return None; }
+        return insn(name, &[fpr(rd, size), fpr(rn, size), fpr(rm, size)]);
+    }
+    if i & 0xdf200c00 == 0x5e200000 {
+        // three different
+        let name = match (u, b(i, 15, 12)) {
+            (false, 9) => "sqdmlal", (false, 11) => "sqdmlsl", (false, 13) => "sqdmull",
+            _ => return None,
+        };
+        if size != 1 && size != 2 { return None; }
+        return insn(name, &[fpr(rd, size + 1), fpr(rn, size), fpr(rm, size)]);
+    }
+    if i & 0xdf3e0c00 == 0x5e200800 {
+        return simd_scalar_two_reg_misc(i);
+    }
+    if i & 0xdf800400 == 0x5f000400 && b(i, 22, 19) != 0 {
+        return simd_scalar_shift_imm(i);
+    }
+    if i & 0xdf000400 == 0x5f000000 {
+        // by element
+        let (l, m, h) = (b(i, 21, 21), b(i, 20, 20), b(i, 11, 11));
+        let opcode = b(i, 15, 12);
+        let fp_op = match (u, opcode) {
+            (false, 1) => Some("fmla"), (false, 5) => Some("fmls"), (false, 9) => Some("fmul"), (true, 9) => Some("fmulx"),
+            _ => None,
+        };
+        if let Some(name) = fp_op {
+            let (esize, index) = match size {
+                2 => (2, (h << 1) | l),
+                3 if l == 0 => (3, h),
+                _ => return None,
+            };
+            return insn(name, &[fpr(rd, esize), fpr(rn, esize), velem(rm, esize, index)]);
+        }
+        let (name, long) = match (u, opcode) {
+            (false, 3) => ("sqdmlal", true), (false, 7) => ("sqdmlsl", true), (false, 11) => ("sqdmull", true),
+            (false, 12) => ("sqdmulh", false), (false, 13) => ("sqrdmulh", false),
+            (true, 13) => ("sqrdmlah", false), (true, 15) => ("sqrdmlsh", false),
+            _ => return None,
+        };
+        let (rm, index) = match size {
+            1 => (b(i, 19, 16), (h << 2) | (l << 1) | m),
+            2 => (rm, (h << 1) | l),
+            _ => return None,
+        };
+        return insn(name, &[fpr(rd, size + long as u32), fpr(rn, size), velem(rm, size, index)]);
+    }
+    None
+}
+
+fn simd_scalar_two_reg_misc(i: u32) -> Option<String> {
+    let u = bit(i, 29);
+    let size = b(i, 23, 22);
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    let opcode = b(i, 16, 12);
+    if opcode >= 0xc && opcode != 0x12 && opcode != 0x14 {
+        let sz = (size & 1) + 2;
+        let zero = match (u, size >> 1, opcode) {
+            (false, 1, 0x0c) => Some("fcmgt"), (false, 1, 0x0d) => Some("fcmeq"), (false, 1, 0x0e) => Some("fcmlt"),
+            (true, 1, 0x0c) => Some("fcmge"), (true, 1, 0x0d) => Some("fcmle"),
+            _ => None,
+        };
+        if let Some(name) = zero {
+            return insn(name, &[fpr(rd, sz), fpr(rn, sz), "#0.0".to_owned()]);
+        }
+        if u && size == 1 && opcode == 0x16 {
+            return insn("fcvtxn", &[fpr(rd, 2), fpr(rn, 3)]);
+        }
+        let name = match (u, size >> 1, opcode) {
+            (false, 0, 0x1a) => "fcvtns", (false, 0, 0x1b) => "fcvtms", (false, 0, 0x1c) => "fcvtas",
+            (false, 0, 0x1d) => "scvtf", (false, 1, 0x1a) => "fcvtps", (false, 1, 0x1b) => "fcvtzs",
+            (false, 1, 0x1d) => "frecpe", (false, 1, 0x1f) => "frecpx",
+            (true, 0, 0x1a) => "fcvtnu", (true, 0, 0x1b) => "fcvtmu", (true, 0, 0x1c) => "fcvtau",
+            (true, 0, 0x1d) => "ucvtf", (true, 1, 0x1a) => "fcvtpu", (true, 1, 0x1b) => "fcvtzu",
+            (true, 1, 0x1d) => "frsqrte",
+            _ => return None,
+        };
+        return insn(name, &[fpr(rd, sz), fpr(rn, sz)]);
+    }
+    match (u, opcode) {
+        (_, 0x03) | (_, 0x07) => {
+            let name = match (u, opcode) {
+                (false, 0x03) => "suqadd", (true, 0x03) => "usqadd", (false, _) => "sqabs", (true, _) => "sqneg",
+            };
+            insn(name, &[fpr(rd, size), fpr(rn, size)])
+        },
+        (_, 0x08) | (_, 0x09) | (false, 0x0a) if size == 3 => {
+            let name = match (u, opcode) {
+                (false, 0x08) => "cmgt", (true, 0x08) => "cmge",
+                (false, 0x09) => "cmeq", (true, 0x09) => "cmle",
+                _ => "cmlt",
+            };
+            insn(name, &[fpr(rd, 3), fpr(rn, 3), "#0".to_owned()])
+        },
+        (_, 0x0b) if size == 3 => insn(if u { "neg" } else { "abs" }, &[fpr(rd, 3), fpr(rn, 3)]),
+        (false, 0x14) | (true, 0x12) | (true, 0x14) if size != 3 => {
+            let name = match (u, opcode) { (false, _) => "sqxtn", (true, 0x12) => "sqxtun", (true, _) => "uqxtn" };
+            insn(name, &[fpr(rd, size), fpr(rn, size + 1)])
+        },
+        _ => None,
+    }
+}
+
+fn simd_scalar_shift_imm(i: u32) -> Option<String> {
+    let u = bit(i, 29);
+    let immh = b(i, 22, 19);
+    let immhb = b(i, 22, 16);
+    let (rd, rn) = (b(i, 4, 0), b(i, 9, 5));
+    let size = 31 - immh.leading_zeros();
+    let esize = 8 << size;
+    let right = imm((2 * esize - immhb) as i64);
+    let left = imm((immhb - esize) as i64);
+    let same = |name: &str, amount: String, ok: bool| {
+        if !ok { return None; }
+        insn(name, &[fpr(rd, size), fpr(rn, size), amount])
+    };
+    let narrow = |name: &str| {
+        if size == 3 { return None; }
+        insn(name, &[fpr(rd, size), fpr(rn, size + 1), right.clone()])
+    };
+    let d = size == 3;
+    let fp = size >= 2;
+    match (u, b(i, 15, 11)) {
+        (false, 0x00) => same("sshr", right.clone(), d), (true, 0x00) => same("ushr", right.clone(), d),
+        (false, 0x02) => same("ssra", right.clone(), d), (true, 0x02) => same("usra", right.clone(), d),
+        (false, 0x04) => same("srshr", right.clone(), d), (true, 0x04) => same("urshr", right.clone(), d),
+        (false, 0x06) => same("srsra", right.clone(), d), (true, 0x06) => same("ursra", right.clone(), d),
+        (true, 0x08) => same("sri", right.clone(), d),
+        (false, 0x0a) => same("shl", left.clone(), d), (true, 0x0a) => same("sli", left.clone(), d),
+        (true, 0x0c) => same("sqshlu", left.clone(), true),
+        (false, 0x0e) => same("sqshl", left.clone(), true), (true, 0x0e) => same("uqshl", left.clone(), true),
+        (true, 0x10) => narrow("sqshrun"), (true, 0x11) => narrow("sqrshrun"),
+        (false, 0x12) => narrow("sqshrn"), (true, 0x12) => narrow("uqshrn"),
+        (false, 0x13) => narrow("sqrshrn"), (true, 0x13) => narrow("uqrshrn"),
+        (false, 0x1c) => same("scvtf", right.clone(), fp), (true, 0x1c) => same("ucvtf", right.clone(), fp),
+        (false, 0x1f) => same("fcvtzs", right.clone(), fp), (true, 0x1f) => same("fcvtzu", right.clone(), fp),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // assembled at 0x10000 and checked against llvm-objdump (with branch targets made absolute)
+    static ENCODINGS: &[(u32, &str)] = &[
+        // branches
+        (0x14000010, "b 0x10040"),
+        (0x97ffffc0, "bl 0xff04"),
+        (0x54000041, "b.ne 0x10010"),
+        (0x54ffffe2, "b.hs 0x10008"),
+        (0x34000103, "cbz w3, 0x10030"),
+        (0xb5008009, "cbnz x9, 0x11014"),
+        (0x36180081, "tbz w1, #3, 0x10028"),
+        (0xb747ff82, "tbnz x2, #40, 0x1000c"),
+        (0xd61f0200, "br x16"),
+        (0xd63f0220, "blr x17"),
+        (0xd65f03c0, "ret"),
+        (0xd65f0060, "ret x3"),
+        (0xd61f083f, "braaz x1"),
+        (0xd65f0bff, "retaa"),
+        // loads and stores
+        (0xf9400020, "ldr x0, [x1]"),
+        (0xf9400420, "ldr x0, [x1, #8]"),
+        (0xb94fffe2, "ldr w2, [sp, #4092]"),
+        (0xf8410c83, "ldr x3, [x4, #16]!"),
+        (0xf85f0483, "ldr x3, [x4], #-16"),
+        (0xf85ff0c5, "ldur x5, [x6, #-1]"),
+        (0xf8696907, "ldr x7, [x8, x9]"),
+        (0xf8697907, "ldr x7, [x8, x9, lsl #3]"),
+        (0xb869d907, "ldr w7, [x8, w9, sxtw #2]"),
+        (0x386c496a, "ldrb w10, [x11, w12, uxtw]"),
+        (0x798005cd, "ldrsh x13, [x14, #2]"),
+        (0x9800020f, "ldrsw x15, 0x100a4"),
+        (0x3dc00820, "ldr q0, [x1, #32]"),
+        (0xfc1f8c41, "str d1, [x2, #-8]!"),
+        (0xa9bf7bfd, "stp x29, x30, [sp, #-16]!"),
+        (0xa8c17bfd, "ldp x29, x30, [sp], #16"),
+        (0xad420400, "ldp q0, q1, [x0, #64]"),
+        (0xc85f7c41, "ldxr x1, [x2]"),
+        (0x889ffc83, "stlr w3, [x4]"),
+        (0xf8e00041, "ldaddal x0, x1, [x2]"),
+        (0x88e0fc41, "casal w0, w1, [x2]"),
+        (0xf9802000, "prfm pldl1keep, [x0, #64]"),
+        // SIMD and FP
+        (0x4ea28420, "add v0.4s, v1.4s, v2.4s"),
+        (0x4e65cc83, "fmla v3.2d, v4.2d, v5.2d"),
+        (0x1e222820, "fadd s0, s1, s2"),
+        (0x9e670020, "fmov d0, x1"),
+        (0x4e010c20, "dup v0.16b, w1"),
+        (0x4e141c61, "mov v1.s[2], w3"),
+        (0x0e0e3c20, "umov w0, v1.h[3]"),
+        (0x4e183c20, "mov x0, v1.d[1]"),
+        (0x4c407000, "ld1 { v0.16b }, [x0]"),
+        (0x4c9fa820, "st1 { v0.4s, v1.4s }, [x1], #32"),
+        (0x6f00e400, "movi v0.2d, #0000000000000000"),
+        (0x6e021820, "ext v0.16b, v1.16b, v2.16b, #3"),
+        (0x4e284820, "aese v0.16b, v1.16b"),
+        // system
+        (0xd53bd040, "mrs x0, TPIDR_EL0"),
+        (0xd51bd041, "msr TPIDR_EL0, x1"),
+        (0xd53b4202, "mrs x2, NZCV"),
+        (0xd50342df, "msr DAIFSet, #2"),
+        (0xd5033bbf, "dmb ish"),
+        (0xd5033f9f, "dsb sy"),
+        (0xd5033fdf, "isb"),
+        (0xd503201f, "nop"),
+        (0xd503245f, "bti c"),
+        (0xd503233f, "paciasp"),
+        (0xd4000001, "svc #0"),
+        (0x90000000, "adrp x0, 0x10000"),
+        // everything else
+        (0x10000081, "adr x1, 0x10104"),
+        (0xd2824680, "mov x0, #4660"),
+        (0xf2aacf00, "movk x0, #22136, lsl #16"),
+        (0x92401c20, "and x0, x1, #0xff"),
+        (0x8b020c20, "add x0, x1, x2, lsl #3"),
+        (0x9a820020, "csel x0, x1, x2, eq"),
+    ];
+
+    #[test]
+    fn test_encodings() {
+        for (i, &(insn, expected)) in ENCODINGS.iter().enumerate() {
+            let pc = 0x10000 + 4 * i as u64;
+            assert_eq!(disassemble(insn, pc).as_ref().map(|s| &s[..]), Some(expected), "{:08x}", insn);
+        }
+    }
+
+    #[test]
+    fn test_unallocated() {
+        assert_eq!(disassemble(0x00000000, 0), Some("udf #0".to_owned()));
+        assert_eq!(disassemble(0x00010000, 0), None);
+        assert_eq!(disassemble(0xd4a00000, 0), None);
+    }
+}
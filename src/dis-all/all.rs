@@ -1,4 +1,5 @@
 extern crate dis;
+extern crate dis_aarch64dis as aarch64dis;
 #[cfg(feature = "use_llvm")]
 extern crate dis_llvmdis as llvmdis;
 #[cfg(feature = "use_llvm")]
@@ -7,6 +8,7 @@ use std::marker::PhantomData;
 use dis::{DisassemblerFamily, DisassemblerFamilyImpl};
 
 pub static ALL_FAMILIES: &'static [&'static dis::DisassemblerFamily] = &[
+    (&DisassemblerFamilyImpl::<aarch64dis::AArch64Disassembler>(PhantomData) as &DisassemblerFamily),
     #[cfg(feature = "use_llvm")]
     (&DisassemblerFamilyImpl::<llvmdis::LLVMDisassembler>(PhantomData) as &DisassemblerFamily),
     #[cfg(feature = "use_llvm")]
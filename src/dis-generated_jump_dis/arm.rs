@@ -0,0 +1,950 @@
+// 32-bit ARM (A32) and Thumb/Thumb-2 (T32), written by hand like x86_64.  The decode mode comes
+// from set_mode; code targets have the low bit set if they're Thumb, so CodeMap can follow
+// BLX/BX interworking.  Registers are r0-r15.  Conditional instructions (including those in an
+// IT block) are downgraded: tails become Bcc and Set/Load/CmpImm become Other.
+
+use super::{GenericHandler, InsnInfo, InsnKind, TableBr, TargetAddr, Addrish, Reg, CC, Size, Size8, Size16, Size32};
+use util::{Endian, Signedness, Signed, Unsigned, SignExtend, ReadCell};
+use exec::VMA;
+use exec::arch::{CodeMode, ARMMode};
+use std::collections::HashMap;
+
+const SP: u32 = 13;
+const LR: u32 = 14;
+const PC: u32 = 15;
+const AL: u32 = 0b1110;
+
+fn reg(r: u32) -> Reg { Reg(r as i8) }
+
+fn arm_expand_imm(imm12: u32) -> u32 {
+    (imm12 & 0xff).rotate_right(2 * (imm12 >> 8))
+}
+
+fn thumb_expand_imm(imm12: u32) -> u32 {
+    let imm8 = imm12 & 0xff;
+    match imm12 >> 8 {
+        0 => imm8,
+        1 => imm8 << 16 | imm8,
+        2 => imm8 << 24 | imm8 << 8,
+        3 => imm8 * 0x0101_0101,
+        _ => (0x80 | (imm12 & 0x7f)).rotate_right(imm12 >> 7),
+    }
+}
+
+fn is_thumb32(hw: u16) -> bool {
+    hw >> 11 >= 0b11101
+}
+
+fn add_sub(base: VMA, imm: u32, add: bool) -> VMA {
+    if add { base.wrapping_add(imm as u64) } else { base.wrapping_add((imm as u64).wrapping_neg()) }
+}
+
+pub struct ARMHandler {
+    endian: Endian,
+    thumb: bool,
+    // conditions of the instructions covered by every IT decoded since the last reset, so the
+    // body of a block can be decoded after other code or again later
+    it_conds: HashMap<VMA, u32>,
+    addr: VMA,
+    info: InsnInfo,
+}
+
+impl ARMHandler {
+    // endian is the instruction endianness, which is little for BE8
+    pub fn new(endian: Endian) -> ARMHandler {
+        ARMHandler {
+            endian: endian,
+            thumb: false,
+            it_conds: HashMap::new(),
+            addr: VMA(0),
+            info: Default::default(),
+        }
+    }
+    fn kill(&mut self, r: u32) {
+        self.info.kill(reg(r));
+    }
+    // value of PC as seen by the instruction
+    fn pc(&self) -> VMA {
+        self.addr.wrapping_add(if self.thumb { 4 } else { 8 })
+    }
+    fn literal(&mut self, imm: u32, add: bool) -> VMA {
+        let a = add_sub(self.pc() & !3, imm, add).trunc32();
+        self.info.target_addr = TargetAddr::Data(a);
+        a
+    }
+    fn set(&mut self, rd: u32, a: Addrish) {
+        self.kill(rd);
+        self.info.kind = InsnKind::Set(reg(rd), a);
+    }
+    fn load(&mut self, rt: u32, a: Addrish, size: Size, signedness: Signedness) {
+        self.kill(rt);
+        self.info.kind = InsnKind::Load(reg(rt), a, size, signedness);
+    }
+    fn code_target(&mut self, target: VMA, thumb: bool) {
+        let target = target.trunc32();
+        self.info.target_addr = TargetAddr::Code(if thumb { target | 1 } else { target });
+    }
+    // BX, MOV pc, etc.
+    fn br(&mut self, rm: u32) {
+        self.info.kind = if rm == LR || rm == PC { InsnKind::Tail } else { InsnKind::Br(reg(rm)) };
+    }
+    fn kill_list(&mut self, list: u32) {
+        for r in 0..15 {
+            if list & (1 << r) != 0 { self.kill(r); }
+        }
+        if list & (1 << PC) != 0 { self.info.kind = InsnKind::Tail; }
+    }
+    fn apply_cond(&mut self, cond: u32) {
+        self.info.kind = match self.info.kind {
+            InsnKind::Tail | InsnKind::Br(_) => InsnKind::Bcc(CC::from_a64(cond)),
+            InsnKind::TableBr(mut tb) => {
+                tb.default_cc = Some(CC::from_a64(cond ^ 1));
+                InsnKind::TableBr(tb)
+            },
+            InsnKind::Set(..) | InsnKind::Load(..) | InsnKind::CmpImm(..) => InsnKind::Other,
+            kind => kind,
+        };
+    }
+
+    fn decode_arm(&mut self, op: u32) {
+        let cond = op >> 28;
+        if cond == 0b1111 {
+            self.arm_uncond(op);
+            return;
+        }
+        match (op >> 25) & 7 {
+            0b000 | 0b001 => self.arm_dp_misc(op),
+            0b010 => self.arm_ldst(op),
+            0b011 => if op & 0x10 == 0 { self.arm_ldst(op) } else { self.arm_media(op) },
+            0b100 => self.arm_ldm(op),
+            0b101 => {
+                let target = self.pc().wrapping_add(((op & 0xff_ffff) << 2).sign_extend(26));
+                self.code_target(target, false);
+                self.info.kind = if op & (1 << 24) != 0 { InsnKind::Call } else { InsnKind::Tail };
+            },
+            _ => self.arm_coproc(op),
+        }
+        if cond != AL { self.apply_cond(cond); }
+    }
+    fn arm_uncond(&mut self, op: u32) {
+        if op & 0x0e00_0000 == 0x0a00_0000 {
+            // BLX imm
+            let imm = (op & 0xff_ffff) << 2 | (op >> 23) & 2;
+            let target = self.pc().wrapping_add(imm.sign_extend(26));
+            self.code_target(target, true);
+            self.info.kind = InsnKind::Call;
+        } else if op & 0x0e50_0000 == 0x0810_0000 {
+            // RFE
+            self.info.kind = InsnKind::Tail;
+        } else if op & 0x0f10_0000 == 0x0400_0000 && op & 0xf != PC {
+            // NEON element/structure load/store with writeback
+            self.kill((op >> 16) & 0xf);
+        }
+    }
+    fn arm_dp_misc(&mut self, op: u32) {
+        let rn = (op >> 16) & 0xf;
+        let rd = (op >> 12) & 0xf;
+        let rm = op & 0xf;
+        let imm = op & (1 << 25) != 0;
+        if !imm && op & 0x90 == 0x90 {
+            if op & 0x60 != 0 {
+                self.arm_extra_ldst(op);
+            } else if op & (1 << 24) == 0 {
+                // multiplies: Rd (RdHi) is 19:16, RdLo is 15:12
+                self.kill(rn);
+                if op & (1 << 23) != 0 { self.kill(rd); }
+            } else {
+                // SWP, LDREX*, STREX*
+                self.kill(rd);
+                if op & 0x00f0_0000 == 0x00b0_0000 { self.kill(rd + 1); }
+            }
+            return;
+        }
+        if op & 0x0190_0000 == 0x0100_0000 {
+            if !imm {
+                self.arm_misc(op);
+            } else if op & 0x0ff0_0000 == 0x0300_0000 {
+                // MOVW
+                self.set(rd, Addrish::Imm(((op >> 4) & 0xf000 | op & 0xfff) as u64));
+            } else if op & 0x0ff0_0000 == 0x0340_0000 {
+                // MOVT
+                self.kill(rd);
+            }
+            return;
+        }
+        let opc = (op >> 21) & 0xf;
+        let val = arm_expand_imm(op & 0xfff);
+        if opc & 0b1100 == 0b1000 {
+            // TST, TEQ, CMP, CMN
+            if opc == 0b1010 && imm { self.info.kind = InsnKind::CmpImm(reg(rn), val as u64); }
+            return;
+        }
+        if rd == PC {
+            if opc == 0b1101 && !imm && op & 0xff0 == 0 { self.br(rm); } else { self.info.kind = InsnKind::Tail; }
+            return;
+        }
+        self.kill(rd);
+        match (opc, imm) {
+            (0b1101, true) => self.set(rd, Addrish::Imm(val as u64)),
+            (0b1111, true) => self.set(rd, Addrish::Imm(!val as u64)),
+            (0b0100, true) | (0b0010, true) => {
+                let add = opc == 0b0100;
+                if rn == PC {
+                    // ADR
+                    let a = self.literal(val, add);
+                    self.set(rd, Addrish::Imm(a.0));
+                } else {
+                    let v = if add { val as u64 } else { (val as u64).wrapping_neg() };
+                    self.set(rd, Addrish::AddImm(reg(rn), v));
+                }
+            },
+            (0b1101, false) if op & 0xff0 == 0 && rm != PC => self.set(rd, Addrish::AddImm(reg(rm), 0)),
+            (0b0100, false) if op & 0x70 == 0 && rn != PC && rm != PC =>
+                self.set(rd, Addrish::AddReg(reg(rn), reg(rm), ((op >> 7) & 0x1f) as u8)),
+            _ => (),
+        }
+    }
+    fn arm_misc(&mut self, op: u32) {
+        let rd = (op >> 12) & 0xf;
+        let op2 = (op >> 4) & 0xf;
+        match op2 {
+            // MRS
+            0b0000 => if op & (1 << 21) == 0 { self.kill(rd); },
+            // BX, CLZ
+            0b0001 => match (op >> 21) & 3 {
+                0b01 => self.br(op & 0xf),
+                0b11 => self.kill(rd),
+                _ => (),
+            },
+            // BXJ, ERET
+            0b0010 | 0b0110 => self.info.kind = InsnKind::Tail,
+            // saturating add/sub
+            0b0101 => self.kill(rd),
+            // BKPT
+            0b0111 => if (op >> 21) & 3 == 0b01 { self.info.kind = InsnKind::Tail; },
+            // halfword multiplies; BLX reg is just a call
+            _ => if op2 & 0b1001 == 0b1000 {
+                self.kill((op >> 16) & 0xf);
+                if (op >> 21) & 3 == 0b10 { self.kill(rd); }
+            },
+        }
+    }
+    fn arm_extra_ldst(&mut self, op: u32) {
+        let (p, u, i, w, l) = (op & (1 << 24) != 0, op & (1 << 23) != 0, op & (1 << 22) != 0,
+                               op & (1 << 21) != 0, op & (1 << 20) != 0);
+        let rn = (op >> 16) & 0xf;
+        let rt = (op >> 12) & 0xf;
+        if !p || w { self.kill(rn); }
+        let (size, signedness, dual) = match (l, (op >> 5) & 3) {
+            (false, 2) => (Size32, Unsigned, true),
+            (true, 1) => (Size16, Unsigned, false),
+            (true, 2) => (Size8, Signed, false),
+            (true, 3) => (Size16, Signed, false),
+            _ => return, // stores
+        };
+        self.kill(rt);
+        if dual { self.kill(rt + 1); }
+        if !p || w { return; }
+        if i {
+            let imm = (op >> 4) & 0xf0 | op & 0xf;
+            if rn == PC {
+                let a = self.literal(imm, u);
+                if !dual { self.load(rt, Addrish::Imm(a.0), size, signedness); }
+            } else if !dual {
+                let off = if u { imm as u64 } else { (imm as u64).wrapping_neg() };
+                self.load(rt, Addrish::AddImm(reg(rn), off), size, signedness);
+            }
+        } else if u && !dual && rn != PC {
+            self.load(rt, Addrish::AddReg(reg(rn), reg(op & 0xf), 0), size, signedness);
+        }
+    }
+    fn arm_ldst(&mut self, op: u32) {
+        let reg_form = op & (1 << 25) != 0;
+        let (p, u, b, w, l) = (op & (1 << 24) != 0, op & (1 << 23) != 0, op & (1 << 22) != 0,
+                               op & (1 << 21) != 0, op & (1 << 20) != 0);
+        let rn = (op >> 16) & 0xf;
+        let rt = (op >> 12) & 0xf;
+        let rm = op & 0xf;
+        if !p || w { self.kill(rn); }
+        if !l { return; }
+        if rt == PC {
+            if reg_form && rn == PC && p && u && !w && !b && op & 0xff0 == 0x100 {
+                // ldr pc, [pc, rX, lsl #2]
+                self.info.kind = InsnKind::TableBr(TableBr {
+                    idx: reg(rm),
+                    table: self.pc(),
+                    size: Size32,
+                    halfword_offsets: false,
+                    default_cc: None,
+                });
+            } else {
+                if !reg_form && rn == PC { self.literal(op & 0xfff, u); }
+                self.info.kind = InsnKind::Tail;
+            }
+            return;
+        }
+        self.kill(rt);
+        if !p || w { return; }
+        let size = if b { Size8 } else { Size32 };
+        if !reg_form {
+            let imm = op & 0xfff;
+            if rn == PC {
+                let a = self.literal(imm, u);
+                self.load(rt, Addrish::Imm(a.0), size, Unsigned);
+            } else {
+                let off = if u { imm as u64 } else { (imm as u64).wrapping_neg() };
+                self.load(rt, Addrish::AddImm(reg(rn), off), size, Unsigned);
+            }
+        } else if u && op & 0x70 == 0 && rn != PC {
+            self.load(rt, Addrish::AddReg(reg(rn), reg(rm), ((op >> 7) & 0x1f) as u8), size, Unsigned);
+        }
+    }
+    fn arm_media(&mut self, op: u32) {
+        let rd = (op >> 12) & 0xf;
+        match (op >> 20) & 0x1f {
+            // UDF
+            0b11111 if op & 0xf0 == 0xf0 => self.info.kind = InsnKind::Tail,
+            // signed multiplies and divides put Rd in 19:16; SMLALD/SMLSLD also write 15:12
+            0b10000..=0b10111 => {
+                self.kill((op >> 16) & 0xf);
+                if (op >> 20) & 0x1f == 0b10100 { self.kill(rd); }
+            },
+            // USAD8/USADA8
+            0b11000 => self.kill((op >> 16) & 0xf),
+            // parallel add/sub, pack, saturate, reverse, extend, bitfield
+            _ => {
+                self.kill(rd);
+                // UXTB/UXTH without rotation
+                if op & 0x0fef_0ff0 == 0x06ef_0070 {
+                    self.set(rd, Addrish::AddImm(reg(op & 0xf), 0));
+                }
+            },
+        }
+    }
+    fn arm_ldm(&mut self, op: u32) {
+        if op & (1 << 21) != 0 { self.kill((op >> 16) & 0xf); }
+        if op & (1 << 20) != 0 { self.kill_list(op & 0xffff); }
+    }
+    // shared with Thumb-2, whose coprocessor encodings are the same apart from the condition
+    fn arm_coproc(&mut self, op: u32) {
+        let rn = (op >> 16) & 0xf;
+        let rt = (op >> 12) & 0xf;
+        if op & 0x0f00_0000 == 0x0f00_0000 {
+            // SVC (or NEON data processing in Thumb)
+        } else if op & 0x0f00_0010 == 0x0e00_0010 {
+            // MRC, VMOV to core, VMRS
+            if op & (1 << 20) != 0 && rt != PC { self.kill(rt); }
+        } else if op & 0x0ff0_0000 == 0x0c50_0000 {
+            // MRRC, VMOV to two core registers
+            self.kill(rt);
+            self.kill(rn);
+        } else if op & 0x0e00_0000 == 0x0c00_0000 {
+            // LDC/STC, VLDR/VSTR, VLDM/VSTM
+            if op & (1 << 21) != 0 { self.kill(rn); }
+            if rn == PC && op & 0x0f30_0000 == 0x0d10_0000 {
+                self.literal((op & 0xff) << 2, op & (1 << 23) != 0);
+            }
+        }
+    }
+
+    fn decode_thumb(&mut self, data: &[ReadCell<u8>]) -> usize {
+        if data.len() < 2 { return 0; }
+        let hw1: u16 = util::copy_from_slice(&data[..2], self.endian);
+        let size = if is_thumb32(hw1) {
+            if data.len() < 4 { return 0; }
+            let hw2: u16 = util::copy_from_slice(&data[2..4], self.endian);
+            self.thumb32(hw1 as u32, hw2 as u32);
+            4
+        } else {
+            self.thumb16(hw1 as u32, data);
+            2
+        };
+        if let Some(&cond) = self.it_conds.get(&self.addr) {
+            if cond != AL { self.apply_cond(cond); }
+        }
+        size
+    }
+    fn start_it(&mut self, hw: u32, data: &[ReadCell<u8>]) {
+        let firstcond = (hw >> 4) & 0xf;
+        let mask = hw & 0xf;
+        let mut pos = 2;
+        for k in 0..(4 - mask.trailing_zeros() as usize) {
+            if data.len() < pos + 2 { break; }
+            let cond = if k == 0 { firstcond } else { firstcond & 0xe | (mask >> (4 - k)) & 1 };
+            self.it_conds.insert(self.addr.wrapping_add(pos as u64), cond);
+            let next: u16 = util::copy_from_slice(&data[pos..pos + 2], self.endian);
+            pos += if is_thumb32(next) { 4 } else { 2 };
+        }
+    }
+    fn thumb16(&mut self, hw: u32, data: &[ReadCell<u8>]) {
+        let lo3 = hw & 7;
+        let mid3 = (hw >> 3) & 7;
+        let imm8 = hw & 0xff;
+        if hw & 0xe000 == 0x0000 && hw & 0x1800 != 0x1800 {
+            // LSL/LSR/ASR imm; LSLS rd, rm, #0 is a move
+            if hw & 0xffc0 == 0 {
+                self.set(lo3, Addrish::AddImm(reg(mid3), 0));
+            } else {
+                self.kill(lo3);
+            }
+        } else if hw & 0xf800 == 0x1800 {
+            // ADD/SUB reg/imm3
+            let x = (hw >> 6) & 7;
+            match (hw >> 9) & 3 {
+                0b00 => self.set(lo3, Addrish::AddReg(reg(mid3), reg(x), 0)),
+                0b10 => self.set(lo3, Addrish::AddImm(reg(mid3), x as u64)),
+                0b11 => self.set(lo3, Addrish::AddImm(reg(mid3), (x as u64).wrapping_neg())),
+                _ => self.kill(lo3),
+            }
+        } else if hw & 0xe000 == 0x2000 {
+            // MOV/CMP/ADD/SUB imm8
+            let rdn = (hw >> 8) & 7;
+            match (hw >> 11) & 3 {
+                0b00 => self.set(rdn, Addrish::Imm(imm8 as u64)),
+                0b01 => self.info.kind = InsnKind::CmpImm(reg(rdn), imm8 as u64),
+                0b10 => self.set(rdn, Addrish::AddImm(reg(rdn), imm8 as u64)),
+                _ => self.set(rdn, Addrish::AddImm(reg(rdn), (imm8 as u64).wrapping_neg())),
+            }
+        } else if hw & 0xfc00 == 0x4000 {
+            // ALU ops other than TST, CMP, CMN
+            match (hw >> 6) & 0xf {
+                0b1000 | 0b1010 | 0b1011 => (),
+                _ => self.kill(lo3),
+            }
+        } else if hw & 0xfc00 == 0x4400 {
+            // hi register ops and BX/BLX
+            let rdn = (hw >> 4) & 8 | lo3;
+            let rm = (hw >> 3) & 0xf;
+            match (hw >> 8) & 3 {
+                0b00 => if rdn == PC {
+                    self.info.kind = InsnKind::Tail;
+                } else if rm == PC || rdn == SP {
+                    self.kill(rdn);
+                } else {
+                    self.set(rdn, Addrish::AddReg(reg(rdn), reg(rm), 0));
+                },
+                0b01 => (),
+                0b10 => if rdn == PC {
+                    self.br(rm);
+                } else if rm == PC {
+                    self.kill(rdn);
+                } else {
+                    self.set(rdn, Addrish::AddImm(reg(rm), 0));
+                },
+                _ => if hw & 0x80 == 0 { self.br(rm); },
+            }
+        } else if hw & 0xf800 == 0x4800 {
+            // LDR literal
+            let rt = (hw >> 8) & 7;
+            let a = self.literal(imm8 << 2, true);
+            self.load(rt, Addrish::Imm(a.0), Size32, Unsigned);
+        } else if hw & 0xf000 == 0x5000 {
+            // register offset loads/stores
+            let (size, signedness) = match (hw >> 9) & 7 {
+                0b011 => (Size8, Signed),
+                0b100 => (Size32, Unsigned),
+                0b101 => (Size16, Unsigned),
+                0b110 => (Size8, Unsigned),
+                0b111 => (Size16, Signed),
+                _ => return,
+            };
+            self.load(lo3, Addrish::AddReg(reg(mid3), reg((hw >> 6) & 7), 0), size, signedness);
+        } else if hw & 0xe000 == 0x6000 || hw & 0xf000 == 0x8000 {
+            // LDR/LDRB/LDRH imm5
+            if hw & 0x800 == 0 { return; }
+            let imm5 = ((hw >> 6) & 0x1f) as u64;
+            let (size, off) = match hw >> 12 {
+                0b0110 => (Size32, imm5 << 2),
+                0b0111 => (Size8, imm5),
+                _ => (Size16, imm5 << 1),
+            };
+            self.load(lo3, Addrish::AddImm(reg(mid3), off), size, Unsigned);
+        } else if hw & 0xf000 == 0x9000 {
+            // LDR/STR [sp, #imm8]
+            if hw & 0x800 != 0 {
+                self.load((hw >> 8) & 7, Addrish::AddImm(reg(SP), (imm8 << 2) as u64), Size32, Unsigned);
+            }
+        } else if hw & 0xf800 == 0xa000 {
+            // ADR
+            let a = self.literal(imm8 << 2, true);
+            self.set((hw >> 8) & 7, Addrish::Imm(a.0));
+        } else if hw & 0xf800 == 0xa800 {
+            self.set((hw >> 8) & 7, Addrish::AddImm(reg(SP), (imm8 << 2) as u64));
+        } else if hw & 0xf000 == 0xb000 {
+            self.thumb16_misc(hw, data);
+        } else if hw & 0xf000 == 0xc000 {
+            // STM/LDM; LDM only writes back if the base isn't loaded
+            let rn = (hw >> 8) & 7;
+            if hw & 0x800 == 0 || imm8 & (1 << rn) == 0 { self.kill(rn); }
+            if hw & 0x800 != 0 { self.kill_list(imm8); }
+        } else if hw & 0xf000 == 0xd000 {
+            match (hw >> 8) & 0xf {
+                // UDF
+                0b1110 => self.info.kind = InsnKind::Tail,
+                // SVC
+                0b1111 => (),
+                cond => {
+                    let target = self.pc().wrapping_add((imm8 << 1).sign_extend(9));
+                    self.code_target(target, true);
+                    self.info.kind = InsnKind::Bcc(CC::from_a64(cond));
+                },
+            }
+        } else if hw & 0xf800 == 0xe000 {
+            let target = self.pc().wrapping_add(((hw & 0x7ff) << 1).sign_extend(12));
+            self.code_target(target, true);
+            self.info.kind = InsnKind::Tail;
+        }
+    }
+    fn thumb16_misc(&mut self, hw: u32, data: &[ReadCell<u8>]) {
+        let lo3 = hw & 7;
+        if hw & 0xff00 == 0xb000 {
+            // ADD/SUB sp, sp, #imm7
+            self.kill(SP);
+        } else if hw & 0xf500 == 0xb100 {
+            // CBZ/CBNZ
+            let imm = (hw >> 3) & 0x40 | (hw >> 2) & 0x3e;
+            let target = self.pc().wrapping_add(imm as u64);
+            self.code_target(target, true);
+        } else if hw & 0xff00 == 0xb200 {
+            // SXTH, SXTB, UXTH, UXTB
+            if hw & 0x80 != 0 {
+                self.set(lo3, Addrish::AddImm(reg((hw >> 3) & 7), 0));
+            } else {
+                self.kill(lo3);
+            }
+        } else if hw & 0xfe00 == 0xb400 {
+            // PUSH
+            self.kill(SP);
+        } else if hw & 0xff00 == 0xba00 {
+            // REV etc.
+            self.kill(lo3);
+        } else if hw & 0xfe00 == 0xbc00 {
+            // POP
+            self.kill(SP);
+            self.kill_list((hw & 0xff) | (hw & 0x100) << 7);
+        } else if hw & 0xff00 == 0xbe00 {
+            // BKPT
+            self.info.kind = InsnKind::Tail;
+        } else if hw & 0xff00 == 0xbf00 && hw & 0xf != 0 {
+            self.start_it(hw, data);
+        }
+    }
+    fn thumb32(&mut self, hw1: u32, hw2: u32) {
+        let rn = hw1 & 0xf;
+        if hw1 & 0xfe40 == 0xe800 {
+            self.thumb32_ldm(hw1, hw2);
+        } else if hw1 & 0xfe40 == 0xe840 {
+            self.thumb32_dual_excl(hw1, hw2);
+        } else if hw1 & 0xfe00 == 0xea00 {
+            self.thumb32_dp_shifted(hw1, hw2);
+        } else if hw1 & 0xec00 == 0xec00 {
+            self.arm_coproc(hw1 << 16 | hw2);
+        } else if hw1 & 0xf800 == 0xf000 && hw2 & 0x8000 != 0 {
+            self.thumb32_branch_misc(hw1, hw2);
+        } else if hw1 & 0xfa00 == 0xf000 {
+            self.thumb32_dp_modified_imm(hw1, hw2);
+        } else if hw1 & 0xfa00 == 0xf200 {
+            self.thumb32_dp_plain_imm(hw1, hw2);
+        } else if hw1 & 0xff10 == 0xf900 {
+            // NEON element/structure load/store
+            if hw2 & 0xf != PC { self.kill(rn); }
+        } else if hw1 & 0xfe00 == 0xf800 {
+            self.thumb32_ldst(hw1, hw2);
+        } else if hw1 & 0xff00 == 0xfa00 {
+            // data processing (register)
+            let rd = (hw2 >> 8) & 0xf;
+            // UXTB/UXTH without rotation
+            if (hw1 == 0xfa5f || hw1 == 0xfa1f) && hw2 & 0xf0f0 == 0xf080 {
+                self.set(rd, Addrish::AddImm(reg(hw2 & 0xf), 0));
+            } else {
+                self.kill(rd);
+            }
+        } else if hw1 & 0xff80 == 0xfb00 {
+            // multiply
+            self.kill((hw2 >> 8) & 0xf);
+        } else if hw1 & 0xff80 == 0xfb80 {
+            // long multiply (RdLo, RdHi) and divide (Rd)
+            self.kill((hw2 >> 8) & 0xf);
+            if hw2 & 0xf0 != 0xf0 { self.kill(hw2 >> 12); }
+        }
+    }
+    fn thumb32_ldm(&mut self, hw1: u32, hw2: u32) {
+        match (hw1 >> 7) & 3 {
+            // SRS, RFE
+            0b00 | 0b11 => if hw1 & 0x10 != 0 { self.info.kind = InsnKind::Tail; },
+            _ => {
+                if hw1 & 0x20 != 0 { self.kill(hw1 & 0xf); }
+                if hw1 & 0x10 != 0 { self.kill_list(hw2); }
+            },
+        }
+    }
+    fn thumb32_dual_excl(&mut self, hw1: u32, hw2: u32) {
+        let rn = hw1 & 0xf;
+        let rt = hw2 >> 12;
+        if hw1 & 0xfff0 == 0xe8d0 && hw2 & 0xffe0 == 0xf000 {
+            // TBB/TBH
+            if rn == PC {
+                self.info.kind = InsnKind::TableBr(TableBr {
+                    idx: reg(hw2 & 0xf),
+                    table: self.pc(),
+                    size: if hw2 & 0x10 != 0 { Size16 } else { Size8 },
+                    halfword_offsets: true,
+                    default_cc: None,
+                });
+            } else {
+                self.info.kind = InsnKind::Tail;
+            }
+        } else if hw1 & 0xfff0 == 0xe840 {
+            // STREX
+            self.kill((hw2 >> 8) & 0xf);
+        } else if hw1 & 0xfff0 == 0xe850 {
+            // LDREX
+            self.kill(rt);
+        } else if hw1 & 0xfff0 == 0xe8c0 {
+            // STREXB/H/D
+            self.kill(hw2 & 0xf);
+        } else if hw1 & 0xfff0 == 0xe8d0 {
+            // LDREXB/H/D
+            self.kill(rt);
+            if hw2 & 0xf0 == 0x70 { self.kill((hw2 >> 8) & 0xf); }
+        } else {
+            // LDRD/STRD
+            if hw1 & 0x20 != 0 { self.kill(rn); }
+            if hw1 & 0x10 != 0 {
+                self.kill(rt);
+                self.kill((hw2 >> 8) & 0xf);
+                if rn == PC { self.literal((hw2 & 0xff) << 2, hw1 & 0x80 != 0); }
+            }
+        }
+    }
+    fn thumb32_dp_shifted(&mut self, hw1: u32, hw2: u32) {
+        let rn = hw1 & 0xf;
+        let rd = (hw2 >> 8) & 0xf;
+        let rm = hw2 & 0xf;
+        // TST, TEQ, CMN, CMP
+        if rd == PC { return; }
+        let imm5 = (hw2 >> 10) & 0x1c | (hw2 >> 6) & 3;
+        let shift_type = (hw2 >> 4) & 3;
+        match (hw1 >> 5) & 0xf {
+            0b0010 if rn == PC && imm5 == 0 && shift_type == 0 => self.set(rd, Addrish::AddImm(reg(rm), 0)),
+            0b1000 if shift_type == 0 && rn != PC => self.set(rd, Addrish::AddReg(reg(rn), reg(rm), imm5 as u8)),
+            _ => self.kill(rd),
+        }
+    }
+    fn thumb32_dp_modified_imm(&mut self, hw1: u32, hw2: u32) {
+        let rn = hw1 & 0xf;
+        let rd = (hw2 >> 8) & 0xf;
+        let val = thumb_expand_imm((hw1 & 0x400) << 1 | (hw2 >> 4) & 0x700 | hw2 & 0xff) as u64;
+        let opc = (hw1 >> 5) & 0xf;
+        if rd == PC {
+            // TST, TEQ, CMN, CMP
+            if opc == 0b1101 { self.info.kind = InsnKind::CmpImm(reg(rn), val); }
+            return;
+        }
+        match opc {
+            0b0010 if rn == PC => self.set(rd, Addrish::Imm(val)),
+            0b0011 if rn == PC => self.set(rd, Addrish::Imm(!val & 0xffff_ffff)),
+            0b1000 => self.set(rd, Addrish::AddImm(reg(rn), val)),
+            0b1101 => self.set(rd, Addrish::AddImm(reg(rn), val.wrapping_neg())),
+            _ => self.kill(rd),
+        }
+    }
+    fn thumb32_dp_plain_imm(&mut self, hw1: u32, hw2: u32) {
+        let rn = hw1 & 0xf;
+        let rd = (hw2 >> 8) & 0xf;
+        let imm12 = (hw1 & 0x400) << 1 | (hw2 >> 4) & 0x700 | hw2 & 0xff;
+        match (hw1 >> 4) & 0x1f {
+            // ADDW, SUBW, ADR
+            opc @ 0b00000 | opc @ 0b01010 => {
+                let add = opc == 0b00000;
+                if rn == PC {
+                    let a = self.literal(imm12, add);
+                    self.set(rd, Addrish::Imm(a.0));
+                } else {
+                    let v = if add { imm12 as u64 } else { (imm12 as u64).wrapping_neg() };
+                    self.set(rd, Addrish::AddImm(reg(rn), v));
+                }
+            },
+            // MOVW
+            0b00100 => self.set(rd, Addrish::Imm((rn << 12 | imm12) as u64)),
+            _ => self.kill(rd),
+        }
+    }
+    fn thumb32_branch_misc(&mut self, hw1: u32, hw2: u32) {
+        let s = (hw1 >> 10) & 1;
+        let j1 = (hw2 >> 13) & 1;
+        let j2 = (hw2 >> 11) & 1;
+        // B.W, BL, BLX
+        let imm25 = s << 24 | (!(j1 ^ s) & 1) << 23 | (!(j2 ^ s) & 1) << 22 | (hw1 & 0x3ff) << 12 | (hw2 & 0x7ff) << 1;
+        match hw2 & 0x5000 {
+            0x0000 => if hw1 & 0x380 != 0x380 {
+                // B<cond>.W
+                let imm21 = s << 20 | j2 << 19 | j1 << 18 | (hw1 & 0x3f) << 12 | (hw2 & 0x7ff) << 1;
+                let target = self.pc().wrapping_add(imm21.sign_extend(21));
+                self.code_target(target, true);
+                self.info.kind = InsnKind::Bcc(CC::from_a64((hw1 >> 6) & 0xf));
+            } else if hw1 & 0xffe0 == 0xf3e0 {
+                // MRS
+                self.kill((hw2 >> 8) & 0xf);
+            } else if hw1 & 0xfff0 == 0xf3c0 || (hw1 == 0xf3de && hw2 & 0xff00 == 0x8f00) ||
+                      (hw1 & 0xfff0 == 0xf7f0 && hw2 & 0xf000 == 0xa000) {
+                // BXJ, SUBS pc, lr (ERET), UDF.W
+                self.info.kind = InsnKind::Tail;
+            },
+            0x1000 => {
+                let target = self.pc().wrapping_add(imm25.sign_extend(25));
+                self.code_target(target, true);
+                self.info.kind = InsnKind::Tail;
+            },
+            0x4000 => {
+                let target = (self.pc() & !3).wrapping_add((imm25 & !2).sign_extend(25));
+                self.code_target(target, false);
+                self.info.kind = InsnKind::Call;
+            },
+            _ => {
+                let target = self.pc().wrapping_add(imm25.sign_extend(25));
+                self.code_target(target, true);
+                self.info.kind = InsnKind::Call;
+            },
+        }
+    }
+    fn thumb32_ldst(&mut self, hw1: u32, hw2: u32) {
+        let rn = hw1 & 0xf;
+        let rt = hw2 >> 12;
+        let signed = hw1 & 0x100 != 0;
+        let (size, signedness) = match ((hw1 >> 5) & 3, signed) {
+            (0b00, false) => (Size8, Unsigned),
+            (0b00, true) => (Size8, Signed),
+            (0b01, false) => (Size16, Unsigned),
+            (0b01, true) => (Size16, Signed),
+            (0b10, false) => (Size32, Unsigned),
+            _ => return,
+        };
+        let imm12_form = hw1 & 0x80 != 0;
+        let imm8_form = !imm12_form && hw2 & 0x800 != 0;
+        // P=0 or W=1
+        let wback = imm8_form && (hw2 & 0x400 == 0 || hw2 & 0x100 != 0);
+        if wback && rn != PC { self.kill(rn); }
+        if hw1 & 0x10 == 0 { return; }
+        if rt == PC {
+            // PLD/PLI are size 8/16 loads to pc; LDR pc is a jump
+            if let Size32 = size {
+                if rn == PC { self.literal(hw2 & 0xfff, imm12_form); }
+                self.info.kind = InsnKind::Tail;
+            }
+            return;
+        }
+        if rn == PC {
+            let a = self.literal(hw2 & 0xfff, imm12_form);
+            self.load(rt, Addrish::Imm(a.0), size, signedness);
+        } else if imm12_form {
+            self.load(rt, Addrish::AddImm(reg(rn), (hw2 & 0xfff) as u64), size, signedness);
+        } else if imm8_form {
+            // only the plain offset forms (P=1 W=0) load from Rn +/- imm8
+            if hw2 & 0x500 == 0x400 {
+                let imm8 = (hw2 & 0xff) as u64;
+                let off = if hw2 & 0x200 != 0 { imm8 } else { imm8.wrapping_neg() };
+                self.load(rt, Addrish::AddImm(reg(rn), off), size, signedness);
+            } else {
+                self.kill(rt);
+            }
+        } else if hw2 & 0xfc0 == 0 {
+            self.load(rt, Addrish::AddReg(reg(rn), reg(hw2 & 0xf), ((hw2 >> 4) & 3) as u8), size, signedness);
+        } else {
+            self.kill(rt);
+        }
+    }
+}
+
+impl GenericHandler for ARMHandler {
+    fn decode<'a>(&'a mut self, addr: VMA, data: &[ReadCell<u8>]) -> (usize, &'a InsnInfo) {
+        self.info = Default::default();
+        self.addr = addr;
+        let size = if self.thumb {
+            self.decode_thumb(data)
+        } else if data.len() >= 4 {
+            let op: u32 = util::copy_from_slice(&data[..4], self.endian);
+            self.decode_arm(op);
+            4
+        } else { 0 };
+        if size == 0 { self.info.kind = InsnKind::Unidentified; }
+        (size, &self.info)
+    }
+    fn set_mode(&mut self, mode: CodeMode) {
+        if let ARMMode { thumb } = mode { self.thumb = thumb; }
+    }
+    fn mode(&self) -> CodeMode {
+        ARMMode { thumb: self.thumb }
+    }
+    fn reset(&mut self) {
+        self.it_conds.clear();
+    }
+    fn grain_shift(&self) -> u8 { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: VMA = VMA(0x8000);
+
+    fn cells(bytes: &[u8]) -> Vec<ReadCell<u8>> {
+        bytes.iter().map(|&b| ReadCell::new(b)).collect()
+    }
+
+    // decode the instruction at addr in code loaded at BASE
+    fn decode(handler: &mut ARMHandler, code: &[ReadCell<u8>], addr: u64) -> (usize, InsnInfo) {
+        let (size, info) = handler.decode(VMA(addr), &code[(addr - BASE.0) as usize..]);
+        (size, *info)
+    }
+
+    fn handler(thumb: bool) -> ARMHandler {
+        let mut handler = ARMHandler::new(Endian::LittleEndian);
+        handler.set_mode(ARMMode { thumb: thumb });
+        handler
+    }
+
+    fn code_target(info: &InsnInfo) -> VMA {
+        match info.target_addr { TargetAddr::Code(a) => a, t => panic!("{:?}", t) }
+    }
+
+    // assembled with llvm-mc
+    #[test]
+    fn test_thumb_branches() {
+        let code = cells(&[
+            0x01, 0xf0, 0x00, 0xf8, // 8000: bl 0x9004
+            0x02, 0xf0, 0x00, 0xe8, // 8004: blx 0xa008
+            0x03, 0xf0, 0x00, 0xb8, // 8008: b.w 0xb00c
+            0x00, 0xf0, 0x80, 0x80, // 800c: beq.w 0x8110
+            0x02, 0xb3,             // 8010: cbz r2, 0x8054
+            0x83, 0xb9,             // 8012: cbnz r3, 0x8036
+        ]);
+        let mut h = handler(true);
+        let (size, info) = decode(&mut h, &code, 0x8000);
+        assert_eq!(size, 4);
+        match info.kind { InsnKind::Call => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0x9005));
+        // BLX switches to ARM, so the target is word aligned and the low bit is clear
+        let (_, info) = decode(&mut h, &code, 0x8004);
+        match info.kind { InsnKind::Call => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0xa008));
+        let (_, info) = decode(&mut h, &code, 0x8008);
+        match info.kind { InsnKind::Tail => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0xb00d));
+        let (_, info) = decode(&mut h, &code, 0x800c);
+        match info.kind { InsnKind::Bcc(_) => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0x8111));
+        // CBZ/CBNZ fall through, with the target marked as code
+        let (size, info) = decode(&mut h, &code, 0x8010);
+        assert_eq!(size, 2);
+        match info.kind { InsnKind::Other => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0x8055));
+        let (_, info) = decode(&mut h, &code, 0x8012);
+        match info.kind { InsnKind::Other => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0x8037));
+    }
+
+    #[test]
+    fn test_arm_branches() {
+        let code = cells(&[
+            0x00, 0x04, 0x00, 0xeb, // 8000: bl 0x9008
+            0x00, 0x08, 0x00, 0xfb, // 8004: blx 0xa00e
+            0xfe, 0xff, 0xff, 0x0a, // 8008: beq 0x8008
+        ]);
+        let mut h = handler(false);
+        let (size, info) = decode(&mut h, &code, 0x8000);
+        assert_eq!(size, 4);
+        match info.kind { InsnKind::Call => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0x9008));
+        // BLX switches to Thumb
+        let (_, info) = decode(&mut h, &code, 0x8004);
+        match info.kind { InsnKind::Call => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0xa00f));
+        let (_, info) = decode(&mut h, &code, 0x8008);
+        match info.kind { InsnKind::Bcc(_) => (), k => panic!("{:?}", k) }
+        assert_eq!(code_target(&info), VMA(0x8008));
+    }
+
+    fn table_br(info: &InsnInfo) -> TableBr {
+        match info.kind { InsnKind::TableBr(tb) => tb, k => panic!("{:?}", k) }
+    }
+
+    #[test]
+    fn test_table_branches() {
+        let code = cells(&[
+            0xdf, 0xe8, 0x01, 0xf0, // 8000: tbb [pc, r1]
+            0xdf, 0xe8, 0x12, 0xf0, // 8004: tbh [pc, r2, lsl #1]
+        ]);
+        let mut h = handler(true);
+        let tb = table_br(&decode(&mut h, &code, 0x8000).1);
+        assert_eq!((tb.idx, tb.table, tb.size.bytes(), tb.halfword_offsets), (Reg(1), VMA(0x8004), 1, true));
+        assert!(tb.default_cc.is_none());
+        let tb = table_br(&decode(&mut h, &code, 0x8004).1);
+        assert_eq!((tb.idx, tb.table, tb.size.bytes(), tb.halfword_offsets), (Reg(2), VMA(0x8008), 2, true));
+
+        let code = cells(&[
+            0x00, 0xf1, 0x9f, 0xe7, // 8000: ldr pc, [pc, r0, lsl #2]
+            0x00, 0xf1, 0x9f, 0x97, // 8004: ldrls pc, [pc, r0, lsl #2]
+        ]);
+        let mut h = handler(false);
+        let tb = table_br(&decode(&mut h, &code, 0x8000).1);
+        assert_eq!((tb.idx, tb.table, tb.size.bytes(), tb.halfword_offsets), (Reg(0), VMA(0x8008), 4, false));
+        assert!(tb.default_cc.is_none());
+        // falls through to the default case when hi
+        let tb = table_br(&decode(&mut h, &code, 0x8004).1);
+        assert_eq!(tb.table, VMA(0x800c));
+        match tb.default_cc { Some(CC::Gtu) => (), cc => panic!("{:?}", cc) }
+    }
+
+    static IT_CODE: &[u8] = &[
+        0x08, 0xbf,             // 8000: it eq
+        0x01, 0x20,             // 8002: moveq r0, #1
+        0x01, 0x20,             // 8004: movs r0, #1
+        0x1a, 0xbf,             // 8006: itte ne
+        0x11, 0x46,             // 8008: movne r1, r2
+        0x05, 0x23,             // 800a: movne r3, #5
+        0x70, 0x47,             // 800c: bxeq lr
+        0x98, 0xbf,             // 800e: it ls
+        0xdf, 0xe8, 0x01, 0xf0, // 8010: tbbls [pc, r1]
+    ];
+
+    fn kind(h: &mut ARMHandler, code: &[ReadCell<u8>], addr: u64) -> InsnKind {
+        decode(h, code, addr).1.kind
+    }
+
+    #[test]
+    fn test_it() {
+        let code = cells(IT_CODE);
+        let mut h = handler(true);
+        for addr in (0x8000..0x8014).step_by(2) {
+            if addr == 0x8012 { continue; }
+            decode(&mut h, &code, addr);
+        }
+        match kind(&mut h, &code, 0x8002) { InsnKind::Other => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x8004) { InsnKind::Set(Reg(0), Addrish::Imm(1)) => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x8008) { InsnKind::Other => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x800a) { InsnKind::Other => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x800c) { InsnKind::Bcc(_) => (), k => panic!("{:?}", k) }
+        match table_br(&decode(&mut h, &code, 0x8010).1).default_cc {
+            Some(CC::Gtu) => (),
+            cc => panic!("{:?}", cc),
+        }
+    }
+
+    #[test]
+    fn test_it_out_of_order() {
+        let code = cells(IT_CODE);
+        let mut h = handler(true);
+        // both ITs before either body, then the bodies backwards
+        decode(&mut h, &code, 0x8006);
+        decode(&mut h, &code, 0x8000);
+        match kind(&mut h, &code, 0x800c) { InsnKind::Bcc(_) => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x8002) { InsnKind::Other => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x8008) { InsnKind::Other => (), k => panic!("{:?}", k) }
+        // the same instruction again, after other code
+        match kind(&mut h, &code, 0x8002) { InsnKind::Other => (), k => panic!("{:?}", k) }
+        h.reset();
+        match kind(&mut h, &code, 0x8002) { InsnKind::Set(Reg(0), Addrish::Imm(1)) => (), k => panic!("{:?}", k) }
+        match kind(&mut h, &code, 0x800c) { InsnKind::Tail => (), k => panic!("{:?}", k) }
+    }
+}
@@ -1,15 +1,20 @@
 #![allow(non_snake_case)]
 extern crate build_run_gen;
 extern crate util;
-use util::{Signedness, Signed, Unsigned, SignExtend, ReadCell};
+use util::{Signedness, Signed, Unsigned, SignExtend, ReadCell, Endian};
 extern crate exec;
 use exec::VMA;
+use exec::arch::{Arch, CodeMode, OtherMode};
 
 use std::mem::transmute;
 
 mod aarch64 {
     include!(concat!(env!("OUT_DIR"), "/jump-dis-aarch64.rs"));
 }
+mod x86_64;
+pub use x86_64::X86_64Handler;
+mod arm;
+pub use arm::ARMHandler;
 
 pub const MAX_REGS: usize = 32;
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -42,6 +47,16 @@ impl InsnInfo {
     pub fn kills_reg(&self, reg: Reg) -> bool {
         self.kills_reg.contains(&reg)
     }
+    // for hand-written handlers; anything past three registers is dropped
+    fn kill(&mut self, r: Reg) {
+        for slot in self.kills_reg.iter_mut() {
+            if *slot == r { return; }
+            if *slot == Reg::invalid() {
+                *slot = r;
+                return;
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -54,6 +69,8 @@ impl Default for TargetAddr { fn default() -> Self { TargetAddr::None } }
 #[derive(Copy, Clone, Debug)]
 pub enum InsnKind {
     Other,
+    // falls through; a code target is a function entry
+    Call,
     Tail,
     Unidentified,
     Load(Reg, Addrish, Size, Signedness),
@@ -61,10 +78,24 @@ pub enum InsnKind {
     CmpImm(Reg, u64),
     Bcc(CC),
     Br(Reg),
+    TableBr(TableBr),
 }
 
 impl Default for InsnKind { fn default() -> Self { InsnKind::Other } }
 
+// A branch that does its own table lookup (ARM tbb/tbh, ldr pc, [pc, rX, lsl #2], x86-64
+// jmp *table(,rX,8)).
+#[derive(Copy, Clone, Debug)]
+pub struct TableBr {
+    pub idx: Reg,
+    pub table: VMA,
+    pub size: Size,
+    // tbb/tbh: the target is table + 2*entry; otherwise the entry is the target itself
+    pub halfword_offsets: bool,
+    // if conditional, the condition under which it falls through instead
+    pub default_cc: Option<CC>,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Addrish {
     Imm(u64),
@@ -98,6 +129,23 @@ impl CC {
 }
 pub trait GenericHandler {
     fn decode<'a>(&'a mut self, addr: VMA, data: &[ReadCell<u8>]) -> (usize, &'a InsnInfo);
+    // Only ARM has more than one mode.  Handlers that do report code targets the way BX would
+    // take them, i.e. with the low bit set for Thumb.
+    fn set_mode(&mut self, _mode: CodeMode) {}
+    fn mode(&self) -> CodeMode { OtherMode }
+    // forget state carried between decodes (ARM's IT blocks) before starting on new code
+    fn reset(&mut self) {}
+    // log2 of the instruction alignment
+    fn grain_shift(&self) -> u8 { 0 }
+}
+
+pub fn create_handler(arch: Arch, endian: Endian) -> Option<Box<dyn GenericHandler>> {
+    match arch {
+        Arch::AArch64 => Some(Box::new(AArch64Handler::new())),
+        Arch::X86_64 => Some(Box::new(X86_64Handler::new())),
+        Arch::ARM => Some(Box::new(ARMHandler::new(endian))),
+        _ => None,
+    }
 }
 
 impl GenericHandler for AArch64Handler {
@@ -114,6 +162,7 @@ impl GenericHandler for AArch64Handler {
         }
         (size, &self.info)
     }
+    fn grain_shift(&self) -> u8 { 2 }
 }
 
 impl AArch64Handler {
@@ -258,6 +307,7 @@ impl aarch64::Handler for AArch64Handler {
     }
     #[inline]
     fn addr_1_BL(&mut self, addr: u32) -> Self::Res {
+        self.info.kind = InsnKind::Call;
         self.info.target_addr = TargetAddr::Code(self.addr.wrapping_add((addr << 2).sign_extend(28)));
     }
     #[inline]
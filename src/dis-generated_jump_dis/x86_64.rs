@@ -0,0 +1,759 @@
+// Unlike AArch64 this one is written by hand: x86-64 needs a length decoder anyway, and only a
+// small part of the opcode map is interesting to CodeMap.  Registers are numbered like the
+// encoding (rax=0 ... r15=15); partial-width accesses use the containing 64-bit register.
+
+use super::{GenericHandler, InsnInfo, InsnKind, TableBr, TargetAddr, Addrish, Reg, CC, Size, Size8, Size16, Size32, Size64};
+use util::{Signedness, Signed, Unsigned, ReadCell};
+use exec::VMA;
+
+const RAX: Reg = Reg(0);
+const RCX: Reg = Reg(1);
+const RDX: Reg = Reg(2);
+const RBX: Reg = Reg(3);
+const RBP: Reg = Reg(5);
+const RSI: Reg = Reg(6);
+const RDI: Reg = Reg(7);
+const R11: Reg = Reg(11);
+
+const MAX_INSN_LEN: usize = 15;
+
+#[derive(Default)]
+pub struct X86_64Handler {
+    info: InsnInfo,
+}
+
+impl X86_64Handler {
+    pub fn new() -> X86_64Handler {
+        Default::default()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct MemOperand {
+    base: Option<Reg>,
+    index: Option<(Reg, u8 /*log2 scale*/)>,
+    disp: u64,
+    rip_rel: bool,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Insn {
+    rex: u8, // also filled in from VEX/EVEX
+    p66: bool,
+    pf2: bool,
+    pf3: bool,
+    addr32: bool,
+    vex: bool,
+    vvvv: u8,
+    map: u8, // 0 = one-byte, 1 = 0f, 2 = 0f38, 3 = 0f3a, 8.. = XOP
+    op: u8,
+    modrm: Option<u8>,
+    mem: Option<MemOperand>,
+    imm: u64, // sign-extended
+}
+
+impl Insn {
+    fn w(&self) -> bool { self.rex & 8 != 0 }
+    fn size(&self) -> Size {
+        if self.w() { Size64 } else if self.p66 { Size16 } else { Size32 }
+    }
+    fn modrm_reg(&self) -> u8 { (self.modrm.unwrap() >> 3) & 7 }
+    fn reg(&self) -> Reg {
+        Reg((self.modrm_reg() | (self.rex & 4) << 1) as i8)
+    }
+    // the r/m operand, if it's a register
+    fn rm(&self) -> Option<Reg> {
+        let modrm = self.modrm.unwrap();
+        if modrm >> 6 == 3 {
+            Some(Reg((modrm & 7 | (self.rex & 1) << 3) as i8))
+        } else { None }
+    }
+    // without REX, byte registers 4-7 are ah/ch/dh/bh
+    fn byte_reg(&self, r: Reg) -> Reg {
+        if self.rex == 0 && r.0 >= 4 && r.0 < 8 { Reg(r.0 - 4) } else { r }
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [ReadCell<u8>],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        if self.pos >= MAX_INSN_LEN { return None; }
+        let b = self.data.get(self.pos)?.get();
+        self.pos += 1;
+        Some(b)
+    }
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).map(|c| c.get())
+    }
+    fn int(&mut self, bytes: usize) -> Option<u64> {
+        let mut val: u64 = 0;
+        for i in 0..bytes {
+            val |= (self.byte()? as u64) << (8 * i);
+        }
+        Some(match bytes {
+            1 => val as i8 as u64,
+            2 => val as i16 as u64,
+            4 => val as i32 as u64,
+            _ => val,
+        })
+    }
+}
+
+// (has ModRM, immediate bytes); prefixes, REX and escapes are dealt with before this
+fn one_byte_shape(op: u8, z: usize, v: usize, moffs: usize) -> Option<(bool, usize)> {
+    Some(match op {
+        0x00..=0x3f => match op & 7 {
+            0..=3 => (true, 0),
+            4 => (false, 1),
+            5 => (false, z),
+            _ => return None, // segment pushes/pops and BCD ops are gone in 64-bit mode
+        },
+        0x50..=0x5f | 0x6c..=0x6f | 0x90..=0x99 | 0x9b..=0x9f |
+        0xa4..=0xa7 | 0xaa..=0xaf | 0xc3 | 0xc9 | 0xcb | 0xcc | 0xcf | 0xd7 |
+        0xec..=0xef | 0xf1 | 0xf4 | 0xf5 | 0xf8..=0xfd => (false, 0),
+        0x63 | 0x84..=0x8f | 0xd0..=0xd3 | 0xd8..=0xdf | 0xf6 | 0xf7 | 0xfe | 0xff => (true, 0),
+        0x68 | 0xa9 => (false, z),
+        0x69 | 0x81 | 0xc7 => (true, z),
+        0x6a | 0x70..=0x7f | 0xa8 | 0xb0..=0xb7 | 0xcd | 0xe0..=0xe7 | 0xeb => (false, 1),
+        0x6b | 0x80 | 0x83 | 0xc0 | 0xc1 | 0xc6 => (true, 1),
+        0xa0..=0xa3 => (false, moffs),
+        0xb8..=0xbf => (false, v),
+        0xc2 | 0xca => (false, 2),
+        0xc8 => (false, 3),
+        0xe8 | 0xe9 => (false, 4),
+        _ => return None,
+    })
+}
+
+fn two_byte_shape(op: u8) -> Option<(bool, usize)> {
+    Some(match op {
+        0x05..=0x09 | 0x0b | 0x0e | 0x30..=0x35 | 0x37 | 0x77 |
+        0xa0..=0xa2 | 0xa8..=0xaa | 0xc8..=0xcf => (false, 0),
+        0x00..=0x03 | 0x0d | 0x10..=0x23 | 0x28..=0x2f | 0x40..=0x6f | 0x74..=0x76 |
+        0x78 | 0x79 | 0x7c..=0x7f | 0x90..=0x9f | 0xa3 | 0xa5 | 0xab | 0xad..=0xb9 |
+        0xbb..=0xc1 | 0xc3 | 0xc7 | 0xd0..=0xff => (true, 0),
+        // 0f 0f is 3DNow!, whose real opcode comes after the operands
+        0x0f | 0x70..=0x73 | 0xa4 | 0xac | 0xba | 0xc2 | 0xc4..=0xc6 => (true, 1),
+        0x80..=0x8f => (false, 4),
+        _ => return None,
+    })
+}
+
+fn vex_imm_bytes(map: u8, op: u8) -> usize {
+    match (map, op) {
+        (1, 0x70..=0x73) | (1, 0xc2) | (1, 0xc4..=0xc6) | (3, _) | (8, _) => 1,
+        (10, _) => 4,
+        _ => 0,
+    }
+}
+
+fn decode_insn(data: &[ReadCell<u8>]) -> Option<(usize, Insn)> {
+    let mut c = Cursor { data: data, pos: 0 };
+    let mut i: Insn = Default::default();
+    let mut op;
+    loop {
+        op = c.byte()?;
+        match op {
+            0x66 => i.p66 = true,
+            0x67 => i.addr32 = true,
+            0xf2 => { i.pf2 = true; i.pf3 = false; },
+            0xf3 => { i.pf3 = true; i.pf2 = false; },
+            0xf0 | 0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 => (),
+            // REX only counts if it's right before the opcode
+            0x40..=0x4f => { i.rex = op; continue; },
+            _ => break,
+        }
+        i.rex = 0;
+    }
+    let has_modrm;
+    let imm_bytes;
+    match op {
+        0xc4 | 0xc5 | 0x62 => {
+            // VEX / EVEX (never LES/LDS/BOUND in 64-bit mode)
+            let b1 = c.byte()?;
+            let pp;
+            if op == 0xc5 {
+                i.rex = 0x40 | (!b1 >> 5) & 4;
+                i.map = 1;
+                i.vvvv = (!b1 >> 3) & 0xf;
+                pp = b1 & 3;
+            } else {
+                let b2 = c.byte()?;
+                if op == 0x62 { c.byte()?; }
+                i.rex = 0x40 | (!b1 >> 5) & 7 | (b2 >> 4) & 8;
+                i.map = b1 & if op == 0x62 { 7 } else { 0x1f };
+                i.vvvv = (!b2 >> 3) & 0xf;
+                pp = b2 & 3;
+            }
+            i.vex = true;
+            i.p66 = pp == 1;
+            i.pf3 = pp == 2;
+            i.pf2 = pp == 3;
+            match (op, i.map) {
+                (0x62, 1..=3) | (0x62, 5) | (0x62, 6) | (0xc4, 1..=3) | (0xc5, _) => (),
+                _ => return None,
+            }
+            i.op = c.byte()?;
+            has_modrm = !(i.map == 1 && i.op == 0x77); // vzeroupper/vzeroall
+            imm_bytes = vex_imm_bytes(i.map, i.op);
+        },
+        0x8f if c.peek().map_or(false, |b| b & 0x1f >= 8) => {
+            // XOP
+            let b1 = c.byte()?;
+            let b2 = c.byte()?;
+            i.rex = 0x40 | (!b1 >> 5) & 7 | (b2 >> 4) & 8;
+            i.map = b1 & 0x1f;
+            i.vvvv = (!b2 >> 3) & 0xf;
+            if i.map > 10 { return None; }
+            i.vex = true;
+            i.op = c.byte()?;
+            has_modrm = true;
+            imm_bytes = vex_imm_bytes(i.map, i.op);
+        },
+        0x0f => {
+            let op2 = c.byte()?;
+            match op2 {
+                0x38 => { i.map = 2; i.op = c.byte()?; has_modrm = true; imm_bytes = 0; },
+                0x3a => { i.map = 3; i.op = c.byte()?; has_modrm = true; imm_bytes = 1; },
+                _ => {
+                    i.map = 1;
+                    i.op = op2;
+                    let (m, ib) = two_byte_shape(op2)?;
+                    has_modrm = m; imm_bytes = ib;
+                },
+            }
+        },
+        _ => {
+            i.op = op;
+            let z = if i.p66 { 2 } else { 4 };
+            let v = if i.rex & 8 != 0 { 8 } else { z };
+            let moffs = if i.addr32 { 4 } else { 8 };
+            let (m, ib) = one_byte_shape(op, z, v, moffs)?;
+            has_modrm = m; imm_bytes = ib;
+        },
+    }
+    if has_modrm {
+        let modrm = c.byte()?;
+        i.modrm = Some(modrm);
+        let md = modrm >> 6;
+        if md != 3 {
+            let mut mem = MemOperand { base: None, index: None, disp: 0, rip_rel: false };
+            let rm = modrm & 7;
+            let mut disp_bytes = [0, 1, 4][md as usize];
+            if rm == 4 {
+                let sib = c.byte()?;
+                let index = (sib >> 3) & 7 | (i.rex & 2) << 2;
+                if index != 4 {
+                    mem.index = Some((Reg(index as i8), sib >> 6));
+                }
+                if sib & 7 == 5 && md == 0 {
+                    disp_bytes = 4;
+                } else {
+                    mem.base = Some(Reg((sib & 7 | (i.rex & 1) << 3) as i8));
+                }
+            } else if rm == 5 && md == 0 {
+                mem.rip_rel = true;
+                disp_bytes = 4;
+            } else {
+                mem.base = Some(Reg((rm | (i.rex & 1) << 3) as i8));
+            }
+            mem.disp = c.int(disp_bytes)?;
+            i.mem = Some(mem);
+        }
+    }
+    let imm_bytes = match (i.map, i.op) {
+        // TEST r/m, imm is the odd one out in group 3
+        (0, 0xf6) if i.modrm_reg() < 2 => 1,
+        (0, 0xf7) if i.modrm_reg() < 2 => if i.p66 { 2 } else { 4 },
+        _ => imm_bytes,
+    };
+    i.imm = c.int(imm_bytes)?;
+    Some((c.pos, i))
+}
+
+fn cc_from_x86(cond: u8) -> CC {
+    match cond {
+        0x2 => CC::Ltu, // jb
+        0x3 => CC::Geu, // jae
+        0x6 => CC::Leu, // jbe
+        0x7 => CC::Gtu, // ja
+        _ => CC::Other,
+    }
+}
+
+// jmp *table(,%reg,8), as used for non-PIC jump tables of absolute addresses
+fn table_br(i: &Insn) -> Option<TableBr> {
+    let mem = i.mem?;
+    match (mem.base, mem.index) {
+        (None, Some((idx, 3))) if !mem.rip_rel && !i.addr32 => Some(TableBr {
+            idx,
+            table: VMA(mem.disp),
+            size: Size64,
+            halfword_offsets: false,
+            default_cc: None,
+        }),
+        _ => None,
+    }
+}
+
+fn size_mask(size: Size) -> u64 {
+    !0 >> (64 - size.bits())
+}
+
+impl X86_64Handler {
+    fn kill(&mut self, r: Reg) {
+        self.info.kill(r);
+    }
+    fn kill_rm(&mut self, i: &Insn) {
+        if let Some(r) = i.rm() { self.kill(r); }
+    }
+    fn kill_rep(&mut self, i: &Insn, regs: &[Reg]) {
+        for &r in regs { self.kill(r); }
+        if i.pf2 || i.pf3 { self.kill(RCX); }
+    }
+    // the memory operand as something CodeMap understands; records RIP-relative targets as data
+    fn mem_addrish(&mut self, i: &Insn, next_pc: VMA) -> Option<Addrish> {
+        let mem = i.mem?;
+        if mem.rip_rel {
+            let mut a = next_pc.wrapping_add(mem.disp);
+            if i.addr32 { a = VMA(a.0 & 0xffff_ffff); }
+            self.info.target_addr = TargetAddr::Data(a);
+            return Some(Addrish::Imm(a.0));
+        }
+        if i.addr32 { return None; }
+        match (mem.base, mem.index) {
+            (None, None) => Some(Addrish::Imm(mem.disp)),
+            (Some(base), None) | (None, Some((base, 0))) => Some(Addrish::AddImm(base, mem.disp)),
+            (Some(base), Some((index, scale))) if mem.disp == 0 => Some(Addrish::AddReg(base, index, scale)),
+            _ => None,
+        }
+    }
+    fn load(&mut self, dst: Reg, i: &Insn, next_pc: VMA, size: Size, signedness: Signedness) {
+        self.kill(dst);
+        if let Some(addrish) = self.mem_addrish(i, next_pc) {
+            self.info.kind = InsnKind::Load(dst, addrish, size, signedness);
+        }
+    }
+
+    fn handle_legacy(&mut self, i: &Insn, next_pc: VMA) {
+        let op = i.op;
+        let size = i.size();
+        let wide = match size { Size32 | Size64 => true, _ => false };
+        match op {
+            0x00..=0x3d => {
+                let alu = op >> 3;
+                let is_cmp = alu == 7;
+                match op & 7 {
+                    0 | 1 => if let Some(dst) = i.rm() {
+                        let dst = if op & 1 == 0 { i.byte_reg(dst) } else { dst };
+                        if is_cmp { return; }
+                        self.kill(dst);
+                        if op == 0x01 && wide {
+                            self.info.kind = InsnKind::Set(dst, Addrish::AddReg(dst, i.reg(), 0));
+                        } else if (op == 0x29 || op == 0x31) && wide && dst == i.reg() {
+                            self.info.kind = InsnKind::Set(dst, Addrish::Imm(0));
+                        }
+                    },
+                    2 | 3 => {
+                        let dst = if op & 1 == 0 { i.byte_reg(i.reg()) } else { i.reg() };
+                        if let Some(src) = i.rm() {
+                            if is_cmp { return; }
+                            self.kill(dst);
+                            if op == 0x03 && wide {
+                                self.info.kind = InsnKind::Set(dst, Addrish::AddReg(dst, src, 0));
+                            } else if (op == 0x2b || op == 0x33) && wide && dst == src {
+                                self.info.kind = InsnKind::Set(dst, Addrish::Imm(0));
+                            }
+                        } else {
+                            self.mem_addrish(i, next_pc);
+                            if !is_cmp { self.kill(dst); }
+                        }
+                        return;
+                    },
+                    _ => {
+                        let size = if op & 1 == 0 { Size8 } else { size };
+                        if is_cmp {
+                            self.info.kind = InsnKind::CmpImm(RAX, i.imm & size_mask(size));
+                        } else {
+                            self.kill(RAX);
+                        }
+                    },
+                }
+                self.mem_addrish(i, next_pc);
+            },
+            0x58..=0x5f => self.kill(Reg((op & 7 | (i.rex & 1) << 3) as i8)),
+            0x63 => {
+                let signedness = if i.w() { Signed } else { Unsigned };
+                if let Some(src) = i.rm() {
+                    // sloppily ignore the extension; switch indices get here after a bounds check
+                    self.kill(i.reg());
+                    self.info.kind = InsnKind::Set(i.reg(), Addrish::AddImm(src, 0));
+                } else {
+                    self.load(i.reg(), i, next_pc, Size32, signedness);
+                }
+            },
+            0x69 | 0x6b => { self.kill(i.reg()); self.mem_addrish(i, next_pc); },
+            0x6c | 0x6d => self.kill_rep(i, &[RDI]),
+            0x6e | 0x6f => self.kill_rep(i, &[RSI]),
+            0x70..=0x7f => {
+                self.info.kind = InsnKind::Bcc(cc_from_x86(op & 0xf));
+                self.info.target_addr = TargetAddr::Code(next_pc.wrapping_add(i.imm));
+            },
+            0x80..=0x83 => {
+                let size = if op == 0x80 { Size8 } else { size };
+                if let Some(dst) = i.rm() {
+                    let dst = if op == 0x80 { i.byte_reg(dst) } else { dst };
+                    match i.modrm_reg() {
+                        7 => self.info.kind = InsnKind::CmpImm(dst, i.imm & size_mask(size)),
+                        0 if wide => {
+                            self.kill(dst);
+                            self.info.kind = InsnKind::Set(dst, Addrish::AddImm(dst, i.imm));
+                        },
+                        5 if wide => {
+                            self.kill(dst);
+                            self.info.kind = InsnKind::Set(dst, Addrish::AddImm(dst, i.imm.wrapping_neg()));
+                        },
+                        _ => self.kill(dst),
+                    }
+                } else {
+                    self.mem_addrish(i, next_pc);
+                }
+            },
+            0x86 | 0x87 => {
+                self.kill(if op == 0x86 { i.byte_reg(i.reg()) } else { i.reg() });
+                self.kill_rm(i);
+                self.mem_addrish(i, next_pc);
+            },
+            0x88 | 0x89 | 0x8c => if let Some(dst) = i.rm() {
+                self.kill(if op == 0x88 { i.byte_reg(dst) } else { dst });
+                if op == 0x89 && wide {
+                    self.info.kind = InsnKind::Set(dst, Addrish::AddImm(i.reg(), 0));
+                }
+            } else {
+                self.mem_addrish(i, next_pc);
+            },
+            0x8a | 0x8b => {
+                let dst = if op == 0x8a { i.byte_reg(i.reg()) } else { i.reg() };
+                if let Some(src) = i.rm() {
+                    self.kill(dst);
+                    if op == 0x8b && wide {
+                        self.info.kind = InsnKind::Set(dst, Addrish::AddImm(src, 0));
+                    }
+                } else if op == 0x8b && wide {
+                    self.load(dst, i, next_pc, size, Unsigned);
+                } else {
+                    self.kill(dst);
+                    self.mem_addrish(i, next_pc);
+                }
+            },
+            0x8d => {
+                self.kill(i.reg());
+                if let Some(addrish) = self.mem_addrish(i, next_pc) {
+                    if wide {
+                        self.info.kind = InsnKind::Set(i.reg(), addrish);
+                    }
+                }
+            },
+            0x8f => { self.kill_rm(i); self.mem_addrish(i, next_pc); },
+            0x90..=0x97 if op != 0x90 || i.rex & 1 != 0 => {
+                self.kill(RAX);
+                self.kill(Reg((op & 7 | (i.rex & 1) << 3) as i8));
+            },
+            0x98 | 0xa0 | 0xa1 | 0xe4 | 0xe5 | 0xec | 0xed => self.kill(RAX),
+            0x99 => self.kill(RDX),
+            0xa4..=0xa7 => self.kill_rep(i, &[RSI, RDI]),
+            0xaa | 0xab | 0xae | 0xaf => self.kill_rep(i, &[RDI]),
+            0xac | 0xad => self.kill_rep(i, &[RAX, RSI]),
+            0xb0..=0xb7 => self.kill(i.byte_reg(Reg((op & 7 | (i.rex & 1) << 3) as i8))),
+            0xb8..=0xbf => {
+                let dst = Reg((op & 7 | (i.rex & 1) << 3) as i8);
+                self.kill(dst);
+                if wide {
+                    self.info.kind = InsnKind::Set(dst, Addrish::Imm(i.imm & size_mask(size)));
+                }
+            },
+            0xc0 | 0xc1 | 0xd0..=0xd3 => {
+                if let Some(dst) = i.rm() {
+                    self.kill(if op & 1 == 0 { i.byte_reg(dst) } else { dst });
+                }
+                self.mem_addrish(i, next_pc);
+            },
+            0xc2 | 0xc3 | 0xca | 0xcb | 0xcf | 0xcc | 0xf4 => self.info.kind = InsnKind::Tail,
+            0xc6 | 0xc7 => if let Some(dst) = i.rm() {
+                if i.modrm == Some(0xf8) {
+                    if op == 0xc7 {
+                        // xbegin: the abort path
+                        self.info.target_addr = TargetAddr::Code(next_pc.wrapping_add(i.imm));
+                    }
+                    return;
+                }
+                self.kill(if op == 0xc6 { i.byte_reg(dst) } else { dst });
+                if op == 0xc7 && wide {
+                    self.info.kind = InsnKind::Set(dst, Addrish::Imm(i.imm & size_mask(size)));
+                }
+            } else {
+                self.mem_addrish(i, next_pc);
+            },
+            0xc9 => self.kill(RBP),
+            0xd8..=0xdf => { self.mem_addrish(i, next_pc); },
+            0xe0..=0xe3 => {
+                if op != 0xe3 { self.kill(RCX); }
+                self.info.target_addr = TargetAddr::Code(next_pc.wrapping_add(i.imm));
+            },
+            0xe8 => {
+                self.info.kind = InsnKind::Call;
+                self.info.target_addr = TargetAddr::Code(next_pc.wrapping_add(i.imm));
+            },
+            0xe9 | 0xeb => {
+                self.info.kind = InsnKind::Tail;
+                self.info.target_addr = TargetAddr::Code(next_pc.wrapping_add(i.imm));
+            },
+            0xf6 | 0xf7 => {
+                match i.modrm_reg() {
+                    2 | 3 => if let Some(dst) = i.rm() {
+                        self.kill(if op == 0xf6 { i.byte_reg(dst) } else { dst });
+                    },
+                    4..=7 => {
+                        self.kill(RAX);
+                        if op == 0xf7 { self.kill(RDX); }
+                    },
+                    _ => (),
+                }
+                self.mem_addrish(i, next_pc);
+            },
+            0xfe | 0xff => {
+                match i.modrm_reg() {
+                    0 | 1 => if let Some(dst) = i.rm() {
+                        self.kill(if op == 0xfe { i.byte_reg(dst) } else { dst });
+                    },
+                    2 if op == 0xff => self.info.kind = InsnKind::Call,
+                    4 if op == 0xff => if let Some(dst) = i.rm() {
+                        self.info.kind = InsnKind::Br(dst);
+                        return;
+                    } else if let Some(tb) = table_br(i) {
+                        self.info.kind = InsnKind::TableBr(tb);
+                        return;
+                    } else {
+                        // through memory: a tail call via the GOT or the like
+                        self.info.kind = InsnKind::Tail;
+                    },
+                    5 if op == 0xff => self.info.kind = InsnKind::Tail,
+                    _ => (),
+                }
+                self.mem_addrish(i, next_pc);
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_0f(&mut self, i: &Insn, next_pc: VMA) {
+        let op = i.op;
+        match op {
+            0x01 => match i.modrm {
+                Some(0xd0) => { self.kill(RAX); self.kill(RDX); }, // xgetbv
+                Some(0xf9) => { self.kill(RAX); self.kill(RDX); self.kill(RCX); }, // rdtscp
+                _ => (),
+            },
+            0x05 => { self.kill(RAX); self.kill(RCX); self.kill(R11); }, // syscall
+            0x07 | 0x0b | 0xb9 | 0xff => self.info.kind = InsnKind::Tail, // sysret, ud2, ud1, ud0
+            0x31..=0x33 => { self.kill(RAX); self.kill(RDX); },
+            // cpuid also clobbers rcx, but there are only three slots
+            0xa2 => { self.kill(RAX); self.kill(RBX); self.kill(RDX); },
+            0x80..=0x8f => {
+                self.info.kind = InsnKind::Bcc(cc_from_x86(op & 0xf));
+                self.info.target_addr = TargetAddr::Code(next_pc.wrapping_add(i.imm));
+            },
+            0x90..=0x9f => if let Some(dst) = i.rm() { self.kill(i.byte_reg(dst)); },
+            0xa4 | 0xa5 | 0xab | 0xac | 0xad | 0xb3 | 0xbb => self.kill_rm(i),
+            0xba if i.modrm_reg() >= 5 => self.kill_rm(i),
+            0xb0 | 0xb1 => { self.kill(RAX); self.kill_rm(i); },
+            0xc0 | 0xc1 => { self.kill(i.reg()); self.kill_rm(i); },
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let size = if op & 1 == 0 { Size8 } else { Size16 };
+                let signedness = if op >= 0xbe { Signed } else { Unsigned };
+                if let Some(src) = i.rm() {
+                    self.kill(i.reg());
+                    // (as with movsxd, but not from ah/ch/dh/bh)
+                    if op & 1 == 1 || i.byte_reg(src) == src {
+                        self.info.kind = InsnKind::Set(i.reg(), Addrish::AddImm(src, 0));
+                    }
+                } else {
+                    self.load(i.reg(), i, next_pc, size, signedness);
+                }
+                return;
+            },
+            0x40..=0x4f | 0xaf | 0xb8 | 0xbc | 0xbd | 0x50 | 0xd7 | 0xc5 => self.kill(i.reg()),
+            0x2c | 0x2d if i.pf2 || i.pf3 => self.kill(i.reg()),
+            0x7e if !i.pf3 => self.kill_rm(i),
+            0xc7 => match i.modrm_reg() {
+                1 => { self.kill(RAX); self.kill(RDX); },
+                6 | 7 => self.kill_rm(i),
+                _ => (),
+            },
+            0xc8..=0xcf => self.kill(Reg((op & 7 | (i.rex & 1) << 3) as i8)),
+            _ => (),
+        }
+        if i.modrm.is_some() {
+            self.mem_addrish(i, next_pc);
+        }
+    }
+
+    fn handle_other_maps(&mut self, i: &Insn, next_pc: VMA) {
+        match (i.map, i.op) {
+            // VEX versions of the SIMD-to-GPR moves above
+            (1, 0x50) | (1, 0xd7) | (1, 0xc5) if i.vex => self.kill(i.reg()),
+            (1, 0x2c) | (1, 0x2d) if i.vex && (i.pf2 || i.pf3) => self.kill(i.reg()),
+            (1, 0x7e) if i.vex && i.p66 => self.kill_rm(i),
+            // movbe/crc32, adcx/adox
+            (2, 0xf0) if !i.vex => self.kill(i.reg()),
+            (2, 0xf1) if !i.vex && i.pf2 => self.kill(i.reg()),
+            (2, 0xf6) if !i.vex && (i.p66 || i.pf3) => self.kill(i.reg()),
+            // BMI: andn, blsr & co, bzhi/pdep/pext, mulx, bextr/shlx/sarx/shrx, rorx
+            (2, 0xf3) if i.vex => self.kill(Reg(i.vvvv as i8)),
+            (2, 0xf6) if i.vex => { self.kill(i.reg()); self.kill(Reg(i.vvvv as i8)); },
+            (2, 0xf2) | (2, 0xf5) | (2, 0xf7) | (3, 0xf0) if i.vex => self.kill(i.reg()),
+            // pextr*, extractps
+            (3, 0x14..=0x17) => self.kill_rm(i),
+            // pcmpestri/pcmpistri
+            (3, 0x61) | (3, 0x63) => self.kill(RCX),
+            _ => (),
+        }
+        self.mem_addrish(i, next_pc);
+    }
+}
+
+impl GenericHandler for X86_64Handler {
+    fn decode<'a>(&'a mut self, addr: VMA, data: &[ReadCell<u8>]) -> (usize, &'a InsnInfo) {
+        self.info = Default::default();
+        let (size, insn) = match decode_insn(data) {
+            Some(x) => x,
+            None => {
+                self.info.kind = InsnKind::Unidentified;
+                return (0, &self.info);
+            },
+        };
+        let next_pc = addr.wrapping_add(size as u64);
+        match insn.map {
+            0 => self.handle_legacy(&insn, next_pc),
+            1 if !insn.vex => self.handle_0f(&insn, next_pc),
+            _ => self.handle_other_maps(&insn, next_pc),
+        }
+        (size, &self.info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(bytes: &[u8]) -> Vec<ReadCell<u8>> {
+        // trailing junk, which shouldn't be taken as part of the instruction
+        bytes.iter().chain(&[0x90; 4]).map(|&b| ReadCell::new(b)).collect()
+    }
+
+    // assembled with GNU as
+    static LENGTHS: &[(&[u8], &str)] = &[
+        // legacy and REX
+        (&[0x90], "nop"),
+        (&[0xc3], "ret"),
+        (&[0x55], "push %rbp"),
+        (&[0x41, 0x54], "push %r12"),
+        (&[0x48, 0x89, 0xe5], "mov %rsp,%rbp"),
+        (&[0x4d, 0x89, 0xc1], "mov %r8,%r9"),
+        (&[0x48, 0xb8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11], "movabs $0x1122334455667788,%rax"),
+        (&[0x41, 0xba, 0x78, 0x56, 0x34, 0x12], "mov $0x12345678,%r10d"),
+        (&[0x48, 0x83, 0xc4, 0x10], "add $0x10,%rsp"),
+        (&[0x48, 0x81, 0xc4, 0x00, 0x10, 0x00, 0x00], "add $0x1000,%rsp"),
+        // SIB and RIP-relative
+        (&[0x48, 0x8d, 0x44, 0x24, 0x10], "lea 0x10(%rsp),%rax"),
+        (&[0x48, 0x8d, 0x0c, 0x98], "lea (%rax,%rbx,4),%rcx"),
+        (&[0x48, 0x8b, 0x14, 0xcd, 0x78, 0x56, 0x34, 0x12], "mov 0x12345678(,%rcx,8),%rdx"),
+        (&[0x49, 0x8b, 0x04, 0x24], "mov (%r12),%rax"),
+        (&[0x49, 0x8b, 0x45, 0x08], "mov 0x8(%r13),%rax"),
+        (&[0x48, 0x8b, 0x05, 0x00, 0x10, 0x00, 0x00], "mov 0x1000(%rip),%rax"),
+        (&[0x48, 0x8d, 0x35, 0xe0, 0xff, 0xff, 0xff], "lea -0x20(%rip),%rsi"),
+        // indirect branches
+        (&[0xff, 0x24, 0xfd, 0x08, 0x20, 0x40, 0x00], "jmp *0x402008(,%rdi,8)"),
+        (&[0xff, 0x25, 0xe2, 0x2f, 0x00, 0x00], "jmp *0x2fe2(%rip)"),
+        (&[0xff, 0xd0], "call *%rax"),
+        (&[0xff, 0x53, 0x10], "call *0x10(%rbx)"),
+        // group 3, where only TEST has an immediate
+        (&[0xf6, 0x07, 0x01], "testb $0x1,(%rdi)"),
+        (&[0x66, 0xf7, 0x47, 0x04, 0x34, 0x12], "testw $0x1234,0x4(%rdi)"),
+        (&[0xa9, 0x78, 0x56, 0x34, 0x12], "testl $0x12345678,%eax"),
+        (&[0xf7, 0xc1, 0x78, 0x56, 0x34, 0x12], "test $0x12345678,%ecx"),
+        (&[0xf7, 0x17], "notl (%rdi)"),
+        (&[0x48, 0xf7, 0x5c, 0x24, 0x08], "negq 0x8(%rsp)"),
+        (&[0x48, 0x69, 0xc0, 0x56, 0x55, 0x55, 0x55], "imul $0x55555556,%rax,%rax"),
+        (&[0x0f, 0xb7, 0x46, 0x02], "movzwl 0x2(%rsi),%eax"),
+        // prefixes
+        (&[0x48, 0x0f, 0x44, 0xc1], "cmove %rcx,%rax"),
+        (&[0xf0, 0x48, 0x0f, 0xb1, 0x0a], "lock cmpxchg %rcx,(%rdx)"),
+        (&[0xf3, 0xa4], "rep movsb"),
+        (&[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00], "mov %fs:0x28,%rax"),
+        (&[0x67, 0x8b, 0x44, 0x24, 0x10], "mov 0x10(%esp),%eax"),
+        // 0f and 0f 3a
+        (&[0x66, 0x0f, 0x70, 0xd1, 0x1b], "pshufd $0x1b,%xmm1,%xmm2"),
+        // VEX
+        (&[0xc5, 0xec, 0x58, 0xd9], "vaddps %ymm1,%ymm2,%ymm3"),
+        (&[0xc4, 0x41, 0x31, 0xef, 0xd0], "vpxor %xmm8,%xmm9,%xmm10"),
+        (&[0xc4, 0xe3, 0xfd, 0x00, 0xd1, 0x4e], "vpermq $0x4e,%ymm1,%ymm2"),
+        (&[0xc4, 0xe2, 0x75, 0xb8, 0x50, 0x20], "vfmadd231ps 0x20(%rax),%ymm1,%ymm2"),
+        // EVEX
+        (&[0x62, 0xf1, 0x6c, 0x48, 0x58, 0xd9], "vaddps %zmm1,%zmm2,%zmm3"),
+        (&[0x62, 0xf1, 0xfe, 0x48, 0x6f, 0x64, 0x58, 0x01], "vmovdqu64 0x40(%rax,%rbx,2),%zmm4"),
+        (&[0x62, 0xf3, 0x6d, 0x48, 0x25, 0xd9, 0xff], "vpternlogd $0xff,%zmm1,%zmm2,%zmm3"),
+        // XOP
+        (&[0x8f, 0xe8, 0x78, 0xc2, 0xd1, 0x03], "vprotd $0x3,%xmm1,%xmm2"),
+        (&[0x8f, 0xe8, 0x60, 0xa2, 0xe2, 0x10], "vpcmov %xmm1,%xmm2,%xmm3,%xmm4"),
+        (&[0x8f, 0xea, 0x78, 0x10, 0xd8, 0x34, 0x12, 0x00, 0x00], "bextr $0x1234,%eax,%ebx"),
+        // misc
+        (&[0xf3, 0x0f, 0x1e, 0xfa], "endbr64"),
+        (&[0x0f, 0x0b], "ud2"),
+        (&[0x0f, 0x05], "syscall"),
+    ];
+
+    #[test]
+    fn test_lengths() {
+        for &(bytes, asm) in LENGTHS {
+            assert_eq!(decode_insn(&cells(bytes)).map(|(len, _)| len), Some(bytes.len()), "{}", asm);
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data: Vec<ReadCell<u8>> = [0x48, 0x8b, 0x05, 0x00].iter().map(|&b| ReadCell::new(b)).collect();
+        assert!(decode_insn(&data).is_none());
+        let data: Vec<ReadCell<u8>> = [0x66; 16].iter().map(|&b| ReadCell::new(b)).collect();
+        assert!(decode_insn(&data).is_none());
+    }
+
+    fn kind(bytes: &[u8]) -> InsnKind {
+        let mut handler = X86_64Handler::new();
+        handler.decode(VMA(0x401000), &cells(bytes)).1.kind
+    }
+
+    #[test]
+    fn test_indirect_branches() {
+        // jmp *0x402008(,%rdi,8)
+        match kind(&[0xff, 0x24, 0xfd, 0x08, 0x20, 0x40, 0x00]) {
+            InsnKind::TableBr(tb) => {
+                assert_eq!((tb.idx, tb.table, tb.size.bytes()), (RDI, VMA(0x402008), 8));
+            },
+            k => panic!("{:?}", k),
+        }
+        // jmp *0x2fe2(%rip)
+        match kind(&[0xff, 0x25, 0xe2, 0x2f, 0x00, 0x00]) { InsnKind::Tail => (), k => panic!("{:?}", k) }
+        // jmp *(%rax,%rdi,8)
+        match kind(&[0xff, 0x24, 0xf8]) { InsnKind::Tail => (), k => panic!("{:?}", k) }
+        // jmp *%rax
+        match kind(&[0xff, 0xe0]) { InsnKind::Br(RAX) => (), k => panic!("{:?}", k) }
+        // call *%rax, call *0x10(%rbx)
+        match kind(&[0xff, 0xd0]) { InsnKind::Call => (), k => panic!("{:?}", k) }
+        match kind(&[0xff, 0x53, 0x10]) { InsnKind::Call => (), k => panic!("{:?}", k) }
+    }
+}
@@ -0,0 +1,498 @@
+// Function boundaries and basic blocks, recovered from a CodeMap once go() is done.  Every root
+// (explicitly marked, or the target of a call) starts a function, which consists of whatever is
+// reachable from it without passing through another root.
+
+use super::{CodeMap, InsnIdx};
+use dis_generated_jump_dis::{GenericHandler, InsnKind, TargetAddr};
+use exec::{VMA, Exec, SymbolSource, SymbolValue};
+use exec::arch::{CodeMode, ARMMode, OtherMode};
+use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::fmt;
+use util;
+use util::json::Json;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Branch,
+    Cond,
+    Switch,
+    Call,
+    // a branch (conditional or not) or fallthrough into another function's entry
+    Tail,
+}
+
+impl EdgeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Branch => "branch",
+            EdgeKind::Cond => "cond",
+            EdgeKind::Switch => "switch",
+            EdgeKind::Call => "call",
+            EdgeKind::Tail => "tail",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Edge {
+    // the instruction the edge leaves from
+    pub from: VMA,
+    pub to: VMA,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    pub start: VMA,
+    // exclusive
+    pub end: VMA,
+    // calls made from within the block, in order, then wherever the last instruction goes;
+    // calls and tail edges lead out of the function
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub start: VMA,
+    pub mode: CodeMode,
+    // sorted by start
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl Function {
+    pub fn end(&self) -> VMA {
+        self.blocks.iter().map(|b| b.end).max().unwrap_or(self.start)
+    }
+    pub fn contains(&self, addr: VMA) -> bool {
+        self.blocks.iter().any(|b| b.start <= addr && addr < b.end)
+    }
+    pub fn to_json(&self, name: Option<&str>) -> Json {
+        let addr = |vma: VMA| Json::str(format!("{}", vma));
+        Json::Object(vec![
+            ("name".to_owned(), Json::opt(name, Json::str)),
+            ("start".to_owned(), addr(self.start)),
+            ("end".to_owned(), addr(self.end())),
+            ("thumb".to_owned(), match self.mode {
+                ARMMode { thumb } => Json::Bool(thumb),
+                OtherMode => Json::Null,
+            }),
+            ("blocks".to_owned(), Json::Array(self.blocks.iter().map(|b| Json::Object(vec![
+                ("start".to_owned(), addr(b.start)),
+                ("end".to_owned(), addr(b.end)),
+                ("edges".to_owned(), Json::Array(b.edges.iter().map(|e| Json::Object(vec![
+                    ("from".to_owned(), addr(e.from)),
+                    ("to".to_owned(), addr(e.to)),
+                    ("kind".to_owned(), Json::str(e.kind.name())),
+                ])).collect())),
+            ])).collect())),
+        ])
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Graphviz, with a cluster per function and a node per block, named by its address.  Edges are
+// written outside the clusters so that call and tail targets which aren't in `funcs` get plain
+// nodes of their own.
+pub fn write_dot(out: &mut dyn fmt::Write, funcs: &[(&Function, Option<&str>)]) -> fmt::Result {
+    writeln!(out, "digraph cfg {{")?;
+    writeln!(out, "    node [fontname=\"monospace\"];")?;
+    for &(func, name) in funcs {
+        let title = name.map_or_else(|| format!("{}", func.start), dot_escape);
+        writeln!(out, "    subgraph \"cluster_{}\" {{", func.start)?;
+        writeln!(out, "        label=\"{}\";", title)?;
+        for b in &func.blocks {
+            let entry = if b.start == func.start { format!("{}\\n", title) } else { String::new() };
+            writeln!(out, "        \"{}\" [shape=box label=\"{}{}-{}\"];", b.start, entry, b.start, b.end)?;
+        }
+        writeln!(out, "    }}")?;
+    }
+    for &(func, _) in funcs {
+        for b in &func.blocks {
+            for e in &b.edges {
+                let style = match e.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Branch => " [style=bold]",
+                    EdgeKind::Cond => " [style=dashed]",
+                    EdgeKind::Switch => " [style=dotted]",
+                    EdgeKind::Call => " [color=blue]",
+                    EdgeKind::Tail => " [color=red]",
+                };
+                writeln!(out, "    \"{}\" -> \"{}\"{};", b.start, e.to, style)?;
+            }
+        }
+    }
+    writeln!(out, "}}")
+}
+
+pub struct FunctionMap {
+    funcs: Vec<Function>,
+    // (start, end, index into funcs) for every block, sorted
+    blocks: Vec<(VMA, VMA, usize)>,
+}
+
+impl FunctionMap {
+    pub fn new(mut funcs: Vec<Function>) -> Self {
+        funcs.sort_by_key(|f| f.start);
+        funcs.dedup_by_key(|f| f.start);
+        let mut blocks = Vec::new();
+        for (i, f) in funcs.iter().enumerate() {
+            blocks.extend(f.blocks.iter().map(|b| (b.start, b.end, i)));
+        }
+        blocks.sort();
+        FunctionMap { funcs: funcs, blocks: blocks }
+    }
+    pub fn functions(&self) -> &[Function] {
+        &self.funcs
+    }
+    // the function starting at addr
+    pub fn at(&self, addr: VMA) -> Option<&Function> {
+        self.funcs.binary_search_by_key(&addr, |f| f.start).ok().map(|i| &self.funcs[i])
+    }
+    // a function containing addr; blocks shared between functions are attributed to the
+    // first one
+    pub fn lookup(&self, addr: VMA) -> Option<&Function> {
+        let i = self.blocks.binary_search_by(|b| if b.0 <= addr { Ordering::Less } else { Ordering::Greater })
+                           .unwrap_err();
+        if i == 0 { return None; }
+        let start = self.blocks[i - 1].0;
+        self.blocks[..i].iter().rev().take_while(|b| b.0 == start)
+                        .filter(|b| addr < b.1).last()
+                        .map(|b| &self.funcs[b.2])
+    }
+}
+
+impl<'a> CodeMap<'a> {
+    pub fn functions(&self, handler: &mut dyn GenericHandler) -> Vec<Function> {
+        let succ_map = self.succ_map();
+        let entries: Vec<InsnIdx> = (0..self.insn_state.len()).filter(|&idx| self.is_root(idx)).collect();
+        let mut out = Vec::new();
+        for &entry in &entries {
+            let mut blocks = Vec::new();
+            let mut seen = HashSet::new();
+            seen.insert(entry);
+            let mut todo = vec![entry];
+            while let Some(leader) = todo.pop() {
+                let mut edges = Vec::new();
+                let mut idx = leader;
+                loop {
+                    let (size, info) = self.decode(handler, idx);
+                    let addr = self.idx_to_addr(idx);
+                    let next_idx = idx + (size >> self.grain_shift);
+                    let target = if let TargetAddr::Code(target) = info.target_addr {
+                        Some(self.split_code_addr(target).0)
+                    } else { None };
+                    if let (InsnKind::Call, Some(target)) = (info.kind, target) {
+                        edges.push(Edge { from: addr, to: target, kind: EdgeKind::Call });
+                    }
+                    let succs = succ_map.get(&idx).map_or(&[][..], |succs| &succs[..]);
+                    let falls = next_idx < self.insn_state.len() &&
+                                self.insn_state[next_idx] == -((next_idx - idx) as i32);
+                    if falls && succs.is_empty() {
+                        idx = next_idx;
+                        continue;
+                    }
+                    // the branch target first, then any switch cases, then fallthrough
+                    let mut targets: Vec<(VMA, EdgeKind)> = Vec::new();
+                    match (info.kind, target) {
+                        (InsnKind::Call, _) | (_, None) => (),
+                        (InsnKind::Tail, Some(target)) => targets.push((target, EdgeKind::Branch)),
+                        (_, Some(target)) => targets.push((target, EdgeKind::Cond)),
+                    }
+                    let next = self.idx_to_addr(next_idx);
+                    for &succ in succs {
+                        let succ_addr = self.idx_to_addr(succ);
+                        if succ_addr != next && Some(succ_addr) != target {
+                            targets.push((succ_addr, EdgeKind::Switch));
+                        }
+                    }
+                    if falls || succs.contains(&next_idx) {
+                        targets.push((next, EdgeKind::Fallthrough));
+                    }
+                    for (to, kind) in targets {
+                        let succ = self.addr_to_idx(to);
+                        let internal = succ.map_or(false, |succ| !self.is_root(succ));
+                        let kind = if internal { kind } else { EdgeKind::Tail };
+                        if edges.iter().any(|e: &Edge| e.to == to && e.kind != EdgeKind::Call) { continue; }
+                        edges.push(Edge { from: addr, to: to, kind: kind });
+                        if internal && seen.insert(succ.unwrap()) {
+                            todo.push(succ.unwrap());
+                        }
+                    }
+                    blocks.push(BasicBlock {
+                        start: self.idx_to_addr(leader),
+                        end: addr + size as u64,
+                        edges: edges,
+                    });
+                    break;
+                }
+            }
+            blocks.sort_by_key(|b| b.start);
+            out.push(Function {
+                start: self.idx_to_addr(entry),
+                mode: if self.interworking { ARMMode { thumb: self.is_thumb(entry) } } else { OtherMode },
+                blocks: blocks,
+            });
+        }
+        out
+    }
+}
+
+// Functions that never return, by their C name; on Mach-O they have an extra leading underscore.
+pub const DEFAULT_NORETURN_NAMES: &[&str] = &[
+    "abort", "exit", "_exit", "_Exit", "__stack_chk_fail", "__assert_rtn", "__assert_fail",
+    "objc_exception_throw", "_Unwind_Resume", "__cxa_throw", "__cxa_rethrow", "longjmp",
+    "siglongjmp", "pthread_exit", "err", "errx",
+];
+
+fn name_matches(name: &[u8], names: &[&str]) -> bool {
+    // ignore any ELF version suffix
+    let name = match name.iter().position(|&c| c == b'@') { Some(i) => &name[..i], None => name };
+    names.iter().any(|n| name == n.as_bytes() || (name.starts_with(b"_") && &name[1..] == n.as_bytes()))
+}
+
+// Stubs for, and exported definitions of, functions with any of `names`.  Local symbols don't
+// count, since nothing stops a static function from being called err.
+pub fn noreturn_addrs_for_names(ex: &dyn Exec, names: &[&str]) -> Vec<VMA> {
+    let mut out: Vec<VMA> = ex.get_stub_list().into_iter()
+                              .filter(|&(name, _)| name_matches(name, names))
+                              .map(|(_, addr)| addr).collect();
+    for sym in ex.get_symbol_list(SymbolSource::Exported, None) {
+        if let SymbolValue::Addr(addr) = sym.val {
+            if name_matches(&sym.name, names) { out.push(addr); }
+        }
+    }
+    out
+}
+
+fn mapping_at(mapping: &[(VMA, u8)], addr: VMA) -> Option<u8> {
+    let i = mapping.binary_search_by(|m| if m.0 <= addr { Ordering::Less } else { Ordering::Greater })
+                   .unwrap_err();
+    if i == 0 { None } else { Some(mapping[i - 1].1) }
+}
+
+// Trawl every executable segment, seeding from the format's function starts and from symbols
+// in executable sections.  Calls to anything in `noreturn_names` (and to functions found to
+// only end by calling those) don't fall through.
+pub fn find_functions(ex: &dyn Exec, handler: &mut dyn GenericHandler, noreturn_names: &[&str]) -> FunctionMap {
+    let eb = ex.get_exec_base();
+    let interworking = handler.mode() != OtherMode;
+    let mut seeds = ex.get_function_starts();
+    let code_sects: Vec<_> = eb.sections.iter().filter(|sect| sect.prot.x).collect();
+    // ARM mapping symbols ($a, $t, $d, optionally with a suffix) mark where each kind of code,
+    // or data, starts
+    let mut mapping = Vec::new();
+    for sym in ex.get_symbol_list(SymbolSource::All, None) {
+        // a resolver with no separate stub is itself a function (e.g. an ELF IFUNC's)
+        let addr = match sym.val {
+            SymbolValue::Addr(addr) | SymbolValue::Resolver(addr, None) => addr,
+            _ => continue,
+        };
+        if sym.name.starts_with(b"$") {
+            if interworking && sym.name.len() >= 2 && (sym.name.len() == 2 || sym.name[2] == b'.') {
+                mapping.push((addr, sym.name[1]));
+            }
+            continue;
+        }
+        let plain = if interworking { addr & !1 } else { addr };
+        if code_sects.is_empty() ||
+           code_sects.iter().any(|sect| plain.wrapping_sub(sect.vmaddr) < sect.vmsize) {
+            seeds.push(addr);
+        }
+    }
+    if !mapping.is_empty() {
+        mapping.sort();
+        seeds.retain(|addr| addr.0 & 1 != 0 || mapping_at(&mapping, *addr) != Some(b'd'));
+        for addr in &mut seeds {
+            if mapping_at(&mapping, *addr) == Some(b't') { *addr = *addr | 1; }
+        }
+    }
+    seeds.sort();
+    seeds.dedup();
+    let noreturn = noreturn_addrs_for_names(ex, noreturn_names);
+    let mut funcs = Vec::new();
+    for seg in &eb.segments {
+        if !seg.prot.x { continue; }
+        let data = some_or!(seg.data.as_ref(), { continue; });
+        let mut codemap = CodeMap::new(seg.vmaddr, handler.grain_shift(), data.get(), eb.endian, &eb.segments);
+        for &addr in &seeds {
+            let (addr, thumb) = if interworking { (addr & !1, addr.0 & 1 != 0) } else { (addr, false) };
+            if let Some(idx) = codemap.addr_to_idx(addr) {
+                if interworking {
+                    codemap.mark_root_mode(idx, ARMMode { thumb: thumb });
+                } else {
+                    codemap.mark_root(idx);
+                }
+            }
+        }
+        for &addr in &noreturn {
+            codemap.mark_noreturn_addr(if interworking { addr & !1 } else { addr });
+        }
+        codemap.set_infer_noreturn(true);
+        codemap.go(handler, &mut |addr, size| eb.get_sane(addr, size).map(util::downgrade));
+        funcs.extend(codemap.functions(handler));
+    }
+    FunctionMap::new(funcs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dis_generated_jump_dis::X86_64Handler;
+    use exec::Segment;
+    use util::ReadCell;
+
+    const BASE: VMA = VMA(0x1000);
+
+    // x86-64, assembled with GNU as: f1 calls f2 and has a diamond, f2 tail calls f3
+    static CODE: &[u8] = &[
+        0x85, 0xff,                   // 1000: f1: test %edi,%edi
+        0x74, 0x07,                   // 1002: je 100b
+        0xe8, 0x07, 0x00, 0x00, 0x00, // 1004: call f2
+        0xeb, 0x01,                   // 1009: jmp 100c
+        0x90,                         // 100b: nop
+        0xc3,                         // 100c: ret
+        0xcc, 0xcc, 0xcc,             // 100d: padding
+        0xe9, 0x0b, 0x00, 0x00, 0x00, // 1010: f2: jmp f3
+        0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc, 0xcc,
+        0xc3,                         // 1020: f3: ret
+    ];
+
+    // trawl CODE from f1 and f3
+    fn functions() -> Vec<Function> {
+        let cells: Vec<ReadCell<u8>> = CODE.iter().map(|&b| ReadCell::new(b)).collect();
+        let segs = vec![Segment { vmaddr: BASE, vmsize: CODE.len() as u64, filesize: CODE.len() as u64, ..Default::default() }];
+        let mut handler = X86_64Handler::new();
+        let mut codemap = CodeMap::new(BASE, handler.grain_shift(), &cells, util::LittleEndian, &segs);
+        for &addr in &[0x1000, 0x1020] {
+            let idx = codemap.addr_to_idx(VMA(addr)).unwrap();
+            codemap.mark_root(idx);
+        }
+        codemap.go(&mut handler, &mut |addr, size| {
+            let off = (addr - BASE) as usize;
+            cells.get(off..off + size as usize)
+        });
+        codemap.functions(&mut handler)
+    }
+
+    fn edges(b: &BasicBlock) -> Vec<(u64, u64, EdgeKind)> {
+        b.edges.iter().map(|e| (e.from.0, e.to.0, e.kind)).collect()
+    }
+
+    #[test]
+    fn test_functions() {
+        use self::EdgeKind::*;
+        let funcs = functions();
+        // f2 is found through the call
+        assert_eq!(funcs.iter().map(|f| f.start.0).collect::<Vec<_>>(), vec![0x1000, 0x1010, 0x1020]);
+        let f1 = &funcs[0];
+        assert_eq!(f1.blocks.iter().map(|b| (b.start.0, b.end.0)).collect::<Vec<_>>(),
+                   vec![(0x1000, 0x1004), (0x1004, 0x100b), (0x100b, 0x100c), (0x100c, 0x100d)]);
+        assert_eq!(edges(&f1.blocks[0]), vec![(0x1002, 0x100b, Cond), (0x1002, 0x1004, Fallthrough)]);
+        assert_eq!(edges(&f1.blocks[1]), vec![(0x1004, 0x1010, Call), (0x1009, 0x100c, Branch)]);
+        assert_eq!(edges(&f1.blocks[2]), vec![(0x100b, 0x100c, Fallthrough)]);
+        assert_eq!(edges(&f1.blocks[3]), vec![]);
+        // the padding isn't part of it
+        assert_eq!(f1.end(), VMA(0x100d));
+        assert_eq!(edges(&funcs[1].blocks[0]), vec![(0x1010, 0x1020, Tail)]);
+        assert_eq!(funcs[1].end(), VMA(0x1015));
+        assert_eq!(funcs[2].end(), VMA(0x1021));
+    }
+
+    #[test]
+    fn test_function_map() {
+        let mut funcs = functions();
+        // a duplicate is dropped
+        let dup = funcs[2].clone();
+        funcs.push(dup);
+        let fm = FunctionMap::new(funcs);
+        assert_eq!(fm.functions().len(), 3);
+        let start = |f: Option<&Function>| f.map(|f| f.start.0);
+        assert_eq!(start(fm.at(VMA(0x1010))), Some(0x1010));
+        assert_eq!(start(fm.at(VMA(0x1004))), None);
+        for &(addr, want) in &[(0xfff, None), (0x1000, Some(0x1000)), (0x1005, Some(0x1000)),
+                               (0x100c, Some(0x1000)), (0x100d, None), (0x1014, Some(0x1010)),
+                               (0x1015, None), (0x1020, Some(0x1020)), (0x1021, None)] {
+            assert_eq!(start(fm.lookup(VMA(addr))), want, "{:#x}", addr);
+        }
+    }
+
+    fn edge(from: u64, to: u64, kind: EdgeKind) -> Edge {
+        Edge { from: VMA(from), to: VMA(to), kind: kind }
+    }
+
+    fn block(start: u64, end: u64, edges: Vec<Edge>) -> BasicBlock {
+        BasicBlock { start: VMA(start), end: VMA(end), edges: edges }
+    }
+
+    // every kind of edge: a call and a conditional branch, a switch, a branch, and a tail call
+    fn every_edge() -> Function {
+        use self::EdgeKind::*;
+        Function {
+            start: VMA(0x1000),
+            mode: ARMMode { thumb: true },
+            blocks: vec![
+                block(0x1000, 0x1008, vec![edge(0x1002, 0x2000, Call), edge(0x1006, 0x1010, Cond),
+                                           edge(0x1006, 0x1008, Fallthrough)]),
+                block(0x1008, 0x100c, vec![edge(0x1008, 0x1010, Switch), edge(0x1008, 0x1014, Switch)]),
+                block(0x1010, 0x1012, vec![edge(0x1010, 0x1014, Branch)]),
+                block(0x1014, 0x1018, vec![edge(0x1016, 0x3000, Tail)]),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_write_dot() {
+        let f = every_edge();
+        let leaf = Function { start: VMA(0x3000), mode: ARMMode { thumb: true }, blocks: vec![block(0x3000, 0x3002, vec![])] };
+        let mut out = String::new();
+        write_dot(&mut out, &[(&f, Some("say \"hi\"")), (&leaf, None)]).unwrap();
+        assert_eq!(out, "\
+digraph cfg {
+    node [fontname=\"monospace\"];
+    subgraph \"cluster_0x1000\" {
+        label=\"say \\\"hi\\\"\";
+        \"0x1000\" [shape=box label=\"say \\\"hi\\\"\\n0x1000-0x1008\"];
+        \"0x1008\" [shape=box label=\"0x1008-0x100c\"];
+        \"0x1010\" [shape=box label=\"0x1010-0x1012\"];
+        \"0x1014\" [shape=box label=\"0x1014-0x1018\"];
+    }
+    subgraph \"cluster_0x3000\" {
+        label=\"0x3000\";
+        \"0x3000\" [shape=box label=\"0x3000\\n0x3000-0x3002\"];
+    }
+    \"0x1000\" -> \"0x2000\" [color=blue];
+    \"0x1000\" -> \"0x1010\" [style=dashed];
+    \"0x1000\" -> \"0x1008\";
+    \"0x1008\" -> \"0x1010\" [style=dotted];
+    \"0x1008\" -> \"0x1014\" [style=dotted];
+    \"0x1010\" -> \"0x1014\" [style=bold];
+    \"0x1014\" -> \"0x3000\" [color=red];
+}
+");
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = every_edge().to_json(Some("f"));
+        let edge = |from: &str, to: &str, kind: &str| format!(r#"{{"from":"{}","to":"{}","kind":"{}"}}"#, from, to, kind);
+        let expected = format!(concat!(r#"{{"name":"f","start":"0x1000","end":"0x1018","thumb":true,"blocks":["#,
+                                       r#"{{"start":"0x1000","end":"0x1008","edges":[{},{},{}]}},"#,
+                                       r#"{{"start":"0x1008","end":"0x100c","edges":[{},{}]}},"#,
+                                       r#"{{"start":"0x1010","end":"0x1012","edges":[{}]}},"#,
+                                       r#"{{"start":"0x1014","end":"0x1018","edges":[{}]}}]}}"#),
+                               edge("0x1002", "0x2000", "call"), edge("0x1006", "0x1010", "cond"),
+                               edge("0x1006", "0x1008", "fallthrough"), edge("0x1008", "0x1010", "switch"),
+                               edge("0x1008", "0x1014", "switch"), edge("0x1010", "0x1014", "branch"),
+                               edge("0x1016", "0x3000", "tail"));
+        assert_eq!(format!("{}", json), expected);
+        let leaf = Function { start: VMA(0x3000), mode: OtherMode, blocks: vec![block(0x3000, 0x3001, vec![])] };
+        assert_eq!(format!("{}", leaf.to_json(None)),
+                   r#"{"name":null,"start":"0x3000","end":"0x3001","thumb":null,"blocks":[{"start":"0x3000","end":"0x3001","edges":[]}]}"#);
+    }
+}
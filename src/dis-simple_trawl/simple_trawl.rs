@@ -4,14 +4,17 @@ extern crate exec;
 #[macro_use]
 extern crate macros;
 
-use self::dis_generated_jump_dis::{Reg, GenericHandler, TargetAddr, InsnInfo, InsnKind, Addrish, Size8, CC};
+use self::dis_generated_jump_dis::{Reg, GenericHandler, TargetAddr, InsnInfo, InsnKind, Addrish, TableBr, Size8, CC};
 use std::collections::VecDeque;
 use self::exec::{VMA, Segment};
+use self::exec::arch::{CodeMode, ARMMode, OtherMode};
 use util::{Narrow, Endian, Unsigned, ReadCell, BitSet32, Fnv};
 use std::mem::replace;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
 
+pub mod functions;
+
 type InsnIdx = usize;
 
 struct InsnStateOther {
@@ -41,11 +44,17 @@ pub struct CodeMap<'a> {
     todo: VecDeque<InsnIdx>,
     switchlike_br_idxs: Vec<InsnIdx>,
     endian: Endian,
+    // one bit per insn, set if it's Thumb; only used if the handler has modes
+    thumb: Vec<u64>,
+    interworking: bool,
 
     segs: &'a [Segment],
     pub out_of_range_idxs: Vec<InsnIdx>,
 
-    noreturn_addrs: Vec<VMA>,
+    // explicitly marked roots and their modes, for starting over
+    roots: Vec<(InsnIdx, bool)>,
+    noreturn_addrs: HashSet<VMA, Fnv>,
+    infer_noreturn: bool,
 
     // key is at_or_before - can_be_at must be true
     value_info_cache: HashMap<ValueInfoKey, Option<ValueInfo>, Fnv>,
@@ -106,22 +115,139 @@ impl<'a> CodeMap<'a> {
             todo: VecDeque::new(),
             switchlike_br_idxs: Vec::new(),
             endian: endian,
+            thumb: util::zero_vec((num_insns + 63) / 64),
+            interworking: false,
             segs: segs,
             out_of_range_idxs: Vec::new(),
-            noreturn_addrs: Vec::new(),
+            roots: Vec::new(),
+            noreturn_addrs: util::new_fnv_hashset(),
+            infer_noreturn: false,
             value_info_cache: util::new_fnv_hashmap(),
         }
     }
     pub fn go<'x>(&mut self, handler: &mut dyn GenericHandler, read: &'x mut dyn FnMut(VMA, u64) -> Option<&'x [ReadCell<u8>]>) {
-        while !self.todo.is_empty() {
-            self.go_round(handler);
-            let idxs = replace(&mut self.switchlike_br_idxs, Vec::new());
-            //println!("switch idxs = {:?}", idxs);
-            for idx in idxs {
-                self.grok_switch(handler, idx, read).unwrap(); // xxx
-                self.value_info_cache.clear();
+        self.interworking = handler.mode() != OtherMode;
+        handler.reset();
+        loop {
+            while !self.todo.is_empty() {
+                self.go_round(handler);
+                let idxs = replace(&mut self.switchlike_br_idxs, Vec::new());
+                //println!("switch idxs = {:?}", idxs);
+                for idx in idxs {
+                    if let Err(e) = self.grok_switch(handler, idx, read) {
+                        errln!("warning: couldn't make sense of switch-like branch at {}: {:?}", self.idx_to_addr(idx), e);
+                    }
+                    self.value_info_cache.clear();
+                }
+            }
+            if !self.infer_noreturn { break; }
+            let found = self.find_noreturn_roots(handler);
+            if found.is_empty() { break; }
+            // whatever we found after calls to them is suspect, so start over
+            self.noreturn_addrs.extend(found);
+            self.restart();
+        }
+    }
+    // Every root (including call targets) is taken to be a function; it's noreturn if every path
+    // through it ends in a call or branch to something noreturn, or loops forever.
+    fn find_noreturn_roots(&self, handler: &mut dyn GenericHandler) -> Vec<VMA> {
+        let succ_map = self.succ_map();
+        // (entry, tail targets) for everything that can't return by itself
+        let mut candidates = Vec::new();
+        'entries: for (entry, &state) in self.insn_state.iter().enumerate() {
+            if state <= 0 || !self.insn_state_other[state as usize].is_root { continue; }
+            let entry_addr = self.idx_to_addr(entry);
+            if self.noreturn_addrs.contains(&entry_addr) { continue; }
+            let mut tails = Vec::new();
+            let mut seen = HashSet::new();
+            seen.insert(entry);
+            let mut todo = vec![entry];
+            while let Some(mut idx) = todo.pop() {
+                loop {
+                    let (size, info) = self.decode(handler, idx);
+                    let next_idx = idx + (size >> self.grain_shift);
+                    let target = if let TargetAddr::Code(target) = info.target_addr {
+                        Some(self.split_code_addr(target).0)
+                    } else { None };
+                    let succs = succ_map.get(&idx).map_or(&[][..], |succs| &succs[..]);
+                    for &succ in succs {
+                        if self.is_root(succ) {
+                            tails.push(self.idx_to_addr(succ));
+                        } else if seen.insert(succ) {
+                            todo.push(succ);
+                        }
+                    }
+                    if next_idx < self.insn_state.len() && next_idx != idx &&
+                       self.insn_state[next_idx] == -((next_idx - idx) as i32) {
+                        idx = next_idx;
+                        continue;
+                    }
+                    match info.kind {
+                        InsnKind::Tail => match target {
+                            Some(target) => if self.addr_to_idx(target).is_none() { tails.push(target); },
+                            None => continue 'entries,
+                        },
+                        InsnKind::Br(_) | InsnKind::TableBr(_) | InsnKind::Unidentified =>
+                            if succs.is_empty() { continue 'entries; },
+                        // it only stops without flowing on if the target is noreturn or we ran
+                        // off the end
+                        _ => {
+                            let to_noreturn = match target {
+                                Some(target) => self.noreturn_addrs.contains(&target),
+                                None => false,
+                            };
+                            if succs.is_empty() && !to_noreturn { continue 'entries; }
+                        },
+                    }
+                    break;
+                }
+            }
+            candidates.push((entry_addr, tails));
+        }
+        // a candidate returns if any of its tail targets might, including other candidates that
+        // do; what's left (even if they only branch to each other) can't
+        let mut found: HashSet<VMA, Fnv> = candidates.iter().map(|c| c.0).collect();
+        loop {
+            let before = found.len();
+            for &(entry, ref tails) in &candidates {
+                if found.contains(&entry) &&
+                   !tails.iter().all(|t| self.noreturn_addrs.contains(t) || found.contains(t)) {
+                    found.remove(&entry);
+                }
+            }
+            if found.len() == before { break; }
+        }
+        found.into_iter().collect()
+    }
+    fn restart(&mut self) {
+        for state in &mut self.insn_state { *state = 0; }
+        self.insn_state_other.truncate(1);
+        for word in &mut self.thumb { *word = 0; }
+        self.out_of_range_idxs.clear();
+        self.value_info_cache.clear();
+        let mut roots = replace(&mut self.roots, Vec::new());
+        roots.sort();
+        roots.dedup();
+        for (idx, thumb) in roots {
+            self.set_thumb(idx, thumb);
+            self.mark_root(idx);
+        }
+    }
+    // branch edges by source (flows_from, inverted); plain fallthrough into an insn nothing else
+    // flows to isn't included
+    fn succ_map(&self) -> HashMap<InsnIdx, Vec<InsnIdx>> {
+        let mut succ_map: HashMap<InsnIdx, Vec<InsnIdx>> = HashMap::new();
+        for (idx, &state) in self.insn_state.iter().enumerate() {
+            if state <= 0 { continue; }
+            for &from in &self.insn_state_other[state as usize].flows_from {
+                succ_map.entry(from).or_insert_with(Vec::new).push(idx);
             }
         }
+        succ_map
+    }
+    fn is_root(&self, idx: InsnIdx) -> bool {
+        let state = self.insn_state[idx];
+        state > 0 && self.insn_state_other[state as usize].is_root
     }
     pub fn addr_to_idx(&self, addr: VMA) -> Option<InsnIdx> {
         let offset = addr.wrapping_sub(self.region_start);
@@ -137,9 +263,23 @@ impl<'a> CodeMap<'a> {
     pub fn idx_to_addr(&self, addr: InsnIdx) -> VMA {
         self.region_start + (addr << self.grain_shift) as u64
     }
+    #[inline]
+    fn is_thumb(&self, idx: InsnIdx) -> bool {
+        self.thumb[idx / 64] & (1 << (idx % 64)) != 0
+    }
+    fn set_thumb(&mut self, idx: InsnIdx, thumb: bool) {
+        let bit = 1 << (idx % 64);
+        if thumb { self.thumb[idx / 64] |= bit; } else { self.thumb[idx / 64] &= !bit; }
+    }
+    // code addresses from an interworking handler have the low bit set for Thumb
+    fn split_code_addr(&self, addr: VMA) -> (VMA, bool) {
+        if self.interworking { (addr & !1, addr.0 & 1 != 0) } else { (addr, false) }
+    }
     // returns whether we should proceed
-    fn mark_flow(&mut self, from: InsnIdx, to: InsnIdx, is_flow: bool) -> bool {
+    // (the first flow into an insn decides its mode)
+    fn mark_flow(&mut self, from: InsnIdx, to: InsnIdx, is_flow: bool, thumb: bool) -> bool {
         //print!("mark_flow from {}/{} to {}/{} ", from, self.idx_to_addr(from), to, self.idx_to_addr(to));
+        if self.insn_state[to] == 0 { self.set_thumb(to, thumb); }
         let state = &mut self.insn_state[to];
         let old = *state;
         //println!("is_flow={} old={:x}", is_flow, old);
@@ -189,11 +329,18 @@ impl<'a> CodeMap<'a> {
         }
         &mut self.insn_state_other[val as usize]
     }
+    // Look for functions that only end by calling noreturn ones (starting with those marked),
+    // and don't trawl past calls to them.
+    pub fn set_infer_noreturn(&mut self, infer: bool) {
+        self.infer_noreturn = infer;
+    }
     pub fn mark_noreturn_addr(&mut self, addr: VMA) {
-        self.noreturn_addrs.push(addr);
+        self.noreturn_addrs.insert(addr);
     }
     pub fn mark_root(&mut self, idx: InsnIdx) {
         // this should only happen at the beginning
+        let thumb = self.is_thumb(idx);
+        self.roots.push((idx, thumb));
         {
         let state = self.get_or_make_insn_state_other(idx);
         if state.is_root { return; }
@@ -202,6 +349,21 @@ impl<'a> CodeMap<'a> {
         self.todo.push_back(idx);
         //println!("mark_root {}/{}", idx, self.idx_to_addr(idx));
     }
+    pub fn mark_root_mode(&mut self, idx: InsnIdx, mode: CodeMode) {
+        self.set_thumb(idx, mode == ARMMode { thumb: true });
+        self.mark_root(idx);
+    }
+    // call targets are function entries rather than part of the caller's flow
+    fn mark_call_target(&mut self, idx: InsnIdx, thumb: bool) {
+        let unseen = self.insn_state[idx] == 0;
+        if unseen { self.set_thumb(idx, thumb); }
+        {
+        let state = self.get_or_make_insn_state_other(idx);
+        if state.is_root { return; }
+        state.is_root = true;
+        }
+        if unseen { self.todo.push_back(idx); }
+    }
     fn go_round(&mut self, handler: &mut dyn GenericHandler) {
         let grain_shift = self.grain_shift;
         let segs = self.segs;
@@ -212,23 +374,25 @@ impl<'a> CodeMap<'a> {
                 let (size, info) = self.decode(handler, idx);
                 //println!("go_round: {}/{} => {:?} size={}", idx, self.idx_to_addr(idx), info, size);
                 let next_idx = idx + (size >> grain_shift);
+                let thumb = self.is_thumb(idx);
                 if let TargetAddr::Code(addr) = info.target_addr {
+                    let (addr, target_thumb) = self.split_code_addr(addr);
                     if let Some(target_idx) = self.addr_to_idx(addr) {
-                        self.mark_flow(idx, target_idx, false);
-                    }
-                }
-                match info.target_addr {
-                    TargetAddr::Code(addr) => {
-                        if self.noreturn_addrs.contains(&addr) {
-                            break;
-                        }
-                        if !segs.iter().any(|seg| addr.wrapping_sub(seg.vmaddr) < seg.vmsize) {
-                            self.out_of_range_idxs.push(idx);
+                        if let InsnKind::Call = info.kind {
+                            self.mark_call_target(target_idx, target_thumb);
+                        } else {
+                            self.mark_flow(idx, target_idx, false, target_thumb);
                         }
-                    },
-                    _ => ()
+                    }
+                    // (a conditional branch to one still falls through)
+                    if let InsnKind::Call = info.kind {
+                        if self.noreturn_addrs.contains(&addr) { break; }
+                    }
+                    if !segs.iter().any(|seg| addr.wrapping_sub(seg.vmaddr) < seg.vmsize) {
+                        self.out_of_range_idxs.push(idx);
+                    }
                 }
-                let should_cont = match info.kind {
+                let falls_through = match info.kind {
                     InsnKind::Tail => false,
                     InsnKind::Unidentified => {
                         errln!("go_round: stopping at unidentified instruction at {}", self.idx_to_addr(idx));
@@ -240,14 +404,15 @@ impl<'a> CodeMap<'a> {
                         }
                         false
                     },
-                    _ => {
-                        if next_idx != self.insn_state.len() {
-                            self.mark_flow(idx, next_idx, true)
-                        } else {
-                            false
-                        }
+                    InsnKind::TableBr(tb) => {
+                        self.switchlike_br_idxs.push(idx);
+                        tb.default_cc.is_some()
                     },
+                    _ => true,
                 };
+                let should_cont = falls_through &&
+                                  next_idx != self.insn_state.len() &&
+                                  self.mark_flow(idx, next_idx, true, thumb);
                 if !should_cont { break; }
                 match info.kind {
                     InsnKind::Set(target, Addrish::AddReg(..)) => {
@@ -267,6 +432,9 @@ impl<'a> CodeMap<'a> {
         let offset = idx << self.grain_shift;
         let data = &self.insn_data[offset..];
         let addr = self.region_start + (offset as u64);
+        if self.interworking {
+            handler.set_mode(ARMMode { thumb: self.is_thumb(idx) });
+        }
         let (size, info) = handler.decode(addr, data);
         (size, *info)
     }
@@ -274,7 +442,9 @@ impl<'a> CodeMap<'a> {
     fn grok_switch<'x>(&mut self, handler: &mut dyn GenericHandler, br_idx: InsnIdx, read: &mut dyn FnMut(VMA, u64) -> Option<&'x [ReadCell<u8>]>) -> Result<(), GrokSwitchFail> {
         //println!("grok_switch: {}", self.idx_to_addr(br_idx));
         let br_reg = match self.decode(handler, br_idx).1.kind {
-            InsnKind::Br(r) => r, _ => panic!(),
+            InsnKind::Br(r) => r,
+            InsnKind::TableBr(tb) => return self.grok_table_br(handler, br_idx, tb, read),
+            _ => panic!(),
         };
         let addr_setter_vi = self.value_info(handler, br_idx, br_reg).map_err(GrokSwitchFail::GettingBrAddrValueInfo)?;
         let addr_setter_idx = addr_setter_vi.setter_idx.ok_or(GrokSwitchFail::GettingBrAddrSetter)?;
@@ -317,54 +487,7 @@ impl<'a> CodeMap<'a> {
         let table_addr = VMA(table_addr_vi.value.ok_or(GrokSwitchFail::GettingTableAddrValue)?);
 
         // one more thing: find the cmp to establish table size
-        let mut table_len: u64 = 0;
-        {
-            let mut idx = load_idx;
-            let mut i = 0;
-            let mut cmp_reg = Reg::invalid();
-            let mut table_idx_regs = BitSet32::empty();
-            let mut cc: Option<CC> = None;
-            table_idx_regs.add(table_idx_reg.0 as u8);
-            loop {
-                i += 1;
-                if i >= 20 {
-                    return Err(GrokSwitchFail::CmpTooFar);
-                }
-                idx = some_or!(self.sole_pred(idx), {
-                    return Err(GrokSwitchFail::CmpNoSolePred);
-                });
-                let (_, info) = self.decode(handler, idx);
-                //println!("ti={} => {:?}", table_idx_regs, info.kind);
-                match info.kind {
-                    InsnKind::Bcc(cc_) if cc.is_none() => cc = Some(cc_),
-                    InsnKind::CmpImm(r, u) if cc.is_some() && cmp_reg == Reg::invalid() => {
-                        cmp_reg = r;
-                        table_len = match cc.unwrap() {
-                            CC::Geu | CC::Ltu => u,
-                            CC::Gtu | CC::Leu => u + 1,
-                            CC::Other => return Err(GrokSwitchFail::UnknownCC),
-                        };
-                    },
-                    InsnKind::Set(dst, Addrish::AddImm(src, 0))
-                        if table_idx_regs.has(dst.0 as u8) =>
-                        table_idx_regs.add(src.0 as u8),
-                    _ => {
-                        for &reg in &info.kills_reg {
-                            if reg != Reg::invalid() {
-                                table_idx_regs.remove(reg.0 as u8);
-                            }
-                        }
-                    },
-                }
-                if cmp_reg != Reg::invalid() && table_idx_regs.has(cmp_reg.0 as u8) {
-                    // OK, it's reasonable
-                    break;
-                }
-            }
-        }
-        if table_len > 100000 {
-            return Err(GrokSwitchFail::TableTooBig);
-        }
+        let table_len = self.find_table_len(handler, load_idx, table_idx_reg, None)?;
 
         // ok!
         //println!("table_addr={} table_abase={} table_len={} item size={:?} sign={:?}", table_addr, table_abase, table_len, table_item_size, table_item_signedness);
@@ -376,9 +499,9 @@ impl<'a> CodeMap<'a> {
         });
         for (i, chunk) in table.chunks(bytes).enumerate() {
             let val = exec::dynsized_integer_from_slice(chunk, table_item_signedness, self.endian);
-            let addr = table_abase.wrapping_add(val);
+            let (addr, thumb) = self.split_code_addr(table_abase.wrapping_add(val));
             if let Some(target_idx) = self.addr_to_idx(addr) {
-                self.mark_flow(br_idx, target_idx, false);
+                self.mark_flow(br_idx, target_idx, false, thumb);
             } else {
                 errln!("grok_switch: out-of-range(?) switch branch to {} from table {} element #{}", addr, table_addr, i);
             }
@@ -386,6 +509,80 @@ impl<'a> CodeMap<'a> {
         Ok(())
     }
 
+    fn grok_table_br<'x>(&mut self, handler: &mut dyn GenericHandler, br_idx: InsnIdx, tb: TableBr, read: &mut dyn FnMut(VMA, u64) -> Option<&'x [ReadCell<u8>]>) -> Result<(), GrokSwitchFail> {
+        let table_len = self.find_table_len(handler, br_idx, tb.idx, tb.default_cc)?;
+        let bytes = tb.size.bytes() as usize;
+        let table: &[ReadCell<u8>] = some_or!(read(tb.table, table_len * bytes as u64), {
+            return Err(GrokSwitchFail::TableReadError);
+        });
+        let br_thumb = self.is_thumb(br_idx);
+        for (i, chunk) in table.chunks(bytes).enumerate() {
+            let val = exec::dynsized_integer_from_slice(chunk, Unsigned, self.endian);
+            let (addr, thumb) = if tb.halfword_offsets {
+                (tb.table.wrapping_add(2 * val), br_thumb)
+            } else {
+                self.split_code_addr(VMA(val))
+            };
+            if let Some(target_idx) = self.addr_to_idx(addr) {
+                self.mark_flow(br_idx, target_idx, false, thumb);
+            } else {
+                errln!("grok_table_br: out-of-range(?) switch branch to {} from table {} element #{}", addr, tb.table, i);
+            }
+        }
+        Ok(())
+    }
+
+    // Walk back from from_idx to the bounds check on idx_reg.  cc is the condition of the
+    // branch that skips the table, if we already know it.
+    fn find_table_len(&mut self, handler: &mut dyn GenericHandler, from_idx: InsnIdx, idx_reg: Reg, mut cc: Option<CC>) -> Result<u64, GrokSwitchFail> {
+        let mut table_len: u64 = 0;
+        let mut idx = from_idx;
+        let mut i = 0;
+        let mut cmp_reg = Reg::invalid();
+        let mut table_idx_regs = BitSet32::empty();
+        table_idx_regs.add(idx_reg.0 as u8);
+        loop {
+            i += 1;
+            if i >= 20 {
+                return Err(GrokSwitchFail::CmpTooFar);
+            }
+            idx = some_or!(self.sole_pred(idx), {
+                return Err(GrokSwitchFail::CmpNoSolePred);
+            });
+            let (_, info) = self.decode(handler, idx);
+            //println!("ti={} => {:?}", table_idx_regs, info.kind);
+            match info.kind {
+                InsnKind::Bcc(cc_) if cc.is_none() => cc = Some(cc_),
+                InsnKind::CmpImm(r, u) if cc.is_some() && cmp_reg == Reg::invalid() => {
+                    cmp_reg = r;
+                    table_len = match cc.unwrap() {
+                        CC::Geu | CC::Ltu => u,
+                        CC::Gtu | CC::Leu => u + 1,
+                        CC::Other => return Err(GrokSwitchFail::UnknownCC),
+                    };
+                },
+                InsnKind::Set(dst, Addrish::AddImm(src, 0))
+                    if table_idx_regs.has(dst.0 as u8) =>
+                    table_idx_regs.add(src.0 as u8),
+                _ => {
+                    for &reg in &info.kills_reg {
+                        if reg != Reg::invalid() {
+                            table_idx_regs.remove(reg.0 as u8);
+                        }
+                    }
+                },
+            }
+            if cmp_reg != Reg::invalid() && table_idx_regs.has(cmp_reg.0 as u8) {
+                // OK, it's reasonable
+                break;
+            }
+        }
+        if table_len > 100000 {
+            return Err(GrokSwitchFail::TableTooBig);
+        }
+        Ok(table_len)
+    }
+
     #[inline]
     fn value_info(&mut self, handler: &mut dyn GenericHandler, before_idx: InsnIdx, reg: Reg) -> Result<ValueInfo, ValueInfoFail> {
         //println!("value_info({:?} before {})", reg, self.idx_to_addr(before_idx));
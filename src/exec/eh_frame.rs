@@ -0,0 +1,281 @@
+// Just enough of .eh_frame to find where each FDE's range starts, and of .eh_frame_hdr to find
+// .eh_frame.
+
+use VMA;
+use util;
+use util::{ReadCell, Endian, SignExtend, SliceExt};
+use std::collections::HashMap;
+use {read_leb128_inner, ByteSliceIterator};
+
+pub const DW_EH_PE_absptr: u8 = 0x00;
+pub const DW_EH_PE_uleb128: u8 = 0x01;
+pub const DW_EH_PE_udata2: u8 = 0x02;
+pub const DW_EH_PE_udata4: u8 = 0x03;
+pub const DW_EH_PE_udata8: u8 = 0x04;
+pub const DW_EH_PE_sleb128: u8 = 0x09;
+pub const DW_EH_PE_sdata2: u8 = 0x0a;
+pub const DW_EH_PE_sdata4: u8 = 0x0b;
+pub const DW_EH_PE_sdata8: u8 = 0x0c;
+pub const DW_EH_PE_pcrel: u8 = 0x10;
+pub const DW_EH_PE_textrel: u8 = 0x20;
+pub const DW_EH_PE_datarel: u8 = 0x30;
+pub const DW_EH_PE_funcrel: u8 = 0x40;
+pub const DW_EH_PE_aligned: u8 = 0x50;
+pub const DW_EH_PE_indirect: u8 = 0x80;
+pub const DW_EH_PE_omit: u8 = 0xff;
+
+struct Reader<'a> {
+    data: &'a [ReadCell<u8>],
+    pos: usize,
+    endian: Endian,
+    pointer_size: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [ReadCell<u8>]> {
+        let res = self.data.slice_opt(self.pos, self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(res)
+    }
+    fn u8(&mut self) -> Option<u8> { self.take(1).map(|s| s[0].get()) }
+    fn u16(&mut self) -> Option<u16> { self.take(2).map(|s| util::copy_from_slice(s, self.endian)) }
+    fn u32(&mut self) -> Option<u32> { self.take(4).map(|s| util::copy_from_slice(s, self.endian)) }
+    fn u64(&mut self) -> Option<u64> { self.take(8).map(|s| util::copy_from_slice(s, self.endian)) }
+    fn leb(&mut self, signed: bool) -> Option<u64> {
+        let mut rest = &self.data[self.pos..];
+        let before = rest.len();
+        let res = read_leb128_inner(&mut ByteSliceIterator(&mut rest), signed);
+        self.pos += before - rest.len();
+        res.map(|(num, _)| num)
+    }
+    fn skip_cstr(&mut self) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        loop {
+            match self.u8()? {
+                0 => return Some(out),
+                c => out.push(c),
+            }
+        }
+    }
+    // None if the encoding is unsupported (or we ran off the end); `sect_addr` is the
+    // address of data[0]
+    fn encoded(&mut self, enc: u8, sect_addr: VMA) -> Option<VMA> {
+        let field_addr = sect_addr.wrapping_add(self.pos as u64);
+        let mut val = match enc & 0xf {
+            DW_EH_PE_absptr => match self.pointer_size {
+                8 => self.u64()?,
+                _ => self.u32()? as u64,
+            },
+            DW_EH_PE_uleb128 => self.leb(false)?,
+            DW_EH_PE_udata2 => self.u16()? as u64,
+            DW_EH_PE_udata4 => self.u32()? as u64,
+            DW_EH_PE_udata8 | DW_EH_PE_sdata8 => self.u64()?,
+            DW_EH_PE_sleb128 => self.leb(true)?,
+            DW_EH_PE_sdata2 => self.u16()?.sign_extend(16),
+            DW_EH_PE_sdata4 => self.u32()?.sign_extend(32),
+            _ => return None,
+        };
+        match enc & 0x70 {
+            DW_EH_PE_absptr => (),
+            DW_EH_PE_pcrel => val = field_addr.0.wrapping_add(val),
+            _ => return None,
+        }
+        if enc & DW_EH_PE_indirect != 0 { return None; }
+        if self.pointer_size == 4 { val &= 0xffff_ffff; }
+        Some(VMA(val))
+    }
+}
+
+// Returns the FDE pointer encoding.
+fn parse_cie(r: &mut Reader, sect_addr: VMA) -> Option<u8> {
+    let version = r.u8()?;
+    let aug = r.skip_cstr()?;
+    let mut aug: &[u8] = &aug;
+    if aug.starts_with(b"eh") {
+        r.take(r.pointer_size)?;
+        aug = &aug[2..];
+    }
+    let _code_align = r.leb(false)?;
+    let _data_align = r.leb(true)?;
+    let _ra_reg = if version == 1 { r.u8()? as u64 } else { r.leb(false)? };
+    let mut enc = DW_EH_PE_absptr;
+    if aug.is_empty() { return Some(enc); }
+    if aug[0] != b'z' { return None; }
+    let _aug_len = r.leb(false)?;
+    for &c in &aug[1..] {
+        match c {
+            b'R' => enc = r.u8()?,
+            b'P' => {
+                // the personality routine is often indirect, so don't care whether we can
+                // resolve it, only how long it is
+                let penc = r.u8()?;
+                r.encoded(penc & !(DW_EH_PE_indirect | 0x70), sect_addr)?;
+            },
+            b'L' => { r.u8()?; },
+            b'S' | b'B' => (),
+            // can't tell how long its data is, but 'R' is conventionally first
+            _ => break,
+        }
+    }
+    Some(enc)
+}
+
+// The initial location of every FDE in `data`, which is .eh_frame as mapped at `sect_addr`.
+pub fn fde_starts(data: &[ReadCell<u8>], sect_addr: VMA, pointer_size: usize, endian: Endian) -> Vec<VMA> {
+    let mut out = Vec::new();
+    let mut cies: HashMap<usize, Option<u8>> = HashMap::new();
+    let mut r = Reader { data: data, pos: 0, endian: endian, pointer_size: pointer_size };
+    while r.pos < data.len() {
+        let mut len = some_or!(r.u32(), break) as u64;
+        if len == 0 { break; }
+        let is_64 = len == 0xffff_ffff;
+        if is_64 { len = some_or!(r.u64(), break); }
+        let id_pos = r.pos;
+        let end = some_or!((r.pos as u64).checked_add(len).filter(|&end| end <= data.len() as u64), {
+            errln!("fde_starts: entry at 0x{:x} runs off end", id_pos);
+            break;
+        }) as usize;
+        // unlike .debug_frame, the CIE id/pointer stays 4 bytes with a 64-bit length
+        let id = some_or!(r.u32(), break) as u64;
+        if id != 0 {
+            let cie_pos = some_or!((id_pos as u64).checked_sub(id), {
+                errln!("fde_starts: bad CIE pointer at 0x{:x}", id_pos);
+                r.pos = end;
+                continue;
+            }) as usize;
+            let enc = *cies.entry(cie_pos).or_insert_with(|| {
+                let mut cr = Reader { data: data, pos: cie_pos, endian: endian, pointer_size: pointer_size };
+                // skip the length and id
+                let len = cr.u32()?;
+                cr.take(if len == 0xffff_ffff { 12 } else { 4 })?;
+                parse_cie(&mut cr, sect_addr)
+            });
+            if let Some(enc) = enc {
+                if let Some(start) = r.encoded(enc, sect_addr) {
+                    out.push(start);
+                }
+            }
+        }
+        r.pos = end;
+    }
+    out
+}
+
+// Where .eh_frame is, from the eh_frame_ptr in `data`, which is .eh_frame_hdr as mapped at
+// `sect_addr`.
+pub fn eh_frame_hdr_target(data: &[ReadCell<u8>], sect_addr: VMA, pointer_size: usize, endian: Endian) -> Option<VMA> {
+    let mut r = Reader { data: data, pos: 0, endian: endian, pointer_size: pointer_size };
+    if r.u8()? != 1 { return None; }
+    let enc = r.u8()?;
+    // fde_count_enc, table_enc
+    r.take(2)?;
+    r.encoded(enc, sect_addr)
+}
+
+// The length of the CIEs and FDEs at the start of `data`, including the zero terminator if there
+// is one.
+pub fn eh_frame_len(data: &[ReadCell<u8>], endian: Endian) -> usize {
+    let mut r = Reader { data: data, pos: 0, endian: endian, pointer_size: 8 };
+    loop {
+        let start = r.pos;
+        let mut len = some_or!(r.u32(), return start) as u64;
+        if len == 0 { return r.pos; }
+        if len == 0xffff_ffff { len = some_or!(r.u64(), return start); }
+        let end = some_or!((r.pos as u64).checked_add(len).filter(|&end| end <= data.len() as u64), return start);
+        r.pos = end as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::LittleEndian;
+
+    const SECT_ADDR: u64 = 0x1000;
+
+    fn cells(bytes: &[u8]) -> Vec<ReadCell<u8>> {
+        bytes.iter().map(|&b| ReadCell::new(b)).collect()
+    }
+
+    // Append a CIE, or an FDE pointing back at `cie`; `body` gets the address its first byte will
+    // be at.  Returns where the entry starts.
+    fn entry<F: FnOnce(u64) -> Vec<u8>>(out: &mut Vec<u8>, dwarf64: bool, cie: Option<usize>, body: F) -> usize {
+        let start = out.len();
+        let len_pos = if dwarf64 { 12 } else { 4 };
+        let body = body(SECT_ADDR + (start + len_pos + 4) as u64);
+        if dwarf64 {
+            out.extend_from_slice(&0xffff_ffffu32.to_le_bytes());
+            out.extend_from_slice(&(4 + body.len() as u64).to_le_bytes());
+        } else {
+            out.extend_from_slice(&(4 + body.len() as u32).to_le_bytes());
+        }
+        let id = cie.map_or(0, |cie| out.len() - cie);
+        out.extend_from_slice(&(id as u32).to_le_bytes());
+        out.extend_from_slice(&body);
+        start
+    }
+
+    // version 1, "zR" with DW_EH_PE_pcrel | DW_EH_PE_sdata4, code/data align 1/-8, ra 16
+    fn cie_pcrel_sdata4(_: u64) -> Vec<u8> {
+        vec![1, b'z', b'R', 0, 1, 0x78, 16, 1, DW_EH_PE_pcrel | DW_EH_PE_sdata4]
+    }
+
+    // pc_begin, pc_range, and an empty augmentation
+    fn fde_pcrel_sdata4(target: u64) -> impl FnOnce(u64) -> Vec<u8> {
+        move |addr| {
+            let mut body = Vec::new();
+            body.extend_from_slice(&(target.wrapping_sub(addr) as u32).to_le_bytes());
+            body.extend_from_slice(&0x20u32.to_le_bytes());
+            body.push(0);
+            body
+        }
+    }
+
+    // returns the data, where the last FDE starts, and the length through the terminator
+    fn sample() -> (Vec<u8>, usize, usize) {
+        let mut out = Vec::new();
+        let a = entry(&mut out, false, None, cie_pcrel_sdata4);
+        entry(&mut out, false, Some(a), fde_pcrel_sdata4(0x1400));
+        entry(&mut out, false, Some(a), fde_pcrel_sdata4(0x800));
+        // no augmentation, so absolute pointers
+        let b = entry(&mut out, false, None, |_| vec![1, 0, 1, 0x78, 16]);
+        entry(&mut out, false, Some(b), |_| {
+            let mut body = 0x40_1000u64.to_le_bytes().to_vec();
+            body.extend_from_slice(&0x10u64.to_le_bytes());
+            body
+        });
+        let c = entry(&mut out, true, None, cie_pcrel_sdata4);
+        let last = entry(&mut out, true, Some(c), fde_pcrel_sdata4(0x1800));
+        out.extend_from_slice(&[0; 4]);
+        let end = out.len();
+        // anything after the terminator isn't part of it
+        out.extend_from_slice(&[0xff; 8]);
+        (out, last, end)
+    }
+
+    #[test]
+    fn test_fde_starts() {
+        let (data, _, _) = sample();
+        let starts = fde_starts(&cells(&data), VMA(SECT_ADDR), 8, LittleEndian);
+        assert_eq!(starts, vec![VMA(0x1400), VMA(0x800), VMA(0x40_1000), VMA(0x1800)]);
+    }
+
+    #[test]
+    fn test_eh_frame_len() {
+        let (data, last, end) = sample();
+        assert_eq!(eh_frame_len(&cells(&data), LittleEndian), end);
+        // without a terminator, stop before an entry that runs off the end
+        assert_eq!(eh_frame_len(&cells(&data[..end - 4]), LittleEndian), end - 4);
+        assert_eq!(eh_frame_len(&cells(&data[..end - 5]), LittleEndian), last);
+    }
+
+    #[test]
+    fn test_eh_frame_hdr_target() {
+        // version, eh_frame_ptr_enc, fde_count_enc, table_enc, eh_frame_ptr
+        let mut hdr = vec![1, DW_EH_PE_pcrel | DW_EH_PE_sdata4, DW_EH_PE_udata4, 0x3b];
+        hdr.extend_from_slice(&(0x100u32.wrapping_sub(4)).to_le_bytes());
+        assert_eq!(eh_frame_hdr_target(&cells(&hdr), VMA(0x2000), 8, LittleEndian), Some(VMA(0x2100)));
+        hdr[0] = 2;
+        assert_eq!(eh_frame_hdr_target(&cells(&hdr), VMA(0x2000), 8, LittleEndian), None);
+    }
+}
@@ -15,11 +15,13 @@ use std::cmp::min;
 use std::any::Any;
 use std::cell::Cell;
 use std::hash::{Hash, Hasher};
-use util::{ByteString, ByteStr, Mem, ReadCell, Narrow, CheckAdd, CheckSub, Signedness, Endian, SignExtend, Signed, slice_find_byte};
+use util::{ByteString, ByteStr, Mem, ReadCell, Narrow, CheckAdd, CheckSub, Signedness, Endian, SignExtend, Signed, slice_find_byte, SliceExt};
 
 pub mod arch;
+pub mod eh_frame;
+pub mod json;
 mod reloc;
-pub use reloc::{RelocKind, RelocContext};
+pub use reloc::{RelocKind, RelocContext, RelocPackFail, PtrAuth};
 
 #[derive(Copy, Debug, Clone, PartialEq, Eq)]
 pub enum ErrorKind {
@@ -263,6 +265,15 @@ pub enum SourceLib {
     Flat,
 }
 
+// what a symbol names, if the format says
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SymbolKind {
+    Unknown,
+    Func,
+    Data,
+    Section,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Symbol<'a> {
     pub name: Cow<'a, ByteStr>,
@@ -270,9 +281,34 @@ pub struct Symbol<'a> {
     pub is_weak: bool,
     pub val: SymbolValue<'a>,
     pub size: Option<u64>,
+    pub kind: SymbolKind,
+    // raw format-specific attributes: ELF st_info | st_other << 8, Mach-O n_type | n_desc << 8
+    pub attrs: u32,
     pub private: usize,
 }
 
+impl<'a> Symbol<'a> {
+    pub fn into_owned(self) -> Symbol<'static> {
+        Symbol {
+            name: self.name.into_owned().into(),
+            is_public: self.is_public,
+            is_weak: self.is_weak,
+            val: match self.val {
+                SymbolValue::ReExport(name, lib) => SymbolValue::ReExport(name.into_owned().into(), lib),
+                SymbolValue::Addr(vma) => SymbolValue::Addr(vma),
+                SymbolValue::Abs(vma) => SymbolValue::Abs(vma),
+                SymbolValue::ThreadLocal(vma) => SymbolValue::ThreadLocal(vma),
+                SymbolValue::Undefined(lib) => SymbolValue::Undefined(lib),
+                SymbolValue::Resolver(vma, stub) => SymbolValue::Resolver(vma, stub),
+            },
+            size: self.size,
+            kind: self.kind,
+            attrs: self.attrs,
+            private: self.private,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum SymbolSource {
     All,
@@ -285,8 +321,9 @@ pub enum SymbolSource {
 pub struct Reloc<'a> {
     pub address: VMA,
     pub kind: RelocKind,
-    pub base: Option<VMA>, // None if 'rel'
+    pub addend: Option<i64>, // None if 'rel'
     pub target: RelocTarget<'a>,
+    pub auth: Option<PtrAuth>, // arm64e signed pointers
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -321,6 +358,24 @@ pub trait Exec : 'static {
         vec!()
     }
 
+    // Known function entry points from format metadata (LC_FUNCTION_STARTS, .eh_frame, ...);
+    // not necessarily complete, and not necessarily sorted
+    fn get_function_starts(&self) -> Vec<VMA> {
+        vec!()
+    }
+
+    // (import name, stub address) for stubs that just jump to an import, like Mach-O
+    // __stubs or ELF PLT entries
+    fn get_stub_list(&self) -> Vec<(&ByteStr, VMA)> {
+        vec!()
+    }
+
+    // (import name, slot address) for pointers the loader fills in with an import's address,
+    // like ELF GOT slots
+    fn get_import_slot_list(&self) -> Vec<(&ByteStr, VMA)> {
+        vec!()
+    }
+
     fn get_dep_libs(&self) -> Cow<[DepLib]> {
         static NONE: [DepLib<'static>; 0] = [];
         (&NONE as &[DepLib]).into()
@@ -329,6 +384,11 @@ pub trait Exec : 'static {
         panic!("describe_dep_lib must be implemented if get_dep_libs is")
     }
 
+    // Serialize the (possibly modified) image back into a complete file.
+    fn write_back(&mut self) -> ExecResult<Mem<u8>> {
+        err(ErrorKind::InvalidArgs, "this format can't be written back")
+    }
+
     fn as_any(&self) -> &dyn std::any::Any;// { self as &std::any::Any }
     #[allow(mutable_transmutes)]
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any { unsafe { transmute(self.as_any()) } }
@@ -534,6 +594,25 @@ impl<'a, 'b> Iterator for ByteSliceIterator<'a, 'b> {
 }
 
 impl ExecBase {
+    // whole_buf, with any segment or section data that isn't a view of it (e.g. from a
+    // SegmentWriter) copied over the corresponding file range; doesn't change the layout
+    pub fn write_back_in_place(&self) -> ExecResult<Mem<u8>> {
+        let whole = some_or!(self.whole_buf.as_ref(), {
+            return err(ErrorKind::InvalidArgs, "no backing file to write back to");
+        });
+        let mut out = unsafe { whole.get_plain_slice() }.to_vec();
+        for seg in self.segments.iter().chain(&self.sections) {
+            let data = some_or!(seg.data.as_ref(), continue);
+            if seg.filesize == 0 || data.byte_offset_in(whole) == Some(seg.fileoff as usize) { continue; }
+            let len = min(seg.filesize, data.len() as u64);
+            let end = some_or!(seg.fileoff.check_add(len).filter(|&end| end <= out.len() as u64), {
+                return err(ErrorKind::BadData, format!("{} (fileoff 0x{:x}, size 0x{:x}) doesn't fit in the file",
+                                                       seg.pretty_name(), seg.fileoff, len));
+            });
+            util::copy_memory(&data.get()[..len as usize], &mut out[seg.fileoff as usize..end as usize]);
+        }
+        Ok(Mem::with_vec(out))
+    }
     // exact size, in one segment
     pub fn get_sane(&self, addr: VMA, size: u64) -> Option<&[Cell<u8>]> {
         let (seg, off, avail) =
@@ -561,8 +640,9 @@ impl ExecBase {
         let (seg, off, _) =
             some_or!(addr_to_seg_off_range(&self.segments, addr),
                      { return None; });
-        let data = some_or!(seg.data.as_ref(), { return None; });
-        let data = &data.get()[off as usize..];
+        let data = some_or!(seg.data.as_ref(), { return None; }).get();
+        // zerofill past the end of the data
+        let data = some_or!(data.slice_opt(off as usize, data.len()), { return None; });
         util::from_cstr_strict(data)
     }
 }
@@ -0,0 +1,113 @@
+// JSON forms of the exec types, for machine-readable listings.  Addresses are "0x..." strings, like
+// elsewhere; sizes and offsets are numbers.
+
+use util::json::Json;
+use {VMA, Segment, Symbol, SymbolKind, SymbolValue, SourceLib, Reloc, RelocTarget, DepLib};
+
+fn field<S: Into<String>>(name: S, val: Json) -> (String, Json) {
+    (name.into(), val)
+}
+
+pub fn addr(vma: VMA) -> Json {
+    Json::str(format!("{}", vma))
+}
+
+impl Segment {
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            field("name", Json::opt(self.name.as_ref(), |n| Json::str(n.lossy()))),
+            field("vmaddr", addr(self.vmaddr)),
+            field("vmsize", Json::num(self.vmsize)),
+            field("fileoff", Json::num(self.fileoff)),
+            field("filesize", Json::num(self.filesize)),
+            field("prot", Json::str(self.prot.to_string())),
+            field("seg_idx", Json::opt(self.seg_idx, Json::num)),
+        ])
+    }
+}
+
+impl SourceLib {
+    // a number for ordinals, else a string
+    pub fn to_json(&self) -> Json {
+        match *self {
+            SourceLib::None => Json::Null,
+            SourceLib::Ordinal(ord) => Json::num(ord),
+            SourceLib::Self_ => Json::str("self"),
+            SourceLib::MainExecutable => Json::str("main-executable"),
+            SourceLib::Flat => Json::str("flat"),
+        }
+    }
+}
+
+impl<'a> SymbolValue<'a> {
+    pub fn kind_name(&self) -> &'static str {
+        match *self {
+            SymbolValue::Addr(..) => "addr",
+            SymbolValue::Abs(..) => "abs",
+            SymbolValue::ThreadLocal(..) => "thread-local",
+            SymbolValue::Undefined(..) => "undefined",
+            SymbolValue::Resolver(..) => "resolver",
+            SymbolValue::ReExport(..) => "re-export",
+        }
+    }
+    // the fields after "kind"
+    fn json_fields(&self) -> Vec<(String, Json)> {
+        match *self {
+            SymbolValue::Addr(vma) | SymbolValue::Abs(vma) | SymbolValue::ThreadLocal(vma) =>
+                vec![field("addr", addr(vma))],
+            SymbolValue::Undefined(ref source) => vec![field("source", source.to_json())],
+            SymbolValue::Resolver(vma, stub) =>
+                vec![field("addr", addr(vma)), field("stub", Json::opt(stub, addr))],
+            SymbolValue::ReExport(ref name, ref source) =>
+                vec![field("target", Json::str(name.lossy())), field("source", source.to_json())],
+        }
+    }
+}
+
+impl<'a> Symbol<'a> {
+    pub fn to_json(&self) -> Json {
+        let mut fields = vec![
+            field("name", Json::str(self.name.lossy())),
+            field("public", Json::Bool(self.is_public)),
+            field("weak", Json::Bool(self.is_weak)),
+            field("size", Json::opt(self.size, Json::num)),
+            field("kind", Json::str(self.val.kind_name())),
+            field("type", match self.kind {
+                SymbolKind::Unknown => Json::Null,
+                SymbolKind::Func => Json::str("func"),
+                SymbolKind::Data => Json::str("data"),
+                SymbolKind::Section => Json::str("section"),
+            }),
+        ];
+        fields.extend(self.val.json_fields());
+        Json::Object(fields)
+    }
+}
+
+impl<'a> Reloc<'a> {
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            field("address", addr(self.address)),
+            field("kind", Json::str(format!("{:?}", self.kind))),
+            field("addend", Json::opt(self.addend, Json::num)),
+            field("target", match self.target {
+                RelocTarget::ThisImageSlide => Json::str("image-slide"),
+                RelocTarget::ThisSegmentSlide => Json::str("segment-slide"),
+                RelocTarget::Import(sym) => sym.to_json(),
+            }),
+            field("auth", Json::opt(self.auth, |auth| Json::Object(vec![
+                field("key", Json::num(auth.key)),
+                field("addr_div", Json::Bool(auth.addr_div)),
+                field("diversity", Json::num(auth.diversity)),
+            ]))),
+        ])
+    }
+}
+
+impl<'a> DepLib<'a> {
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            field("path", Json::str(self.path.lossy())),
+        ])
+    }
+}
@@ -8,10 +8,35 @@ pub enum RelocKind {
     Pointer,
     _64Bit,
     _32Bit,
+    _32BitPCRel,
+    _32BitGOTPCRel, // points at a GOT slot for the target
     Arm64Br26,
+    Arm64Adrp,
+    Arm64AdrpGot,
+    Arm64AddLo12,
+    Arm64LdstLo12(/* log2 access size */ u8),
+    Arm64Ld64GotLo12,
+    ArmBr24,
+    ThumbBr22,
+    // the following are only meaningful to a dynamic linker
+    Copy,
+    IRelative,
+    TLSModule,
+    TLSOffset, // from the start of the module's TLS block
+    TLSTPOffset, // from the thread pointer
+    TLSDesc,
+    Other(u32),
 }
 use RelocKind::*;
 
+// how an arm64e pointer is signed
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PtrAuth {
+    pub key: u8, // 0-3: IA, IB, DA, DB
+    pub addr_div: bool, // blend in the pointer's own address
+    pub diversity: u16,
+}
+
 #[derive(Copy, Clone)]
 pub struct RelocContext {
     pub kind: RelocKind,
@@ -31,21 +56,37 @@ pub enum RelocPackFail {
     AddrOutOfRange,
     Truncated,
     UnexpectedData,
+    Unsupported,
 }
 
 impl RelocContext {
     pub fn size(&self) -> usize {
         match self.kind {
-            Pointer => self.pointer_size,
-            _32Bit => 4, _64Bit => 8,
-            Arm64Br26 => 4,
+            Pointer | IRelative | TLSModule | TLSOffset | TLSTPOffset => self.pointer_size,
+            TLSDesc => 2 * self.pointer_size,
+            _32Bit | _32BitPCRel | _32BitGOTPCRel => 4, _64Bit => 8,
+            Arm64Br26 | Arm64Adrp | Arm64AdrpGot | Arm64AddLo12 |
+            Arm64LdstLo12(_) | Arm64Ld64GotLo12 => 4,
+            ArmBr24 | ThumbBr22 => 4,
+            Copy | Other(_) => 0,
         }
     }
+    fn word(&self, data: &[Cell<u8>]) -> Result<u32, RelocPackFail> {
+        if data.len() < 4 { return Err(RelocPackFail::Truncated); }
+        Ok(util::copy_from_slice(&data[..4], self.endian))
+    }
+    // For the Arm64*Lo12 kinds, only the low 12 bits of the address are encoded, so that's all
+    // we return; when packing, the upper bits of `new` are ignored.
     pub fn pack_unpack_insn(&self, data: &[Cell<u8>], new: Option<VMA>) -> Result<VMA, RelocPackFail> {
         let mut kind = self.kind;
-        if kind == Pointer { kind = if self.pointer_size == 8 { _64Bit } else { _32Bit }; }
+        if kind == Pointer || kind == IRelative {
+            kind = if self.pointer_size == 8 { _64Bit } else { _32Bit };
+        }
+        if kind == _32BitGOTPCRel { kind = _32BitPCRel; }
+        if kind == Arm64AdrpGot { kind = Arm64Adrp; }
+        if kind == Arm64Ld64GotLo12 { kind = Arm64LdstLo12(3); }
         match kind {
-            Pointer => unreachable!(),
+            Pointer | IRelative | _32BitGOTPCRel | Arm64AdrpGot | Arm64Ld64GotLo12 => unreachable!(),
             _64Bit => {
                 if data.len() < 8 { return Err(RelocPackFail::Truncated); }
                 let old: u64 = util::copy_from_slice(&data[..8], self.endian);
@@ -55,17 +96,26 @@ impl RelocContext {
                 Ok(VMA(old))
             },
             _32Bit => {
-                if data.len() < 4 { return Err(RelocPackFail::Truncated); }
-                let old: u32 = util::copy_from_slice(&data[..4], self.endian);
+                let old = self.word(data)?;
                 if let Some(VMA(new)) = new {
                     let new: u32 = new.narrow().ok_or(RelocPackFail::AddrOutOfRange)?;
                     util::copy_to_slice(&data[..4], &new, self.endian);
                 }
                 Ok(VMA(old.ext()))
             },
+            _32BitPCRel => {
+                let old = self.word(data)?;
+                let old_addr = self.base_addr.wrapping_add(old.sign_extend(32));
+                if let Some(new) = new {
+                    let rel = new.wrapping_sub(self.base_addr);
+                    let x: u32 = rel.trunc();
+                    if SignExtend::<u64>::sign_extend(x, 32) != rel { return Err(RelocPackFail::AddrOutOfRange); }
+                    util::copy_to_slice(&data[..4], &x, self.endian);
+                }
+                Ok(old_addr)
+            },
             Arm64Br26 => {
-                if data.len() < 4 { return Err(RelocPackFail::Truncated); }
-                let old_word: u32 = util::copy_from_slice(&data[..4], self.endian);
+                let old_word = self.word(data)?;
                 if old_word & 0x7c000000 != 0x14000000 { return Err(RelocPackFail::UnexpectedData); }
                 let old_addr = self.base_addr.wrapping_add(
                     ((old_word & 0x3ffffff) * 4).sign_extend(28)
@@ -80,6 +130,167 @@ impl RelocContext {
                 }
                 Ok(old_addr)
             },
+            Arm64Adrp => {
+                let old_word = self.word(data)?;
+                if old_word & 0x9f000000 != 0x90000000 { return Err(RelocPackFail::UnexpectedData); }
+                let page = self.base_addr.0 & !0xfff;
+                let imm: u64 = ((old_word >> 29 & 3) | (old_word >> 3 & 0x1ffffc)).ext();
+                let old_addr = VMA(page.wrapping_add((imm << 12).sign_extend(33)));
+                if let Some(VMA(new)) = new {
+                    let rel = (new & !0xfff).wrapping_sub(page);
+                    let x: u64 = rel.un_sign_extend(33).ok_or(RelocPackFail::AddrOutOfRange)?;
+                    let x = (x >> 12) as u32;
+                    let word = (old_word & !0x60ffffe0) | (x & 3) << 29 | (x >> 2) << 5;
+                    util::copy_to_slice(&data[..4], &word, self.endian);
+                }
+                Ok(old_addr)
+            },
+            Arm64AddLo12 | Arm64LdstLo12(_) => {
+                let old_word = self.word(data)?;
+                let shift = if let Arm64LdstLo12(s) = kind { s } else { 0 };
+                let old = ((old_word >> 10 & 0xfff) << shift) & 0xfff;
+                if let Some(VMA(new)) = new {
+                    let lo = (new & 0xfff) as u32;
+                    if lo & ((1 << shift) - 1) != 0 { return Err(RelocPackFail::AddrOutOfRange); }
+                    let word = (old_word & !(0xfff << 10)) | (lo >> shift) << 10;
+                    util::copy_to_slice(&data[..4], &word, self.endian);
+                }
+                Ok(VMA(old.ext()))
+            },
+            ArmBr24 => {
+                let old_word = self.word(data)?;
+                if old_word & 0x0e000000 != 0x0a000000 { return Err(RelocPackFail::UnexpectedData); }
+                let pc = self.base_addr.wrapping_add(8);
+                let old_addr = pc.wrapping_add(((old_word & 0xffffff) * 4).sign_extend(26));
+                if let Some(new) = new {
+                    let rel = new.wrapping_sub(pc);
+                    if rel & 3 != 0 { return Err(RelocPackFail::AddrOutOfRange); }
+                    let x: u32 = rel.un_sign_extend(26).ok_or(RelocPackFail::AddrOutOfRange)?;
+                    let word = (old_word & !0xffffff) | x >> 2;
+                    util::copy_to_slice(&data[..4], &word, self.endian);
+                }
+                Ok(old_addr)
+            },
+            ThumbBr22 => {
+                // two halfwords, each in the file's endianness
+                if data.len() < 4 { return Err(RelocPackFail::Truncated); }
+                let hi: u16 = util::copy_from_slice(&data[..2], self.endian);
+                let lo: u16 = util::copy_from_slice(&data[2..4], self.endian);
+                if hi & 0xf800 != 0xf000 || lo & 0x8000 != 0x8000 { return Err(RelocPackFail::UnexpectedData); }
+                let (hi, lo) = (hi as u32, lo as u32);
+                let s = hi >> 10 & 1;
+                let i1 = !(lo >> 13 ^ s) & 1;
+                let i2 = !(lo >> 11 ^ s) & 1;
+                let imm = s << 24 | i1 << 23 | i2 << 22 | (hi & 0x3ff) << 12 | (lo & 0x7ff) << 1;
+                // BLX switches to ARM, and is relative to the word-aligned pc
+                let is_blx = lo & 0x1000 == 0;
+                let pc = self.base_addr.wrapping_add(4);
+                let pc = if is_blx { VMA(pc.0 & !3) } else { pc };
+                let old_addr = pc.wrapping_add(imm.sign_extend(25));
+                if let Some(new) = new {
+                    let rel = if is_blx { (new.0 & !3).wrapping_sub(pc.0) } else { new.wrapping_sub(pc) };
+                    if rel & 1 != 0 { return Err(RelocPackFail::AddrOutOfRange); }
+                    let x: u32 = rel.un_sign_extend(25).ok_or(RelocPackFail::AddrOutOfRange)?;
+                    let s = x >> 24 & 1;
+                    let j1 = (!(x >> 23) ^ s) & 1;
+                    let j2 = (!(x >> 22) ^ s) & 1;
+                    let hi = ((hi & 0xf800) | s << 10 | (x >> 12 & 0x3ff)) as u16;
+                    let lo = ((lo & 0xd000) | j1 << 13 | j2 << 11 | (x >> 1 & 0x7ff)) as u16;
+                    util::copy_to_slice(&data[..2], &hi, self.endian);
+                    util::copy_to_slice(&data[2..4], &lo, self.endian);
+                }
+                Ok(old_addr)
+            },
+            Copy | TLSModule | TLSOffset | TLSTPOffset | TLSDesc | Other(_) =>
+                Err(RelocPackFail::Unsupported),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::{BigEndian, LittleEndian};
+
+    fn ctx(kind: RelocKind, base_addr: u64) -> RelocContext {
+        RelocContext { kind: kind, base_addr: VMA(base_addr), pointer_size: 8, endian: LittleEndian }
+    }
+
+    fn cells(bytes: &[u8]) -> Vec<Cell<u8>> {
+        bytes.iter().map(|&b| Cell::new(b)).collect()
+    }
+
+    fn bytes(data: &[Cell<u8>]) -> Vec<u8> {
+        data.iter().map(|c| c.get()).collect()
+    }
+
+    // unpack `before` (old), pack new, check the result is `after` and unpacks to new
+    fn round_trip(ctx: RelocContext, before: &[u8], old: u64, new: u64, after: &[u8]) {
+        let data = cells(before);
+        assert_eq!(ctx.pack_unpack_insn(&data, None), Ok(VMA(old)), "{:?}", ctx.kind);
+        assert_eq!(bytes(&data), before);
+        assert_eq!(ctx.pack_unpack_insn(&data, Some(VMA(new))), Ok(VMA(old)), "{:?}", ctx.kind);
+        assert_eq!(bytes(&data), after, "{:?}", ctx.kind);
+        assert_eq!(ctx.pack_unpack_insn(&data, None), Ok(VMA(new)), "{:?}", ctx.kind);
+    }
+
+    fn pack(ctx: RelocContext, before: &[u8], new: u64) -> Result<VMA, RelocPackFail> {
+        ctx.pack_unpack_insn(&cells(before), Some(VMA(new)))
+    }
+
+    #[test]
+    fn test_data() {
+        round_trip(ctx(_64Bit, 0), &[8, 7, 6, 5, 4, 3, 2, 1], 0x0102030405060708, 0x1122, &[0x22, 0x11, 0, 0, 0, 0, 0, 0]);
+        round_trip(RelocContext { endian: BigEndian, ..ctx(Pointer, 0) }, &[1, 2, 3, 4, 5, 6, 7, 8], 0x0102030405060708,
+                   0x1122, &[0, 0, 0, 0, 0, 0, 0x11, 0x22]);
+        round_trip(RelocContext { pointer_size: 4, ..ctx(Pointer, 0) }, &[4, 3, 2, 1], 0x01020304, 0x1122, &[0x22, 0x11, 0, 0]);
+        round_trip(ctx(_32Bit, 0), &[4, 3, 2, 1], 0x01020304, 0xffff_ffff, &[0xff; 4]);
+        assert_eq!(pack(ctx(_32Bit, 0), &[0; 4], 0x1_0000_0000), Err(RelocPackFail::AddrOutOfRange));
+        // relative to the end of the field, which is where base_addr points
+        round_trip(ctx(_32BitPCRel, 0x1000), &[0x10, 0, 0, 0], 0x1010, 0x800, &[0x00, 0xf8, 0xff, 0xff]);
+        round_trip(ctx(_32BitGOTPCRel, 0x1000), &[0x10, 0, 0, 0], 0x1010, 0x800, &[0x00, 0xf8, 0xff, 0xff]);
+        assert_eq!(pack(ctx(_32BitPCRel, 0x1000), &[0; 4], 0x1_0000_1000), Err(RelocPackFail::AddrOutOfRange));
+        assert_eq!(pack(ctx(_64Bit, 0), &[0; 4], 0), Err(RelocPackFail::Truncated));
+        assert_eq!(pack(ctx(TLSOffset, 0), &[0; 8], 0), Err(RelocPackFail::Unsupported));
+    }
+
+    // instruction encodings from llvm-mc
+    #[test]
+    fn test_arm64() {
+        // b #0x1000 -> b #-0x800
+        round_trip(ctx(Arm64Br26, 0x1000), &[0x00, 0x04, 0x00, 0x14], 0x2000, 0x800, &[0x00, 0xfe, 0xff, 0x17]);
+        assert_eq!(pack(ctx(Arm64Br26, 0x1000), &[0x00, 0x04, 0x00, 0x14], 0x1002), Err(RelocPackFail::AddrOutOfRange));
+        assert_eq!(pack(ctx(Arm64Br26, 0x1000), &[0x00, 0x04, 0x00, 0x14], 0x1000_1000), Err(RelocPackFail::AddrOutOfRange));
+        // adrp x1, #0x1000 -> adrp x1, #-0x2000; the low 12 bits don't matter
+        round_trip(ctx(Arm64Adrp, 0x1000_0123), &[0x01, 0x00, 0x00, 0xb0], 0x1000_1000, 0x0fff_e000, &[0xe1, 0xff, 0xff, 0xd0]);
+        round_trip(ctx(Arm64AdrpGot, 0x1000_0123), &[0x01, 0x00, 0x00, 0xb0], 0x1000_1000, 0x0fff_e000, &[0xe1, 0xff, 0xff, 0xd0]);
+        assert_eq!(pack(ctx(Arm64Adrp, 0x1000_0123), &[0x01, 0x00, 0x00, 0xb0], 0x0fff_e456), Ok(VMA(0x1000_1000)));
+        // add x1, x1, #0x123 -> #0xabc
+        round_trip(ctx(Arm64AddLo12, 0), &[0x21, 0x8c, 0x04, 0x91], 0x123, 0xabc, &[0x21, 0xf0, 0x2a, 0x91]);
+        // ldr x2, [x1, #0x18] -> #0x7f8, which has to be a multiple of 8
+        round_trip(ctx(Arm64LdstLo12(3), 0), &[0x22, 0x0c, 0x40, 0xf9], 0x18, 0x7f8, &[0x22, 0xfc, 0x43, 0xf9]);
+        round_trip(ctx(Arm64Ld64GotLo12, 0), &[0x22, 0x0c, 0x40, 0xf9], 0x18, 0x7f8, &[0x22, 0xfc, 0x43, 0xf9]);
+        assert_eq!(pack(ctx(Arm64LdstLo12(3), 0), &[0x22, 0x0c, 0x40, 0xf9], 0x7f4), Err(RelocPackFail::AddrOutOfRange));
+        // the wrong instruction
+        assert_eq!(pack(ctx(Arm64Adrp, 0), &[0x00, 0x04, 0x00, 0x14], 0), Err(RelocPackFail::UnexpectedData));
+        assert_eq!(pack(ctx(Arm64Br26, 0), &[0x01, 0x00, 0x00, 0xb0], 0), Err(RelocPackFail::UnexpectedData));
+    }
+
+    #[test]
+    fn test_arm() {
+        let arm = |kind, base_addr| RelocContext { pointer_size: 4, ..ctx(kind, base_addr) };
+        // bl 0x9000 -> bl 0x7000, from 0x8000; and beq
+        round_trip(arm(ArmBr24, 0x8000), &[0xfe, 0x03, 0x00, 0xeb], 0x9000, 0x7000, &[0xfe, 0xfb, 0xff, 0xeb]);
+        round_trip(arm(ArmBr24, 0x8000), &[0xfe, 0x03, 0x00, 0x0a], 0x9000, 0x7000, &[0xfe, 0xfb, 0xff, 0x0a]);
+        assert_eq!(pack(arm(ArmBr24, 0x8000), &[0xfe, 0x03, 0x00, 0xeb], 0x7002), Err(RelocPackFail::AddrOutOfRange));
+        // Thumb bl 0x9000 -> bl 0x7000, from 0x8000
+        round_trip(arm(ThumbBr22, 0x8000), &[0x00, 0xf0, 0xfe, 0xff], 0x9000, 0x7000, &[0xfe, 0xf7, 0xfe, 0xff]);
+        assert_eq!(pack(arm(ThumbBr22, 0x8000), &[0x00, 0xf0, 0xfe, 0xff], 0x7001), Err(RelocPackFail::AddrOutOfRange));
+        // blx 0xa000 -> blx 0x7000, from a halfword-aligned 0x8002, so relative to 0x8004
+        round_trip(arm(ThumbBr22, 0x8002), &[0x01, 0xf0, 0xfe, 0xef], 0xa000, 0x7000, &[0xfe, 0xf7, 0xfe, 0xef]);
+        // big-endian halfwords
+        let be = RelocContext { endian: BigEndian, ..arm(ThumbBr22, 0x8000) };
+        round_trip(be, &[0xf0, 0x00, 0xff, 0xfe], 0x9000, 0x7000, &[0xf7, 0xfe, 0xff, 0xfe]);
+        assert_eq!(pack(arm(ThumbBr22, 0), &[0xfe, 0x03, 0x00, 0xeb], 0), Err(RelocPackFail::UnexpectedData));
+    }
+}
@@ -15,24 +15,34 @@ extern crate dis;
 extern crate macros;
 extern crate dis_all;
 extern crate fmt_all;
+extern crate dis_generated_jump_dis;
+extern crate dis_simple_trawl;
+extern crate db;
 
 use std::fs;
 use std::path::Path;
 use std::io::Write;
-use std::cmp::min;
+use std::cmp::{min, max};
 use std::str::FromStr;
 use std::any::Any;
+use std::collections::HashMap;
 
-use util::{into_cow, Ext, vec_extend_from_slice};
-use exec::{arch, SymbolValue, VMA};
+use util::{into_cow, Ext, vec_extend_from_slice, ReadCell, ByteStr};
+use util::json::Json;
+use exec::{arch, SymbolValue, SymbolKind, RelocTarget, VMA};
 use exec::arch::{ArchAndOptions, CodeMode};
+use dis_simple_trawl::functions::{Function, FunctionMap};
+use dis_generated_jump_dis::{GenericHandler, InsnKind, TargetAddr, Addrish, Reg};
 
-fn macho_filedata_info(mo: &macho::MachO) {
-    println!("File data:");
-    let entry = |mc: &util::Mem<u8>, name| {
+// with --json, listings are collected here, keyed by option name, and printed as one object at
+// the end
+type JsonOut = Option<Vec<(String, Json)>>;
+
+fn macho_filedata_info(mo: &macho::MachO, json: &mut JsonOut) {
+    let mut entries = Vec::new();
+    let mut entry = |mc: &util::Mem<u8>, name| {
         if let Some(offset) = mc.byte_offset_in(&mo.eb.whole_buf.as_ref().unwrap()) {
-            println!("{:<16}: offset {:<#8x}, length {:<#8x}",
-                name, offset, mc.len());
+            entries.push((name, offset, mc.len()));
         }
     };
     entry(&mo.symtab,         "symtab");
@@ -46,6 +56,19 @@ fn macho_filedata_info(mo: &macho::MachO) {
     entry(&mo.dyld_weak_bind, "dyld weak_bind");
     entry(&mo.dyld_lazy_bind, "dyld lazy_bind");
     entry(&mo.dyld_export,    "dyld export");
+    if let Some(ref mut out) = *json {
+        out.push(("macho-filedata-info".to_owned(), Json::Array(entries.into_iter().map(|(name, offset, len)| Json::Object(vec![
+            ("name".to_owned(), Json::str(name)),
+            ("offset".to_owned(), Json::num(offset)),
+            ("length".to_owned(), Json::num(len)),
+        ])).collect())));
+        return;
+    }
+    println!("File data:");
+    for (name, offset, len) in entries {
+        println!("{:<16}: offset {:<#8x}, length {:<#8x}",
+            name, offset, len);
+    }
 }
 
 fn elf_dynamic_raw(elf: &elf::Elf) {
@@ -63,35 +86,116 @@ fn elf_dynamic(elf: &mut elf::Elf) {
     elf.dynamic_info.dump(Some(elf));
 }
 
-fn get_dump_from_spec(ex: &Box<exec::Exec>, dump_spec: String) -> Result<Vec<u8>, String> {
+// the section, or failing that the segment, containing addr
+fn containing_seg(eb: &exec::ExecBase, addr: VMA) -> Option<&exec::Segment> {
+    exec::addr_to_seg_off_range(&eb.sections, addr)
+        .or_else(|| exec::addr_to_seg_off_range(&eb.segments, addr))
+        .map(|(seg, _, _)| seg)
+}
+
+// ".text", "__TEXT,__text" or a segment name
+fn section_range(eb: &exec::ExecBase, name: &str) -> Option<(VMA, u64)> {
+    let named = |seg: &exec::Segment, name: &str| seg.name.as_ref().map_or(false, |n| **n == *name);
+    if let Some(comma) = name.find(',') {
+        let (segname, sectname) = (&name[..comma], &name[comma + 1..]);
+        for sect in &eb.sections {
+            if !named(sect, sectname) { continue; }
+            if let Some(seg) = sect.seg_idx.and_then(|i| eb.segments.get(i)) {
+                if named(seg, segname) { return Some((sect.vmaddr, sect.vmsize)); }
+            }
+        }
+        return None;
+    }
+    eb.sections.iter().chain(eb.segments.iter())
+        .find(|seg| named(seg, name))
+        .map(|seg| (seg.vmaddr, seg.vmsize))
+}
+
+// the size is the symbol's own if it has one, else up to the next symbol or the end of the section
+fn symbol_range(ex: &dyn exec::Exec, name: &str) -> Option<(VMA, u64)> {
     let eb = ex.get_exec_base();
-    let z;
-    let is_addr_end: bool;
-    if let Some(z_) = dump_spec.find('+') {
-        z = z_; is_addr_end = false;
-    } else if let Some(z_) = dump_spec.find('-') {
-        z = z_; is_addr_end = true;
-    } else {
-        return Err(format!("invalid dump spec '{}' - should be addr+len or addr1-addr2", dump_spec));
+    let strip = |vma: VMA| if eb.arch == arch::Arch::ARM { vma & !1 } else { vma };
+    let name = ByteStr::from_str(name);
+    let mut syms = ex.lookup_export(name, None);
+    if syms.is_empty() {
+        syms = ex.get_symbol_list(exec::SymbolSource::All, None).into_iter()
+            .filter(|sym| *sym.name == *name).collect();
     }
-    let addr: u64 = util::stoi(&dump_spec[..z]).unwrap();
-    let mut size: u64 = util::stoi(&dump_spec[z+1..]).unwrap();
-    assert!(size <= (std::usize::MAX as u64));
-    let mut ret = Vec::with_capacity(size as usize);
-    if is_addr_end {
-        // 'size' is actually end
-        if size < addr {
-            return Err(format!("in dump spec '{}', end < start", dump_spec));
+    let (addr, size) = some_or!(syms.iter().filter_map(|sym| match sym.val {
+        SymbolValue::Addr(vma) | SymbolValue::Resolver(vma, None) => Some((strip(vma), sym.size)),
+        _ => None,
+    }).next(), return None);
+    if let Some(size) = size {
+        if size != 0 { return Some((addr, size)); }
+    }
+    let seg = some_or!(containing_seg(eb, addr), return Some((addr, 0)));
+    let seg_end = seg.vmaddr + seg.vmsize;
+    let next = ex.get_symbol_list(exec::SymbolSource::All, None).into_iter().filter_map(|sym| match sym.val {
+        SymbolValue::Addr(vma) | SymbolValue::Resolver(vma, None)
+            if !sym.name.starts_with(b"$") && strip(vma) > addr => Some(strip(vma)),
+        _ => None,
+    }).min().map_or(seg_end, |next| min(next, seg_end));
+    Some((addr, next - addr))
+}
+
+// a function start (from get_function_starts) up to the next one or the end of the section
+fn function_range(ex: &dyn exec::Exec, addr: VMA) -> Option<(VMA, u64)> {
+    let eb = ex.get_exec_base();
+    let starts = ex.get_function_starts();
+    if !starts.contains(&addr) { return None; }
+    let seg = some_or!(containing_seg(eb, addr), return None);
+    let seg_end = seg.vmaddr + seg.vmsize;
+    let end = starts.into_iter().filter(|&start| start > addr).min().map_or(seg_end, |next| min(next, seg_end));
+    Some((addr, end - addr))
+}
+
+// addr+len, addr1-addr2, a section or segment name, a symbol name, or the address of a function
+// start; "section:", "symbol:" and "function:" prefixes say which of the last three is meant
+fn get_range_from_spec(ex: &dyn exec::Exec, spec: &str) -> Result<(VMA, u64), String> {
+    let eb = ex.get_exec_base();
+    let (kind, name) = match spec.find(':') {
+        Some(i) if ["section", "symbol", "function"].contains(&&spec[..i]) => (Some(&spec[..i]), &spec[i + 1..]),
+        _ => (None, spec),
+    };
+    let not_found = || Err(format!("invalid range '{}' - should be addr+len, addr1-addr2, or a section, symbol or function start", spec));
+    match kind {
+        Some("section") => return section_range(eb, name).map_or_else(not_found, Ok),
+        Some("symbol") => return symbol_range(ex, name).map_or_else(not_found, Ok),
+        Some(_) => {
+            let addr = some_or!(util::stoi(name), return not_found());
+            return function_range(ex, VMA(addr)).map_or_else(not_found, Ok);
+        },
+        None => (),
+    }
+    if let Some(z) = spec.find(|c| c == '+' || c == '-') {
+        if let (Some(addr), Some(size)) = (util::stoi::<u64>(&spec[..z]), util::stoi::<u64>(&spec[z+1..])) {
+            if spec.as_bytes()[z] == b'+' { return Ok((VMA(addr), size)); }
+            // 'size' is actually end
+            if size < addr {
+                return Err(format!("in range '{}', end < start", spec));
+            }
+            return Ok((VMA(addr), size - addr));
         }
-        size -= addr;
     }
+    if let Some(addr) = util::stoi(spec) {
+        return function_range(ex, VMA(addr)).map_or_else(not_found, Ok);
+    }
+    section_range(eb, spec).or_else(|| symbol_range(ex, spec)).map_or_else(not_found, Ok)
+}
 
-    let (mut addr, mut size) = (VMA(addr), size);
+// the start address and the data
+fn get_dump_from_spec(ex: &dyn exec::Exec, dump_spec: &str) -> Result<(VMA, Vec<u8>), String> {
+    let eb = ex.get_exec_base();
+    let (start, size) = get_range_from_spec(ex, dump_spec)?;
+    assert!(size <= (std::usize::MAX as u64));
+    let mut ret = Vec::with_capacity(size as usize);
+    let (mut addr, mut size) = (start, size);
     while size != 0 {
         if let Some((seg, off, osize)) = exec::addr_to_seg_off_range(&eb.segments, addr) {
             let osize = min(osize, size);
-            if osize > seg.filesize {
-                return Err(format!("zerofill at: {} (in segment '{}')", addr + seg.filesize, seg.pretty_name()));
+            if off + osize > seg.filesize {
+                let zf = seg.vmaddr + seg.filesize;
+                return Err(format!("zerofill at: {} (in segment '{}')", max(zf, addr), seg.pretty_name()));
             }
             let buf = seg.data.as_ref().unwrap().get();
             vec_extend_from_slice(&mut ret, &buf[off as usize..(off+osize) as usize]);
@@ -102,7 +206,239 @@ fn get_dump_from_spec(ex: &Box<exec::Exec>, dump_spec: String) -> Result<Vec<u8>
         }
     }
 
-    Ok(ret)
+    Ok((start, ret))
+}
+
+// one name per address, preferring public symbols; ARM mapping symbols ($a, $t, $d, $x) aren't
+// names
+fn symbol_names(ex: &dyn exec::Exec, arch: arch::Arch) -> HashMap<VMA, String> {
+    let mut names = HashMap::new();
+    for sym in ex.get_symbol_list(exec::SymbolSource::All, None) {
+        if let SymbolValue::Addr(vma) | SymbolValue::Resolver(vma, None) = sym.val {
+            if sym.name.starts_with(b"$") { continue; }
+            let vma = if arch == arch::Arch::ARM { vma & !1 } else { vma };
+            let name = names.entry(vma).or_insert((sym.is_public, sym.name.lossy().into_owned()));
+            if sym.is_public && !name.0 { *name = (true, sym.name.lossy().into_owned()); }
+        }
+    }
+    names.into_iter().map(|(vma, (_, name))| (vma, name)).collect()
+}
+
+// the functions, plus names for them (preferring public symbols to section symbols and local
+// labels, then names saved in the database); the database gets the results
+fn find_functions(ex: &dyn exec::Exec, arch: arch::Arch, mut bdb: Option<&mut db::BinaryDB>) -> (FunctionMap, HashMap<VMA, String>) {
+    let eb = ex.get_exec_base();
+    let mut handler = dis_generated_jump_dis::create_handler(arch, eb.endian).unwrap_or_else(|| {
+        errln!("functions: no trawling support for {:?}", arch);
+        util::exit();
+    });
+    let fm = dis_simple_trawl::functions::find_functions(ex, &mut *handler, dis_simple_trawl::functions::DEFAULT_NORETURN_NAMES);
+    let mut names = symbol_names(ex, arch);
+    if let Some(ref mut bdb) = bdb {
+        for func in fm.functions() {
+            let size = func.end() - func.start;
+            bdb.insert(func.start, size, db::Annotation::Function { thumb: func.mode == (arch::ARMMode { thumb: true }) });
+            if let Some(name) = names.get(&func.start) {
+                bdb.insert(func.start, size, db::Annotation::Name(name.clone()));
+            }
+        }
+        for (start, node) in bdb.all(db::Category::Name) {
+            if let db::Annotation::Name(ref name) = node.ann {
+                names.entry(start).or_insert_with(|| name.clone());
+            }
+        }
+    }
+    (fm, names)
+}
+
+fn load_db(path: &str) -> db::RangeDB {
+    if !Path::new(path).exists() { return db::RangeDB::new(); }
+    db::RangeDB::load(Path::new(path)).unwrap_or_else(|e| {
+        errln!("{}", e.message);
+        util::exit();
+    })
+}
+
+fn parse_addr_opt(opt: &str, s: &str) -> VMA {
+    VMA(some_or!(util::stoi(s), {
+        errln!("--{}: invalid address", opt);
+        util::exit();
+    }))
+}
+
+// `spec` is [format:]file
+fn import_map(ex: &dyn exec::Exec, spec: &str, source_base: Option<VMA>, bdb: &mut db::BinaryDB) {
+    let (format, path) = match spec.find(':').and_then(|i| db::import::MapFormat::from_name(&spec[..i]).map(|f| (f, i))) {
+        Some((format, i)) => (Some(format), &spec[i + 1..]),
+        None => (None, spec),
+    };
+    let text = fs::read(path).unwrap_or_else(|e| {
+        errln!("import: couldn't read {}: {}", path, e);
+        util::exit();
+    });
+    let text = String::from_utf8_lossy(&text);
+    let format = format.unwrap_or_else(|| db::import::MapFormat::guess(&text));
+    let eb = ex.get_exec_base();
+    let map = db::import::parse_map(format, &text, eb).unwrap_or_else(|e| {
+        errln!("{}", e.message);
+        util::exit();
+    });
+    let syms = ex.get_symbol_list(exec::SymbolSource::All, None);
+    let slide = map.slide(eb, &syms, source_base).unwrap_or_else(|| {
+        errln!("import: nothing in {} to work out the slide from; assuming none (see --import-base)", path);
+        0
+    });
+    let count = map.import_into(bdb, slide);
+    errln!("import: {} entries from {} ({}, slid by {:#x})", count, path, format.name(), slide);
+}
+
+fn list_functions(ex: &dyn exec::Exec, arch: arch::Arch, verbose: bool, bdb: Option<&mut db::BinaryDB>, json: &mut JsonOut) {
+    let (fm, names) = find_functions(ex, arch, bdb);
+    if let Some(ref mut out) = *json {
+        // blocks are always included
+        out.push(("functions".to_owned(), Json::Array(fm.functions().iter().map(|func| {
+            func.to_json(names.get(&func.start).map(|n| &**n))
+        }).collect())));
+        return;
+    }
+    println!("Functions:");
+    for func in fm.functions() {
+        print!("{}-{}", func.start, func.end());
+        if func.mode == (arch::ARMMode { thumb: true }) { print!(" [thumb]"); }
+        if let Some(name) = names.get(&func.start) { print!(" {}", name); }
+        println!(" ({} blocks)", func.blocks.len());
+        if !verbose { continue; }
+        for block in &func.blocks {
+            print!("    {}-{}", block.start, block.end);
+            for edge in &block.edges {
+                print!(" {}:{}", edge.kind.name(), edge.to);
+            }
+            println!("");
+        }
+    }
+}
+
+fn print_cfg(ex: &dyn exec::Exec, arch: arch::Arch, format: &str, only: Option<VMA>, bdb: Option<&mut db::BinaryDB>, json: &mut JsonOut) {
+    let (fm, names) = find_functions(ex, arch, bdb);
+    let selected: Vec<&Function> = match only {
+        Some(addr) => fm.lookup(addr).into_iter().collect(),
+        None => fm.functions().iter().collect(),
+    };
+    let funcs: Vec<_> = selected.into_iter().map(|func| (func, names.get(&func.start).map(|n| &**n))).collect();
+    if only.is_some() && funcs.is_empty() {
+        errln!("cfg: no function found containing {}", only.unwrap());
+        util::exit();
+    }
+    match format {
+        "dot" => {
+            let mut out = String::new();
+            dis_simple_trawl::functions::write_dot(&mut out, &funcs).unwrap();
+            print!("{}", out);
+        },
+        "json" => {
+            let j = Json::Array(funcs.iter().map(|&(func, name)| func.to_json(name)).collect());
+            if let Some(ref mut out) = *json {
+                out.push(("cfg".to_owned(), j));
+            } else {
+                println!("{:#}", j);
+            }
+        },
+        _ => {
+            errln!("cfg: unknown format '{}' (expected dot or json)", format);
+            util::exit();
+        },
+    }
+}
+
+// names for addresses in a disassembly listing
+struct Symbolizer {
+    arch: arch::Arch,
+    // sorted by address
+    syms: Vec<(VMA, String)>,
+    stubs: HashMap<VMA, String>,
+    // import pointer slots, like the GOT
+    slots: HashMap<VMA, String>,
+}
+
+impl Symbolizer {
+    fn new(ex: &dyn exec::Exec, arch: arch::Arch, bdb: Option<&db::BinaryDB>) -> Self {
+        let mut names = symbol_names(ex, arch);
+        if let Some(bdb) = bdb {
+            for (start, node) in bdb.all(db::Category::Name) {
+                if let db::Annotation::Name(ref name) = node.ann {
+                    names.entry(start).or_insert_with(|| name.clone());
+                }
+            }
+        }
+        let mut syms: Vec<_> = names.into_iter().collect();
+        syms.sort();
+        let stubs = ex.get_stub_list().into_iter().map(|(name, addr)| (addr, name.lossy().into_owned())).collect();
+        let slots = ex.get_import_slot_list().into_iter().map(|(name, addr)| (addr, name.lossy().into_owned())).collect();
+        Symbolizer { arch: arch, syms: syms, stubs: stubs, slots: slots }
+    }
+    fn label_at(&self, addr: VMA) -> Option<&str> {
+        self.syms.binary_search_by_key(&addr, |sym| sym.0).ok().map(|i| &*self.syms[i].1)
+    }
+    // "name", "name+0x10" (only within the section the symbol is in), "name (stub)" or
+    // "name@got"; with `exact`, only names that start at addr
+    fn describe(&self, eb: &exec::ExecBase, addr: VMA, exact: bool) -> Option<String> {
+        if let Some(name) = self.stubs.get(&addr) { return Some(format!("{} (stub)", name)); }
+        if let Some(name) = self.slots.get(&addr) { return Some(format!("{}@got", name)); }
+        let i = match self.syms.binary_search_by_key(&addr, |sym| sym.0) {
+            Ok(i) => return Some(self.syms[i].1.clone()),
+            Err(0) => return None,
+            Err(_) if exact => return None,
+            Err(i) => i - 1,
+        };
+        let (start, ref name) = self.syms[i];
+        // don't let a name run on past the section it's in (e.g. from .init into .plt)
+        let seg = some_or!(containing_seg(eb, addr), return None);
+        if start < seg.vmaddr { return None; }
+        Some(format!("{}+{:#x}", name, addr - start))
+    }
+    // the C string at addr, quoted, if it looks like text
+    fn describe_cstr(&self, eb: &exec::ExecBase, addr: VMA) -> Option<String> {
+        const MAX_LEN: usize = 60;
+        let s = some_or!(eb.read_cstr_sane(addr), return None);
+        if s.len() == 0 || !s.iter().all(|&c| (c >= 0x20 && c < 0x7f) || c == b'\n' || c == b'\t' || c == b'\r') {
+            return None;
+        }
+        let s = s.lossy();
+        if s.len() > MAX_LEN {
+            Some(format!("{:?}...", &s[..MAX_LEN]))
+        } else {
+            Some(format!("{:?}", s))
+        }
+    }
+    fn describe_data(&self, eb: &exec::ExecBase, addr: VMA, exact: bool) -> Option<String> {
+        self.describe_cstr(eb, addr).or_else(|| self.describe(eb, addr, exact))
+    }
+    // what the instruction at pc refers to; `regs` holds registers known to contain an address
+    // (from AArch64 adrp/adr), so the add or load that finishes off the address can be annotated
+    fn annotate_insn(&self, handler: &mut dyn GenericHandler, eb: &exec::ExecBase, pc: VMA, data: &[ReadCell<u8>],
+                     regs: &mut HashMap<Reg, VMA>) -> Option<String> {
+        let info = *handler.decode(pc, data).1;
+        let note = match (info.kind, info.target_addr) {
+            (_, TargetAddr::Code(addr)) => {
+                let addr = if self.arch == arch::Arch::ARM { addr & !1 } else { addr };
+                self.describe(eb, addr, false)
+            },
+            (InsnKind::Set(_, Addrish::AddImm(r, imm)), _) |
+            (InsnKind::Load(_, Addrish::AddImm(r, imm), _, _), _) if regs.contains_key(&r) =>
+                self.describe_data(eb, regs[&r] + imm, false),
+            // an adrp's page address is rarely interesting in itself
+            (InsnKind::Set(_, Addrish::Imm(_)), TargetAddr::Data(addr)) => self.describe_data(eb, addr, true),
+            (_, TargetAddr::Data(addr)) => self.describe_data(eb, addr, false),
+            _ => None,
+        };
+        for r in &info.kills_reg {
+            regs.remove(r);
+        }
+        if let (InsnKind::Set(r, Addrish::Imm(_)), TargetAddr::Data(addr)) = (info.kind, info.target_addr) {
+            regs.insert(r, addr);
+        }
+        note
+    }
 }
 
 fn print_segs(segs: &[exec::Segment]) {
@@ -120,17 +456,28 @@ fn print_segs(segs: &[exec::Segment]) {
     }
 }
 
-fn do_stuff(ex: &Box<exec::Exec>, m: &getopts::Matches) {
+fn do_stuff(ex: &Box<exec::Exec>, filename: &str, m: &getopts::Matches, json: &mut JsonOut) {
+    if json.is_some() {
+        if m.opt_present("dump") {
+            errln!("--dump writes raw bytes and can't be combined with --json");
+            util::exit();
+        }
+        if m.opt_str("cfg").map_or(false, |format| format != "json") {
+            errln!("--cfg with --json only supports the json format");
+            util::exit();
+        }
+    }
     let eb = ex.get_exec_base();
     let macho = ex.as_any().downcast_ref::<macho::MachO>();
     let elf = ex.as_any().downcast_ref::<elf::Elf>();
-    if m.opt_present("segs") {
-        println!("All segments:");
-        print_segs(&eb.segments);
-    }
-    if m.opt_present("sects") {
-        println!("All sections:");
-        print_segs(&eb.sections);
+    for &(name, desc, segs) in &[("segs", "All segments:", &eb.segments), ("sects", "All sections:", &eb.sections)] {
+        if !m.opt_present(name) { continue; }
+        if let Some(ref mut out) = *json {
+            out.push((name.to_owned(), Json::Array(segs.iter().map(|seg| seg.to_json()).collect())));
+        } else {
+            println!("{}", desc);
+            print_segs(segs);
+        }
     }
     let mut elf_specific = elf::ElfGetSymbolListSpecific::default();
     if m.opt_present("elf-append-version") {
@@ -144,10 +491,22 @@ fn do_stuff(ex: &Box<exec::Exec>, m: &getopts::Matches) {
     ];
     for &(name, desc, kind) in KINDS {
         if !m.opt_present(name) { continue; }
-        println!("{}:", desc);
         let opts = if elf.is_some() {
             Some(&elf_specific as &Any)
         } else { None };
+        if let Some(ref mut out) = *json {
+            out.push((name.to_owned(), Json::Array(ex.get_symbol_list(kind, opts).iter().map(|sym| {
+                let mut j = sym.to_json();
+                if let (Some(_), &mut Json::Object(ref mut fields)) = (elf, &mut j) {
+                    fields.push(("elf_type".to_owned(), Json::str(elf::sym_type_name(sym.attrs))));
+                    fields.push(("elf_bind".to_owned(), Json::str(elf::sym_bind_name(sym.attrs))));
+                    fields.push(("elf_visibility".to_owned(), Json::str(elf::sym_visibility_name(sym.attrs))));
+                }
+                j
+            }).collect())));
+            continue;
+        }
+        println!("{}:", desc);
         for sym in ex.get_symbol_list(kind, opts) {
             let name = sym.name.lossy();
             match sym.val {
@@ -161,6 +520,17 @@ fn do_stuff(ex: &Box<exec::Exec>, m: &getopts::Matches) {
             print!(" {}", name);
             if sym.is_public { print!(" [pub]") }
             if sym.is_weak   { print!(" [weak]") }
+            match sym.kind {
+                SymbolKind::Func =>    print!(" [func]"),
+                SymbolKind::Data =>    print!(" [data]"),
+                SymbolKind::Section => print!(" [section]"),
+                SymbolKind::Unknown => (),
+            }
+            if elf.is_some() {
+                if elf::sym_bind_name(sym.attrs) == "unique" { print!(" [unique]") }
+                let vis = elf::sym_visibility_name(sym.attrs);
+                if vis != "default" { print!(" [{}]", vis) }
+            }
             match sym.val {
                 SymbolValue::Abs(_) =>                  print!(" [abs]"),
                 SymbolValue::ThreadLocal(..) =>         print!(" [thread]"),
@@ -172,32 +542,66 @@ fn do_stuff(ex: &Box<exec::Exec>, m: &getopts::Matches) {
             println!("");
         }
     }
-    if m.opt_present("dep-libs") {
+    if m.opt_present("dep-libs") && json.is_some() {
+        let libs = ex.get_dep_libs().iter().map(|dl| {
+            let mut j = dl.to_json();
+            if let Json::Object(ref mut fields) = j {
+                fields.push(("description".to_owned(), Json::str(ex.describe_dep_lib(dl))));
+            }
+            j
+        }).collect();
+        json.as_mut().unwrap().push(("dep-libs".to_owned(), Json::Array(libs)));
+    } else if m.opt_present("dep-libs") {
         println!("Library dependencies:");
         for dl in &*ex.get_dep_libs() {
             println!("{}", ex.describe_dep_lib(dl));
         }
 
     }
-    if m.opt_present("relocs") {
+    if m.opt_present("relocs") && json.is_some() {
+        let relocs = ex.get_reloc_list(None).iter().map(|rel| rel.to_json()).collect();
+        json.as_mut().unwrap().push(("relocs".to_owned(), Json::Array(relocs)));
+    } else if m.opt_present("relocs") {
         println!("Relocations:");
         for rel in ex.get_reloc_list(None) {
-            println!("addr={} kind={:?}", rel.address, rel.kind);
-            /*
+            print!("addr={} kind={:?}", rel.address, rel.kind);
+            match rel.target {
+                RelocTarget::ThisImageSlide => (),
+                RelocTarget::ThisSegmentSlide => print!(" target=<segment slide>"),
+                RelocTarget::Import(sym) => print!(" target={}", sym.name),
+            }
+            if let Some(auth) = rel.auth {
+                print!(" auth={}{} diversity=0x{:x}", ["IA", "IB", "DA", "DB"][(auth.key & 3) as usize],
+                       if auth.addr_div { "+addr" } else { "" }, auth.diversity);
+            }
             if let Some(add) = rel.addend {
-                println!(" addend=0x{:x}", add);
+                if add < 0 {
+                    println!(" addend=-0x{:x}", add.wrapping_neg());
+                } else {
+                    println!(" addend=0x{:x}", add);
+                }
             } else {
                 println!("");
             }
-            */
+        }
+    }
+    if m.opt_present("elf-versions") {
+        let elf = elf.expect("elf-versions: not elf");
+        if let Some(ref mut out) = *json {
+            out.push(("elf-versions".to_owned(), elf.versions_to_json()));
+        } else {
+            elf.dump_versions();
         }
     }
     if m.opt_present("macho-filedata-info") {
-        macho_filedata_info(macho.expect("macho-filedata-info: not mach-o"));
+        macho_filedata_info(macho.expect("macho-filedata-info: not mach-o"), json);
     }
     if let Some(off_str) = m.opt_str("o2a") {
         let off: u64 = util::stoi(&off_str).unwrap();
-        if let Some(VMA(vma)) = exec::off_to_addr(&eb.segments, off, 0) {
+        let addr = exec::off_to_addr(&eb.segments, off, 0);
+        if let Some(ref mut out) = *json {
+            out.push(("o2a".to_owned(), Json::opt(addr, exec::json::addr)));
+        } else if let Some(VMA(vma)) = addr {
             println!("0x{:x}", vma);
         } else {
             println!("-");
@@ -205,68 +609,161 @@ fn do_stuff(ex: &Box<exec::Exec>, m: &getopts::Matches) {
     }
     if let Some(addr_str) = m.opt_str("a2o") {
         let addr: u64 = util::stoi(&addr_str).unwrap();
-        if let Some(off) = exec::addr_to_off(&eb.segments, VMA(addr), 0) {
+        let off = exec::addr_to_off(&eb.segments, VMA(addr), 0);
+        if let Some(ref mut out) = *json {
+            out.push(("a2o".to_owned(), Json::opt(off, |off| Json::str(format!("0x{:x}", off)))));
+        } else if let Some(off) = off {
             println!("0x{:x}", off);
         } else {
             println!("-");
         }
     }
-    if let Some(dump_spec) = m.opt_str("dump") {
-        match get_dump_from_spec(ex, dump_spec) {
-            Ok(dump_data) => {std::io::stdout().write(&*dump_data).unwrap();},
+    for dump_spec in m.opt_strs("dump") {
+        match get_dump_from_spec(&**ex, &dump_spec) {
+            Ok((_, dump_data)) => {std::io::stdout().write(&*dump_data).unwrap();},
             Err(msg) => errln!("dump error: {}", msg),
         };
     }
     let arch = match m.opt_str("arch") {
         Some(arch_s) => arch::Arch::from_str(&*arch_s).unwrap(),
-        None => arch::Arch::UnknownArch,
+        None => eb.arch,
     };
     // XXXXX
     let arch_opts: ArchAndOptions = ArchAndOptions::new_default(arch);
     // XXX should accept multiple copies of these
-    let mut dis_opts = vec!["llvmdis".to_owned()];
+    // the native AArch64 disassembler doesn't need LLVM
+    let mut dis_opts = vec![if arch == arch::Arch::AArch64 { "aarch64" } else { "llvm" }.to_owned()];
     if let Some(name) = m.opt_str("dis") {
         // XXX I should probably support [ and ] - also depends on customizing getopts
         dis_opts = vec![name.to_owned()];
     }
-    if let Some(dump_spec) = m.opt_str("dis-range") {
-        let dis = dis::create(dis_all::ALL_FAMILIES, arch_opts, &dis_opts).unwrap();
-        let dump_data = get_dump_from_spec(ex, dump_spec).unwrap();
-        let base_pc = VMA(0);
-        let results = dis.disassemble_multiple_to_str(&dis::DisassemblerInput {
-            data: &dump_data[..],
-            pc: base_pc,
-            mode: CodeMode::new(&arch_opts, &[]).unwrap(), // XXX
+    let db_path = m.opt_str("db");
+    let mut rdb = db_path.as_ref().map(|path| load_db(path));
+    if m.opt_present("functions") || m.opt_present("cfg") {
+        // forget functions from earlier runs; done before --import so its entries survive
+        if let Some(ref mut rdb) = rdb {
+            rdb.binary_mut(filename).clear(db::Category::Function);
+        }
+    }
+    if let Some(spec) = m.opt_str("import") {
+        let source_base = m.opt_str("import-base").map(|addr| parse_addr_opt("import-base", &addr));
+        let rdb = rdb.get_or_insert_with(db::RangeDB::new);
+        import_map(&**ex, &spec, source_base, rdb.binary_mut(filename));
+    }
+    if m.opt_present("functions") {
+        list_functions(&**ex, arch, m.opt_present("v"), rdb.as_mut().map(|rdb| rdb.binary_mut(filename)), json);
+    }
+    if let Some(format) = m.opt_str("cfg") {
+        let only = m.opt_str("cfg-func").map(|addr| parse_addr_opt("cfg-func", &addr));
+        print_cfg(&**ex, arch, &format, only, rdb.as_mut().map(|rdb| rdb.binary_mut(filename)), json);
+    }
+    if let (Some(path), Some(rdb)) = (db_path, rdb.as_ref()) {
+        rdb.save(Path::new(&path)).unwrap_or_else(|e| {
+            errln!("couldn't write {}: {}", path, e);
+            util::exit();
         });
-        let mut last_end: VMA = base_pc;
-        for (dissed, pc, length) in results {
-            let diff = last_end - pc;
-            if diff != 0 {
-                println!("...skip {}", diff);
+    }
+    let dis_specs = m.opt_strs("dis-range");
+    if !dis_specs.is_empty() {
+        let dis = dis::create(dis_all::ALL_FAMILIES, arch_opts, &dis_opts).unwrap();
+        let mode = CodeMode::new(&arch_opts, &[]).unwrap(); // XXX
+        let syms = Symbolizer::new(&**ex, arch, rdb.as_ref().and_then(|rdb| rdb.binary(filename)));
+        let mut handler = dis_generated_jump_dis::create_handler(arch, eb.endian);
+        let mut ranges = Vec::new();
+        for (i, dump_spec) in dis_specs.iter().enumerate() {
+            let (base_pc, dump_data) = match get_dump_from_spec(&**ex, dump_spec) {
+                Ok(x) => x,
+                Err(msg) => { errln!("dis-range error: {}", msg); continue; },
+            };
+            if i != 0 && json.is_none() { println!(""); }
+            let results = dis.disassemble_multiple_to_str(&dis::DisassemblerInput {
+                data: &dump_data[..],
+                pc: base_pc,
+                mode: mode,
+            });
+            if let Some(ref mut handler) = handler {
+                handler.set_mode(mode);
+                handler.reset();
+            }
+            let mut regs = HashMap::new();
+            let mut last_end: VMA = base_pc;
+            let expected_end = base_pc + (dump_data.len() as u64);
+            let mut insns = Vec::new();
+            for (dissed, pc, length) in results {
+                let diff = last_end - pc;
+                let label = syms.label_at(pc);
+                let note = handler.as_mut().and_then(|handler| {
+                    if pc >= expected_end { return None; }
+                    let data = some_or!(eb.get_sane(pc, min(16, expected_end - pc)), return None);
+                    syms.annotate_insn(&mut **handler, eb, pc, util::downgrade(data), &mut regs)
+                });
+                last_end = pc + length.ext();
+                // gaps and over-reads can be worked out from the addresses and lengths
+                if json.is_some() {
+                    insns.push(Json::Object(vec![
+                        ("addr".to_owned(), exec::json::addr(pc)),
+                        ("length".to_owned(), Json::num(length)),
+                        ("label".to_owned(), Json::opt(label, Json::str)),
+                        ("text".to_owned(), Json::opt(dissed, Json::str)),
+                        ("note".to_owned(), Json::opt(note, Json::str)),
+                    ]));
+                    continue;
+                }
+                if diff != 0 {
+                    println!("...skip {}", diff);
+                }
+                if let Some(label) = label {
+                    println!("{}:", label);
+                }
+                print!("-> {}: {}", pc,
+                    if let Some(ref s) = dissed { &s[..] } else { "<?>" });
+                if let Some(note) = note { print!("  ; {}", note); }
+                println!("");
+            }
+            if json.is_some() {
+                ranges.push(Json::Object(vec![
+                    ("spec".to_owned(), Json::str(&dump_spec[..])),
+                    ("start".to_owned(), exec::json::addr(base_pc)),
+                    ("end".to_owned(), exec::json::addr(expected_end)),
+                    ("insns".to_owned(), Json::Array(insns)),
+                ]));
+            } else if last_end < expected_end {
+                println!("...skip {}", expected_end - last_end);
+            } else if last_end > expected_end {
+                println!("...over-read by {}", last_end - expected_end);
             }
-            println!("-> {}: {}", pc,
-                if let Some(ref s) = dissed { &s[..] } else { "<?>" });
-            last_end = pc + length.ext();
         }
-        let expected_end = base_pc + (dump_data.len() as u64);
-        if last_end < expected_end {
-            println!("...skip {}", expected_end - last_end);
-        } else if last_end > expected_end {
-            println!("...over-read by {}", last_end - expected_end);
+        if let Some(ref mut out) = *json {
+            out.push(("dis-range".to_owned(), Json::Array(ranges)));
         }
     }
 }
 
-fn do_mut_stuff(ex: &mut exec::Exec, m: &getopts::Matches) {
+fn do_mut_stuff(ex: &mut exec::Exec, m: &getopts::Matches, json: &mut JsonOut) {
     fn get_elf<'a>(exe: &'a mut exec::Exec) -> &'a mut elf::Elf { exe.as_any_mut().downcast_mut::<elf::Elf>().expect("not elf") }
-    fn get_macho<'a>(exe: &'a mut exec::Exec) -> &'a mut macho::MachO { exe.as_any_mut().downcast_mut::<macho::MachO>().expect("not macho") }
     if let Some(out_file) = m.opt_str("extract") {
-        // TODO generic
-        let macho = get_macho(ex);
-        macho_dsc_extraction::extract_as_necessary(macho, None, None, /*minimal_processing*/ false).unwrap();
+        let res = if let Some(macho) = ex.as_any_mut().downcast_mut::<macho::MachO>() {
+            // this ends with a write_back, after pulling the image out of a cache if needed
+            macho_dsc_extraction::extract_as_necessary(macho, None, None, /*minimal_processing*/ false)
+                .map(|()| macho.eb.whole_buf.clone().unwrap())
+        } else {
+            ex.write_back()
+        };
+        let buf = res.unwrap_or_else(|e| {
+            errln!("extract failed: {}", e);
+            util::exit();
+        });
         let mut fp = fs::File::create(&Path::new(&out_file)).unwrap();
-        let buf = unsafe { macho.eb.whole_buf.as_ref().unwrap().get_plain_slice() };
-        fp.write_all(buf).unwrap();
+        fp.write_all(unsafe { buf.get_plain_slice() }).unwrap();
+    }
+    // the JSON form is the raw entries either way
+    if let Some(ref mut out) = *json {
+        for &name in &["elf-dynamic", "elf-dynamic-raw"] {
+            if !m.opt_present(name) { continue; }
+            let elf = get_elf(ex);
+            out.push((name.to_owned(), Json::Array(elf.dyns.iter().map(|dyn| dyn.to_json()).collect())));
+        }
+        return;
     }
     if m.opt_present("elf-dynamic") {
         let elf = get_elf(ex);
@@ -287,6 +784,7 @@ fn main() {
     let top = "Usage: exectool <binary> [format...] [-- ops...]";
     let mut optgrps = vec!(
         getopts::optflag("v", "verbose", "Verbose mode"),
+        getopts::optflag("",  "json",  "Print listings as one JSON object, keyed by option name"),
         getopts::optopt( "",  "arch",  "Architecture bias", "arch"),
         getopts::optflag("",  "segs",  "List segments"),
         getopts::optflag("",  "sects", "List sections"),
@@ -297,15 +795,22 @@ fn main() {
         getopts::optflag("",  "relocs", "List relocs"),
         getopts::optopt( "",  "o2a",   "Offset to address", "off"),
         getopts::optopt( "",  "a2o",   "Address to offset", "addr"),
-        getopts::optopt( "",  "dump",  "Dump address range", "addr+len"),
-        getopts::optopt( "",  "dis-range",   "Disassemble address range", "addr+len"),
+        getopts::optmulti("", "dump",  "Dump address range (or section, symbol or function start)", "addr+len"),
+        getopts::optmulti("", "dis-range",   "Disassemble address range (or section, symbol or function start)", "addr+len"),
         getopts::optopt( "",  "dis",   "Disassembler name and options", "llvm/..."),
+        getopts::optflag("",  "functions", "List functions (with -v, their basic blocks)"),
+        getopts::optopt( "",  "cfg",   "Print control flow graphs", "dot|json"),
+        getopts::optopt( "",  "cfg-func", "Limit --cfg to the function containing addr", "addr"),
+        getopts::optopt( "",  "db",    "Load names from, and save functions to, a range database", "file"),
+        getopts::optopt( "",  "import", "Import names from a symbol map (nm, ld-map, ld64-map, idc, ida-map, ghidra-csv)", "[format:]file"),
+        getopts::optopt( "",  "import-base", "Image base the symbol map was made against, if it can't be worked out", "addr"),
         getopts::optopt( "",  "extract", "Rewrite whole file", "outfile"),
         // todo: option groups
         getopts::optflag("",  "macho-filedata-info", "List data areas within the file"),
         getopts::optflag("",  "elf-dynamic", "List ELF .dynamic contents"),
         getopts::optflag("",  "elf-dynamic-raw", "List ELF .dynamic contents (raw)"),
-        getopts::optflag("",  "elf-append-version", "When listing symbols, include @VERSION"),
+        getopts::optflag("",  "elf-append-version", "When listing symbols, include @VERSION (@@VERSION for default definitions)"),
+        getopts::optflag("",  "elf-versions", "List ELF symbol version definitions and requirements"),
     );
     let mut args: Vec<String> = std::env::args().collect();
     if args.len() < 2 || args[1].starts_with("-") {
@@ -337,8 +842,12 @@ fn main() {
             }
         });
         let m = util::do_getopts_or_usage(&*real_args, top, 0, 0, &mut optgrps).unwrap_or_else(usage_panic);
-        do_stuff(&ex, &m);
-        do_mut_stuff(&mut *ex, &m);
+        let mut json = if m.opt_present("json") { Some(Vec::new()) } else { None };
+        do_stuff(&ex, &filename, &m, &mut json);
+        do_mut_stuff(&mut *ex, &m, &mut json);
+        if let Some(fields) = json {
+            println!("{:#}", Json::Object(fields));
+        }
     } else {
         let results = exec::probe_all(&fmt_all::all_probers(), mm.clone());
         // no format specified, give a list
@@ -352,3 +861,95 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exec::{Exec, ExecBase, Segment, Symbol};
+    use std::borrow::Cow;
+
+    // __TEXT maps 0x100 bytes whose values are their offsets at 0x1000, with __text in the middle;
+    // __DATA maps 0x80 bytes at 0x2000, then zerofill
+    struct Fixture {
+        eb: ExecBase,
+        syms: Vec<(&'static str, u64, Option<u64>)>,
+    }
+
+    impl Exec for Fixture {
+        fn get_exec_base(&self) -> &ExecBase { &self.eb }
+        fn get_symbol_list(&self, _source: exec::SymbolSource, _specific: Option<&dyn Any>) -> Vec<Symbol> {
+            self.syms.iter().map(|&(name, addr, size)| Symbol {
+                name: Cow::Borrowed(ByteStr::from_str(name)),
+                is_public: !name.starts_with('$'),
+                is_weak: false,
+                val: SymbolValue::Addr(VMA(addr)),
+                size: size,
+                kind: SymbolKind::Func,
+                attrs: 0,
+                private: 0,
+            }).collect()
+        }
+        fn get_function_starts(&self) -> Vec<VMA> {
+            vec![VMA(0x1020), VMA(0x1010)]
+        }
+        fn as_any(&self) -> &dyn Any { self }
+    }
+
+    fn fixture() -> Fixture {
+        let seg = |name: &str, vmaddr: u64, vmsize: u64, fileoff: u64, filesize: u64, seg_idx: Option<usize>| Segment {
+            vmaddr: VMA(vmaddr), vmsize: vmsize, fileoff: fileoff, filesize: filesize,
+            name: Some(util::ByteString::from_str(name)), seg_idx: seg_idx, ..Default::default()
+        };
+        let mut segments = vec![seg("__TEXT", 0x1000, 0x100, 0, 0x100, None), seg("__DATA", 0x2000, 0x100, 0x100, 0x80, None)];
+        segments[0].data = Some(util::Mem::with_vec((0..0x100).map(|i| i as u8).collect()));
+        segments[1].data = Some(util::Mem::with_vec(vec![0xdd; 0x80]));
+        let eb = ExecBase {
+            segments: segments,
+            sections: vec![seg("__text", 0x1010, 0x40, 0x10, 0x40, Some(0))],
+            ..Default::default()
+        };
+        Fixture {
+            eb: eb,
+            // _helper has no size, so it runs to _tail, which runs to the end of __text; the
+            // mapping symbol doesn't count
+            syms: vec![("_main", 0x1010, Some(0x10)), ("_helper", 0x1030, None), ("_tail", 0x1038, None),
+                       ("$x", 0x1040, None)],
+        }
+    }
+
+    #[test]
+    fn test_range_spec() {
+        let ex = fixture();
+        let range = |spec: &str| get_range_from_spec(&ex, spec).map(|(addr, size)| (addr.0, size));
+        assert_eq!(range("0x1000+0x10"), Ok((0x1000, 0x10)));
+        assert_eq!(range("0x1000-0x1010"), Ok((0x1000, 0x10)));
+        assert!(range("0x1010-0x1000").unwrap_err().contains("end < start"));
+        // sections and segments
+        assert_eq!(range("__TEXT,__text"), Ok((0x1010, 0x40)));
+        assert_eq!(range("section:__text"), Ok((0x1010, 0x40)));
+        assert_eq!(range("__DATA"), Ok((0x2000, 0x100)));
+        assert!(range("__DATA,__text").is_err());
+        // symbols
+        assert_eq!(range("_main"), Ok((0x1010, 0x10)));
+        assert_eq!(range("symbol:_helper"), Ok((0x1030, 8)));
+        assert_eq!(range("_tail"), Ok((0x1038, 0x18)));
+        assert!(range("section:_main").is_err());
+        assert!(range("symbol:__text").is_err());
+        // function starts, up to the next one or the end of the section
+        assert_eq!(range("0x1010"), Ok((0x1010, 0x10)));
+        assert_eq!(range("function:0x1020"), Ok((0x1020, 0x30)));
+        assert!(range("0x1030").is_err());
+        assert!(range("nonesuch").is_err());
+    }
+
+    #[test]
+    fn test_dump_spec() {
+        let ex = fixture();
+        assert_eq!(get_dump_from_spec(&ex, "0x1010+4"), Ok((VMA(0x1010), vec![0x10, 0x11, 0x12, 0x13])));
+        let (addr, data) = get_dump_from_spec(&ex, "_main").unwrap();
+        assert_eq!((addr, data), (VMA(0x1010), (0x10..0x20).collect::<Vec<u8>>()));
+        assert_eq!(get_dump_from_spec(&ex, "0x207e+2"), Ok((VMA(0x207e), vec![0xdd, 0xdd])));
+        assert!(get_dump_from_spec(&ex, "0x207e+4").unwrap_err().starts_with("zerofill"));
+        assert!(get_dump_from_spec(&ex, "0x10fe+4").unwrap_err().starts_with("unmapped"));
+    }
+}
@@ -3,14 +3,16 @@
 extern crate macros;
 extern crate util;
 extern crate exec;
+extern crate bsdlike_getopts as getopts;
 extern crate fmt_elf_bind as elf_bind;
 
 use std::mem::size_of;
 use std::fmt::{Display, LowerHex, Write};
 use std::borrow::Cow;
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
+use std::cmp::max;
 
 extern crate vec_map;
 use vec_map::VecMap;
@@ -18,9 +20,15 @@ use util::SmallVector;
 
 use exec::arch::Arch;
 use util::{Mem, SliceExt, ByteStr, ByteString, Swap, CheckMul, CheckAdd, CheckSub, Ext, Lazy, Narrow, ReadCell};
-use exec::{ExecResult, ErrorKind, Segment, VMA, Prot, Symbol, SymbolValue, SymbolSource, SourceLib, DepLib, read_cstr, ReadVMA};
+use util::json::Json;
+use exec::{ExecResult, ErrorKind, Segment, VMA, Prot, Symbol, SymbolValue, SymbolKind, SymbolSource, SourceLib, DepLib, Reloc, read_cstr, ReadVMA};
 use elf_bind::*;
 
+mod reloc;
+mod plt;
+mod sections;
+pub use plt::PltStub;
+
 macro_rules! convert_each {
     ($val:expr, $ty:ident, $($field:ident),*) => {
         $ty { $($field: $val.$field.into()),* }
@@ -47,6 +55,7 @@ pub struct DynamicInfo {
     gnu_hash: Option<VMA>,
     rela: Option<OffCountSize>,
     rel: Option<OffCountSize>,
+    relr: Option<OffCountSize>,
     init: Option<VMA>,
     fini: Option<VMA>,
     soname: Option<ByteString>,
@@ -63,6 +72,7 @@ pub struct DynamicInfo {
     flags: Option<u32>,
     versym: Option<VMA>,
     verneed: Option<OffCountSize>,
+    verdef: Option<OffCountSize>,
     // shouldn't be used really
     relacount: Option<u32>,
     relcount: Option<u32>,
@@ -81,10 +91,12 @@ struct DynamicInfoTemp {
     strtab: OptOffCountSize,
     rela: OptOffCountSize,
     rel: OptOffCountSize,
+    relr: OptOffCountSize,
     init_array: OptOffCountSize,
     fini_array: OptOffCountSize,
     preinit_array: OptOffCountSize,
     verneed: OptOffCountSize,
+    verdef: OptOffCountSize,
 }
 
 
@@ -118,6 +130,28 @@ struct VerneedInfo {
     dep_lib_idx_to_verneed_idxs: Vec<SmallVector<usize>>,
 }
 
+// the versions this object defines
+#[derive(Debug)]
+struct Verdef {
+    ndx: u16,
+    flags: u16,
+    name: ByteString,
+    // the remaining verdaux entries, which name the versions this one inherits from
+    parents: Vec<ByteString>,
+}
+
+#[derive(Default)]
+struct VerdefInfo {
+    verdef: Vec<Verdef>,
+    ndx_to_idx: VecMap<usize>,
+}
+
+impl VerdefInfo {
+    fn name(&self, ndx: u16) -> Option<&ByteStr> {
+        self.ndx_to_idx.get(ndx.ext()).map(|&i| &*self.verdef[i].name)
+    }
+}
+
 impl DynamicInfo {
     #[inline(always)]
     fn do_<OS: DIFunc<Option<ByteString>>,
@@ -139,6 +173,7 @@ impl DynamicInfo {
         ovma(&mut self.gnu_hash, DT_GNU_HASH) ||
         oocs(&mut self.rela, &mut temp.rela, (DT_RELA, DT_RELASZ, DT_RELAENT), OCSTotalSize) ||
         oocs(&mut self.rel, &mut temp.rel, (DT_REL, DT_RELSZ, DT_RELENT), OCSTotalSize) ||
+        oocs(&mut self.relr, &mut temp.relr, (DT_RELR, DT_RELRSZ, DT_RELRENT), OCSTotalSize) ||
         ovma(&mut self.init, DT_INIT) ||
         ovma(&mut self.fini, DT_FINI) ||
         os(&mut self.soname, DT_SONAME) ||
@@ -154,6 +189,7 @@ impl DynamicInfo {
         os(&mut self.runpath, DT_RUNPATH) ||
         ou32(&mut self.flags, DT_FLAGS) ||
         oocs(&mut self.verneed, &mut temp.verneed, (DT_VERNEED, DT_VERNEEDNUM, 0), OCSCount) ||
+        oocs(&mut self.verdef, &mut temp.verdef, (DT_VERDEF, DT_VERDEFNUM, 0), OCSCount) ||
         ovma(&mut self.versym, DT_VERSYM) ||
         ou32(&mut self.relacount, DT_RELACOUNT) ||
         ou32(&mut self.relcount, DT_RELCOUNT) ||
@@ -174,6 +210,7 @@ impl DynamicInfo {
         temp.fini_array.size = Some(1);
         temp.preinit_array.size = Some(1);
         temp.verneed.size = Some(if is64 { size_of::<Elf64_Verneed>() } else { size_of::<Elf32_Verneed>() } as u64);
+        temp.verdef.size = Some(if is64 { size_of::<Elf64_Verdef>() } else { size_of::<Elf32_Verdef>() } as u64);
         for &d in dyn {
             let res = me.do_(&mut temp,
                      #[inline(always)] |os: &mut Option<ByteString>, tag| {
@@ -265,6 +302,12 @@ impl DynamicInfo {
                 errln!("warning: unhandled dyn tag {} ({})", d.tag, d.tag_name().unwrap_or("?"));
             }
         }
+        // Elf::dynsym_syment papers over these
+        match me.syment {
+            None if me.symtab.is_some() => errln!("warning: SYMTAB but no SYMENT?"),
+            Some(syment) if (syment as usize) < min_syment(is64) => errln!("warning: bad DT_SYMENT {}", syment),
+            _ => (),
+        }
         me
 
     }
@@ -299,6 +342,7 @@ impl DynamicInfo {
                     if let Some(e) = elf {
                         match otag {
                             DT_VERNEED => e.dump_verneed(),
+                            DT_VERDEF => e.dump_verdef(),
                             _ => (),
 
                         }
@@ -414,6 +458,67 @@ impl DynamicInfo {
             dep_lib_idx_to_verneed_idxs: dep_lib_idx_to_verneed_idxs,
         }
     }
+    fn fetch_verdef_info(&self, elf: &Elf) -> VerdefInfo {
+        let ocs = some_or!(self.verdef, { return VerdefInfo::default(); });
+        let mut addr = VMA(ocs.off);
+        let mut verdef = Vec::new();
+        let mut ndx_to_idx = VecMap::new();
+        for _ in 0..ocs.count {
+            let data = elf.eb.read(addr, ocs.size);
+            if (data.len() as u64) < ocs.size {
+                errln!("warning: verdef data truncated");
+                break;
+            }
+            branch!(if (elf.basics.is64) {
+                type ElfX_Verdef = Elf64_Verdef;
+                type ElfX_Verdaux = Elf64_Verdaux;
+            } else {
+                type ElfX_Verdef = Elf32_Verdef;
+                type ElfX_Verdaux = Elf32_Verdaux;
+            } then {
+                let vd: ElfX_Verdef = util::copy_from_slice(data.get(), elf.basics.endian);
+                if vd.vd_version as u32 != VER_DEF_CURRENT {
+                    errln!("warning: verdef version = {}, not VER_DEF_CURRENT({})",
+                           vd.vd_version, VER_DEF_CURRENT);
+                }
+                // the first verdaux entry names the version itself
+                let mut auxaddr = addr + vd.vd_aux as u64;
+                let mut names = Vec::new();
+                for _j in 0..vd.vd_cnt {
+                    let data = elf.eb.read(auxaddr, size_of::<ElfX_Verdaux>() as u64);
+                    if data.len() < size_of::<ElfX_Verdaux>() {
+                        errln!("warning: invalid verdaux entry");
+                        break;
+                    }
+                    let vda: ElfX_Verdaux = util::copy_from_slice(data.get(), elf.basics.endian);
+                    auxaddr = auxaddr + vda.vda_next as u64;
+                    let name = some_or!(elf.read_dynstr(vda.vda_name as u64),
+                                        { errln!("warning: verdaux name invalid"); continue; });
+                    names.push(name.into_owned());
+                }
+                if names.is_empty() {
+                    errln!("warning: verdef index {} has no valid name", vd.vd_ndx);
+                } else {
+                    if ndx_to_idx.insert(vd.vd_ndx.ext(), verdef.len()).is_some() {
+                        errln!("warning: duplicate verdef index {}", vd.vd_ndx);
+                    }
+                    let name = names.remove(0);
+                    verdef.push(Verdef {
+                        ndx: vd.vd_ndx,
+                        flags: vd.vd_flags,
+                        name: name,
+                        parents: names,
+                    });
+                }
+                if vd.vd_next == 0 { break; }
+                addr = addr + vd.vd_next as u64;
+            });
+        }
+        VerdefInfo {
+            verdef: verdef,
+            ndx_to_idx: ndx_to_idx,
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -439,11 +544,20 @@ pub struct Elf {
     pub eb: exec::ExecBase,
     pub basics: ElfBasics,
     pub ehdr: Ehdr,
+    pub phdrs: Vec<Phdr>,
     pub shdrs: Vec<Shdr>,
+    // the section headers were missing or bad, so shdrs and eb.sections were made up
+    pub sections_reconstructed: bool,
+    // if so, have write_back add a section header table
+    write_sections: bool,
     pub dyns: Vec<Dyn>,
     pub dynamic_info: DynamicInfo,
     pub dynstr: Mem<u8>,
     verneed_info_cache: Lazy<VerneedInfo>,
+    verdef_info_cache: Lazy<VerdefInfo>,
+    dynsym_cache: Lazy<Vec<Symbol<'static>>>,
+    symtab_cache: Vec<Lazy<Vec<Symbol<'static>>>>,
+    plt_cache: Lazy<Vec<PltStub>>,
 }
 
 fn fix_ocs(cs: &mut OffCountSize, len: usize, what: &str) {
@@ -502,6 +616,7 @@ fn get_phdrs(basics: &ElfBasics, buf: &[ReadCell<u8>], ocs: &OffCountSize) -> (V
         type ElfX_Phdr = Elf32_Phdr;
     } then {
         let sizeo = size_of::<ElfX_Phdr>();
+        if ocs.count == 0 { return (Vec::new(), Vec::new()); }
         if ocs.size < sizeo as u64 {
             errln!("warning: phdr size ({}) too small, expected at least {}", ocs.size, sizeo);
             return (Vec::new(), Vec::new());
@@ -560,7 +675,7 @@ fn get_shdrs(basics: &ElfBasics, buf: &[ReadCell<u8>], ocs: &OffCountSize) -> (V
                 vmaddr: VMA(shdr.sh_addr as u64),
                 vmsize: shdr.sh_size as u64,
                 fileoff: shdr.sh_offset as u64,
-                filesize: shdr.sh_size as u64,
+                filesize: if shdr.sh_type == SHT_NOBITS { 0 } else { shdr.sh_size as u64 },
                 name: None, // fill in later
                 prot: Prot {
                     r: true,
@@ -583,6 +698,39 @@ fn get_shdrs(basics: &ElfBasics, buf: &[ReadCell<u8>], ocs: &OffCountSize) -> (V
     (segs, shdrs)
 }
 
+// ET_REL files have no program headers, and their sections all start at 0.  Give each allocated
+// section its own address and a segment to go with it, so ReadVMA and friends work.
+fn lay_out_rel_sections(segs: &mut Vec<Segment>, sects: &mut [Segment], shdrs: &[Shdr], base: VMA) {
+    let mut addr = base;
+    for (i, (sect, shdr)) in sects.iter_mut().zip(shdrs).enumerate() {
+        if shdr.sh_flags & (SHF_ALLOC as u64) == 0 || shdr.sh_size == 0 { continue; }
+        let mut align = max(shdr.sh_addralign, 1);
+        if !align.is_power_of_two() {
+            errln!("warning: section {} has non-power-of-2 alignment {}", sect.pretty_name(), align);
+            align = 1;
+        }
+        addr = addr.wrapping_align_up_to(align);
+        sect.vmaddr = addr;
+        sect.seg_idx = Some(segs.len());
+        segs.push(Segment {
+            vmaddr: addr,
+            vmsize: sect.vmsize,
+            fileoff: sect.fileoff,
+            filesize: sect.filesize,
+            name: sect.name.clone(),
+            prot: sect.prot,
+            data: sect.data.clone(),
+            seg_idx: None,
+            private: i,
+        });
+        addr = addr.wrapping_add(sect.vmsize);
+    }
+}
+
+fn min_syment(is64: bool) -> usize {
+    if is64 { size_of::<Elf64_Sym>() } else { size_of::<Elf32_Sym>() }
+}
+
 fn check_start_size(start: u64, size: u64) -> Option<(usize, usize)> {
     if start > (std::usize::MAX as u64) || size > (std::usize::MAX as u64) { return None; }
     let start = start as usize; let size = size as usize;
@@ -632,6 +780,12 @@ impl Dyn {
             d_tag_to_str(self.tag as u32)
         } else { None }
     }
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("tag".to_owned(), self.tag_name().map_or_else(|| Json::num(self.tag), Json::str)),
+            ("val".to_owned(), Json::str(format!("0x{:x}", self.val))),
+        ])
+    }
 }
 
 
@@ -733,23 +887,36 @@ impl Swap for GNUHashHeader {
 
 struct GNUHash {
     header: GNUHashHeader,
-    #[allow(dead_code)]
     bitmask: Mem<u8>,
     buckets: Mem<u8>,
     chain_addr: VMA,
 }
 
 impl Elf {
-    fn new(buf: Mem<u8>) -> ExecResult<Self> {
+    // rel_base: where to start laying out sections if this is an ET_REL file; reconstruct: make
+    // up sections if the section headers are missing or look bad
+    fn new(buf: Mem<u8>, rel_base: VMA, reconstruct: bool) -> ExecResult<Self> {
+        let mut bad_shdrs = None;
         let mut res = {
             let b = buf.get();
             let basics = try!(check_elf_basics(b, true).map_err(|a| exec::err_only(ErrorKind::BadData, a)));
             let ehdr = try!(get_ehdr(&basics, b));
             let (mut segs, phdrs) = get_phdrs(&basics, b, &ehdr.ph);
-            let (mut sects, shdrs) = get_shdrs(&basics, b, &ehdr.sh);
+            let (mut sects, mut shdrs) = get_shdrs(&basics, b, &ehdr.sh);
             fill_in_data(&mut segs, &buf);
             fill_in_data(&mut sects, &buf);
             fill_in_sect_names(&mut sects, &shdrs, ehdr.shstrndx);
+            if ehdr.type_ as u32 == ET_REL && phdrs.is_empty() {
+                lay_out_rel_sections(&mut segs, &mut sects, &shdrs, rel_base);
+            }
+            if reconstruct {
+                bad_shdrs = sections::shdrs_look_bad(&phdrs, &sects, &shdrs);
+            }
+            if let Some(ref why) = bad_shdrs {
+                errln!("warning: reconstructing sections: {}", why);
+                sects.clear();
+                shdrs.clear();
+            }
             let dyns = get_dynamic(&basics, &segs, &phdrs, &sects, &shdrs);
             let eb = exec::ExecBase {
                 arch: basics.arch,
@@ -763,18 +930,29 @@ impl Elf {
                 eb: eb,
                 basics: basics,
                 ehdr: ehdr,
+                phdrs: phdrs,
                 shdrs: shdrs,
+                sections_reconstructed: false,
+                write_sections: false,
                 dyns: dyns,
                 dynamic_info: Default::default(),
                 dynstr: Default::default(),
                 verneed_info_cache: Lazy::new(),
+                verdef_info_cache: Lazy::new(),
+                dynsym_cache: Lazy::new(),
+                symtab_cache: Vec::new(),
+                plt_cache: Lazy::new(),
             }
         };
         res.eb.whole_buf = Some(buf);
+        res.symtab_cache = res.shdrs.iter().map(|_| Lazy::new()).collect();
         res.dynamic_info = DynamicInfo::decode(&res.dyns, &res.eb, res.basics.is64);
         if let Some(strtab) = res.dynamic_info.strtab {
             res.dynstr = res.eb.read(VMA(strtab.off), strtab.count);
         }
+        if bad_shdrs.is_some() {
+            res.reconstruct_sections();
+        }
         Ok(res)
     }
     fn read_dynstr<'a>(&'a self, off: u64) -> Option<Cow<'a, ByteStr>> {
@@ -834,14 +1012,10 @@ impl Elf {
     }
     fn get_full_symtab(&self, mode: SymtabTraverseMode) -> Option<(Mem<u8> /*symtab*/, Mem<u8> /*versym*/, usize /* syment */)> {
         let symtab = some_or!(self.dynamic_info.symtab, { return None });
-        let syment = self.dynamic_info.syment.unwrap_or_else(|| {
-            errln!("warning: SYMTAB but no SYMENT? (get_full_symtab)");
-            (if self.basics.is64 { size_of::<Elf64_Sym>() } else { size_of::<Elf32_Sym>() }) as u32
-        }) as u64;
+        let syment = self.dynsym_syment() as u64;
         let symcount: u64 = match mode {
             SymtabTraverseMode::Hash => {
                 let dt_hash = some_or!(self.dynamic_info.hash, { return None });
-                some_or!(None, return None);
                 let nchain_buf = self.eb.read(dt_hash + 4, 4);
                 if nchain_buf.len() != 4 {
                     errln!("warning: invalid DT_HASH address (get_full_symtab)");
@@ -919,6 +1093,56 @@ impl Elf {
     fn get_verneed_info(&self) -> &VerneedInfo {
         self.verneed_info_cache.get(|| self.dynamic_info.fetch_verneed_info(self))
     }
+    fn get_verdef_info(&self) -> &VerdefInfo {
+        self.verdef_info_cache.get(|| self.dynamic_info.fetch_verdef_info(self))
+    }
+    fn dump_verdef(&self) {
+        for vd in &self.get_verdef_info().verdef {
+            print!(" -> def '{}'(id={}, flags={})", vd.name, vd.ndx, vd.flags);
+            for parent in &vd.parents {
+                print!(" parent '{}'", parent);
+            }
+            print!("\n");
+        }
+    }
+    // the versions defined here (with the ones each inherits from) and those needed from each
+    // dependency
+    pub fn dump_versions(&self) {
+        println!("Version definitions:");
+        for vd in &self.get_verdef_info().verdef {
+            print!("  {:<3} {}{}", vd.ndx, vd.name, version_flags_str(vd.flags));
+            if !vd.parents.is_empty() {
+                print!(" <- {}", vd.parents.iter().map(|p| p.lossy()).collect::<Vec<_>>().join(", "));
+            }
+            print!("\n");
+        }
+        println!("Version requirements:");
+        for vn in &self.get_verneed_info().verneed {
+            println!("  {}:", vn.filename);
+            for vna in &vn.aux {
+                println!("    {:<3} {}{}", vna.other, vna.name, version_flags_str(vna.flags));
+            }
+        }
+    }
+    pub fn versions_to_json(&self) -> Json {
+        let flags = |flags: u16| Json::Array(version_flag_names(flags).into_iter().map(Json::str).collect());
+        Json::Object(vec![
+            ("definitions".to_owned(), Json::Array(self.get_verdef_info().verdef.iter().map(|vd| Json::Object(vec![
+                ("index".to_owned(), Json::num(vd.ndx)),
+                ("name".to_owned(), Json::str(vd.name.lossy())),
+                ("flags".to_owned(), flags(vd.flags)),
+                ("parents".to_owned(), Json::Array(vd.parents.iter().map(|p| Json::str(p.lossy())).collect())),
+            ])).collect())),
+            ("requirements".to_owned(), Json::Array(self.get_verneed_info().verneed.iter().map(|vn| Json::Object(vec![
+                ("file".to_owned(), Json::str(vn.filename.lossy())),
+                ("versions".to_owned(), Json::Array(vn.aux.iter().map(|vna| Json::Object(vec![
+                    ("index".to_owned(), Json::num(vna.other)),
+                    ("name".to_owned(), Json::str(vna.name.lossy())),
+                    ("flags".to_owned(), flags(vna.flags)),
+                ])).collect())),
+            ])).collect())),
+        ])
+    }
     fn dump_verneed(&self) {
         let vns = self.get_verneed_info();
         for vn in &vns.verneed {
@@ -930,89 +1154,92 @@ impl Elf {
         }
 
     }
-
-}
-
-#[derive(Default, Clone)]
-pub struct ElfGetSymbolListSpecific {
-    pub append_version: bool,
-}
-
-impl exec::Exec for Elf {
-    fn get_exec_base<'a>(&'a self) -> &'a exec::ExecBase {
-        &self.eb
-    }
-    fn as_any(&self) -> &Any { self as &Any }
-
-    fn get_symbol_list(&self, source: SymbolSource, specific: Option<&Any>) -> Vec<Symbol> {
-        let esp = if let Some(sp) = specific {
-            sp.downcast_ref::<ElfGetSymbolListSpecific>().unwrap()
+    // strtab None means dynstr
+    fn read_strtab<'a>(&'a self, strtab: Option<&'a [ReadCell<u8>]>, off: u64) -> Option<Cow<'a, ByteStr>> {
+        if let Some(st) = strtab {
+            if off < st.len() as u64 {
+                return util::from_cstr_strict(&st[(off as usize)..]).map(Cow::Borrowed);
+            }
+            None
         } else {
-            static DEFAULT: ElfGetSymbolListSpecific = ElfGetSymbolListSpecific { append_version: false };
-            &DEFAULT
-        };
-
-        // try to get the count somehow
-        let (symtab, versym, syment) = some_or!(
-            self.get_full_symtab(SymtabTraverseMode::BruteForce).or_else(||
-            self.get_full_symtab(SymtabTraverseMode::GNUHash)).or_else(||
-            self.get_full_symtab(SymtabTraverseMode::Hash)),
-            { return Vec::new(); });
-        let symtab = symtab.get();
-        let versym = versym.get();
+            self.read_dynstr(off)
+        }
+    }
+    // `first` is the number of the first symbol in symtab (and versym)
+    fn decode_symbols<'a>(&'a self, symtab: &[ReadCell<u8>], versym: &[ReadCell<u8>], syment: usize, first: usize,
+                          strtab: Option<&'a [ReadCell<u8>]>, source: SymbolSource,
+                          esp: &ElfGetSymbolListSpecific) -> Vec<Symbol<'a>> {
         let verneed_info = self.get_verneed_info();
+        let verdef_info = self.get_verdef_info();
         let end = self.eb.endian;
         branch!(if (self.basics.is64) {
             type ElfX_Sym = Elf64_Sym;
         } else {
             type ElfX_Sym = Elf32_Sym;
         } then {
-            symtab.chunks(syment).enumerate().map(|(i, symdat)| {
-                let sym: ElfX_Sym = util::copy_from_slice(symdat, end);
-                let vs: u16 = if versym.len() >= (2 * i + 2) {
-                    util::copy_from_slice(&versym[2 * i..2 * i + 2], end)
+            symtab.chunks(syment).enumerate().map(|(j, symdat)| {
+                let i = first + j;
+                let sym: ElfX_Sym = util::copy_from_slice(&symdat[..size_of::<ElfX_Sym>()], end);
+                let st_type = sym.st_info & 0xf;
+                // the null symbol and file names aren't interesting
+                if i == 0 || st_type as u32 == STT_FILE { return None; }
+                let has_vs = versym.len() >= (2 * j + 2);
+                let vs: u16 = if has_vs {
+                    util::copy_from_slice(&versym[2 * j..2 * j + 2], end)
                 } else { 0 };
-                let mut name = self.read_dynstr(sym.st_name as u64)
+                let mut name = self.read_strtab(strtab, sym.st_name as u64)
                                .unwrap_or_else(|| {
                                 errln!("warning: symbol has invalid st_name {}", sym.st_name);
                                 ByteString::from_string(format!("<<{}>>", sym.st_name)).into()
                                 });
+                if st_type as u32 == STT_SECTION && name.len() == 0 {
+                    if let Some(&Some(ref sect_name)) = self.eb.sections.get(sym.st_shndx as usize).map(|s| &s.name) {
+                        name = Cow::Borrowed(&**sect_name);
+                    }
+                }
                 let vs_hidden = vs & 0x8000 != 0;
                 let vs_key = vs & 0x7fff;
+                let defined = sym.st_shndx as u32 != SHN_UNDEF;
+                // 0 and 1 are local and global (unversioned); anything else is a verdef index for
+                // definitions and a vernaux one for references
                 let mut source_lib = SourceLib::None;
-                if vs_hidden || (esp.append_version && vs_key != 0) {
-                    // only visible with an explicit version number, so... tack on the version
-                    // number? (otherwise i don't really want to)
-                    let verstr: &ByteStr = if vs_key == 0 {
-                        ByteStr::from_str("")
-                    } else if vs_key == 1 {
-                        ByteStr::from_str("Base")
-                    } else if let Some(&(i, j)) =
-                                    verneed_info.vna_other_to_idx.get(vs_key.ext()) {
+                let mut verstr: Option<&ByteStr> = None;
+                if vs_key as u32 > VER_NDX_GLOBAL {
+                    if defined {
+                        verstr = verdef_info.name(vs_key);
+                        if verstr.is_none() {
+                            errln!("warning: invalid verdef index 0x{:x}", vs_key);
+                        }
+                    } else if let Some(&(i, j)) = verneed_info.vna_other_to_idx.get(vs_key.ext()) {
                         let vn = &verneed_info.verneed[i];
                         source_lib = SourceLib::Ordinal(vn.dep_lib_idx.narrow().unwrap());
-                        &*vn.aux[j].name
+                        verstr = Some(&*vn.aux[j].name);
                     } else {
                         errln!("warning: invalid verneed key 0x{:x}", vs_key);
-                        ByteStr::from_str("?????")
-                    };
-                    // note: vernum=0 means none, vernum=1 means 'Base' whatever that means, but
-                    // for hidden it probably shouldn't be those? so keep the error
-                    let n = name.to_mut();
-                    n.0.push(b'@');
-                    if vs_hidden { n.0.push(b'@'); }
-                    n.0.extend_from_slice(verstr);
+                    }
+                }
+                // a hidden version is only visible with an explicit version number, so it always
+                // gets one: name@VER, vs. name@@VER for the default version of a definition
+                if let Some(verstr) = verstr {
+                    if vs_hidden || esp.append_version {
+                        let n = name.to_mut();
+                        n.0.push(b'@');
+                        if defined && !vs_hidden { n.0.push(b'@'); }
+                        n.0.extend_from_slice(verstr);
+                    }
                 }
                 let st_bind = sym.st_info >> 4;
                 let stval = sym.st_value as u64;
                 if source == SymbolSource::Exported &&
-                   (sym.st_shndx as u32 == SHN_UNDEF ||
-                    vs != 0) {
+                   (!defined ||
+                    st_bind as u32 == STB_LOCAL ||
+                    (has_vs && vs_key as u32 == VER_NDX_LOCAL)) {
                     return None;
                 }
                 if source == SymbolSource::Imported && sym.st_shndx as u32 != SHN_UNDEF {
                     return None;
                 }
+                let is_tls = st_type as u32 == STT_TLS;
                 // TODO SHN_XINDEX
                 let val = match sym.st_shndx as u32 {
                     SHN_ABS => SymbolValue::Abs(VMA(stval)),
@@ -1023,13 +1250,19 @@ impl exec::Exec for Elf {
                     },
                     // ugh ugh ugh
                     _ => if self.ehdr.type_ as u32 != ET_REL {
-                        SymbolValue::Addr(VMA(stval))
+                        // TLS values are already offsets into the PT_TLS image
+                        if is_tls { SymbolValue::ThreadLocal(VMA(stval)) } else { SymbolValue::Addr(VMA(stval)) }
                     } else {
                         if let Some(sect) = self.eb.sections.get(sym.st_shndx as usize) {
                             if stval > sect.vmsize || sym.st_size as u64 > sect.vmsize - stval {
                                 errln!("warning: section offset out of range for symbol {}", i);
                             }
-                            SymbolValue::Addr(sect.vmaddr + stval)
+                            if is_tls {
+                                let off = self.rel_tls_offset(sym.st_shndx as usize).unwrap_or(0);
+                                SymbolValue::ThreadLocal(VMA(off + stval))
+                            } else {
+                                SymbolValue::Addr(sect.vmaddr + stval)
+                            }
                         } else {
                             errln!("warning: invalid non-special st_shndx {} for symbol {}", sym.st_shndx, i);
                             SymbolValue::Undefined(source_lib)
@@ -1037,17 +1270,433 @@ impl exec::Exec for Elf {
 
                     }
                 };
+                // an IFUNC's value is its resolver
+                let val = match val {
+                    SymbolValue::Addr(addr) if st_type as u32 == STT_GNU_IFUNC => SymbolValue::Resolver(addr, None),
+                    val => val,
+                };
                 Some(Symbol {
                     name: name,
                     is_public: st_bind as u32 != STB_LOCAL,
                     is_weak: st_bind as u32 == STB_WEAK,
                     val: val,
                     size: if sym.st_size == 0 { None } else { Some(sym.st_size as u64) },
+                    kind: match st_type as u32 {
+                        STT_FUNC | STT_GNU_IFUNC => SymbolKind::Func,
+                        STT_OBJECT | STT_COMMON | STT_TLS => SymbolKind::Data,
+                        STT_SECTION => SymbolKind::Section,
+                        _ => SymbolKind::Unknown,
+                    },
+                    attrs: sym.st_info as u32 | (sym.st_other as u32) << 8,
                     private: i,
                 })
             }).filter_map(|x| x).collect::<Vec<Symbol>>()
         })
     }
+    // everything from the dynamic symbol table and the symbol table sections
+    fn get_table_symbols(&self, source: SymbolSource, specific: Option<&Any>) -> Vec<Symbol> {
+        let esp = if let Some(sp) = specific {
+            sp.downcast_ref::<ElfGetSymbolListSpecific>().unwrap()
+        } else {
+            static DEFAULT: ElfGetSymbolListSpecific = ElfGetSymbolListSpecific { append_version: false };
+            &DEFAULT
+        };
+
+        let mut out = Vec::new();
+        let full_symtab = self.get_dynamic_symtab();
+        let have_dynsym = full_symtab.is_some();
+        if let Some((symtab, versym, syment)) = full_symtab {
+            out = self.decode_symbols(symtab.get(), versym.get(), syment, 0, None, source, esp);
+        }
+        // .symtab in a linked file repeats every dynamic symbol, so those are only kept once (the
+        // dynamic copy, which has the version and is what relocations refer to)
+        let dyn_keys: HashSet<(Vec<u8>, Option<u64>)> = out.iter().map(symbol_dedup_key).collect();
+        // then anything in the section table; .dynsym is skipped if we already have it
+        for (i, shdr) in self.shdrs.iter().enumerate() {
+            if shdr.sh_type == SHT_DYNSYM {
+                if have_dynsym { continue; }
+            } else if shdr.sh_type != SHT_SYMTAB {
+                continue;
+            }
+            let (symtab, strtab, versym, syment) = some_or!(self.get_section_symtab(i), { continue; });
+            out.extend(self.decode_symbols(symtab, versym, syment, 0, Some(strtab), source, esp)
+                           .into_iter().filter(|sym| !dyn_keys.contains(&symbol_dedup_key(sym))));
+        }
+        out
+    }
+    // the dynamic symbol table, trying to get the count somehow
+    fn get_dynamic_symtab(&self) -> Option<(Mem<u8>, Mem<u8>, usize)> {
+        self.get_full_symtab(SymtabTraverseMode::BruteForce).or_else(||
+        self.get_full_symtab(SymtabTraverseMode::GNUHash)).or_else(||
+        self.get_full_symtab(SymtabTraverseMode::Hash))
+    }
+    // for ET_REL files: where a TLS section would end up in PT_TLS, which the linker fills with
+    // initialized (.tdata) sections and then zerofill (.tbss) ones
+    fn rel_tls_offset(&self, shndx: usize) -> Option<u64> {
+        let mut off = 0u64;
+        for &nobits in &[false, true] {
+            for (i, shdr) in self.shdrs.iter().enumerate() {
+                if shdr.sh_flags & (SHF_TLS as u64) == 0 || (shdr.sh_type == SHT_NOBITS) != nobits { continue; }
+                let align = max(shdr.sh_addralign, 1);
+                off = off.wrapping_add(align - 1) & !(align - 1);
+                if i == shndx { return Some(off); }
+                off = off.wrapping_add(shdr.sh_size);
+            }
+        }
+        None
+    }
+    // only dynamic symbols, since that's what dynamic relocations index (mixing in .symtab would
+    // make 'private' ambiguous)
+    fn get_dynsym(&self) -> &[Symbol<'static>] {
+        self.dynsym_cache.get(|| {
+            let esp = ElfGetSymbolListSpecific { append_version: false };
+            if let Some((symtab, versym, syment)) = self.get_dynamic_symtab() {
+                self.decode_symbols(symtab.get(), versym.get(), syment, 0, None, SymbolSource::All, &esp)
+                    .into_iter().map(Symbol::into_owned).collect()
+            } else if let Some(i) = self.shdrs.iter().position(|shdr| shdr.sh_type == SHT_DYNSYM) {
+                let (symtab, strtab, versym, syment) = some_or!(self.get_section_symtab(i), { return Vec::new(); });
+                self.decode_symbols(symtab, versym, syment, 0, Some(strtab), SymbolSource::All, &esp)
+                    .into_iter().map(Symbol::into_owned).collect()
+            } else {
+                Vec::new()
+            }
+        })
+    }
+    // (symtab, strtab, versym, syment) for a SHT_SYMTAB/SHT_DYNSYM section
+    fn get_section_symtab(&self, shndx: usize) -> Option<(&[ReadCell<u8>], &[ReadCell<u8>], &[ReadCell<u8>], usize)> {
+        let shdr = some_or!(self.shdrs.get(shndx), {
+            errln!("warning: symbol table section index {} out of range", shndx);
+            return None;
+        });
+        let sect = &self.eb.sections[shndx];
+        if shdr.sh_type != SHT_SYMTAB && shdr.sh_type != SHT_DYNSYM {
+            errln!("warning: section {} is not a symbol table", sect.pretty_name());
+            return None;
+        }
+        let data = some_or!(sect.data.as_ref(), {
+            errln!("warning: symbol table {} out of file range", sect.pretty_name());
+            return None;
+        });
+        let strtab = self.eb.sections.get(shdr.sh_link as usize).and_then(|s| s.data.as_ref());
+        let strtab = some_or!(strtab, {
+            errln!("warning: symbol table {} has invalid string table link {}",
+                   sect.pretty_name(), shdr.sh_link);
+            return None;
+        });
+        let versym = self.shdrs.iter().zip(&self.eb.sections).find(|&(vshdr, _)| {
+            vshdr.sh_type == SHT_GNU_versym && vshdr.sh_link as usize == shndx
+        }).and_then(|(_, vsect)| vsect.data.as_ref());
+        let min_syment = min_syment(self.basics.is64);
+        let syment = if (shdr.sh_entsize as usize) < min_syment {
+            errln!("warning: symbol table {} has bad sh_entsize {}", sect.pretty_name(), shdr.sh_entsize);
+            min_syment
+        } else { shdr.sh_entsize as usize };
+        let data = data.get();
+        let data = &data[..data.len() - data.len() % syment];
+        Some((data, strtab.get(), versym.map(|v| v.get()).unwrap_or(&[]), syment))
+    }
+    // symbols from a SHT_SYMTAB/SHT_DYNSYM section; 'private' is the symbol number
+    fn get_section_symbols(&self, shndx: usize) -> &[Symbol<'static>] {
+        let cache = some_or!(self.symtab_cache.get(shndx), {
+            errln!("warning: symbol table section index {} out of range", shndx);
+            return &[];
+        });
+        let shdr = &self.shdrs[shndx];
+        if shdr.sh_type == SHT_DYNSYM && self.dynamic_info.symtab == Some(VMA(shdr.sh_addr)) {
+            return self.get_dynsym();
+        }
+        cache.get(|| {
+            let (symtab, strtab, versym, syment) = some_or!(self.get_section_symtab(shndx), { return Vec::new(); });
+            let esp = ElfGetSymbolListSpecific::default();
+            self.decode_symbols(symtab, versym, syment, 0, Some(strtab), SymbolSource::All, &esp)
+                .into_iter().map(Symbol::into_owned).collect()
+        })
+    }
+    fn read_u32(&self, addr: VMA) -> Option<u32> {
+        let buf = self.eb.read(addr, 4);
+        if buf.len() != 4 { return None; }
+        Some(util::copy_from_slice(buf.get(), self.eb.endian))
+    }
+    // DT_SYMENT, unless it's missing or too small to hold a symbol
+    pub fn dynsym_syment(&self) -> usize {
+        let min = min_syment(self.basics.is64);
+        match self.dynamic_info.syment {
+            Some(syment) if syment as usize >= min => syment as usize,
+            _ => min,
+        }
+    }
+    // (symbol, versym entry) for dynamic symbol number idx
+    fn read_dynsym_entry(&self, idx: u64) -> Option<(Mem<u8>, Mem<u8>)> {
+        let symtab = some_or!(self.dynamic_info.symtab, { return None; });
+        let syment = self.dynsym_syment() as u64;
+        let addr = some_or!(idx.check_mul(syment).and_then(|off| symtab.check_add(off)), { return None; });
+        let data = self.eb.read(addr, syment);
+        if (data.len() as u64) < syment { return None; }
+        let versym = match self.dynamic_info.versym {
+            Some(versym) => self.eb.read(versym + 2 * idx, 2),
+            None => Mem::<u8>::default(),
+        };
+        Some((data, versym))
+    }
+    // is this a definition of `base`, in the right version?  `version` is (name, default only)
+    fn dynsym_matches(&self, symdat: &[ReadCell<u8>], versym: &[ReadCell<u8>], base: &ByteStr,
+                      version: Option<(&ByteStr, bool)>) -> bool {
+        let (st_name, st_info, st_shndx) = branch!(if (self.basics.is64) {
+            type ElfX_Sym = Elf64_Sym;
+        } else {
+            type ElfX_Sym = Elf32_Sym;
+        } then {
+            let sym: ElfX_Sym = util::copy_from_slice(&symdat[..size_of::<ElfX_Sym>()], self.eb.endian);
+            (sym.st_name as u64, sym.st_info, sym.st_shndx as u32)
+        });
+        if st_shndx == SHN_UNDEF || (st_info >> 4) as u32 == STB_LOCAL {
+            return false;
+        }
+        if self.read_dynstr(st_name).map_or(true, |name| *name != *base) {
+            return false;
+        }
+        let vs: u16 = if versym.len() == 2 { util::copy_from_slice(versym, self.eb.endian) } else { 0 };
+        let (vs_hidden, vs_key) = (vs & 0x8000 != 0, vs & 0x7fff);
+        match version {
+            // unversioned, or the default version
+            None => !vs_hidden,
+            Some((ver, default_only)) => {
+                !(default_only && vs_hidden) &&
+                vs_key as u32 > VER_NDX_GLOBAL &&
+                self.get_verdef_info().name(vs_key) == Some(ver)
+            },
+        }
+    }
+    // dynamic symbol numbers that might be `base`, from DT_GNU_HASH or DT_HASH; None if neither
+    // is present (or readable)
+    fn hash_candidates(&self, base: &ByteStr) -> Option<Vec<u64>> {
+        let mut out = Vec::new();
+        if let Some(gh) = self.get_gnu_hash() {
+            let hdr = gh.header;
+            if hdr.nbuckets == 0 { return Some(out); }
+            let h = gnu_hash(base);
+            // the bloom filter: two bits per symbol, in pointer-sized words
+            let word_size = self.eb.pointer_size;
+            let word_bits = (word_size * 8) as u32;
+            if hdr.bitmask_nwords != 0 {
+                let idx = ((h / word_bits) % hdr.bitmask_nwords) as usize * word_size;
+                let word: u64 = if word_size == 8 {
+                    util::copy_from_slice(&gh.bitmask.get()[idx..idx + 8], self.eb.endian)
+                } else {
+                    util::copy_from_slice::<u32, _>(&gh.bitmask.get()[idx..idx + 4], self.eb.endian) as u64
+                };
+                if (word >> (h % word_bits)) & (word >> ((h >> hdr.shift) % word_bits)) & 1 == 0 {
+                    return Some(out);
+                }
+            }
+            let bucket = (h % hdr.nbuckets) as usize * 4;
+            let mut idx: u32 = util::copy_from_slice(&gh.buckets.get()[bucket..bucket + 4], self.eb.endian);
+            if idx < hdr.symbias { return Some(out); }
+            loop {
+                let chain = some_or!(self.read_u32(gh.chain_addr + 4 * (idx - hdr.symbias) as u64), {
+                    errln!("warning: DT_GNU_HASH chain out of range");
+                    break;
+                });
+                if chain | 1 == h | 1 { out.push(idx as u64); }
+                if chain & 1 != 0 { break; }
+                idx = some_or!(idx.checked_add(1), { break; });
+            }
+            return Some(out);
+        }
+        if let Some(dt_hash) = self.dynamic_info.hash {
+            let (nbucket, nchain) = match (self.read_u32(dt_hash), self.read_u32(dt_hash + 4)) {
+                (Some(nbucket), Some(nchain)) => (nbucket as u64, nchain as u64),
+                _ => {
+                    errln!("warning: invalid DT_HASH address");
+                    return None;
+                },
+            };
+            if nbucket == 0 { return Some(out); }
+            let buckets = dt_hash + 8;
+            let chains = buckets + 4 * nbucket;
+            let mut idx = self.read_u32(buckets + 4 * (sysv_hash(base) as u64 % nbucket));
+            // the chain count bounds the walk, in case of loops
+            for _ in 0..nchain {
+                let i = some_or!(idx, { break; }) as u64;
+                if i == 0 || i >= nchain { break; }
+                out.push(i);
+                idx = self.read_u32(chains + 4 * i);
+            }
+            return Some(out);
+        }
+        None
+    }
+
+}
+
+fn version_flag_names(flags: u16) -> Vec<String> {
+    let mut out = Vec::new();
+    if flags as u32 & VER_FLG_BASE != 0 { out.push("base".to_owned()); }
+    if flags as u32 & VER_FLG_WEAK != 0 { out.push("weak".to_owned()); }
+    let rest = flags as u32 & !(VER_FLG_BASE | VER_FLG_WEAK);
+    if rest != 0 { out.push(format!("0x{:x}", rest)); }
+    out
+}
+
+fn version_flags_str(flags: u16) -> String {
+    let names = version_flag_names(flags);
+    if names.is_empty() { String::new() } else { format!(" [{}]", names.join(", ")) }
+}
+
+// the parts of an ELF Symbol::attrs, for listings
+pub fn sym_type_name(attrs: u32) -> Cow<'static, str> {
+    Cow::Borrowed(match attrs & 0xf {
+        STT_NOTYPE => "notype",
+        STT_OBJECT => "object",
+        STT_FUNC => "func",
+        STT_SECTION => "section",
+        STT_FILE => "file",
+        STT_COMMON => "common",
+        STT_TLS => "tls",
+        STT_GNU_IFUNC => "ifunc",
+        other => return format!("type{}", other).into(),
+    })
+}
+pub fn sym_bind_name(attrs: u32) -> Cow<'static, str> {
+    Cow::Borrowed(match (attrs >> 4) & 0xf {
+        STB_LOCAL => "local",
+        STB_GLOBAL => "global",
+        STB_WEAK => "weak",
+        STB_GNU_UNIQUE => "unique",
+        other => return format!("bind{}", other).into(),
+    })
+}
+pub fn sym_visibility_name(attrs: u32) -> &'static str {
+    match (attrs >> 8) & 3 {
+        STV_DEFAULT => "default",
+        STV_INTERNAL => "internal",
+        STV_HIDDEN => "hidden",
+        _ => "protected",
+    }
+}
+
+// name@VER or name@@VER (only the default version) -> (name, Some((VER, default only)))
+fn split_version(name: &ByteStr) -> (&ByteStr, Option<(&ByteStr, bool)>) {
+    let at = some_or!(name.iter().position(|&c| c == b'@'), { return (name, None); });
+    let rest = &name[at + 1..];
+    let version = if rest.first() == Some(&b'@') { (&rest[1..], true) } else { (rest, false) };
+    (ByteStr::from_bytes(&name[..at]), Some((ByteStr::from_bytes(version.0), version.1)))
+}
+
+fn gnu_hash(name: &ByteStr) -> u32 {
+    name.iter().fold(5381u32, |h, &c| h.wrapping_mul(33).wrapping_add(c as u32))
+}
+
+fn sysv_hash(name: &ByteStr) -> u32 {
+    name.iter().fold(0u32, |h, &c| {
+        let h = (h << 4).wrapping_add(c as u32);
+        let g = h & 0xf0000000;
+        (h ^ (g >> 24)) & !g
+    })
+}
+
+// the same symbol in .dynsym and .symtab: the version may only be spelled out in one of them, and
+// an import has no library in .symtab
+fn symbol_dedup_key(sym: &Symbol) -> (Vec<u8>, Option<u64>) {
+    (split_version(&sym.name).0.to_vec(), sym.val.some_vma().map(|vma| vma.0))
+}
+
+#[derive(Default, Clone)]
+pub struct ElfGetSymbolListSpecific {
+    // name@@VER for default versions of definitions, name@VER for everything else versioned;
+    // hidden versions get this regardless
+    pub append_version: bool,
+}
+
+impl exec::Exec for Elf {
+    fn get_exec_base<'a>(&'a self) -> &'a exec::ExecBase {
+        &self.eb
+    }
+    fn as_any(&self) -> &Any { self as &Any }
+
+    // plus name@plt for each PLT stub
+    fn get_symbol_list(&self, source: SymbolSource, specific: Option<&Any>) -> Vec<Symbol> {
+        let mut out = self.get_table_symbols(source, specific);
+        if source == SymbolSource::All {
+            // 'private' is the index into plt_stubs()
+            out.extend(self.plt_stubs().iter().enumerate().map(|(i, stub)| {
+                Symbol {
+                    name: Cow::Owned(ByteString::concat2(&stub.name, ByteStr::from_str("@plt"))),
+                    is_public: false,
+                    is_weak: false,
+                    val: SymbolValue::Addr(stub.addr),
+                    size: Some(stub.size),
+                    kind: SymbolKind::Func,
+                    attrs: STT_FUNC | STB_LOCAL << 4,
+                    private: i,
+                }
+            }));
+        }
+        out
+    }
+
+    // via the hash tables if there are any, else a scan of the dynamic symbol table (or, with no
+    // dynamic symbols at all, every symbol)
+    fn lookup_export(&self, name: &ByteStr, specific: Option<&Any>) -> Vec<Symbol> {
+        let esp = if let Some(sp) = specific {
+            sp.downcast_ref::<ElfGetSymbolListSpecific>().unwrap()
+        } else {
+            static DEFAULT: ElfGetSymbolListSpecific = ElfGetSymbolListSpecific { append_version: false };
+            &DEFAULT
+        };
+        let (base, version) = split_version(name);
+        let candidates: Vec<u64> = if let Some(candidates) = self.hash_candidates(base) {
+            candidates
+        } else if let Some((symtab, _, syment)) = self.get_full_symtab(SymtabTraverseMode::BruteForce) {
+            (1..(symtab.len() / syment) as u64).collect()
+        } else {
+            return self.get_symbol_list(SymbolSource::All, specific).into_iter().filter(|sym| {
+                sym.is_public && *sym.name == *name &&
+                match sym.val { SymbolValue::Undefined(..) => false, _ => true }
+            }).collect();
+        };
+        let mut out = Vec::new();
+        for idx in candidates {
+            let (symdat, versym) = some_or!(self.read_dynsym_entry(idx), { continue; });
+            if !self.dynsym_matches(symdat.get(), versym.get(), base, version) { continue; }
+            let syment = symdat.len();
+            out.extend(self.decode_symbols(symdat.get(), versym.get(), syment, idx as usize, None, SymbolSource::All, esp)
+                           .into_iter().map(Symbol::into_owned));
+        }
+        out
+    }
+
+    fn get_reloc_list<'a>(&'a self, specific: Option<&'a Any>) -> Vec<Reloc<'a>> {
+        assert!(specific.is_none());
+        self.get_relocs()
+    }
+
+    fn get_stub_list(&self) -> Vec<(&ByteStr, VMA)> {
+        self.plt_stubs().iter().map(|stub| (&*stub.name, stub.addr)).collect()
+    }
+    fn get_import_slot_list(&self) -> Vec<(&ByteStr, VMA)> {
+        self.got_imports().into_iter().map(|(slot, name)| (name, slot)).collect()
+    }
+
+    fn get_function_starts(&self) -> Vec<VMA> {
+        let mut out = Vec::new();
+        let mut done = Vec::new();
+        for sect in &self.eb.sections {
+            if sect.name.is_none() || &**sect.name.as_ref().unwrap() != ByteStr::from_str(".eh_frame") { continue; }
+            let data = some_or!(sect.data.as_ref(), { continue; });
+            out.extend(exec::eh_frame::fde_starts(data.get(), sect.vmaddr, self.eb.pointer_size, self.eb.endian));
+            done.push(sect.vmaddr);
+        }
+        // without section headers, PT_GNU_EH_FRAME still leads to it
+        for (seg, phdr) in self.eb.segments.iter().zip(&self.phdrs) {
+            if phdr.p_type != PT_GNU_EH_FRAME { continue; }
+            let (addr, data) = some_or!(self.eh_frame_from_hdr(seg), { continue; });
+            if done.contains(&addr) { continue; }
+            out.extend(exec::eh_frame::fde_starts(data, addr, self.eb.pointer_size, self.eb.endian));
+            done.push(addr);
+        }
+        out
+    }
 
     fn get_dep_libs(&self) -> Cow<[DepLib]> {
         (&*self.get_verneed_info().dep_libs).into()
@@ -1064,6 +1713,17 @@ impl exec::Exec for Elf {
         }
         out
     }
+
+    // headers aren't regenerated, so segments can be patched but not resized or moved; the one
+    // exception is a table for reconstructed sections, if asked for
+    fn write_back(&mut self) -> ExecResult<Mem<u8>> {
+        let buf = try!(self.eb.write_back_in_place());
+        if self.sections_reconstructed && self.write_sections {
+            self.write_section_headers(buf)
+        } else {
+            Ok(buf)
+        }
+    }
 }
 
 fn check_elf_basics(buf: &[ReadCell<u8>], warn: bool) -> Result<ElfBasics, &'static str> {
@@ -1155,10 +1815,20 @@ impl exec::ExecProber for ElfProber {
     }
     fn create(&self, _eps: &Vec<&'static exec::ExecProber>, buf: Mem<u8>, args: Vec<String>) -> exec::ExecResult<(Box<exec::Exec>, Vec<String>)> {
         let m = try!(exec::usage_to_invalid_args(util::do_getopts_or_usage(&*args, "elf ...", 0, std::usize::MAX, &mut vec!(
-            // ...
+            getopts::optopt("", "rel-base", "address to lay out relocatable (ET_REL) files at (default 0)", "ADDR"),
+            getopts::optflag("", "reconstruct-sections", "if the section headers are missing or look bad, derive sections from the dynamic table and program headers"),
+            getopts::optflag("", "write-sections", "with --reconstruct-sections, have write-back (e.g. exectool --extract) add them as a section header table"),
         ))));
+        let rel_base = if let Some(s) = m.opt_str("rel-base") {
+            VMA(some_or!(util::stoi(&s), { return exec::err(ErrorKind::InvalidArgs, "--rel-base: invalid address") }))
+        } else { VMA(0) };
+        let write_sections = m.opt_present("write-sections");
+        let reconstruct = write_sections || m.opt_present("reconstruct-sections");
         let free = m.free;
-        Elf::new(buf).map(|res| (Box::new(res) as Box<exec::Exec>, free))
+        Elf::new(buf, rel_base, reconstruct).map(|mut res| {
+            res.write_sections = write_sections;
+            (Box::new(res) as Box<exec::Exec>, free)
+        })
     }
     fn probe(&self, _eps: &Vec<&'static exec::ExecProber>, buf: Mem<u8>) -> Vec<exec::ProbeResult> {
         match check_elf_basics(buf.get(), false) {
@@ -1179,3 +1849,342 @@ impl exec::ExecProber for ElfProber {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exec::Exec;
+
+    fn put(buf: &mut Vec<u8>, off: usize, bytes: &[u8]) {
+        if buf.len() < off + bytes.len() { buf.resize(off + bytes.len(), 0); }
+        buf[off..off + bytes.len()].copy_from_slice(bytes);
+    }
+
+    pub fn build_so(syms: &[(&str, u64)], bloom: bool) -> Elf {
+        build_versioned_so(&globals(syms), &[], bloom)
+    }
+
+    fn globals<'a>(syms: &[(&'a str, u64)]) -> Vec<(&'a str, u64, u16)> {
+        syms.iter().map(|&(name, value)| (name, value, VER_NDX_GLOBAL as u16)).collect()
+    }
+
+    // What a fixture needs besides its symbols: more program headers (type, flags, offset and
+    // size, mapped at their offset), dynamic entries (after the fixture's own, so they win) and
+    // file contents.  `sysv_hash` swaps the DT_GNU_HASH for a DT_HASH.
+    #[derive(Default)]
+    pub struct Extras<'a> {
+        pub phdrs: Vec<(u32, u32, u64, u64)>,
+        pub dyns: Vec<(u32, u64)>,
+        pub contents: Vec<(usize, &'a [u8])>,
+        pub sysv_hash: bool,
+    }
+
+    pub fn build_so_with(syms: &[(&str, u64)], extras: &Extras) -> Elf {
+        build_elf(&globals(syms), &[], true, extras)
+    }
+
+    fn build_versioned_so(syms: &[(&str, u64, u16)], verdefs: &[(u16, &[&str])], bloom: bool) -> Elf {
+        build_elf(syms, verdefs, bloom, &Extras::default())
+    }
+
+    // An x86-64 shared library with no section headers, just the dynamic symbols (all defined
+    // functions, with their versym) and a DT_GNU_HASH with two buckets and a one-word bloom
+    // filter, which is left empty unless `bloom`.  If there are verdefs (flags and verdaux names,
+    // given indexes from 1), there's a DT_VERSYM too.  The first page is mapped read-only; the
+    // extras can go after it.
+    fn build_elf(syms: &[(&str, u64, u16)], verdefs: &[(u16, &[&str])], bloom: bool, extras: &Extras) -> Elf {
+        const DYNSTR: usize = 0x200;
+        const DYNSYM: usize = 0x300;
+        const VERSYM: usize = 0x500;
+        const VERDEF: usize = 0x580;
+        const HASH: usize = 0x700;
+        const DYNAMIC: usize = 0x900;
+        const SIZE: u64 = 0x1000;
+        let mut buf = Vec::new();
+        put(&mut buf, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut buf, 16, &(ET_DYN as u16).to_le_bytes());
+        put(&mut buf, 18, &(EM_X86_64 as u16).to_le_bytes());
+        put(&mut buf, 20, &1u32.to_le_bytes());
+        put(&mut buf, 32, &64u64.to_le_bytes()); // e_phoff
+        let mut phdrs = vec![(PT_LOAD, PF_R, 0, SIZE), (PT_DYNAMIC, PF_R, DYNAMIC as u64, 0x100)];
+        phdrs.extend_from_slice(&extras.phdrs);
+        // e_ehsize, e_phentsize, e_phnum, e_shentsize
+        put(&mut buf, 52, &[64, 0, 56, 0, phdrs.len() as u8, 0, 64, 0]);
+        for (i, &(type_, flags, off, size)) in phdrs.iter().enumerate() {
+            let ph = 64 + i * 56;
+            put(&mut buf, ph, &type_.to_le_bytes());
+            put(&mut buf, ph + 4, &flags.to_le_bytes());
+            for &(field, val) in &[(8, off), (16, off), (24, off), (32, size), (40, size)] {
+                put(&mut buf, ph + field, &val.to_le_bytes());
+            }
+        }
+        // the hash table wants symbols grouped by bucket
+        let nbuckets = 2;
+        let mut syms = syms.to_vec();
+        syms.sort_by_key(|&(name, _, _)| gnu_hash(ByteStr::from_str(name)) % nbuckets);
+        let mut strtab = vec![0u8];
+        let mut add_str = |s: &str| {
+            let off = strtab.len() as u32;
+            strtab.extend_from_slice(s.as_bytes());
+            strtab.push(0);
+            off
+        };
+        let mut buckets = [0u32; 2];
+        let mut chains = Vec::new();
+        let mut bloom_word = 0u64;
+        for (i, &(name, value, versym)) in syms.iter().enumerate() {
+            let idx = i + 1;
+            let sym = DYNSYM + idx * 24;
+            put(&mut buf, sym, &add_str(name).to_le_bytes());
+            put(&mut buf, sym + 4, &[(STB_GLOBAL << 4 | STT_FUNC) as u8, 0, 1, 0]);
+            put(&mut buf, sym + 8, &value.to_le_bytes());
+            put(&mut buf, VERSYM + 2 * idx, &versym.to_le_bytes());
+            let h = gnu_hash(ByteStr::from_str(name));
+            let bucket = (h % nbuckets) as usize;
+            if buckets[bucket] == 0 { buckets[bucket] = idx as u32; }
+            let last = syms.get(i + 1).map(|&(next, _, _)| gnu_hash(ByteStr::from_str(next)) % nbuckets) != Some(bucket as u32);
+            chains.push(h & !1 | last as u32);
+            bloom_word |= 1 << (h % 64) | 1 << ((h >> 6) % 64);
+        }
+        // Elf64_Verdef is 20 bytes, then its Elf64_Verdaux entries (8 bytes each)
+        let mut vd = VERDEF;
+        for (i, &(flags, names)) in verdefs.iter().enumerate() {
+            let size = 20 + 8 * names.len();
+            let next = if i + 1 == verdefs.len() { 0 } else { size as u32 };
+            for (field, &val) in [VER_DEF_CURRENT as u16, flags, i as u16 + 1, names.len() as u16].iter().enumerate() {
+                put(&mut buf, vd + 2 * field, &val.to_le_bytes());
+            }
+            put(&mut buf, vd + 8, &sysv_hash(ByteStr::from_str(names[0])).to_le_bytes());
+            put(&mut buf, vd + 12, &20u32.to_le_bytes());
+            put(&mut buf, vd + 16, &next.to_le_bytes());
+            for (j, &name) in names.iter().enumerate() {
+                let aux = vd + 20 + 8 * j;
+                put(&mut buf, aux, &add_str(name).to_le_bytes());
+                put(&mut buf, aux + 4, &(if j + 1 == names.len() { 0u32 } else { 8 }).to_le_bytes());
+            }
+            vd += size;
+        }
+        put(&mut buf, DYNSTR, &strtab);
+        let hash = if extras.sysv_hash {
+            // nbucket, nchain, then one bucket chaining through every symbol
+            let words: Vec<u32> = [1, syms.len() as u32 + 1, syms.len() as u32, 0].iter().cloned()
+                                  .chain(0..syms.len() as u32).collect();
+            for (i, w) in words.iter().enumerate() {
+                put(&mut buf, HASH + 4 * i, &w.to_le_bytes());
+            }
+            (DT_HASH, HASH as u64)
+        } else {
+            // nbuckets, symbias, bitmask_nwords, shift; the bloom filter; the buckets; the chains
+            for (i, &val) in [nbuckets, 1, 1, 6].iter().enumerate() {
+                put(&mut buf, HASH + 4 * i, &val.to_le_bytes());
+            }
+            put(&mut buf, HASH + 16, &(if bloom { bloom_word } else { 0 }).to_le_bytes());
+            for (i, &val) in buckets.iter().chain(&chains).enumerate() {
+                put(&mut buf, HASH + 24 + 4 * i, &val.to_le_bytes());
+            }
+            (DT_GNU_HASH, HASH as u64)
+        };
+        let mut dyns = vec![(DT_STRTAB, DYNSTR as u64), (DT_STRSZ, strtab.len() as u64), (DT_SYMTAB, DYNSYM as u64),
+                            (DT_SYMENT, 24), hash];
+        if !verdefs.is_empty() {
+            dyns.extend_from_slice(&[(DT_VERSYM, VERSYM as u64), (DT_VERDEF, VERDEF as u64), (DT_VERDEFNUM, verdefs.len() as u64)]);
+        }
+        dyns.extend_from_slice(&extras.dyns);
+        dyns.push((DT_NULL, 0));
+        for (i, &(tag, val)) in dyns.iter().enumerate() {
+            put(&mut buf, DYNAMIC + 16 * i, &(tag as u64).to_le_bytes());
+            put(&mut buf, DYNAMIC + 16 * i + 8, &val.to_le_bytes());
+        }
+        for &(off, bytes) in &extras.contents {
+            put(&mut buf, off, bytes);
+        }
+        if buf.len() < SIZE as usize { buf.resize(SIZE as usize, 0); }
+        Elf::new(Mem::with_vec(buf), VMA(0), false).unwrap()
+    }
+
+    fn lookup(elf: &Elf, name: &str) -> Vec<Option<VMA>> {
+        elf.lookup_export(ByteStr::from_str(name), None).iter().map(|sym| sym.val.some_vma()).collect()
+    }
+
+    #[test]
+    fn test_hashes() {
+        assert_eq!(gnu_hash(ByteStr::from_str("")), 0x1505);
+        assert_eq!(gnu_hash(ByteStr::from_str("printf")), 0x156b2bb8);
+        assert_eq!(gnu_hash(ByteStr::from_str("exit")), 0x7c967e3f);
+        assert_eq!(sysv_hash(ByteStr::from_str("printf")), 0x077905a6);
+        assert_eq!(sysv_hash(ByteStr::from_str("exit")), 0x0006cf04);
+    }
+
+    #[test]
+    fn test_gnu_hash_lookup() {
+        // foo, qux and nope are in one bucket; bar, baz and quux in the other
+        let syms = [("foo", 0x1000), ("bar", 0x1010), ("baz", 0x1020), ("qux", 0x1030), ("quux", 0x1040)];
+        let elf = build_so(&syms, true);
+        for &(name, addr) in &syms {
+            assert_eq!(lookup(&elf, name), vec![Some(VMA(addr))], "{}", name);
+        }
+        assert_eq!(lookup(&elf, "nope"), vec![]);
+        assert_eq!(lookup(&elf, "fo"), vec![]);
+        // an empty bloom filter rules everything out
+        let elf = build_so(&syms, false);
+        assert_eq!(lookup(&elf, "foo"), vec![]);
+        // and an empty bucket has nothing in it
+        let elf = build_so(&[("bar", 0x1010)], true);
+        assert_eq!(lookup(&elf, "bar"), vec![Some(VMA(0x1010))]);
+        assert_eq!(lookup(&elf, "foo"), vec![]);
+    }
+
+    #[test]
+    fn test_sysv_hash_lookup() {
+        let syms = [("foo", 0x1000), ("bar", 0x1010), ("baz", 0x1020)];
+        let elf = build_so_with(&syms, &Extras { sysv_hash: true, ..Default::default() });
+        for &(name, addr) in &syms {
+            assert_eq!(lookup(&elf, name), vec![Some(VMA(addr))], "{}", name);
+        }
+        assert_eq!(lookup(&elf, "nope"), vec![]);
+        // with no sections or DT_GNU_HASH, DT_HASH's nchain is all that says how many symbols there are
+        let mut names: Vec<String> = elf.get_symbol_list(SymbolSource::All, None).iter()
+                                        .map(|sym| sym.name.to_string()).collect();
+        names.sort();
+        assert_eq!(names, vec!["bar", "baz", "foo"]);
+    }
+
+    #[test]
+    fn test_lookup_bad_syment() {
+        for &sysv_hash in &[false, true] {
+            let elf = build_so_with(&[("foo", 0x1000), ("bar", 0x1010)],
+                                    &Extras { dyns: vec![(DT_SYMENT, 0)], sysv_hash: sysv_hash, ..Default::default() });
+            assert_eq!(lookup(&elf, "bar"), vec![Some(VMA(0x1010))]);
+            assert_eq!(lookup(&elf, "bar@V1"), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_split_version() {
+        let split = |name: &str| {
+            let (base, version) = split_version(ByteStr::from_str(name));
+            (base.to_string(), version.map(|(ver, default_only)| (ver.to_string(), default_only)))
+        };
+        let s = |s: &str| s.to_owned();
+        assert_eq!(split("foo"), (s("foo"), None));
+        assert_eq!(split("foo@V1"), (s("foo"), Some((s("V1"), false))));
+        assert_eq!(split("foo@@V2"), (s("foo"), Some((s("V2"), true))));
+        // only the first @ splits
+        assert_eq!(split("foo@V1@x"), (s("foo"), Some((s("V1@x"), false))));
+        assert_eq!(split("foo@"), (s("foo"), Some((s(""), false))));
+    }
+
+    const VERDEFS: &[(u16, &[&str])] = &[(VER_FLG_BASE as u16, &["libt.so"]), (0, &["V1"]), (0, &["V2", "V1"])];
+
+    #[test]
+    fn test_verdef() {
+        let elf = build_versioned_so(&[("foo", 0x1000, 3)], VERDEFS, true);
+        let info = elf.get_verdef_info();
+        let got: Vec<_> = info.verdef.iter().map(|vd| {
+            (vd.ndx, vd.flags, vd.name.to_string(), vd.parents.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+        }).collect();
+        assert_eq!(got, vec![
+            (1, VER_FLG_BASE as u16, "libt.so".to_owned(), vec![]),
+            (2, 0, "V1".to_owned(), vec![]),
+            (3, 0, "V2".to_owned(), vec!["V1".to_owned()]),
+        ]);
+        assert_eq!(info.name(3).map(|n| n.to_string()), Some("V2".to_owned()));
+        assert_eq!(info.name(4), None);
+    }
+
+    #[test]
+    fn test_versioned_lookup() {
+        // foo@V1 is hidden, foo@@V2 is the default
+        let syms = [("foo", 0x1000, 0x8002), ("foo", 0x1010, 3), ("bar", 0x1020, VER_NDX_GLOBAL as u16)];
+        let elf = build_versioned_so(&syms, VERDEFS, true);
+        let found = |name: &str| lookup(&elf, name).into_iter().map(|vma| vma.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(found("foo"), vec![0x1010]);
+        assert_eq!(found("foo@V1"), vec![0x1000]);
+        assert_eq!(found("foo@@V1"), vec![]);
+        assert_eq!(found("foo@V2"), vec![0x1010]);
+        assert_eq!(found("foo@@V2"), vec![0x1010]);
+        assert_eq!(found("foo@V3"), vec![]);
+        assert_eq!(found("bar"), vec![0x1020]);
+        assert_eq!(found("bar@V1"), vec![]);
+        // the names that come back say which version they are
+        let esp = ElfGetSymbolListSpecific { append_version: true };
+        let names: Vec<String> = elf.lookup_export(ByteStr::from_str("foo@V1"), Some(&esp as &Any)).iter()
+                                    .chain(elf.lookup_export(ByteStr::from_str("foo"), Some(&esp as &Any)).iter())
+                                    .map(|sym| sym.name.to_string()).collect();
+        assert_eq!(names, vec!["foo@V1", "foo@@V2"]);
+    }
+
+    #[test]
+    fn test_bad_syment() {
+        // too small for a symbol, so the real size is used instead
+        for &syment in &[0, 8] {
+            let elf = build_so_with(&[("foo", 0x1000), ("bar", 0x1010)],
+                                    &Extras { dyns: vec![(DT_SYMENT, syment)], ..Default::default() });
+            assert_eq!(elf.dynsym_syment(), 24);
+            let mut names: Vec<String> = elf.get_symbol_list(SymbolSource::All, None).iter()
+                                            .map(|sym| sym.name.to_string()).collect();
+            names.sort();
+            assert_eq!(names, vec!["bar", "foo"]);
+        }
+    }
+
+    #[test]
+    fn test_rel_tls_offset() {
+        let shdr = |type_: u32, flags: u32, size: u64, align: u64| Shdr {
+            sh_name: 0, sh_type: type_, sh_flags: flags as u64, sh_addr: 0, sh_offset: 0, sh_size: size,
+            sh_link: 0, sh_info: 0, sh_addralign: align, sh_entsize: 0,
+        };
+        let mut elf = build_so(&[], true);
+        elf.shdrs = vec![
+            shdr(SHT_NULL, 0, 0, 0),
+            shdr(SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0x100, 16),
+            shdr(SHT_NOBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 4, 4),
+            shdr(SHT_PROGBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 5, 1),
+            shdr(SHT_PROGBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 8, 8),
+            shdr(SHT_NOBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 16, 16),
+        ];
+        // .tdata sections first, in order and aligned, then the .tbss ones
+        let offsets: Vec<_> = (0..7).map(|i| elf.rel_tls_offset(i)).collect();
+        assert_eq!(offsets, vec![None, None, Some(16), Some(0), Some(8), Some(32), None]);
+    }
+
+    fn bytes(mem: &Mem<u8>) -> Vec<u8> {
+        mem.get().iter().map(|c| c.get()).collect()
+    }
+
+    #[test]
+    fn test_write_back() {
+        let mut elf = build_so(&[("foo", 0x1000)], true);
+        let orig = bytes(elf.eb.whole_buf.as_ref().unwrap());
+        // everything is still a view of the file
+        assert_eq!(bytes(&elf.write_back().unwrap()), orig);
+        // replaced data, as from a SegmentWriter, is copied over the segment's file range
+        let mut patched = orig[..0x1000].to_vec();
+        patched[0xf00] = 0xaa;
+        elf.eb.segments[0].data = Some(Mem::with_vec(patched.clone()));
+        assert_eq!(bytes(&elf.write_back().unwrap()), patched);
+        // but the layout doesn't change
+        elf.eb.segments[0].fileoff = 0x800;
+        assert_eq!(elf.write_back().unwrap_err().kind, ErrorKind::BadData);
+        elf.eb.whole_buf = None;
+        assert_eq!(elf.write_back().unwrap_err().kind, ErrorKind::InvalidArgs);
+    }
+
+    #[test]
+    fn test_relr() {
+        // an address, a bitmap for the 63 words after it, a bitmap for the 63 after that, and again
+        // for a second address
+        let words: Vec<u8> = [0x2000u64, 1 | 1 << 1 | 1 << 3, 1 | 1 << 63, 0x3000, 1 | 1 << 2].iter()
+                             .flat_map(|w| w.to_le_bytes().to_vec()).collect();
+        let elf = build_so_with(&[("foo", 0x1000)], &Extras {
+            dyns: vec![(DT_RELR, 0xb00), (DT_RELRSZ, words.len() as u64), (DT_RELRENT, 8)],
+            contents: vec![(0xb00, &words)],
+            ..Default::default()
+        });
+        let relocs = elf.get_relocs();
+        assert!(relocs.iter().all(|r| r.kind == exec::RelocKind::Pointer && r.target == exec::RelocTarget::ThisImageSlide &&
+                                      r.addend.is_none()));
+        let addrs: Vec<u64> = relocs.iter().map(|r| r.address.0).collect();
+        assert_eq!(addrs, vec![0x2000, 0x2008, 0x2018, 0x2000 + 8 + 63 * 8 + 62 * 8, 0x3000, 0x3010]);
+    }
+}
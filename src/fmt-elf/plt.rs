@@ -0,0 +1,228 @@
+// PLT stubs.  Rather than assuming a layout, each stub is recognized by the indirect jump it makes
+// through a GOT slot, and the dynamic relocations say which import the slot is for.
+
+use util::{self, ByteStr, ByteString, ReadCell};
+use exec::{VMA, RelocKind, RelocTarget};
+use exec::arch::Arch;
+use std::collections::HashMap;
+use ::Elf;
+
+#[derive(Debug, Clone)]
+pub struct PltStub {
+    pub addr: VMA,
+    // up to the next stub or the end of the section
+    pub size: u64,
+    pub got_slot: VMA,
+    pub name: ByteString,
+}
+
+fn u32_at(data: &[ReadCell<u8>], off: usize, end: util::Endian) -> Option<u32> {
+    if off + 4 > data.len() { return None; }
+    Some(util::copy_from_slice(&data[off..off + 4], end))
+}
+
+// ARM modified immediate
+fn arm_imm(insn: u32) -> u32 {
+    (insn & 0xff).rotate_right(((insn >> 8) & 0xf) * 2)
+}
+
+impl Elf {
+    // GOT slots (from JUMP_SLOT and GLOB_DAT relocations) and the imports they'll hold
+    pub fn got_imports(&self) -> Vec<(VMA, &ByteStr)> {
+        let mut out: Vec<(VMA, &ByteStr)> = self.get_relocs().into_iter().filter_map(|rel| {
+            match (rel.kind, rel.target) {
+                (RelocKind::Pointer, RelocTarget::Import(sym)) => Some((rel.address, &*sym.name)),
+                _ => None,
+            }
+        }).collect();
+        out.sort();
+        out.dedup();
+        out
+    }
+    pub fn plt_stubs(&self) -> &[PltStub] {
+        self.plt_cache.get(|| self.decode_plt())
+    }
+    fn decode_plt(&self) -> Vec<PltStub> {
+        let got: HashMap<VMA, &ByteStr> = self.got_imports().into_iter().collect();
+        let mut out = Vec::new();
+        if got.is_empty() { return out; }
+        // .plt, .plt.sec, .plt.got, .plt.bnd, .iplt
+        for sect in &self.eb.sections {
+            let name = some_or!(sect.name.as_ref(), { continue; });
+            if !name.starts_with(b".plt") && **name != *ByteStr::from_str(".iplt") { continue; }
+            let data = some_or!(sect.data.as_ref(), { continue; }).get();
+            let first = out.len();
+            let grain = match self.basics.arch { Arch::X86 | Arch::X86_64 => 8, _ => 4 };
+            let mut off = 0;
+            while off < data.len() {
+                if let Some((len, slot)) = plt_entry_at(self.basics.arch, self.basics.is64, self.basics.endian,
+                                                        self.dynamic_info.pltgot, data, off, sect.vmaddr + off as u64) {
+                    if let Some(&import) = got.get(&slot) {
+                        out.push(PltStub {
+                            addr: sect.vmaddr + off as u64,
+                            size: 0,
+                            got_slot: slot,
+                            name: import.to_owned(),
+                        });
+                    }
+                    off += (len + grain - 1) / grain * grain;
+                } else {
+                    off += grain;
+                }
+            }
+            let end = sect.vmaddr + sect.vmsize;
+            for i in first..out.len() {
+                let next = out.get(i + 1).map_or(end, |stub| stub.addr);
+                out[i].size = next - out[i].addr;
+            }
+        }
+        out.sort_by_key(|stub| stub.addr);
+        out
+    }
+}
+
+// if there's a stub at data[off..], the length of the part we recognized and the GOT slot it
+// jumps through; `pltgot` is DT_PLTGOT, which i386 PIC stubs are relative to
+pub fn plt_entry_at(arch: Arch, is64: bool, end: util::Endian, pltgot: Option<VMA>,
+                    data: &[ReadCell<u8>], off: usize, addr: VMA) -> Option<(usize, VMA)> {
+    let byte = |i: usize| data.get(off + i).map(|b| b.get());
+    match arch {
+        Arch::X86 | Arch::X86_64 => {
+            let mut p = 0;
+            // endbr64/endbr32 for IBT, then maybe a bnd prefix for MPX
+            if byte(0) == Some(0xf3) && byte(1) == Some(0x0f) && byte(2) == Some(0x1e) &&
+               (byte(3) == Some(0xfa) || byte(3) == Some(0xfb)) {
+                p = 4;
+            }
+            if byte(p) == Some(0xf2) { p += 1; }
+            if byte(p) != Some(0xff) { return None; }
+            let modrm = some_or!(byte(p + 1), { return None; });
+            let disp = some_or!(u32_at(data, off + p + 2, util::LittleEndian), { return None; });
+            let len = p + 6;
+            let slot = match (arch, modrm) {
+                // jmp *disp(%rip)
+                (Arch::X86_64, 0x25) => (addr.0 + len as u64).wrapping_add(disp as i32 as i64 as u64),
+                // jmp *abs
+                (Arch::X86, 0x25) => disp as u64,
+                // jmp *disp(%ebx), with %ebx pointing at the GOT
+                (Arch::X86, 0xa3) => some_or!(pltgot, { return None; }).0.wrapping_add(disp as i32 as i64 as u64),
+                _ => return None,
+            };
+            Some((len, VMA(slot & if is64 { !0 } else { 0xffffffff })))
+        },
+        Arch::AArch64 => {
+            let insn = |i: usize| u32_at(data, off + i * 4, util::LittleEndian);
+            let mut i = 0;
+            // bti c
+            if insn(0) == Some(0xd503245f) { i = 1; }
+            // adrp x16, page; ldr x17, [x16, #lo]
+            let adrp = some_or!(insn(i), { return None; });
+            let ldr = some_or!(insn(i + 1), { return None; });
+            if adrp & 0x9f00001f != 0x90000010 || ldr & 0xffc003ff != 0xf9400211 { return None; }
+            let imm = (((adrp >> 5) & 0x7ffff) << 2 | (adrp >> 29) & 3) as u64;
+            let imm = ((imm << 43) as i64 >> 31) as u64;
+            let page = (addr.0 + i as u64 * 4) & !0xfff;
+            let lo = ((ldr >> 10) & 0xfff) as u64 * 8;
+            Some(((i + 2) * 4, VMA(page.wrapping_add(imm).wrapping_add(lo))))
+        },
+        Arch::ARM => {
+            // add ip, pc, #a; add ip, ip, #b (once or twice); ldr pc, [ip, #c]!
+            let insn = |i: usize| u32_at(data, off + i * 4, end);
+            let first = some_or!(insn(0), { return None; });
+            if first & 0x0ffff000 != 0x028fc000 { return None; }
+            let mut slot = (addr.0 as u32).wrapping_add(8).wrapping_add(arm_imm(first));
+            let mut i = 1;
+            loop {
+                let next = some_or!(insn(i), { return None; });
+                i += 1;
+                if next & 0x0ffff000 == 0x028cc000 && i <= 4 {
+                    slot = slot.wrapping_add(arm_imm(next));
+                } else if next & 0x0ffff000 == 0x05bcf000 {
+                    slot = slot.wrapping_add(next & 0xfff);
+                    break;
+                } else {
+                    return None;
+                }
+            }
+            Some((i * 4, VMA(slot as u64)))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(bytes: &[u8]) -> Vec<ReadCell<u8>> {
+        bytes.iter().map(|&b| ReadCell::new(b)).collect()
+    }
+    fn words(words: &[u32], end: util::Endian) -> Vec<ReadCell<u8>> {
+        let mut bytes = Vec::new();
+        for &w in words {
+            let w = if end == util::BigEndian { w.to_be_bytes() } else { w.to_le_bytes() };
+            bytes.extend_from_slice(&w);
+        }
+        cells(&bytes)
+    }
+
+    #[test]
+    fn test_x86_64() {
+        let at = |bytes: &[u8], addr: u64| plt_entry_at(Arch::X86_64, true, util::LittleEndian, None, &cells(bytes), 0, VMA(addr));
+        // PLT0's push isn't a stub
+        assert_eq!(at(&[0xff, 0x35, 0xe2, 0x2f, 0x00, 0x00], 0x1020), None);
+        // .plt: jmp *GOT(%rip); push $0; jmp PLT0
+        assert_eq!(at(&[0xff, 0x25, 0xe2, 0x2f, 0x00, 0x00, 0x68, 0x00, 0x00, 0x00, 0x00, 0xe9, 0xe0, 0xff, 0xff, 0xff], 0x1030),
+                   Some((6, VMA(0x4018))));
+        // .plt.sec with IBT: endbr64; bnd jmp *GOT(%rip); nopl
+        assert_eq!(at(&[0xf3, 0x0f, 0x1e, 0xfa, 0xf2, 0xff, 0x25, 0xc5, 0x2f, 0x00, 0x00, 0x0f, 0x1f, 0x44, 0x00, 0x00], 0x1050),
+                   Some((11, VMA(0x4020))));
+        // without bnd, and a negative displacement
+        assert_eq!(at(&[0xf3, 0x0f, 0x1e, 0xfa, 0xff, 0x25, 0xf6, 0xff, 0xff, 0xff], 0x1060), Some((10, VMA(0x1060))));
+        // truncated
+        assert_eq!(at(&[0xff, 0x25, 0xe2, 0x2f], 0x1030), None);
+    }
+
+    #[test]
+    fn test_i386() {
+        let at = |bytes: &[u8], pltgot: Option<VMA>| plt_entry_at(Arch::X86, false, util::LittleEndian, pltgot, &cells(bytes), 0, VMA(0x8049030));
+        // non-PIC: jmp *abs
+        assert_eq!(at(&[0xff, 0x25, 0x0c, 0xa0, 0x04, 0x08, 0x68, 0x00, 0x00, 0x00, 0x00], None), Some((6, VMA(0x804a00c))));
+        // PIC: jmp *disp(%ebx)
+        let pic = [0xff, 0xa3, 0x0c, 0x00, 0x00, 0x00, 0x68, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(at(&pic, Some(VMA(0x4000))), Some((6, VMA(0x400c))));
+        assert_eq!(at(&pic, None), None);
+        // .plt.sec with IBT: endbr32; jmp *disp(%ebx)
+        assert_eq!(at(&[0xf3, 0x0f, 0x1e, 0xfb, 0xff, 0xa3, 0x10, 0x00, 0x00, 0x00], Some(VMA(0x4000))), Some((10, VMA(0x4010))));
+        // the slot wraps at 32 bits
+        assert_eq!(at(&[0xff, 0xa3, 0xfc, 0xff, 0xff, 0xff], Some(VMA(0))), Some((6, VMA(0xfffffffc))));
+    }
+
+    #[test]
+    fn test_arm() {
+        let at = |data: &[ReadCell<u8>], end: util::Endian| plt_entry_at(Arch::ARM, false, end, None, data, 0, VMA(0x1000));
+        for &end in &[util::LittleEndian, util::BigEndian] {
+            // add ip, pc, #0, 12; add ip, ip, #16, 20; ldr pc, [ip, #0xf58]!
+            assert_eq!(at(&words(&[0xe28fc600, 0xe28cca10, 0xe5bcff58], end), end), Some((12, VMA(0x11f60))));
+            // the long form: add ip, pc, #0, 4; add ip, ip, #1, 12; add ip, ip, #16, 20; ldr pc, [ip, #0xf58]!
+            assert_eq!(at(&words(&[0xe28fc200, 0xe28cc601, 0xe28cca10, 0xe5bcff58], end), end), Some((16, VMA(0x111f60))));
+        }
+        // too many adds
+        assert_eq!(at(&words(&[0xe28fc600, 0xe28cca00, 0xe28cca00, 0xe28cca00, 0xe28cca00, 0xe5bcff58], util::LittleEndian), util::LittleEndian), None);
+        // PLT0: str lr, [sp, #-4]!
+        assert_eq!(at(&words(&[0xe52de004], util::LittleEndian), util::LittleEndian), None);
+    }
+
+    #[test]
+    fn test_aarch64() {
+        let at = |ws: &[u32], addr: u64| plt_entry_at(Arch::AArch64, true, util::LittleEndian, None, &words(ws, util::LittleEndian), 0, VMA(addr));
+        // adrp x16, page+0x11000; ldr x17, [x16, #8]; add x16, x16, #8; br x17
+        assert_eq!(at(&[0xb0000090, 0xf9400611, 0x91002210, 0xd61f0220], 0x1000), Some((8, VMA(0x12008))));
+        // bti c first, with the adrp in the next instruction
+        assert_eq!(at(&[0xd503245f, 0xb0000090, 0xf947ee11, 0x913f6210, 0xd61f0220], 0x2ffc), Some((12, VMA(0x14fd8))));
+        // a negative page offset: adrp x16, page-0x1000
+        assert_eq!(at(&[0xf0fffff0, 0xf9400611], 0x3000), Some((8, VMA(0x2008))));
+        // PLT0: stp x16, x30, [sp, #-16]!
+        assert_eq!(at(&[0xa9bf7bf0, 0xb0000090, 0xf9400611], 0x1000), None);
+    }
+}
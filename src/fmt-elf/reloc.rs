@@ -0,0 +1,272 @@
+use util;
+use util::{ReadCell, Mem};
+use exec::{Reloc, RelocKind, RelocTarget, VMA, Symbol, ReadVMA};
+use exec::arch::Arch;
+use elf_bind::*;
+use std::mem::size_of;
+use ::{Elf, OffCountSize};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RelFormat {
+    Rel,
+    Rela,
+    Relr,
+}
+
+// None means R_*_NONE, i.e. skip it
+pub fn reloc_kind(arch: Arch, type_: u32) -> Option<RelocKind> {
+    use exec::RelocKind::*;
+    Some(match arch {
+        Arch::X86_64 => match type_ {
+            R_X86_64_NONE => return None,
+            R_X86_64_64 | R_X86_64_RELATIVE64 => _64Bit,
+            R_X86_64_32 | R_X86_64_32S => _32Bit,
+            R_X86_64_PC32 | R_X86_64_PLT32 => _32BitPCRel,
+            R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX => _32BitGOTPCRel,
+            R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT | R_X86_64_RELATIVE => Pointer,
+            R_X86_64_COPY => Copy,
+            R_X86_64_IRELATIVE => IRelative,
+            R_X86_64_DTPMOD64 => TLSModule,
+            R_X86_64_DTPOFF64 => TLSOffset,
+            R_X86_64_TPOFF64 => TLSTPOffset,
+            R_X86_64_TLSDESC => TLSDesc,
+            _ => Other(type_),
+        },
+        Arch::X86 => match type_ {
+            R_386_NONE => return None,
+            R_386_32 => _32Bit,
+            R_386_PC32 | R_386_PLT32 => _32BitPCRel,
+            R_386_GLOB_DAT | R_386_JMP_SLOT | R_386_RELATIVE => Pointer,
+            R_386_COPY => Copy,
+            R_386_IRELATIVE => IRelative,
+            R_386_TLS_DTPMOD32 => TLSModule,
+            R_386_TLS_DTPOFF32 => TLSOffset,
+            R_386_TLS_TPOFF | R_386_TLS_TPOFF32 => TLSTPOffset,
+            R_386_TLS_DESC => TLSDesc,
+            _ => Other(type_),
+        },
+        Arch::ARM => match type_ {
+            R_ARM_NONE => return None,
+            R_ARM_ABS32 => _32Bit,
+            R_ARM_REL32 => _32BitPCRel,
+            R_ARM_PC24 | R_ARM_CALL | R_ARM_JUMP24 => ArmBr24,
+            R_ARM_THM_PC22 | R_ARM_THM_JUMP24 => ThumbBr22,
+            R_ARM_GLOB_DAT | R_ARM_JUMP_SLOT | R_ARM_RELATIVE => Pointer,
+            R_ARM_COPY => Copy,
+            R_ARM_IRELATIVE => IRelative,
+            R_ARM_TLS_DTPMOD32 => TLSModule,
+            R_ARM_TLS_DTPOFF32 => TLSOffset,
+            R_ARM_TLS_TPOFF32 => TLSTPOffset,
+            R_ARM_TLS_DESC => TLSDesc,
+            _ => Other(type_),
+        },
+        Arch::AArch64 => match type_ {
+            R_AARCH64_NONE => return None,
+            R_AARCH64_ABS64 => _64Bit,
+            R_AARCH64_ABS32 => _32Bit,
+            R_AARCH64_PREL32 => _32BitPCRel,
+            R_AARCH64_CALL26 | R_AARCH64_JUMP26 => Arm64Br26,
+            R_AARCH64_ADR_PREL_PG_HI21 => Arm64Adrp,
+            R_AARCH64_ADR_GOT_PAGE => Arm64AdrpGot,
+            R_AARCH64_ADD_ABS_LO12_NC => Arm64AddLo12,
+            R_AARCH64_LDST8_ABS_LO12_NC => Arm64LdstLo12(0),
+            R_AARCH64_LDST16_ABS_LO12_NC => Arm64LdstLo12(1),
+            R_AARCH64_LDST32_ABS_LO12_NC => Arm64LdstLo12(2),
+            R_AARCH64_LDST64_ABS_LO12_NC => Arm64LdstLo12(3),
+            R_AARCH64_LDST128_ABS_LO12_NC => Arm64LdstLo12(4),
+            R_AARCH64_LD64_GOT_LO12_NC => Arm64Ld64GotLo12,
+            R_AARCH64_GLOB_DAT | R_AARCH64_JUMP_SLOT | R_AARCH64_RELATIVE => Pointer,
+            R_AARCH64_COPY => Copy,
+            R_AARCH64_IRELATIVE => IRelative,
+            R_AARCH64_TLS_DTPMOD64 => TLSModule,
+            R_AARCH64_TLS_DTPREL64 => TLSOffset,
+            R_AARCH64_TLS_TPREL64 => TLSTPOffset,
+            R_AARCH64_TLSDESC => TLSDesc,
+            _ => Other(type_),
+        },
+        _ => if type_ == 0 { return None } else { Other(type_) },
+    })
+}
+
+impl Elf {
+    fn read_reloc_table(&self, addr: VMA, size: u64, what: &str) -> Mem<u8> {
+        let data = self.eb.read(addr, size);
+        if (data.len() as u64) < size {
+            errln!("warning: {} table truncated (got {}/{} bytes)", what, data.len(), size);
+        }
+        data
+    }
+    pub fn push_relocs<'a>(&self, data: &[ReadCell<u8>], entsize: u64, format: RelFormat,
+                           bias: u64, syms: &'a [Symbol<'static>], out: &mut Vec<Reloc<'a>>) {
+        let end = self.basics.endian;
+        let arch = self.basics.arch;
+        let mut push = |offset: u64, type_: u32, symidx: u32, addend: Option<i64>| {
+            let kind = some_or!(reloc_kind(arch, type_), { return });
+            let target = if symidx == 0 {
+                RelocTarget::ThisImageSlide
+            } else if let Ok(i) = syms.binary_search_by_key(&(symidx as usize), |sym| sym.private) {
+                RelocTarget::Import(&syms[i])
+            } else {
+                errln!("warning: relocation at 0x{:x} has invalid symbol index {}", offset, symidx);
+                return;
+            };
+            out.push(Reloc {
+                address: VMA(offset.wrapping_add(bias)),
+                kind: kind,
+                addend: addend,
+                target: target,
+                auth: None,
+            });
+        };
+        branch!(if (self.basics.is64) {
+            type ElfX_Rel = Elf64_Rel;
+            type ElfX_Rela = Elf64_Rela;
+            type Word = u64;
+            const SYM_SHIFT: u32 = 32;
+            const TYPE_MASK: u64 = 0xffffffff;
+        } else {
+            type ElfX_Rel = Elf32_Rel;
+            type ElfX_Rela = Elf32_Rela;
+            type Word = u32;
+            const SYM_SHIFT: u32 = 8;
+            const TYPE_MASK: u64 = 0xff;
+        } then {
+            let min_size = match format {
+                RelFormat::Rel => size_of::<ElfX_Rel>(),
+                RelFormat::Rela => size_of::<ElfX_Rela>(),
+                RelFormat::Relr => size_of::<Word>(),
+            };
+            if entsize < min_size as u64 {
+                errln!("warning: {:?} entry size ({}) too small, expected at least {}", format, entsize, min_size);
+                return;
+            }
+            let entsize = entsize as usize;
+            match format {
+                RelFormat::Rel => for chunk in data.chunks(entsize) {
+                    if chunk.len() < min_size { break; }
+                    let rel: ElfX_Rel = util::copy_from_slice(&chunk[..min_size], end);
+                    let info = rel.r_info as u64;
+                    push(rel.r_offset as u64, (info & TYPE_MASK) as u32, (info >> SYM_SHIFT) as u32, None);
+                },
+                RelFormat::Rela => for chunk in data.chunks(entsize) {
+                    if chunk.len() < min_size { break; }
+                    let rela: ElfX_Rela = util::copy_from_slice(&chunk[..min_size], end);
+                    let info = rela.r_info as u64;
+                    push(rela.r_offset as u64, (info & TYPE_MASK) as u32, (info >> SYM_SHIFT) as u32, Some(rela.r_addend as i64));
+                },
+                RelFormat::Relr => {
+                    // an even word is an address; an odd one is a bitmap of the following words
+                    let relative = match arch {
+                        Arch::X86_64 => R_X86_64_RELATIVE,
+                        Arch::X86 => R_386_RELATIVE,
+                        Arch::ARM => R_ARM_RELATIVE,
+                        Arch::AArch64 => R_AARCH64_RELATIVE,
+                        _ => { errln!("warning: DT_RELR on unknown architecture"); return; },
+                    };
+                    let word_size = size_of::<Word>() as u64;
+                    let bits = 8 * word_size - 1;
+                    let mut base: Option<u64> = None;
+                    for chunk in data.chunks(entsize) {
+                        if chunk.len() < min_size { break; }
+                        let word: Word = util::copy_from_slice(&chunk[..min_size], end);
+                        let word = word as u64;
+                        if word & 1 == 0 {
+                            push(word, relative, 0, None);
+                            base = Some(word.wrapping_add(word_size));
+                        } else {
+                            let b = some_or!(base, {
+                                errln!("warning: RELR bitmap with no preceding address");
+                                continue;
+                            });
+                            for i in 0..bits {
+                                if (word >> (i + 1)) & 1 != 0 {
+                                    push(b.wrapping_add(i * word_size), relative, 0, None);
+                                }
+                            }
+                            base = Some(b.wrapping_add(bits * word_size));
+                        }
+                    }
+                },
+            }
+        })
+    }
+    pub fn get_relocs(&self) -> Vec<Reloc> {
+        let mut out = Vec::new();
+        let di = &self.dynamic_info;
+        if !self.dyns.is_empty() {
+            let dynsym = self.get_dynsym();
+            let (rel_size, rela_size) = if self.basics.is64 {
+                (size_of::<Elf64_Rel>(), size_of::<Elf64_Rela>())
+            } else {
+                (size_of::<Elf32_Rel>(), size_of::<Elf32_Rela>())
+            };
+            let mut tables: Vec<(&str, OffCountSize, RelFormat)> = Vec::new();
+            if let Some(ocs) = di.rela { tables.push(("DT_RELA", ocs, RelFormat::Rela)); }
+            if let Some(ocs) = di.rel { tables.push(("DT_REL", ocs, RelFormat::Rel)); }
+            if let Some(ocs) = di.relr { tables.push(("DT_RELR", ocs, RelFormat::Relr)); }
+            if let (Some(VMA(jmprel)), Some(pltrelsz)) = (di.jmprel, di.pltrelsz) {
+                let format = match di.pltrel {
+                    Some(DT_RELA) => Some(RelFormat::Rela),
+                    Some(DT_REL) => Some(RelFormat::Rel),
+                    Some(x) => {
+                        errln!("warning: invalid DT_PLTREL {}", x);
+                        None
+                    },
+                    None => {
+                        errln!("warning: DT_JMPREL but no DT_PLTREL");
+                        None
+                    },
+                };
+                if let Some(format) = format {
+                    let size = if format == RelFormat::Rela { rela_size } else { rel_size } as u64;
+                    // some linkers include the PLT relocations in DT_RELASZ/DT_RELSZ
+                    let overlaps = tables.iter().any(|&(_, ocs, f)| {
+                        f == format && jmprel >= ocs.off &&
+                        jmprel - ocs.off < ocs.count.saturating_mul(ocs.size)
+                    });
+                    if !overlaps {
+                        tables.push(("DT_JMPREL", OffCountSize { off: jmprel, count: pltrelsz / size, size: size }, format));
+                    }
+                }
+            }
+            for (what, ocs, format) in tables {
+                let data = self.read_reloc_table(VMA(ocs.off), ocs.count.saturating_mul(ocs.size), what);
+                self.push_relocs(data.get(), ocs.size, format, 0, dynsym, &mut out);
+            }
+        }
+        // static relocation sections; if there's a dynamic table, the allocated ones are already
+        // covered
+        let is_rel = self.ehdr.type_ as u32 == ET_REL;
+        for (i, shdr) in self.shdrs.iter().enumerate() {
+            let format = match shdr.sh_type {
+                SHT_REL => RelFormat::Rel,
+                SHT_RELA => RelFormat::Rela,
+                SHT_RELR => RelFormat::Relr,
+                _ => continue,
+            };
+            if !self.dyns.is_empty() && shdr.sh_flags & (SHF_ALLOC as u64) != 0 { continue; }
+            let sect = &self.eb.sections[i];
+            let data = some_or!(sect.data.as_ref(), {
+                errln!("warning: relocation section {} out of file range", sect.pretty_name());
+                continue;
+            });
+            let bias = if is_rel {
+                let target = some_or!(self.eb.sections.get(shdr.sh_info as usize), {
+                    errln!("warning: relocation section {} has invalid sh_info {}", sect.pretty_name(), shdr.sh_info);
+                    continue;
+                });
+                target.vmaddr.0
+            } else { 0 };
+            let syms: &[Symbol<'static>] = if format == RelFormat::Relr || shdr.sh_link == 0 {
+                &[]
+            } else {
+                self.get_section_symbols(shdr.sh_link as usize)
+            };
+            let entsize = if format == RelFormat::Relr && shdr.sh_entsize == 0 {
+                self.eb.pointer_size as u64
+            } else { shdr.sh_entsize };
+            self.push_relocs(data.get(), entsize, format, bias, syms, &mut out);
+        }
+        out
+    }
+}
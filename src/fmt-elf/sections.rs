@@ -0,0 +1,502 @@
+// Section headers derived from the dynamic table and program headers, for files whose own are
+// missing (e_shnum == 0, as sstrip leaves them) or can't be trusted; and writing them back out as
+// a real section header table.
+
+use util::{self, ByteStr, ByteString, Mem, CheckMul, CheckAdd, CheckSub, ReadCell};
+use exec::{self, Segment, VMA, Prot, ExecResult, ErrorKind, ReadVMA};
+use exec::eh_frame;
+use exec::arch::Arch;
+use elf_bind::*;
+use std::mem::size_of;
+use std::cmp::{min, max};
+use std::collections::HashSet;
+use ::{Elf, Phdr, Shdr, fill_in_data};
+use plt::plt_entry_at;
+
+// Why the section headers can't be used, if they can't.  Only files with program headers can be
+// checked (or reconstructed).
+pub fn shdrs_look_bad(phdrs: &[Phdr], sects: &[Segment], shdrs: &[Shdr]) -> Option<String> {
+    if phdrs.is_empty() { return None; }
+    if shdrs.is_empty() { return Some("no section headers".to_owned()); }
+    if sects.iter().skip(1).all(|sect| sect.name.as_ref().map_or(true, |name| name.is_empty())) {
+        return Some("no section names".to_owned());
+    }
+    for (sect, shdr) in sects.iter().zip(shdrs) {
+        if shdr.sh_flags & (SHF_ALLOC as u64) == 0 || shdr.sh_type == SHT_NOBITS || shdr.sh_size == 0 { continue; }
+        // every allocated section should be mapped by some PT_LOAD, at the same file offset
+        let mapped = phdrs.iter().any(|phdr| {
+            let off = shdr.sh_addr.wrapping_sub(phdr.p_vaddr);
+            phdr.p_type == PT_LOAD && off < phdr.p_filesz && shdr.sh_offset.wrapping_sub(phdr.p_offset) == off
+        });
+        if !mapped {
+            return Some(format!("section {} isn't where the program headers map it", sect.pretty_name()));
+        }
+    }
+    let has_dynamic = sects.iter().any(|sect| sect.name.as_ref().map_or(false, |name| **name == *ByteStr::from_str(".dynamic")));
+    if !has_dynamic && phdrs.iter().any(|phdr| phdr.p_type == PT_DYNAMIC) {
+        return Some("PT_DYNAMIC but no .dynamic section".to_owned());
+    }
+    None
+}
+
+// a section to make; sh_link and sh_info are by name, since indices aren't known until they're
+// sorted
+struct Planned {
+    name: &'static str,
+    addr: VMA,
+    size: u64,
+    type_: u32,
+    link: Option<&'static str>,
+    info: Option<&'static str>,
+    align: u64,
+    entsize: u64,
+}
+
+fn plan(name: &'static str, addr: VMA, size: u64, type_: u32, link: Option<&'static str>, align: u64, entsize: u64) -> Planned {
+    Planned { name: name, addr: addr, size: size, type_: type_, link: link, info: None, align: align, entsize: entsize }
+}
+
+impl Elf {
+    // the PT_LOAD segment containing addr
+    fn load_containing(&self, addr: VMA) -> Option<&Segment> {
+        self.eb.segments.iter().zip(&self.phdrs).find(|&(seg, phdr)| {
+            phdr.p_type == PT_LOAD && addr.wrapping_sub(seg.vmaddr) < seg.vmsize
+        }).map(|(seg, _)| seg)
+    }
+    // Where the .eh_frame_hdr in `hdr_seg` says .eh_frame is, and the data from there to the end
+    // of its segment; it runs until a zero terminator, or that end.
+    pub fn eh_frame_from_hdr(&self, hdr_seg: &Segment) -> Option<(VMA, &[ReadCell<u8>])> {
+        let data = hdr_seg.data.as_ref()?;
+        let addr = eh_frame::eh_frame_hdr_target(data.get(), hdr_seg.vmaddr, self.eb.pointer_size, self.eb.endian)?;
+        let load = self.load_containing(addr)?;
+        let data = load.data.as_ref()?.get();
+        let off = (addr - load.vmaddr) as usize;
+        if off >= data.len() { return None; }
+        Some((addr, &data[off..]))
+    }
+    fn read_u32_at(&self, addr: VMA) -> Option<u32> {
+        let buf = self.eb.read(addr, 4);
+        if buf.len() != 4 { return None; }
+        Some(util::copy_from_slice(buf.get(), self.eb.endian))
+    }
+
+    fn plan_sections(&self) -> Vec<Planned> {
+        let di = &self.dynamic_info;
+        let ptr = self.eb.pointer_size as u64;
+        let dynsym = Some(".dynsym");
+        let mut out = Vec::new();
+        for (seg, phdr) in self.eb.segments.iter().zip(&self.phdrs) {
+            match phdr.p_type {
+                PT_INTERP => out.push(plan(".interp", seg.vmaddr, seg.vmsize, SHT_PROGBITS, None, 1, 0)),
+                PT_DYNAMIC => out.push(plan(".dynamic", seg.vmaddr, seg.vmsize, SHT_DYNAMIC, Some(".dynstr"), ptr, 2 * ptr)),
+                PT_GNU_EH_FRAME => {
+                    out.push(plan(".eh_frame_hdr", seg.vmaddr, seg.vmsize, SHT_PROGBITS, None, 4, 0));
+                    let (addr, data) = some_or!(self.eh_frame_from_hdr(seg), { continue; });
+                    let len = eh_frame::eh_frame_len(data, self.eb.endian);
+                    out.push(plan(".eh_frame", addr, len as u64, SHT_PROGBITS, None, ptr, 0));
+                },
+                _ => (),
+            }
+        }
+        let symcount = self.get_dynamic_symtab().map_or(0, |(symtab, _, syment)| (symtab.len() / syment) as u64);
+        if let Some(addr) = di.symtab {
+            let syment = self.dynsym_syment() as u64;
+            out.push(plan(".dynsym", addr, symcount * syment, SHT_DYNSYM, Some(".dynstr"), ptr, syment));
+        }
+        if let Some(ocs) = di.strtab {
+            out.push(plan(".dynstr", VMA(ocs.off), ocs.count * ocs.size, SHT_STRTAB, None, 1, 0));
+        }
+        if let Some(addr) = di.hash {
+            if let (Some(nbucket), Some(nchain)) = (self.read_u32_at(addr), self.read_u32_at(addr + 4)) {
+                let size = (2 + nbucket as u64 + nchain as u64) * 4;
+                out.push(plan(".hash", addr, size, SHT_HASH, dynsym, ptr, 4));
+            }
+        }
+        if let (Some(addr), Some(gh)) = (di.gnu_hash, self.get_gnu_hash()) {
+            let chains = symcount.saturating_sub(gh.header.symbias as u64) * 4;
+            let size = (gh.chain_addr - addr) + chains;
+            out.push(plan(".gnu.hash", addr, size, SHT_GNU_HASH, dynsym, ptr, 0));
+        }
+        if let Some(addr) = di.versym {
+            out.push(plan(".gnu.version", addr, symcount * 2, SHT_GNU_versym, dynsym, 2, 2));
+        }
+        let (rel_type, rel_ent) = match (di.pltrel, self.basics.is64) {
+            (Some(DT_REL), true) => (SHT_REL, 16),
+            (Some(DT_REL), false) => (SHT_REL, 8),
+            (_, true) => (SHT_RELA, 24),
+            (_, false) => (SHT_RELA, 12),
+        };
+        for &(name, ocs, type_) in &[(".rela.dyn", di.rela, SHT_RELA), (".rel.dyn", di.rel, SHT_REL), (".relr.dyn", di.relr, SHT_RELR)] {
+            let ocs = some_or!(ocs, { continue; });
+            let addr = VMA(ocs.off);
+            let mut size = ocs.count * ocs.size;
+            // some linkers count the PLT relocations in DT_RELASZ too
+            if let Some(jmprel) = di.jmprel {
+                if jmprel > addr && jmprel - addr < size { size = jmprel - addr; }
+            }
+            let link = if type_ == SHT_RELR { None } else { dynsym };
+            out.push(plan(name, addr, size, type_, link, ptr, ocs.size));
+        }
+        // the PLT part of the GOT: three reserved words, then a slot per PLT relocation, which is
+        // what they apply to
+        let got_plt = match (di.pltgot, self.basics.arch) {
+            (Some(addr), Arch::X86) | (Some(addr), Arch::X86_64) | (Some(addr), Arch::ARM) | (Some(addr), Arch::AArch64) => {
+                let slots = di.pltrelsz.map_or(0, |size| size / rel_ent);
+                out.push(plan(".got.plt", addr, (3 + slots) * ptr, SHT_PROGBITS, None, ptr, ptr));
+                Some(".got.plt")
+            },
+            _ => None,
+        };
+        if let (Some(addr), Some(size)) = (di.jmprel, di.pltrelsz) {
+            let name = if rel_type == SHT_REL { ".rel.plt" } else { ".rela.plt" };
+            let mut p = plan(name, addr, size, rel_type, dynsym, ptr, rel_ent);
+            p.info = got_plt;
+            out.push(p);
+        }
+        if let Some(p) = self.plan_plt(rel_ent) {
+            out.push(p);
+        }
+        for &(name, ocs, type_) in &[(".preinit_array", di.preinit_array, SHT_PREINIT_ARRAY),
+                                     (".init_array", di.init_array, SHT_INIT_ARRAY),
+                                     (".fini_array", di.fini_array, SHT_FINI_ARRAY)] {
+            let ocs = some_or!(ocs, { continue; });
+            out.push(plan(name, VMA(ocs.off), ocs.count * ocs.size, type_, None, ptr, ptr));
+        }
+        if let Some(p) = self.plan_text(&out) {
+            out.push(p);
+        }
+        out.retain(|p| p.size != 0);
+        out.sort_by_key(|p| p.addr);
+        out
+    }
+
+    // .plt: the longest run of stubs in an executable segment that jump through the GOT slots of
+    // the PLT relocations.  This covers the stubs but not PLT0 (the lazy binding stub before
+    // them); with IBT, the stubs found are really .plt.sec.  .plt.got and the like, which jump
+    // through other slots, end up in .text.
+    fn plan_plt(&self, rel_ent: u64) -> Option<Planned> {
+        let di = &self.dynamic_info;
+        let (jmprel, size) = match (di.jmprel, di.pltrelsz) {
+            (Some(jmprel), Some(size)) => (jmprel, size),
+            _ => return None,
+        };
+        let (arch, is64, end) = (self.basics.arch, self.basics.is64, self.eb.endian);
+        // r_offset comes first in both Rel and Rela
+        let table = self.eb.read(jmprel, size);
+        let slots: HashSet<VMA> = table.get().chunks(rel_ent as usize).filter(|ent| ent.len() as u64 == rel_ent).map(|ent| {
+            VMA(if is64 { util::copy_from_slice(&ent[..8], end) } else { util::copy_from_slice::<u32, _>(&ent[..4], end) as u64 })
+        }).collect();
+        if slots.is_empty() { return None; }
+        let grain = match arch { Arch::X86 | Arch::X86_64 => 8, _ => 4 };
+        // (first, last, length of the last, count)
+        let mut best: Option<(VMA, VMA, usize, u64)> = None;
+        for (seg, phdr) in self.eb.segments.iter().zip(&self.phdrs) {
+            if phdr.p_type != PT_LOAD || !seg.prot.x { continue; }
+            let data = some_or!(seg.data.as_ref(), { continue; }).get();
+            let mut run = None;
+            let mut off = 0;
+            while off < data.len() {
+                let addr = seg.vmaddr + off as u64;
+                off += grain;
+                let (len, slot) = some_or!(plt_entry_at(arch, is64, end, di.pltgot, data, off - grain, addr), { continue; });
+                if !slots.contains(&slot) { continue; }
+                let cur = match run {
+                    Some((first, last, _, count)) if addr - last <= 32 => (first, addr, len, count + 1),
+                    _ => (addr, addr, len, 1),
+                };
+                run = Some(cur);
+                if best.map_or(true, |best| cur.3 > best.3) { best = run; }
+            }
+        }
+        let (first, last, len, count) = some_or!(best, { return None; });
+        // stubs are evenly spaced; on ARM they're all instructions, elsewhere padded to 16 bytes
+        let stride = if count > 1 {
+            (last - first) / (count - 1)
+        } else if arch == Arch::ARM {
+            len as u64
+        } else {
+            max(len as u64, 16)
+        };
+        Some(plan(".plt", first, last - first + stride, SHT_PROGBITS, None, if arch == Arch::ARM { 4 } else { 16 }, 0))
+    }
+
+    // .text: whatever's left of the executable segment with the entry point, between the headers
+    // and anything planned below the entry point, and up to the next planned section or _fini (in
+    // .fini, which follows .text); this lumps .init in with it if .plt isn't in between
+    fn plan_text(&self, planned: &[Planned]) -> Option<Planned> {
+        let di = &self.dynamic_info;
+        let entry = self.ehdr.entry;
+        let seg = some_or!(self.eb.segments.iter().zip(&self.phdrs).find(|&(seg, phdr)| {
+            phdr.p_type == PT_LOAD && seg.prot.x && entry.wrapping_sub(seg.vmaddr) < seg.vmsize
+        }), { return None; }).0;
+        let mut start = seg.vmaddr;
+        let mut end = some_or!(seg.vmaddr.check_add(seg.vmsize), { return None; });
+        let ph = &self.ehdr.ph;
+        let headers_end = ph.count.check_mul(ph.size).and_then(|size| ph.off.check_add(size));
+        if let Some(headers_end) = headers_end {
+            if seg.fileoff < headers_end && headers_end - seg.fileoff < seg.vmsize {
+                start = seg.vmaddr + (headers_end - seg.fileoff);
+            }
+        }
+        for p in planned {
+            let p_end = some_or!(p.addr.check_add(p.size), { continue; });
+            if p.addr >= seg.vmaddr && p_end <= entry && p_end > start { start = p_end; }
+            if p.addr > entry && p.addr < end { end = p.addr; }
+        }
+        if let Some(fini) = di.fini {
+            if fini > entry && fini < end { end = fini; }
+        }
+        // in an odd file the entry point could be in the headers
+        let size = some_or!(end.check_sub(start), { return None; });
+        Some(plan(".text", start, size, SHT_PROGBITS, None, 16, 0))
+    }
+
+    // Replace the sections with ones derived from the dynamic table and program headers.
+    pub fn reconstruct_sections(&mut self) {
+        let planned = self.plan_sections();
+        let mut sects = vec![Segment {
+            vmaddr: VMA(0),
+            vmsize: 0,
+            fileoff: 0,
+            filesize: 0,
+            name: Some(ByteString::from_str("")),
+            prot: Prot { r: false, w: false, x: false },
+            data: None,
+            seg_idx: None,
+            private: 0,
+        }];
+        let mut shdrs = vec![Shdr {
+            sh_name: 0, sh_type: SHT_NULL, sh_flags: 0, sh_addr: 0, sh_offset: 0, sh_size: 0,
+            sh_link: 0, sh_info: 0, sh_addralign: 0, sh_entsize: 0,
+        }];
+        for p in &planned {
+            let (fileoff, filesize, prot) = if let Some(seg) = self.load_containing(p.addr) {
+                let off = p.addr - seg.vmaddr;
+                (seg.fileoff + off, min(p.size, seg.filesize.saturating_sub(off)), seg.prot)
+            } else {
+                errln!("warning: reconstructed section {} at {} isn't in any PT_LOAD", p.name, p.addr);
+                (0, 0, Prot { r: true, w: false, x: false })
+            };
+            let index = |name: Option<&str>| name.and_then(|name| planned.iter().position(|q| q.name == name)).map_or(0, |i| i + 1);
+            let (link, info) = (index(p.link), index(p.info));
+            let mut flags = SHF_ALLOC as u64;
+            if prot.w { flags |= SHF_WRITE as u64; }
+            if prot.x { flags |= SHF_EXECINSTR as u64; }
+            if info != 0 { flags |= SHF_INFO_LINK as u64; }
+            shdrs.push(Shdr {
+                sh_name: 0, // filled in by write_section_headers
+                sh_type: if filesize == 0 { SHT_NOBITS } else { p.type_ },
+                sh_flags: flags,
+                sh_addr: p.addr.0,
+                sh_offset: fileoff,
+                sh_size: p.size,
+                sh_link: link as u32,
+                sh_info: info as u32,
+                sh_addralign: p.align,
+                sh_entsize: p.entsize,
+            });
+            sects.push(Segment {
+                vmaddr: p.addr,
+                vmsize: p.size,
+                fileoff: fileoff,
+                filesize: filesize,
+                name: Some(ByteString::from_str(p.name)),
+                prot: prot,
+                data: None,
+                seg_idx: None,
+                private: sects.len(),
+            });
+        }
+        if let Some(ref buf) = self.eb.whole_buf {
+            fill_in_data(&mut sects, buf);
+        }
+        self.eb.sections = sects;
+        self.symtab_cache = shdrs.iter().map(|_| util::Lazy::new()).collect();
+        self.shdrs = shdrs;
+        self.sections_reconstructed = true;
+    }
+
+    // Append .shstrtab and a section header table describing self.shdrs to `buf` (the rest of the
+    // file), and point the ELF header at them.
+    pub fn write_section_headers(&self, buf: Mem<u8>) -> ExecResult<Mem<u8>> {
+        let mut out = unsafe { buf.get_plain_slice() }.to_vec();
+        let mut shstrtab = vec![0u8];
+        let mut names = Vec::new();
+        for sect in &self.eb.sections {
+            let name = sect.name.as_ref().map_or(&[][..], |name| &name[..]);
+            if name.is_empty() {
+                names.push(0);
+            } else {
+                names.push(shstrtab.len() as u32);
+                shstrtab.extend_from_slice(name);
+                shstrtab.push(0);
+            }
+        }
+        names.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(b".shstrtab\0");
+        let shstrndx = self.shdrs.len();
+        if shstrndx >= SHN_LORESERVE as usize {
+            return exec::err(ErrorKind::BadData, "too many sections to write back");
+        }
+        let shstrtab_hdr = Shdr {
+            sh_name: 0, sh_type: SHT_STRTAB, sh_flags: 0, sh_addr: 0, sh_offset: out.len() as u64,
+            sh_size: shstrtab.len() as u64, sh_link: 0, sh_info: 0, sh_addralign: 1, sh_entsize: 0,
+        };
+        out.extend_from_slice(&shstrtab);
+        while out.len() % self.eb.pointer_size != 0 { out.push(0); }
+        let shoff = out.len();
+        let end = self.basics.endian;
+        branch!(if (self.basics.is64) {
+            type ElfX_Shdr = Elf64_Shdr;
+            type ElfX_Ehdr = Elf64_Ehdr;
+        } else {
+            type ElfX_Shdr = Elf32_Shdr;
+            type ElfX_Ehdr = Elf32_Ehdr;
+        } then {
+            for (shdr, &name) in self.shdrs.iter().chain(Some(&shstrtab_hdr)).zip(&names) {
+                let x = ElfX_Shdr {
+                    sh_name: name,
+                    sh_type: shdr.sh_type,
+                    sh_flags: shdr.sh_flags as _,
+                    sh_addr: shdr.sh_addr as _,
+                    sh_offset: shdr.sh_offset as _,
+                    sh_size: shdr.sh_size as _,
+                    sh_link: shdr.sh_link,
+                    sh_info: shdr.sh_info,
+                    sh_addralign: shdr.sh_addralign as _,
+                    sh_entsize: shdr.sh_entsize as _,
+                };
+                util::copy_to_vec(&mut out, &x, end);
+            }
+            let ehsize = size_of::<ElfX_Ehdr>();
+            let mut eh: ElfX_Ehdr = util::copy_from_slice(&out[..ehsize], end);
+            eh.e_shoff = shoff as _;
+            eh.e_shnum = (shstrndx + 1) as u16;
+            eh.e_shentsize = size_of::<ElfX_Shdr>() as u16;
+            eh.e_shstrndx = shstrndx as u16;
+            util::copy_to_slice(&mut out[..ehsize], &eh, end);
+        });
+        Ok(Mem::with_vec(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::{check_elf_basics, get_ehdr, get_shdrs, fill_in_sect_names};
+    use ::tests::{build_so, build_so_with, Extras};
+    use exec::Exec;
+
+    #[test]
+    fn test_write_section_headers() {
+        let mut elf = build_so(&[("foo", 0x1000), ("bar", 0x1010)], true);
+        elf.reconstruct_sections();
+        let names: Vec<String> = elf.eb.sections.iter().map(|sect| sect.name.as_ref().unwrap().to_string()).collect();
+        assert_eq!(names, vec!["", ".dynstr", ".dynsym", ".gnu.hash", ".dynamic"]);
+
+        let out = elf.write_section_headers(elf.eb.whole_buf.clone().unwrap()).unwrap();
+        let basics = check_elf_basics(out.get(), false).unwrap();
+        let ehdr = get_ehdr(&basics, out.get()).unwrap();
+        let (mut sects, shdrs) = get_shdrs(&basics, out.get(), &ehdr.sh);
+        assert_eq!(ehdr.shstrndx as usize, elf.shdrs.len());
+        assert_eq!(shdrs.len(), elf.shdrs.len() + 1);
+        let fields = |sh: &Shdr| (sh.sh_type, sh.sh_flags, sh.sh_addr, sh.sh_offset, sh.sh_size, sh.sh_link,
+                                   sh.sh_info, sh.sh_addralign, sh.sh_entsize);
+        for (read, written) in shdrs.iter().zip(&elf.shdrs) {
+            assert_eq!(fields(read), fields(written));
+        }
+        assert_eq!(shdrs.last().unwrap().sh_type, SHT_STRTAB);
+        fill_in_data(&mut sects, &out);
+        fill_in_sect_names(&mut sects, &shdrs, ehdr.shstrndx);
+        let read_names: Vec<String> = sects.iter().map(|sect| sect.name.as_ref().map_or(String::new(), |name| name.to_string())).collect();
+        assert_eq!(read_names[..names.len()], names[..]);
+        assert_eq!(read_names.last().unwrap(), ".shstrtab");
+    }
+
+    #[test]
+    fn test_bad_syment() {
+        let mut elf = build_so_with(&[("foo", 0x1000)], &Extras { dyns: vec![(DT_SYMENT, 0)], ..Default::default() });
+        elf.reconstruct_sections();
+        let (_, dynsym) = elf.eb.sections.iter().zip(&elf.shdrs).find(|&(sect, _)| {
+            **sect.name.as_ref().unwrap() == *ByteStr::from_str(".dynsym")
+        }).unwrap();
+        assert_eq!((dynsym.sh_size, dynsym.sh_entsize), (2 * 24, 24));
+    }
+
+    fn le64(vals: &[u64]) -> Vec<u8> {
+        vals.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    // x86-64 PLT stub i at addr: jmp *slot(%rip); push $i; jmp PLT0
+    fn plt_stub(addr: u64, slot: u64, i: u32, plt0: u64) -> Vec<u8> {
+        let mut out = vec![0xff, 0x25];
+        out.extend_from_slice(&((slot - (addr + 6)) as u32).to_le_bytes());
+        out.push(0x68);
+        out.extend_from_slice(&i.to_le_bytes());
+        out.push(0xe9);
+        out.extend_from_slice(&(plt0.wrapping_sub(addr + 16) as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn test_reconstruct_sections() {
+        // the first page has .rela.plt at 0xa00 and .eh_frame_hdr at 0xc00, for the .eh_frame at
+        // 0xd00; the second is code, PLT0 and two stubs then .text, which _fini ends; the third
+        // is .got.plt
+        let rela_plt = le64(&[0x2018, 1 << 32 | R_X86_64_JUMP_SLOT as u64, 0,
+                              0x2020, 2 << 32 | R_X86_64_JUMP_SLOT as u64, 0]);
+        // version, eh_frame_ptr_enc (pcrel sdata4), fde_count_enc, table_enc, eh_frame_ptr
+        let mut eh_frame_hdr = vec![1, 0x1b, 0x03, 0x3b];
+        eh_frame_hdr.extend_from_slice(&(0xd00u32 - 0xc04).to_le_bytes());
+        let mut eh_frame = Vec::new();
+        // a CIE (augmentation "zR", pcrel sdata4 pointers), an FDE for _fini, and the terminator;
+        // what comes after it isn't part of .eh_frame
+        eh_frame.extend_from_slice(&[0x14, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0, 1, 0x78, 0x10, 1, 0x1b,
+                                     0x0c, 0x07, 0x08, 0x90, 0x01, 0, 0]);
+        eh_frame.extend_from_slice(&[0x10, 0, 0, 0, 0x1c, 0, 0, 0]);
+        eh_frame.extend_from_slice(&(0x1200u32.wrapping_sub(0xd20)).to_le_bytes());
+        eh_frame.extend_from_slice(&[0x10, 0, 0, 0, 0, 0, 0, 0]);
+        eh_frame.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        let mut plt = vec![0xff, 0x35, 0xfa, 0x0f, 0, 0, 0xff, 0x25, 0xfc, 0x0f, 0, 0, 0x0f, 0x1f, 0x40, 0];
+        plt.extend(plt_stub(0x1010, 0x2018, 0, 0x1000));
+        plt.extend(plt_stub(0x1020, 0x2020, 1, 0x1000));
+        let entry = le64(&[0x1100]);
+        let extras = Extras {
+            phdrs: vec![(PT_LOAD, PF_R | PF_X, 0x1000, 0x1000), (PT_LOAD, PF_R | PF_W, 0x2000, 0x1000),
+                        (PT_GNU_EH_FRAME, PF_R, 0xc00, eh_frame_hdr.len() as u64)],
+            dyns: vec![(DT_PLTGOT, 0x2000), (DT_JMPREL, 0xa00), (DT_PLTRELSZ, rela_plt.len() as u64),
+                       (DT_PLTREL, DT_RELA as u64), (DT_FINI, 0x1200)],
+            contents: vec![(24, &entry), (0xa00, &rela_plt), (0xc00, &eh_frame_hdr), (0xd00, &eh_frame),
+                           (0x1000, &plt), (0x1200, &[0xc3]), (0x2ff8, &[0; 8])],
+            ..Default::default()
+        };
+        let mut elf = build_so_with(&[("foo", 0x1100), ("bar", 0x1180)], &extras);
+        // found through PT_GNU_EH_FRAME, and only once when there's a section for it too
+        assert_eq!(elf.get_function_starts(), vec![VMA(0x1200)]);
+        elf.reconstruct_sections();
+        assert_eq!(elf.get_function_starts(), vec![VMA(0x1200)]);
+        let got: Vec<_> = elf.eb.sections.iter().zip(&elf.shdrs).skip(1).map(|(sect, sh)| {
+            (sect.name.as_ref().unwrap().to_string(), sh.sh_addr, sh.sh_size, sh.sh_flags, sh.sh_link, sh.sh_info)
+        }).collect();
+        let s = |name: &str| name.to_owned();
+        let (a, ax, aw, ai) = (SHF_ALLOC as u64, (SHF_ALLOC | SHF_EXECINSTR) as u64,
+                               (SHF_ALLOC | SHF_WRITE) as u64, (SHF_ALLOC | SHF_INFO_LINK) as u64);
+        assert_eq!(got, vec![
+            (s(".dynstr"), 0x200, 9, a, 0, 0),
+            (s(".dynsym"), 0x300, 3 * 24, a, 1, 0),
+            (s(".gnu.hash"), 0x700, 32 + 2 * 4, a, 2, 0),
+            (s(".dynamic"), 0x900, 0x100, a, 1, 0),
+            // linked to the GOT slots it fills in
+            (s(".rela.plt"), 0xa00, 48, ai, 2, 10),
+            (s(".eh_frame_hdr"), 0xc00, 8, a, 0, 0),
+            // up to and including the terminator
+            (s(".eh_frame"), 0xd00, 0x30, a, 0, 0),
+            // the stubs, but not PLT0
+            (s(".plt"), 0x1010, 0x20, ax, 0, 0),
+            // from after .plt to _fini
+            (s(".text"), 0x1030, 0x1d0, ax, 0, 0),
+            // three reserved words, then the two slots
+            (s(".got.plt"), 0x2000, 5 * 8, aw, 0, 0),
+        ]);
+    }
+}
@@ -10,6 +10,31 @@ impl<T> Swap for __BindgenUnionField<T> {
 
 include!(concat!(env!("OUT_DIR"), "/out.rs"));
 
+// newer than our copy of elf.h
+pub const DT_RELRSZ: u32 = 35;
+pub const DT_RELR: u32 = 36;
+pub const DT_RELRENT: u32 = 37;
+pub const SHT_RELR: u32 = 19;
+
+pub const R_X86_64_GOTPCRELX: u32 = 41;
+pub const R_X86_64_REX_GOTPCRELX: u32 = 42;
+pub const R_ARM_CALL: u32 = 28;
+pub const R_ARM_JUMP24: u32 = 29;
+pub const R_ARM_THM_JUMP24: u32 = 30;
+pub const R_AARCH64_PREL32: u32 = 261;
+pub const R_AARCH64_ADR_PREL_PG_HI21: u32 = 275;
+pub const R_AARCH64_ADD_ABS_LO12_NC: u32 = 277;
+pub const R_AARCH64_LDST8_ABS_LO12_NC: u32 = 278;
+pub const R_AARCH64_JUMP26: u32 = 282;
+pub const R_AARCH64_CALL26: u32 = 283;
+pub const R_AARCH64_LDST16_ABS_LO12_NC: u32 = 284;
+pub const R_AARCH64_LDST32_ABS_LO12_NC: u32 = 285;
+pub const R_AARCH64_LDST64_ABS_LO12_NC: u32 = 286;
+pub const R_AARCH64_LDST128_ABS_LO12_NC: u32 = 299;
+pub const R_AARCH64_ADR_GOT_PAGE: u32 = 311;
+pub const R_AARCH64_LD64_GOT_LO12_NC: u32 = 312;
+pub const R_AARCH64_IRELATIVE: u32 = 1032;
+
 // perl -ne 'if (/#define (DT_\w+)(?<!NUM)(?<!DT_ENCODING)\s+([0-9]+|0x[0-9a-fA-F]+a)/) { print "$1 => \"$1\",\n" }' externals/elf/elf.h | pbcopy
 pub fn d_tag_to_str(tag: u32) -> Option<&'static str> {
     Some(match tag {
@@ -41,6 +66,9 @@ pub fn d_tag_to_str(tag: u32) -> Option<&'static str> {
         DT_FLAGS => "DT_FLAGS",
         DT_PREINIT_ARRAY => "DT_PREINIT_ARRAY",
         DT_PREINIT_ARRAYSZ => "DT_PREINIT_ARRAYSZ",
+        DT_RELRSZ => "DT_RELRSZ",
+        DT_RELR => "DT_RELR",
+        DT_RELRENT => "DT_RELRENT",
         DT_VALRNGLO => "DT_VALRNGLO",
         DT_GNU_PRELINKED => "DT_GNU_PRELINKED",
         DT_GNU_CONFLICTSZ => "DT_GNU_CONFLICTSZ",
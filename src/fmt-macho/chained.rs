@@ -0,0 +1,432 @@
+use exec::{VMA, Symbol, SymbolKind, SymbolValue, SourceLib, PtrAuth};
+use util::{self, Swap, ReadCell, Ext, SignExtend, SliceExt};
+use std::mem::size_of;
+use MachO;
+
+// from mach-o/fixup-chains.h, which we don't have a copy of
+pub const DYLD_CHAINED_PTR_ARM64E: u16 = 1;
+pub const DYLD_CHAINED_PTR_64: u16 = 2;
+pub const DYLD_CHAINED_PTR_32: u16 = 3;
+pub const DYLD_CHAINED_PTR_32_CACHE: u16 = 4;
+pub const DYLD_CHAINED_PTR_32_FIRMWARE: u16 = 5;
+pub const DYLD_CHAINED_PTR_64_OFFSET: u16 = 6;
+pub const DYLD_CHAINED_PTR_ARM64E_KERNEL: u16 = 7;
+pub const DYLD_CHAINED_PTR_64_KERNEL_CACHE: u16 = 8;
+pub const DYLD_CHAINED_PTR_ARM64E_USERLAND: u16 = 9;
+pub const DYLD_CHAINED_PTR_ARM64E_FIRMWARE: u16 = 10;
+pub const DYLD_CHAINED_PTR_X86_64_KERNEL_CACHE: u16 = 11;
+pub const DYLD_CHAINED_PTR_ARM64E_USERLAND24: u16 = 12;
+
+pub const DYLD_CHAINED_IMPORT: u32 = 1;
+pub const DYLD_CHAINED_IMPORT_ADDEND: u32 = 2;
+pub const DYLD_CHAINED_IMPORT_ADDEND64: u32 = 3;
+
+pub const DYLD_CHAINED_PTR_START_NONE: u16 = 0xffff;
+pub const DYLD_CHAINED_PTR_START_MULTI: u16 = 0x8000;
+pub const DYLD_CHAINED_PTR_START_LAST: u16 = 0x8000;
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct dyld_chained_fixups_header {
+    pub fixups_version: u32,
+    pub starts_offset: u32,
+    pub imports_offset: u32,
+    pub symbols_offset: u32,
+    pub imports_count: u32,
+    pub imports_format: u32,
+    pub symbols_format: u32,
+}
+impl Swap for dyld_chained_fixups_header {
+    fn bswap(&mut self) {
+        self.fixups_version.bswap();
+        self.starts_offset.bswap();
+        self.imports_offset.bswap();
+        self.symbols_offset.bswap();
+        self.imports_count.bswap();
+        self.imports_format.bswap();
+        self.symbols_format.bswap();
+    }
+}
+
+// followed by page_start[page_count]
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct dyld_chained_starts_in_segment {
+    pub size: u32,
+    pub page_size: u16,
+    pub pointer_format: u16,
+    pub segment_offset: u64,
+    pub max_valid_pointer: u32,
+    pub page_count: u16,
+}
+impl Swap for dyld_chained_starts_in_segment {
+    fn bswap(&mut self) {
+        self.size.bswap();
+        self.page_size.bswap();
+        self.pointer_format.bswap();
+        self.segment_offset.bswap();
+        self.max_valid_pointer.bswap();
+        self.page_count.bswap();
+    }
+}
+const STARTS_IN_SEGMENT_SIZE: usize = 22; // without the tail padding
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChainedFixup {
+    Rebase(VMA),
+    Bind { ordinal: usize, addend: i64 },
+}
+
+fn chained_ptr_stride(format: u16) -> Option<u64> {
+    Some(match format {
+        DYLD_CHAINED_PTR_ARM64E | DYLD_CHAINED_PTR_ARM64E_USERLAND |
+        DYLD_CHAINED_PTR_ARM64E_USERLAND24 => 8,
+        DYLD_CHAINED_PTR_X86_64_KERNEL_CACHE => 1,
+        DYLD_CHAINED_PTR_64 | DYLD_CHAINED_PTR_32 | DYLD_CHAINED_PTR_32_CACHE |
+        DYLD_CHAINED_PTR_32_FIRMWARE | DYLD_CHAINED_PTR_64_OFFSET |
+        DYLD_CHAINED_PTR_ARM64E_KERNEL | DYLD_CHAINED_PTR_64_KERNEL_CACHE |
+        DYLD_CHAINED_PTR_ARM64E_FIRMWARE => 4,
+        _ => return None,
+    })
+}
+
+fn bits(val: u64, start: u32, count: u32) -> u64 {
+    (val >> start) & ((1 << count) - 1)
+}
+
+// Decode one chain entry into (fixup, auth, next).  `base` is the image's load address, for
+// formats that store offsets rather than addresses.  None means a non-pointer.
+fn decode_chained_ptr(format: u16, raw: u64, base: VMA, max_valid_pointer: u32) -> (Option<ChainedFixup>, Option<PtrAuth>, u64) {
+    let rebase = |target: u64, high8: u64, is_offset: bool| {
+        let target = if is_offset { base.wrapping_add(target).0 } else { target };
+        Some(ChainedFixup::Rebase(VMA(target | high8 << 56)))
+    };
+    match format {
+        DYLD_CHAINED_PTR_ARM64E | DYLD_CHAINED_PTR_ARM64E_KERNEL |
+        DYLD_CHAINED_PTR_ARM64E_USERLAND | DYLD_CHAINED_PTR_ARM64E_FIRMWARE |
+        DYLD_CHAINED_PTR_ARM64E_USERLAND24 => {
+            let next = bits(raw, 51, 11);
+            let is_bind = bits(raw, 62, 1) != 0;
+            let is_auth = bits(raw, 63, 1) != 0;
+            let ordinal_bits = if format == DYLD_CHAINED_PTR_ARM64E_USERLAND24 { 24 } else { 16 };
+            // auth rebases and binds put these in the same place
+            let auth = if is_auth {
+                Some(PtrAuth {
+                    key: bits(raw, 49, 2) as u8,
+                    addr_div: bits(raw, 48, 1) != 0,
+                    diversity: bits(raw, 32, 16) as u16,
+                })
+            } else { None };
+            let fixup = match (is_bind, is_auth) {
+                (true, true) => Some(ChainedFixup::Bind { ordinal: bits(raw, 0, ordinal_bits) as usize, addend: 0 }),
+                (true, false) => Some(ChainedFixup::Bind {
+                    ordinal: bits(raw, 0, ordinal_bits) as usize,
+                    addend: bits(raw, 32, 19).sign_extend(19) as i64,
+                }),
+                // auth rebases are always offsets
+                (false, true) => rebase(bits(raw, 0, 32), 0, true),
+                (false, false) => rebase(bits(raw, 0, 43), bits(raw, 43, 8),
+                                         format != DYLD_CHAINED_PTR_ARM64E &&
+                                         format != DYLD_CHAINED_PTR_ARM64E_FIRMWARE),
+            };
+            (fixup, auth, next)
+        },
+        DYLD_CHAINED_PTR_64 | DYLD_CHAINED_PTR_64_OFFSET => {
+            let next = bits(raw, 51, 12);
+            let fixup = if bits(raw, 63, 1) != 0 {
+                Some(ChainedFixup::Bind { ordinal: bits(raw, 0, 24) as usize, addend: bits(raw, 24, 8) as i64 })
+            } else {
+                rebase(bits(raw, 0, 36), bits(raw, 36, 8), format == DYLD_CHAINED_PTR_64_OFFSET)
+            };
+            (fixup, None, next)
+        },
+        DYLD_CHAINED_PTR_64_KERNEL_CACHE | DYLD_CHAINED_PTR_X86_64_KERNEL_CACHE =>
+            (rebase(bits(raw, 0, 30), 0, true), None, bits(raw, 51, 12)),
+        DYLD_CHAINED_PTR_32 => {
+            let next = bits(raw, 26, 5);
+            let fixup = if bits(raw, 31, 1) != 0 {
+                Some(ChainedFixup::Bind { ordinal: bits(raw, 0, 20) as usize, addend: bits(raw, 20, 6) as i64 })
+            } else {
+                let target = bits(raw, 0, 26);
+                if target > max_valid_pointer.ext() { None } else { rebase(target, 0, false) }
+            };
+            (fixup, None, next)
+        },
+        DYLD_CHAINED_PTR_32_CACHE => (rebase(bits(raw, 0, 30), 0, false), None, bits(raw, 30, 2)),
+        DYLD_CHAINED_PTR_32_FIRMWARE => (rebase(bits(raw, 0, 26), 0, false), None, bits(raw, 26, 6)),
+        _ => unreachable!(),
+    }
+}
+
+impl MachO {
+    fn chained_fixups_header(&self) -> Option<dyld_chained_fixups_header> {
+        let blob = self.dyld_chained_fixups.get();
+        if blob.is_empty() { return None; }
+        let size = size_of::<dyld_chained_fixups_header>();
+        if blob.len() < size {
+            errln!("warning: LC_DYLD_CHAINED_FIXUPS data too small for header");
+            return None;
+        }
+        let hdr: dyld_chained_fixups_header = util::copy_from_slice(&blob[..size], self.eb.endian);
+        if hdr.fixups_version != 0 {
+            errln!("warning: unknown chained fixups version {}", hdr.fixups_version);
+            return None;
+        }
+        Some(hdr)
+    }
+
+    // one entry per import, indexed by the ordinal in bind fixups
+    pub fn parse_chained_imports<'a>(&'a self) -> Vec<Symbol<'a>> {
+        let mut out = Vec::new();
+        let hdr = some_or!(self.chained_fixups_header(), { return out; });
+        let blob: &'a [ReadCell<u8>] = self.dyld_chained_fixups.get();
+        let end = self.eb.endian;
+        if hdr.symbols_format != 0 {
+            errln!("warning: compressed chained fixup symbols not supported");
+            return out;
+        }
+        let entry_size = match hdr.imports_format {
+            DYLD_CHAINED_IMPORT => 4,
+            DYLD_CHAINED_IMPORT_ADDEND => 8,
+            DYLD_CHAINED_IMPORT_ADDEND64 => 16,
+            _ => {
+                errln!("warning: unknown chained imports format {}", hdr.imports_format);
+                return out;
+            },
+        };
+        let symbols = some_or!(blob.slice_opt(hdr.symbols_offset as usize, blob.len()), {
+            errln!("warning: chained fixups symbols_offset out of range");
+            return out;
+        });
+        let mut off = hdr.imports_offset as usize;
+        for _ in 0..hdr.imports_count {
+            let entry = some_or!(blob.slice_opt(off, off + entry_size), {
+                errln!("warning: chained fixups imports table truncated");
+                break;
+            });
+            off += entry_size;
+            let (ordinal, weak, name_offset) = if entry_size == 16 {
+                let raw: u64 = util::copy_from_slice(&entry[..8], end);
+                (bits(raw, 0, 16).sign_extend(16) as i64, bits(raw, 16, 1) != 0, bits(raw, 32, 32))
+            } else {
+                let raw: u32 = util::copy_from_slice(&entry[..4], end);
+                let raw: u64 = raw.ext();
+                (bits(raw, 0, 8).sign_extend(8) as i64, bits(raw, 8, 1) != 0, bits(raw, 9, 23))
+            };
+            let source_lib = match ordinal {
+                0 => SourceLib::Self_,
+                -1 => SourceLib::MainExecutable,
+                -2 | -3 /* weak lookup */ => SourceLib::Flat,
+                n if n > 0 && (n as usize) <= self.load_dylib.len() => SourceLib::Ordinal((n - 1) as u32),
+                _ => {
+                    errln!("warning: chained import ordinal out of range (ord={}, count={})", ordinal, self.load_dylib.len());
+                    SourceLib::None
+                },
+            };
+            let name = some_or!(symbols.get(name_offset as usize..), {
+                errln!("warning: chained import name offset out of range");
+                &[]
+            });
+            out.push(Symbol {
+                name: util::from_cstr(name).into(),
+                is_public: true,
+                is_weak: weak || ordinal == -3,
+                val: SymbolValue::Undefined(source_lib),
+                size: None,
+                kind: SymbolKind::Unknown,
+                attrs: 0,
+                private: out.len(),
+            });
+        }
+        out
+    }
+
+    fn chained_import_addend(&self, hdr: &dyld_chained_fixups_header, ordinal: usize) -> i64 {
+        let blob = self.dyld_chained_fixups.get();
+        let end = self.eb.endian;
+        let (entry_size, addend_off, addend_size) = match hdr.imports_format {
+            DYLD_CHAINED_IMPORT_ADDEND => (8, 4, 4),
+            DYLD_CHAINED_IMPORT_ADDEND64 => (16, 8, 8),
+            _ => return 0,
+        };
+        let off = (hdr.imports_offset as usize).wrapping_add(ordinal.wrapping_mul(entry_size)).wrapping_add(addend_off);
+        let data = some_or!(blob.slice_opt(off, off + addend_size), { return 0; });
+        if addend_size == 4 {
+            let addend: i32 = util::copy_from_slice(data, end);
+            addend as i64
+        } else {
+            util::copy_from_slice(data, end)
+        }
+    }
+
+    // Walk every fixup chain.  Bind addends include the one from the imports table.
+    pub fn parse_chained_fixups(&self, cb: &mut dyn FnMut(VMA, ChainedFixup, Option<PtrAuth>) -> bool) {
+        let hdr = some_or!(self.chained_fixups_header(), { return; });
+        let blob = self.dyld_chained_fixups.get();
+        let end = self.eb.endian;
+        let base = self.dyld_base.unwrap_or(VMA(0));
+        let starts_off = hdr.starts_offset as usize;
+        let seg_count: u32 = util::copy_from_slice(some_or!(blob.slice_opt(starts_off, starts_off + 4), {
+            errln!("warning: chained fixups starts_offset out of range");
+            return;
+        }), end);
+        for segi in 0..seg_count as usize {
+            let info_off_off = starts_off + 4 + segi * 4;
+            let info_off: u32 = util::copy_from_slice(some_or!(blob.slice_opt(info_off_off, info_off_off + 4), {
+                errln!("warning: chained fixups seg_info_offset table truncated");
+                return;
+            }), end);
+            if info_off == 0 { continue; }
+            let seg_off = starts_off + info_off as usize;
+            let data = some_or!(blob.slice_opt(seg_off, seg_off + STARTS_IN_SEGMENT_SIZE), {
+                errln!("warning: chained fixups starts for segment {} out of range", segi);
+                continue;
+            });
+            let mut padded = [0u8; 24];
+            for (i, c) in data.iter().enumerate() { padded[i] = c.get(); }
+            let sis: dyld_chained_starts_in_segment = util::copy_from_slice(&padded[..], end);
+            let stride = some_or!(chained_ptr_stride(sis.pointer_format), {
+                errln!("warning: unknown chained pointer format {} in segment {}", sis.pointer_format, segi);
+                continue;
+            });
+            let ptr_size = if sis.pointer_format == DYLD_CHAINED_PTR_32 ||
+                              sis.pointer_format == DYLD_CHAINED_PTR_32_CACHE ||
+                              sis.pointer_format == DYLD_CHAINED_PTR_32_FIRMWARE { 4 } else { 8 };
+            let page_start = |i: usize| -> Option<u16> {
+                let off = seg_off + STARTS_IN_SEGMENT_SIZE + i * 2;
+                blob.slice_opt(off, off + 2).map(|s| util::copy_from_slice(s, end))
+            };
+            let seg_addr = base.wrapping_add(sis.segment_offset);
+            // returns false to stop everything
+            let mut walk_chain = |mut addr: VMA| -> bool {
+                loop {
+                    let data = some_or!(self.eb.get_sane(addr, ptr_size), {
+                        errln!("warning: chained fixup at {} isn't in a segment", addr);
+                        return true;
+                    });
+                    let raw: u64 = if ptr_size == 4 {
+                        let raw: u32 = util::copy_from_slice(data, end);
+                        raw.ext()
+                    } else {
+                        util::copy_from_slice(data, end)
+                    };
+                    let (fixup, auth, next) = decode_chained_ptr(sis.pointer_format, raw, base, sis.max_valid_pointer);
+                    if let Some(mut fixup) = fixup {
+                        if let ChainedFixup::Bind { ordinal, ref mut addend } = fixup {
+                            *addend = addend.wrapping_add(self.chained_import_addend(&hdr, ordinal));
+                        }
+                        if !cb(addr, fixup, auth) { return false; }
+                    }
+                    if next == 0 { return true; }
+                    addr = addr.wrapping_add(next * stride);
+                }
+            };
+            for pagei in 0..sis.page_count as usize {
+                let start = some_or!(page_start(pagei), {
+                    errln!("warning: chained fixups page_start table truncated");
+                    break;
+                });
+                if start == DYLD_CHAINED_PTR_START_NONE { continue; }
+                let page_addr = seg_addr.wrapping_add((pagei as u64) * (sis.page_size as u64));
+                if start & DYLD_CHAINED_PTR_START_MULTI != 0 && ptr_size == 4 {
+                    let mut overflow = (start & !DYLD_CHAINED_PTR_START_MULTI) as usize;
+                    loop {
+                        let start = some_or!(page_start(overflow), {
+                            errln!("warning: chained fixups overflow chain starts truncated");
+                            break;
+                        });
+                        let off = (start & !DYLD_CHAINED_PTR_START_LAST) as u64;
+                        if !walk_chain(page_addr.wrapping_add(off)) { return; }
+                        if start & DYLD_CHAINED_PTR_START_LAST != 0 { break; }
+                        overflow += 1;
+                    }
+                } else {
+                    if !walk_chain(page_addr.wrapping_add(start as u64)) { return; }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exec::{RelocTarget, RelocKind};
+    use LC_DYLD_CHAINED_FIXUPS;
+    use tests::{BASE, build_macho, put, put_words};
+
+    const TARGET: u64 = BASE + 0x1020;
+
+    fn rebase(target: u64) -> Option<ChainedFixup> { Some(ChainedFixup::Rebase(VMA(target))) }
+
+    #[test]
+    fn test_decode_64() {
+        let base = VMA(BASE);
+        let raw = 2 << 51 | 0x80 << 36 | TARGET;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_64, raw, base, 0),
+                   (rebase(0x8000_0000_0000_0000 | TARGET), None, 2));
+        let raw = 1 << 63 | 1 << 51 | 3 << 24 | 5;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_64, raw, base, 0),
+                   (Some(ChainedFixup::Bind { ordinal: 5, addend: 3 }), None, 1));
+        // same layout, but the target is from the load address
+        let raw = 0x80 << 36 | 0x1020;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_64_OFFSET, raw, base, 0),
+                   (rebase(0x8000_0000_0000_0000 | TARGET), None, 0));
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_64_OFFSET, 1 << 63 | 7, base, 0),
+                   (Some(ChainedFixup::Bind { ordinal: 7, addend: 0 }), None, 0));
+    }
+
+    #[test]
+    fn test_decode_arm64e() {
+        let base = VMA(BASE);
+        let raw = 3 << 51 | 0x12 << 43 | TARGET;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_ARM64E, raw, base, 0),
+                   (rebase(0x1200_0000_0000_0000 | TARGET), None, 3));
+        // userland rebases are offsets
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_ARM64E_USERLAND, 0x1020, base, 0),
+                   (rebase(TARGET), None, 0));
+        let raw = 1 << 62 | 0x7fffc << 32 | 9;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_ARM64E, raw, base, 0),
+                   (Some(ChainedFixup::Bind { ordinal: 9, addend: -4 }), None, 0));
+        let raw = 1 << 63 | 1 << 51 | 1 << 49 | 0x1234 << 32 | 0x1020;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_ARM64E, raw, base, 0),
+                   (rebase(TARGET), Some(PtrAuth { key: 1, addr_div: false, diversity: 0x1234 }), 1));
+        let raw = 1 << 63 | 1 << 62 | 2 << 49 | 1 << 48 | 0xbeef << 32 | 0x12_3456;
+        assert_eq!(decode_chained_ptr(DYLD_CHAINED_PTR_ARM64E_USERLAND24, raw, base, 0),
+                   (Some(ChainedFixup::Bind { ordinal: 0x12_3456, addend: 0 }),
+                    Some(PtrAuth { key: 2, addr_div: true, diversity: 0xbeef }), 0));
+    }
+
+    #[test]
+    fn test_chained_relocs() {
+        // header, starts for __DATA only, one import named _foo
+        let mut fixups = Vec::new();
+        put_words(&mut fixups, 0, &[0, 0x20, 0x50, 0x58, 1, DYLD_CHAINED_IMPORT, 0]);
+        put_words(&mut fixups, 0x20, &[3, 0, 0x10, 0]);
+        put_words(&mut fixups, 0x30, &[24, (DYLD_CHAINED_PTR_ARM64E as u32) << 16 | 0x1000, 0x1000, 0, 0, 1]);
+        put_words(&mut fixups, 0x50, &[1 << 9]);
+        put(&mut fixups, 0x58, b"\0_foo\0");
+        // a plain rebase, an auth bind and an auth rebase
+        let mut data = Vec::new();
+        put(&mut data, 0, &(1 << 51 | 0x12 << 43 | TARGET).to_le_bytes());
+        put(&mut data, 8, &(1u64 << 63 | 1 << 62 | 2 << 51 | 2 << 49 | 1 << 48 | 0xbeef << 32).to_le_bytes());
+        put(&mut data, 0x18, &(1u64 << 63 | 1 << 49 | 0x1234 << 32 | 0x1020).to_le_bytes());
+        let mo = build_macho(&[], &[(LC_DYLD_CHAINED_FIXUPS, &[0x2000, fixups.len() as u32])],
+                             &[(0x1000, &data), (0x2000, &fixups)]);
+        let relocs = mo.get_relocs();
+        let got: Vec<_> = relocs.iter().map(|r| {
+            assert_eq!(r.kind, RelocKind::Pointer);
+            let target = match r.target {
+                RelocTarget::ThisImageSlide => "slide".to_owned(),
+                RelocTarget::Import(sym) => sym.name.to_string(),
+                _ => panic!(),
+            };
+            (r.address.0 - BASE, target, r.addend, r.auth)
+        }).collect();
+        assert_eq!(got, vec![
+            (0x1000, "slide".to_owned(), Some(0x1200_0000_0000_0000 | TARGET as i64), None),
+            (0x1008, "_foo".to_owned(), Some(0), Some(PtrAuth { key: 2, addr_div: true, diversity: 0xbeef })),
+            (0x1018, "slide".to_owned(), Some(TARGET as i64), Some(PtrAuth { key: 1, addr_div: false, diversity: 0x1234 })),
+        ]);
+    }
+}
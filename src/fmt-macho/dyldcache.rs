@@ -1,16 +1,18 @@
 use util;
 use exec;
 use macho_bind;
-use util::{Mem, ByteString, Ext, SliceExt, ByteStr, Narrow, Lazy, Fnv, CheckMul, Cast, Unswapped, CheckSub, ReadCell, LazyBox, LittleEndian};
+use util::{Mem, ByteString, Ext, SliceExt, ByteStr, Narrow, Lazy, Fnv, CheckMul, Cast, Unswapped, CheckSub, ReadCell, LazyBox, LittleEndian, Swap};
 use exec::ErrorKind::BadData;
 use exec::arch;
-use exec::{Reloc, RelocKind, RelocTarget, ExecResult, err, ExecBase, VMA, Exec, ExecProber, ProbeResult, Segment, ErrorKind, intersect_start_size};
+use exec::{Reloc, RelocKind, RelocTarget, PtrAuth, ExecResult, err, ExecBase, VMA, Exec, ExecProber, ProbeResult, Segment, ErrorKind, intersect_start_size};
 use std::mem::size_of;
 use std::cmp::{min, max, Ordering};
 use std::ops::Range;
 use std::collections::{HashSet, HashMap};
 use std::collections::hash_map::Entry;
 use std::any::Any;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::cell::Cell;
 use std;
 pub use macho_bind::{dyld_cache_header, dyld_cache_mapping_info, dyld_cache_image_info, dyld_cache_local_symbols_info, dyld_cache_local_symbols_entry, dyld_cache_slide_info, dyld_cache_slide_info2, DYLD_CACHE_SLIDE_PAGE_ATTR_NO_REBASE, DYLD_CACHE_SLIDE_PAGE_ATTR_EXTRA, DYLD_CACHE_SLIDE_PAGE_ATTR_END};
@@ -25,6 +27,7 @@ pub struct ImageInfo {
 
 pub struct LocalSymbols {
     entries: Mem<u8>,
+    entries_64: bool,
     symtab: Mem<u8>,
     strtab: Mem<u8>,
     nlist_count: u32,
@@ -38,6 +41,13 @@ pub struct DyldCache {
     pub cs_blob: Option<Mem<u8>>,
     pub local_symbols: Option<LocalSymbols>,
     pub have_images_text_offset: bool,
+    pub subcaches: Vec<SubCacheEntry>,
+}
+
+pub struct SubCacheEntry {
+    pub uuid: [u8; 16],
+    pub vm_offset: u64,
+    pub suffix: String,
 }
 
 const SLIDE_GRANULARITY: u64 = 4;
@@ -50,6 +60,7 @@ pub struct SlideInfoV1 {
     endian: util::Endian,
 }
 const SLAB_PAGES: usize = 128;
+// also used for v4, which has the same header but different page attrs and values
 pub struct SlideInfoV2 {
     pub page_size: u64,
     pub page_starts: Mem<Unswapped<u16>>,
@@ -57,15 +68,225 @@ pub struct SlideInfoV2 {
     pub delta_mask: u64,
     pub delta_shift: u32, // not including the -2
     pub value_add: u64,
+    pub v4: bool,
+    pub data_seg_idx: usize,
+    rebase_list_by_slab: Vec<LazyBox<Vec<Reloc<'static>>>>,
+}
+// also used for v5, which has the same layout but different pointers
+pub struct SlideInfoV3 {
+    pub page_size: u64,
+    pub page_starts: Mem<Unswapped<u16>>,
+    pub value_add: u64, // auth_value_add for v3
+    pub v5: bool,
+    pub data_seg_idx: usize,
     rebase_list_by_slab: Vec<LazyBox<Vec<Reloc<'static>>>>,
 }
 pub enum SlideInfo {
     V1(SlideInfoV1),
     V2(SlideInfoV2),
+    V3(SlideInfoV3),
+    // one per mapping, from mappingWithSlideOffset
+    Multi(Vec<SlideInfo>),
+}
+
+// newer than our copy of dyld_cache_format.h
+const DYLD_CACHE_SLIDE4_PAGE_NO_REBASE: u16 = 0xffff;
+const DYLD_CACHE_SLIDE4_PAGE_INDEX: u16 = 0x7fff;
+const DYLD_CACHE_SLIDE4_PAGE_USE_EXTRA: u16 = 0x8000;
+const DYLD_CACHE_SLIDE4_PAGE_EXTRA_END: u16 = 0x8000;
+const DYLD_CACHE_SLIDE_V3_PAGE_ATTR_NO_REBASE: u16 = 0xffff;
+const DYLD_CACHE_SLIDE_V5_PAGE_ATTR_NO_REBASE: u16 = 0xffff;
+
+// v5 is the same with value_add in place of auth_value_add; both are followed by page_starts[]
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct dyld_cache_slide_info3 {
+    pub version: u32,
+    pub page_size: u32,
+    pub page_starts_count: u32,
+    pub pad: u32,
+    pub auth_value_add: u64,
+}
+impl Swap for dyld_cache_slide_info3 {
+    fn bswap(&mut self) {
+        self.version.bswap();
+        self.page_size.bswap();
+        self.page_starts_count.bswap();
+        self.auth_value_add.bswap();
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct dyld_cache_mapping_and_slide_info {
+    pub address: u64,
+    pub size: u64,
+    pub fileOffset: u64,
+    pub slideInfoFileOffset: u64,
+    pub slideInfoFileSize: u64,
+    pub flags: u64,
+    pub maxProt: u32,
+    pub initProt: u32,
+}
+impl Swap for dyld_cache_mapping_and_slide_info {
+    fn bswap(&mut self) {
+        self.address.bswap();
+        self.size.bswap();
+        self.fileOffset.bswap();
+        self.slideInfoFileOffset.bswap();
+        self.slideInfoFileSize.bswap();
+        self.flags.bswap();
+        self.maxProt.bswap();
+        self.initProt.bswap();
+    }
+}
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone)]
+pub struct dyld_cache_local_symbols_entry_64 {
+    pub dylibOffset: u64,
+    pub nlistStartIndex: u32,
+    pub nlistCount: u32,
+}
+impl Swap for dyld_cache_local_symbols_entry_64 {
+    fn bswap(&mut self) {
+        self.dylibOffset.bswap();
+        self.nlistStartIndex.bswap();
+        self.nlistCount.bswap();
+    }
+}
+
+// past the end of our dyld_cache_header
+const HDR_MAPPING_WITH_SLIDE_OFFSET: usize = 0x138;
+const HDR_MAPPING_WITH_SLIDE_COUNT: usize = 0x13c;
+const HDR_SUBCACHE_ARRAY_OFFSET: usize = 0x188;
+const HDR_SUBCACHE_ARRAY_COUNT: usize = 0x18c;
+const HDR_SYMBOL_FILE_UUID: usize = 0x190;
+const HDR_IMAGES_OFFSET: usize = 0x1c0;
+const HDR_IMAGES_COUNT: usize = 0x1c4;
+const HDR_CACHE_SUB_TYPE: usize = 0x1c8;
+const SUBCACHE_ENTRY_V1_SIZE: usize = 24; // uuid, cacheVMOffset
+const SUBCACHE_ENTRY_SIZE: usize = 56; // ...and fileSuffix
+
+// a header field, if the header is new enough to have it (as usual, judging by mappingOffset)
+fn hdr_u32(buf: &[ReadCell<u8>], hdr: &dyld_cache_header, off: usize, end: util::Endian) -> Option<u32> {
+    if (hdr.mappingOffset as usize) < off + 4 { return None; }
+    buf.slice_opt(off, off + 4).map(|slice| util::copy_from_slice(slice, end))
+}
+fn hdr_uuid(buf: &[ReadCell<u8>], hdr: &dyld_cache_header, off: usize) -> Option<[u8; 16]> {
+    if (hdr.mappingOffset as usize) < off + 16 { return None; }
+    let uuid: [u8; 16] = util::copy_from_slice(some_or!(buf.slice_opt(off, off + 16), { return None; }), LittleEndian);
+    if uuid == [0; 16] { None } else { Some(uuid) }
+}
+
+fn read_header(mc: &Mem<u8>) -> ExecResult<(arch::Arch, util::Endian, bool, dyld_cache_header)> {
+    // note - not all fields in older caches, but at least a page should be there, so don't worry about size calculation
+    let hdr_size = size_of::<dyld_cache_header>();
+    let buf = mc.get();
+    if buf.len() < hdr_size { return err(BadData, "truncated"); }
+    let top: [u8; 16] = util::copy_from_slice(&buf[..16], LittleEndian);
+    if &top[..7] != b"dyld_v1" {
+        return err(BadData, "bad magic");
+    }
+    let padded_arch = &top[7..16];
+    let (arch, is64) = if padded_arch == b"    i386\0" {
+        (arch::X86, false)
+    } else if padded_arch == b"  x86_64\0" ||
+              padded_arch == b" x86_64h\0" {
+        (arch::X86_64, true)
+    } else if padded_arch == b"   armv7\0" ||
+              padded_arch == b"   armv6\0" ||
+              padded_arch == b"  armv7s\0" ||
+              padded_arch == b"  armv7k\0" {
+        (arch::ARM, false)
+    } else if padded_arch == b"   arm64\0" ||
+              padded_arch == b"  arm64e\0" {
+        (arch::AArch64, true)
+    } else {
+        return err(BadData, "unknown architecture, ergo can't determine endianness");
+    };
+    let end = LittleEndian;
+    let hdr: dyld_cache_header = util::copy_from_slice(&buf[..hdr_size], end);
+    Ok((arch, end, is64, hdr))
+}
+
+// from the main cache's header; the suffixes are appended to its path to get the subcaches'
+pub fn subcache_entries(mc: &Mem<u8>) -> ExecResult<Vec<SubCacheEntry>> {
+    let (_, end, _, hdr) = read_header(mc)?;
+    let buf = mc.get();
+    let offset = some_or!(hdr_u32(buf, &hdr, HDR_SUBCACHE_ARRAY_OFFSET, end), { return Ok(Vec::new()); });
+    let count = hdr_u32(buf, &hdr, HDR_SUBCACHE_ARRAY_COUNT, end).unwrap_or(0);
+    // the first version had no suffixes; they were just numbered
+    let has_suffix = hdr.mappingOffset as usize > HDR_CACHE_SUB_TYPE;
+    let entry_size = if has_suffix { SUBCACHE_ENTRY_SIZE } else { SUBCACHE_ENTRY_V1_SIZE };
+    let entries = file_array(mc, "subcache array", offset.ext(), count.ext(), entry_size);
+    Ok(entries.get().chunks(entry_size).enumerate().map(|(i, entry)| {
+        let suffix = if has_suffix {
+            util::from_cstr(&entry[24..]).lossy().into_owned()
+        } else {
+            format!(".{}", i + 1)
+        };
+        SubCacheEntry {
+            uuid: util::copy_from_slice(&entry[..16], end),
+            vm_offset: util::copy_from_slice(&entry[16..24], end),
+            suffix: suffix,
+        }
+    }).collect())
+}
+
+// the segments for one (sub)cache file, which is at `base` in the whole buffer
+fn push_mappings(file: &Mem<u8>, base: u64, hdr: &dyld_cache_header, end: util::Endian, segments: &mut Vec<Segment>) {
+    let so = size_of::<dyld_cache_mapping_info>();
+    let mapping_mc = file_array(file, "mapping info", hdr.mappingOffset.ext(), hdr.mappingCount.ext(), so);
+    let len = file.len() as u64;
+    for (i, mi_buf) in mapping_mc.get().chunks(so).enumerate() {
+        let mut mi: dyld_cache_mapping_info = util::copy_from_slice(mi_buf, end);
+        if mi.fileOffset >= len {
+            errln!("warning: mapping_info {} in shared cache offset ({}) past end of file ({})", i, mi.fileOffset, len);
+            mi.size = 0;
+            mi.fileOffset = 0;
+        } else if mi.size > len - mi.fileOffset {
+            errln!("warning: mapping_info {} in shared cache bounds ({}+{}) extend past end of file ({}); truncating", i, mi.fileOffset, mi.size, len);
+            mi.size = len - mi.fileOffset;
+        }
+
+        segments.push(Segment {
+            vmaddr: VMA(mi.address),
+            vmsize: mi.size,
+            fileoff: base + mi.fileOffset,
+            filesize: mi.size,
+            name: None,
+            prot: ::u32_to_prot(mi.initProt),
+            data: file.slice(mi.fileOffset as usize, (mi.fileOffset + mi.size) as usize),
+            seg_idx: None,
+            private: base as usize + hdr.mappingOffset as usize + i * so,
+        });
+    }
+}
+
+// which pages of the segment at data_seg_idx overlap range, in slabs
+fn slab_range(eb: &ExecBase, data_seg_idx: usize, page_size: u64, page_count: usize, range: Option<(VMA, u64)>) -> Option<(&Segment, Range<usize>)> {
+    let data_seg = some_or!(eb.segments.get(data_seg_idx), {
+        errln!("SlideInfo::save_rebase_list: no data segment");
+        return None;
+    });
+    if eb.endian != LittleEndian {
+        errln!("SlideInfo::save_rebase_list: not little endian; hardcoded just for optimization's sake");
+        return None;
+    }
+    let (mut page_start, mut page_end) = (0usize, page_count);
+    if let Some((raddr, rsize)) = range {
+        // need better utilities for ranges
+        let (raddr, rsize) = intersect_start_size((raddr, rsize), (data_seg.vmaddr, data_seg.vmsize));
+        page_start = max(page_start, ((raddr - data_seg.vmaddr) / page_size) as usize);
+        page_end = min(page_end, ((raddr + rsize - data_seg.vmaddr + page_size - 1) / page_size) as usize);
+    }
+    if page_start > page_end { return None; }
+    assert!(data_seg.name.is_none()); // the eb has to be the whole dyldcache, not a member...
+    Some((data_seg, page_start / SLAB_PAGES..(page_end + SLAB_PAGES - 1) / SLAB_PAGES))
 }
 
 impl SlideInfo {
-    pub fn new(blob: Mem<u8>, end: util::Endian, is64: bool, data_addr: VMA, data_size: u64) -> ExecResult<SlideInfo> {
+    pub fn new(blob: Mem<u8>, end: util::Endian, is64: bool, data_seg_idx: usize, data_addr: VMA, data_size: u64) -> ExecResult<SlideInfo> {
         let slide_info_version: u32 = {
             let slice = blob.get();
             if slice.len() < 4 {
@@ -75,16 +296,33 @@ impl SlideInfo {
         };
         match slide_info_version {
             1 => Ok(SlideInfo::V1(SlideInfoV1::new(blob, end, data_addr, data_size)?)),
-            2 => Ok(SlideInfo::V2(SlideInfoV2::new(blob, end, is64)?)),
+            2 | 4 => Ok(SlideInfo::V2(SlideInfoV2::new(blob, end, is64, slide_info_version == 4, data_seg_idx)?)),
+            3 | 5 => Ok(SlideInfo::V3(SlideInfoV3::new(blob, end, is64, slide_info_version == 5, data_seg_idx)?)),
             _ => err(BadData, format!("unknown dyld slide info version {}", slide_info_version)),
         }
     }
+    // decodes (and rewrites) the chains in range if necessary; not applicable to V1
+    pub fn save_rebase_list(&self, eb: &ExecBase, range: Option<(VMA, u64)>) -> Vec<&[LazyBox<Vec<Reloc<'static>>>]> {
+        match self {
+            &SlideInfo::V1(_) => vec!(),
+            &SlideInfo::V2(ref v2) => vec!(v2.save_rebase_list(eb, range)),
+            &SlideInfo::V3(ref v3) => vec!(v3.save_rebase_list(eb, range)),
+            &SlideInfo::Multi(ref infos) => infos.iter().flat_map(|info| info.save_rebase_list(eb, range)).collect(),
+        }
+    }
     pub fn iter<F>(&self, eb: &ExecBase, range: Option<(VMA, u64)>, mut func: F) where F: FnMut(VMA) {
+        self.iter_dyn(eb, range, &mut func)
+    }
+    fn iter_dyn(&self, eb: &ExecBase, range: Option<(VMA, u64)>, func: &mut dyn FnMut(VMA)) {
         match self {
             &SlideInfo::V1(ref v1) => v1.iter(range, func),
-            &SlideInfo::V2(ref v2) => {
-                let slabs = v2.save_rebase_list(eb, range);
-                for slab in slabs {
+            &SlideInfo::Multi(ref infos) => {
+                for info in infos {
+                    info.iter_dyn(eb, range, func);
+                }
+            },
+            _ => {
+                for slab in self.save_rebase_list(eb, range).into_iter().flatten() {
                     let relocs = &slab.get().unwrap()[..];
                     for reloc in relocs {
                         if let Some((start_addr, size)) = range {
@@ -174,7 +412,7 @@ impl SlideInfoV1 {
 }
 
 impl SlideInfoV2 {
-    pub fn new(blob: Mem<u8>, end: util::Endian, is64: bool) -> ExecResult<Self> {
+    pub fn new(blob: Mem<u8>, end: util::Endian, is64: bool, v4: bool, data_seg_idx: usize) -> ExecResult<Self> {
         let size = size_of::<dyld_cache_slide_info2>();
         if blob.len() < size {
             return err(BadData, "slide info blob too small for header");
@@ -212,34 +450,27 @@ impl SlideInfoV2 {
             delta_mask: slide_info.delta_mask,
             delta_shift: delta_shift,
             value_add: slide_info.value_add,
+            v4: v4,
+            data_seg_idx: data_seg_idx,
             rebase_list_by_slab: (0..num_slabs).map(|_| LazyBox::new()).collect(),
         })
     }
     pub fn save_rebase_list(&self, eb: &ExecBase, range: Option<(VMA, u64)>) -> &[LazyBox<Vec<Reloc<'static>>>] {
         //let _sw = util::stopwatch("save_rebase_list");
-        let data_seg = some_or!(eb.segments.get(1), {
-            errln!("SlideInfoV2::save_rebase_list: no data segment");
+        let (data_seg, slabs) = some_or!(slab_range(eb, self.data_seg_idx, self.page_size, self.page_starts.len(), range), {
             return &[];
         });
-        //println!("save_rebase_list: range={:?} data={:?}", range, (data_seg.vmaddr, data_seg.vmsize));
-        if eb.endian != LittleEndian {
-            errln!("SlideInfoV2::save_rebase_list: not little endian; hardcoded just for optimization's sake");
-            return &[];
-        }
-        let (mut page_start, mut page_end) = (0usize, self.page_starts.len());
-        if let Some((raddr, rsize)) = range {
-            // need better utilities for ranges
-            let (raddr, rsize) = intersect_start_size((raddr, rsize), (data_seg.vmaddr, data_seg.vmsize));
-            page_start = max(page_start, ((raddr - data_seg.vmaddr) / self.page_size) as usize);
-            page_end = min(page_end, ((raddr + rsize - data_seg.vmaddr + self.page_size - 1) / self.page_size) as usize);
-        }
-        //println!("save_rebase_list: page_start={} page_end={}", page_start, page_end);
-        if page_start > page_end { return &[]; }
         let delta_mask = self.delta_mask;
         let delta_shift = self.delta_shift;
         let value_add = self.value_add;
-        let slab_start = page_start / SLAB_PAGES;
-        let slab_end = (page_end + SLAB_PAGES - 1) / SLAB_PAGES;
+        let (no_rebase, use_extra, page_index, extra_end) = if self.v4 {
+            (DYLD_CACHE_SLIDE4_PAGE_NO_REBASE, DYLD_CACHE_SLIDE4_PAGE_USE_EXTRA,
+             DYLD_CACHE_SLIDE4_PAGE_INDEX, DYLD_CACHE_SLIDE4_PAGE_EXTRA_END)
+        } else {
+            (DYLD_CACHE_SLIDE_PAGE_ATTR_NO_REBASE as u16, DYLD_CACHE_SLIDE_PAGE_ATTR_EXTRA as u16,
+             0x3fff, DYLD_CACHE_SLIDE_PAGE_ATTR_END as u16)
+        };
+        let (slab_start, slab_end) = (slabs.start, slabs.end);
         for slab in slab_start..slab_end {
             let lazybox = &self.rebase_list_by_slab[slab];
             if lazybox.get().is_some() { continue; }
@@ -247,7 +478,6 @@ impl SlideInfoV2 {
             let page_extras = self.page_extras.get();
             let page_size = self.page_size as usize;
             let pointer_size = eb.pointer_size;
-            assert!(data_seg.name.is_none()); // the eb has to be the whole dyldcache, not a member...
             let data_data: &[Cell<u8>] = data_seg.data.as_ref().unwrap().get_mut();
             let endian = eb.endian;
             let mut got_straddle = false;
@@ -260,31 +490,31 @@ impl SlideInfoV2 {
                 let addr: VMA = data_seg.vmaddr.wrapping_add(offset as u64);
                 let ps = ps.copy(endian);
                 //println!("{:x}: ps={:x}", i, ps);
-                let start: u16;
+                let start: usize;
                 let rest_extras: &[ReadCell<Unswapped<u16>>];
-                if ps == (DYLD_CACHE_SLIDE_PAGE_ATTR_NO_REBASE as u16) {
+                if ps == no_rebase {
                     continue;
-                } else if ps & (DYLD_CACHE_SLIDE_PAGE_ATTR_EXTRA as u16) == 0 {
-                    start = ps * 4;
+                } else if ps & use_extra == 0 {
+                    start = (ps as usize) * 4;
                     rest_extras = &[];
                 } else {
-                    let extras_start_idx = (ps & 0x3fff) as usize;
+                    // the entry with the END bit is the last one in the list, not a terminator
+                    let extras_start_idx = (ps & page_index) as usize;
                     let mut extras_idx = extras_start_idx;
                     loop {
                         let pe = some_or!(page_extras.get(extras_idx), {
                             errln!("SlideInfoV2::save_rebase_list: page_extras index out of range");
                             break;
                         });
-                        let pe = pe.copy(endian);
-                        if pe & (DYLD_CACHE_SLIDE_PAGE_ATTR_END as u16) != 0 { break; }
                         extras_idx += 1;
+                        if pe.copy(endian) & extra_end != 0 { break; }
                     }
                     if extras_start_idx == extras_idx { continue; }
-                    start = page_extras[extras_start_idx].copy(endian);
+                    start = ((page_extras[extras_start_idx].copy(endian) & page_index) as usize) * 4;
                     rest_extras = &page_extras[extras_start_idx+1..extras_idx];
                 }
                 let mut rest_extras = rest_extras.into_iter();
-                let mut offset_in_page = start as usize;
+                let mut offset_in_page = start;
                 // for each linked list...
                 loop {
                     // for each entry in the list...
@@ -304,8 +534,9 @@ impl SlideInfoV2 {
                             rebase_list.push(Reloc {
                                 address: addr + offset_in_page.ext(),
                                 kind: RelocKind::_32Bit,
-                                base: None,
+                                addend: None,
                                 target: RelocTarget::ThisImageSlide,
+                                auth: None,
                             });
                             got_straddle = true;
                             break;
@@ -325,7 +556,18 @@ impl SlideInfoV2 {
                             //println!("number={:x} delta_shift={} delta_mask={:x}", number, self.delta_shift, self.delta_mask);
                             delta = (4 * ((number & (delta_mask as Ptr)) >> delta_shift)) as usize;
                             let mut value = number & !(delta_mask as Ptr);
-                            if value != 0 {
+                            if self.v4 {
+                                // v4 is 32-bit only
+                                let v = value as u64;
+                                if v & 0xffff8000 == 0 {
+                                    // small positive non-pointer; leave it alone
+                                } else if v & 0x3fff8000 == 0x3fff8000 {
+                                    // small negative non-pointer
+                                    value = (v | 0xc0000000) as Ptr;
+                                } else {
+                                    value = value.wrapping_add(value_add as Ptr);
+                                }
+                            } else if value != 0 {
                                 value = value.wrapping_add(value_add as Ptr);
                             }
                             util::copy_to_slice(slice, &value, LittleEndian);
@@ -333,8 +575,9 @@ impl SlideInfoV2 {
                         rebase_list.push(Reloc {
                             address: addr + offset_in_page.ext(),
                             kind: RelocKind::Pointer,
-                            base: None,
+                            addend: None,
                             target: RelocTarget::ThisImageSlide,
+                            auth: None,
                         });
                         if delta == 0 {
                             break;
@@ -347,7 +590,7 @@ impl SlideInfoV2 {
                     }
 
                     let next = some_or!(rest_extras.next(), { break });
-                    offset_in_page = (next.copy(endian) & !(DYLD_CACHE_SLIDE_PAGE_ATTR_END as u16)).ext();
+                    offset_in_page = ((next.copy(endian) & page_index) as usize) * 4;
                 }
             }
             if got_straddle && self.value_add != 0 {
@@ -359,6 +602,121 @@ impl SlideInfoV2 {
     }
 }
 
+impl SlideInfoV3 {
+    pub fn new(blob: Mem<u8>, end: util::Endian, is64: bool, v5: bool, data_seg_idx: usize) -> ExecResult<Self> {
+        let size = size_of::<dyld_cache_slide_info3>();
+        if blob.len() < size {
+            return err(BadData, "slide info blob too small for header");
+        }
+        if !is64 {
+            return err(BadData, "slide info 3/5 in a 32-bit cache");
+        }
+        let slide_info: dyld_cache_slide_info3 = util::copy_from_slice(&blob.get()[..size], end);
+        let (page_starts, _) =
+            file_array(&blob, "page starts",
+                       size as u64,
+                       slide_info.page_starts_count.ext(),
+                       2).cast();
+        if slide_info.page_size % 4096 != 0 ||
+           slide_info.page_size > 1048576 { // arbitrary
+            return err(BadData, "unreasonable slide info 3 page size");
+        }
+        if (page_starts.len() as u64).check_mul(slide_info.page_size as u64).is_none() {
+            return err(BadData, "unreasonable slide info 3 page count");
+        }
+        let num_slabs = (page_starts.len() + SLAB_PAGES - 1) / SLAB_PAGES;
+        Ok(SlideInfoV3 {
+            page_size: slide_info.page_size.ext(),
+            page_starts: page_starts,
+            value_add: slide_info.auth_value_add,
+            v5: v5,
+            data_seg_idx: data_seg_idx,
+            rebase_list_by_slab: (0..num_slabs).map(|_| LazyBox::new()).collect(),
+        })
+    }
+    // the unslid target of a dyld_cache_slide_pointer3/5, and how it's signed if it's authenticated
+    fn decode(&self, raw: u64) -> (u64, Option<PtrAuth>) {
+        let is_auth = raw >> 63 != 0;
+        if self.v5 {
+            let runtime_offset = raw & 0x3_ffff_ffff;
+            let target = self.value_add.wrapping_add(runtime_offset);
+            if is_auth {
+                // v5 only has a bit for whether it's a data key, and always uses A
+                (target, Some(PtrAuth {
+                    key: if (raw >> 51) & 1 != 0 { 2 } else { 0 },
+                    addr_div: (raw >> 50) & 1 != 0,
+                    diversity: (raw >> 34) as u16,
+                }))
+            } else {
+                (target | ((raw >> 34) & 0xff) << 56, None)
+            }
+        } else if is_auth {
+            (self.value_add.wrapping_add(raw & 0xffff_ffff), Some(PtrAuth {
+                key: ((raw >> 49) & 3) as u8,
+                addr_div: (raw >> 48) & 1 != 0,
+                diversity: (raw >> 32) as u16,
+            }))
+        } else {
+            // 51-bit value with the top byte squeezed in below the chain bits
+            let top8 = raw & 0x0007_f800_0000_0000;
+            let bottom43 = raw & 0x0000_07ff_ffff_ffff;
+            (top8 << 13 | bottom43, None)
+        }
+    }
+    pub fn save_rebase_list(&self, eb: &ExecBase, range: Option<(VMA, u64)>) -> &[LazyBox<Vec<Reloc<'static>>>] {
+        let (data_seg, slabs) = some_or!(slab_range(eb, self.data_seg_idx, self.page_size, self.page_starts.len(), range), {
+            return &[];
+        });
+        let no_rebase = if self.v5 { DYLD_CACHE_SLIDE_V5_PAGE_ATTR_NO_REBASE } else { DYLD_CACHE_SLIDE_V3_PAGE_ATTR_NO_REBASE };
+        for slab in slabs.clone() {
+            let lazybox = &self.rebase_list_by_slab[slab];
+            if lazybox.get().is_some() { continue; }
+            let mut rebase_list: Vec<Reloc> = Vec::new();
+            let page_size = self.page_size as usize;
+            let data_data: &[Cell<u8>] = data_seg.data.as_ref().unwrap().get_mut();
+            let page_starts = &self.page_starts.get();
+            let page_starts = &page_starts[slab * SLAB_PAGES .. min((slab + 1) * SLAB_PAGES, page_starts.len())];
+            for (i, ps) in page_starts.iter().enumerate() {
+                let offset: usize = (slab * SLAB_PAGES + i) * page_size;
+                let addr: VMA = data_seg.vmaddr.wrapping_add(offset as u64);
+                let ps = ps.copy(LittleEndian);
+                if ps == no_rebase { continue; }
+                // unlike v2, the start is in bytes and the deltas are in 8-byte units
+                let mut offset_in_page = ps as usize;
+                loop {
+                    if offset_in_page > page_size - 8 {
+                        errln!("SlideInfoV3::save_rebase_list: offset-in-page past page size");
+                        break;
+                    }
+                    let slice = some_or!(data_data.slice_opt(offset + offset_in_page, offset + offset_in_page + 8), {
+                        errln!("SlideInfoV3::save_rebase_list: out of range of data segment");
+                        break;
+                    });
+                    let raw: u64 = util::copy_from_slice(slice, LittleEndian);
+                    let (target, auth) = self.decode(raw);
+                    util::copy_to_slice(slice, &target, LittleEndian);
+                    rebase_list.push(Reloc {
+                        address: addr + offset_in_page.ext(),
+                        kind: RelocKind::Pointer,
+                        addend: None,
+                        target: RelocTarget::ThisImageSlide,
+                        auth: auth,
+                    });
+                    // v5 moved next up a bit to make room for keyIsData
+                    let next_shift = if self.v5 { 52 } else { 51 };
+                    let delta = (((raw >> next_shift) & 0x7ff) * 8) as usize;
+                    if delta == 0 {
+                        break;
+                    }
+                    offset_in_page += delta;
+                }
+            }
+            let _ = lazybox.store(Box::new(rebase_list)); // doesn't matter if this is a dupe
+        }
+        &self.rebase_list_by_slab[slabs]
+    }
+}
+
 trait RangeCast {
     fn range_cast(self) -> Range<usize>;
 }
@@ -376,67 +734,77 @@ pub enum SlideMode {
 
 impl DyldCache {
     pub fn new(mc: Mem<u8>, inner_sects: bool, unslide: bool) -> ExecResult<DyldCache> {
-        // note - not all fields in older caches, but at least a page should be there, so don't worry about size calculation
-        let hdr_size = size_of::<dyld_cache_header>();
-        let (arch, end, is64, hdr) = {
-            let buf = mc.get();
-            if buf.len() < hdr_size { return err(BadData, "truncated"); }
-            let top: [u8; 16] = util::copy_from_slice(&buf[..16], LittleEndian);
-            if &top[..7] != b"dyld_v1" {
-                return err(BadData, "bad magic");
-            }
-            let padded_arch = &top[7..16];
-            let (arch, is64) = if padded_arch == b"    i386\0" {
-                (arch::X86, false)
-            } else if padded_arch == b"  x86_64\0" ||
-                      padded_arch == b" x86_64h\0" {
-                (arch::X86_64, true)
-            } else if padded_arch == b"   armv7\0" ||
-                      padded_arch == b"   armv6\0" ||
-                      padded_arch == b"  armv7s\0" ||
-                      padded_arch == b"  armv7k\0" {
-                (arch::ARM, false)
-            } else if padded_arch == b"   arm64\0" {
-                (arch::AArch64, true)
-            } else {
-                return err(BadData, "unknown architecture, ergo can't determine endianness");
-            };
-            let end = LittleEndian;
-            let hdr: dyld_cache_header = util::copy_from_slice(&buf[..hdr_size], end);
-            (arch, end, is64, hdr)
+        DyldCache::new_split(mc, &[], None, inner_sects, unslide)
+    }
+    // `mc` has the main cache at offset 0 and each subcache (and the .symbols file) at the given
+    // offsets, as from util::memmap_concat; see `open`
+    pub fn new_split(mc: Mem<u8>, subcache_offs: &[usize], symbols_off: Option<usize>, inner_sects: bool, unslide: bool) -> ExecResult<DyldCache> {
+        let (arch, end, is64, hdr) = read_header(&mc)?;
+        let buf = mc.get();
+        // each file runs up to the next one
+        let file_at = |off: usize| {
+            let next = subcache_offs.iter().chain(&symbols_off).cloned().filter(|&o| o > off).min();
+            mc.slice(off, next.unwrap_or(mc.len()))
+        };
+        // newer caches moved imagesOffset and zeroed the old one, which would confuse this
+        let new_images = hdr_u32(buf, &hdr, HDR_IMAGES_OFFSET, end).map(|off| (off, hdr_u32(buf, &hdr, HDR_IMAGES_COUNT, end).unwrap_or(0)));
+        let (images_offset, images_count, min_low_offset) = match new_images {
+            Some((off, count)) if hdr.imagesOffset == 0 => (off, count, hdr.mappingOffset as usize),
+            _ => (hdr.imagesOffset, hdr.imagesCount, min(min(hdr.mappingOffset, hdr.imagesOffset) as u64, hdr.slideInfoOffset) as usize),
         };
-        let min_low_offset = min(min(hdr.mappingOffset, hdr.imagesOffset) as u64, hdr.slideInfoOffset) as usize;
         let cs_blob = if min_low_offset >= offset_of!(dyld_cache_header, codeSignatureSize) {
             Some(file_array(&mc, "code signature", hdr.codeSignatureOffset, hdr.codeSignatureSize, 1))
         } else { None };
         let slide_info_blob = if min_low_offset >= offset_of!(dyld_cache_header, slideInfoSize) {
             Some(file_array(&mc, "slide info", hdr.slideInfoOffset, hdr.slideInfoSize, 1))
         } else { None };
+        // split caches keep the local symbols in their own file
+        let (ls_file, ls_hdr) = if let Some(off) = symbols_off {
+            let ls_file = some_or!(file_at(off), { return err(BadData, "symbols file offset out of range"); });
+            let (_, _, _, ls_hdr) = read_header(&ls_file)?;
+            if let Some(uuid) = hdr_uuid(buf, &hdr, HDR_SYMBOL_FILE_UUID) {
+                if uuid != ls_hdr.uuid {
+                    errln!("warning: .symbols file UUID doesn't match the cache's symbolFileUUID");
+                }
+            }
+            (ls_file, ls_hdr)
+        } else {
+            (mc.clone(), hdr)
+        };
+        let ls_min_low_offset = if symbols_off.is_some() { ls_hdr.mappingOffset as usize } else { min_low_offset };
         // TODO these checks should become bypassable
-        let local_symbols = if min_low_offset >= offset_of!(dyld_cache_header, localSymbolsSize) {
-            let ls_mc = file_array(&mc, "slide info blob", hdr.localSymbolsOffset, hdr.localSymbolsSize, 1);
+        let local_symbols = if ls_min_low_offset >= offset_of!(dyld_cache_header, localSymbolsSize) {
+            let ls_mc = file_array(&ls_file, "slide info blob", ls_hdr.localSymbolsOffset, ls_hdr.localSymbolsSize, 1);
             let so = size_of::<dyld_cache_local_symbols_info>() as u64;
-            if hdr.localSymbolsSize < so {
-                if hdr.localSymbolsSize > 0 {
+            if ls_hdr.localSymbolsSize < so {
+                if ls_hdr.localSymbolsSize > 0 {
                     errln!("local symbols blob too small for header");
                 }
                 None
             } else {
-                let ls_hdr: dyld_cache_local_symbols_info = util::copy_from_slice(&ls_mc.get()[..so as usize], end);
+                let ls_info: dyld_cache_local_symbols_info = util::copy_from_slice(&ls_mc.get()[..so as usize], end);
                 let nlist_size = if is64 {
                     size_of::<macho_bind::nlist_64>()
                 } else {
                     size_of::<macho_bind::nlist>()
                 } as usize;
-                let symtab = file_array(&ls_mc, "dyld cache local symbols - nlist", ls_hdr.nlistOffset.ext(), ls_hdr.nlistCount.ext(), nlist_size);
-                let strtab = file_array(&ls_mc, "dyld cache local symbols - strtab", ls_hdr.stringsOffset.ext(), ls_hdr.stringsSize.ext(), 1);
-                let entry_size = size_of::<dyld_cache_local_symbols_entry>();
-                let entries = file_array(&ls_mc, "dyld cache local symbols - entries", ls_hdr.entriesOffset.ext(), ls_hdr.entriesCount.ext(), entry_size);
+                // from the same version, entries got 64-bit dylibOffsets that are relative to the
+                // cache's address rather than a file
+                let entries_64 = ls_min_low_offset >= HDR_SYMBOL_FILE_UUID;
+                let symtab = file_array(&ls_mc, "dyld cache local symbols - nlist", ls_info.nlistOffset.ext(), ls_info.nlistCount.ext(), nlist_size);
+                let strtab = file_array(&ls_mc, "dyld cache local symbols - strtab", ls_info.stringsOffset.ext(), ls_info.stringsSize.ext(), 1);
+                let entry_size = if entries_64 {
+                    size_of::<dyld_cache_local_symbols_entry_64>()
+                } else {
+                    size_of::<dyld_cache_local_symbols_entry>()
+                };
+                let entries = file_array(&ls_mc, "dyld cache local symbols - entries", ls_info.entriesOffset.ext(), ls_info.entriesCount.ext(), entry_size);
                 Some(LocalSymbols {
                     entries: entries,
+                    entries_64: entries_64,
                     symtab: symtab,
                     strtab: strtab,
-                    nlist_count: ls_hdr.nlistCount,
+                    nlist_count: ls_info.nlistCount,
                 })
             }
         } else { None };
@@ -448,9 +816,8 @@ impl DyldCache {
 
         let image_info = {
             let so = size_of::<dyld_cache_image_info>();
-            let hdrmc = file_array(&mc, "images info", hdr.imagesOffset.ext(), hdr.imagesCount.ext(), so);
+            let hdrmc = file_array(&mc, "images info", images_offset.ext(), images_count.ext(), so);
             let hdrbuf = hdrmc.get();
-            let buf = mc.get();
             hdrbuf.chunks(so).map(|ii_buf| {
                 let ii: dyld_cache_image_info = util::copy_from_slice(ii_buf, end);
                 ImageInfo {
@@ -461,34 +828,30 @@ impl DyldCache {
                 }
             }).collect()
         };
-        let segments: Vec<_> = {
-            let so = size_of::<dyld_cache_mapping_info>();
-            let mapping_mc = file_array(&mc, "mapping info", hdr.mappingOffset.ext(), hdr.mappingCount.ext(), so);
-            let len = mc.len() as u64;
-            mapping_mc.get().chunks(so).enumerate().map(|(i, mi_buf)| {
-                let mut mi: dyld_cache_mapping_info = util::copy_from_slice(mi_buf, end);
-                if mi.fileOffset >= len {
-                    errln!("warning: mapping_info {} in shared cache offset ({}) past end of file ({})", i, mi.fileOffset, len);
-                    mi.size = 0;
-                    mi.fileOffset = 0;
-                } else if mi.size > len - mi.fileOffset {
-                    errln!("warning: mapping_info {} in shared cache bounds ({}+{}) extend past end of file ({}); truncating", i, mi.fileOffset, mi.size, len);
-                    mi.size = len - mi.fileOffset;
-                }
 
-                Segment {
-                    vmaddr: VMA(mi.address),
-                    vmsize: mi.size,
-                    fileoff: mi.fileOffset,
-                    filesize: mi.size,
-                    name: None,
-                    prot: ::u32_to_prot(mi.initProt),
-                    data: mc.slice(mi.fileOffset as usize, (mi.fileOffset + mi.size) as usize),
-                    seg_idx: None,
-                    private: hdr.mappingOffset as usize + i * so,
-                }
-            }).collect()
-        };
+        let subcaches = if subcache_offs.is_empty() { Vec::new() } else { subcache_entries(&mc)? };
+        if subcaches.len() != subcache_offs.len() {
+            return err(BadData, format!("cache has {} subcaches but got {}", subcaches.len(), subcache_offs.len()));
+        }
+        // (file, its offset in mc, its header)
+        let mut files = vec![(file_at(0).unwrap(), 0, hdr)];
+        for (entry, &off) in subcaches.iter().zip(subcache_offs) {
+            let file = some_or!(file_at(off), { return err(BadData, "subcache offset out of range"); });
+            let (sub_arch, _, _, sub_hdr) = read_header(&file)?;
+            if sub_arch != arch {
+                return err(BadData, format!("subcache {} has a different architecture", entry.suffix));
+            }
+            if sub_hdr.uuid != entry.uuid {
+                errln!("warning: subcache {} UUID doesn't match the main cache's subcache array", entry.suffix);
+            }
+            files.push((file, off, sub_hdr));
+        }
+        let mut segments = Vec::new();
+        let mut first_seg_idxs = Vec::new();
+        for &(ref file, off, ref file_hdr) in &files {
+            first_seg_idxs.push(segments.len());
+            push_mappings(file, off as u64, file_hdr, end, &mut segments);
+        }
         let mut dc = DyldCache {
             eb: ExecBase {
                 arch: arch,
@@ -496,7 +859,7 @@ impl DyldCache {
                 endian: end,
                 segments: segments,
                 sections: vec!(),
-                whole_buf: Some(mc),
+                whole_buf: Some(mc.clone()),
             },
             slide_info: None,
             image_info: image_info,
@@ -504,8 +867,26 @@ impl DyldCache {
             cs_blob: cs_blob,
             local_symbols: local_symbols,
             have_images_text_offset: have_images_text_offset,
+            subcaches: subcaches,
         };
-        if let Some(blob) = slide_info_blob {
+        // newer caches leave slideInfoOffset empty and have slide info per mapping instead
+        let mut infos = Vec::new();
+        for (&(ref file, _, ref file_hdr), &first_seg_idx) in files.iter().zip(&first_seg_idxs) {
+            let file_buf = file.get();
+            let mws = hdr_u32(file_buf, file_hdr, HDR_MAPPING_WITH_SLIDE_OFFSET, end).map(|offset| {
+                let count = hdr_u32(file_buf, file_hdr, HDR_MAPPING_WITH_SLIDE_COUNT, end).unwrap_or(0);
+                file_array(file, "mapping with slide info", offset.ext(), count.ext(), size_of::<dyld_cache_mapping_and_slide_info>())
+            });
+            if let Some(mws) = mws {
+                match dc.make_mapping_slide_info(file, &mws, first_seg_idx) {
+                    Ok(x) => infos.extend(x),
+                    Err(e) => errln!("couldn't get slide info: {}", e),
+                }
+            }
+        }
+        if !infos.is_empty() {
+            dc.slide_info = Some(SlideInfo::Multi(infos));
+        } else if let Some(blob) = slide_info_blob {
             match dc.make_slide_info(blob) {
                 Ok(x) => dc.slide_info = x,
                 Err(e) => errln!("couldn't get slide info: {}", e),
@@ -531,18 +912,59 @@ impl DyldCache {
         }
         Ok(dc)
     }
+    // opens a cache along with the subcaches and .symbols file next to it, if any
+    pub fn open(path: &Path, inner_sects: bool, unslide: bool) -> ExecResult<DyldCache> {
+        let open = |path: &Path| File::open(path).or_else(|e| {
+            err(ErrorKind::Other, format!("open {:?} failed: {}", path, e))
+        });
+        let main = open(path)?;
+        let main_mc = util::memmap(&main).or_else(|e| err(ErrorKind::Other, format!("mmap failed: {}", e)))?;
+        read_header(&main_mc)?;
+        let subcaches = subcache_entries(&main_mc)?;
+        if subcaches.is_empty() {
+            return DyldCache::new(main_mc, inner_sects, unslide);
+        }
+        let with_suffix = |suffix: &str| {
+            let mut p = path.as_os_str().to_owned();
+            p.push(suffix);
+            PathBuf::from(p)
+        };
+        let mut files = vec![main];
+        for entry in &subcaches {
+            files.push(open(&with_suffix(&entry.suffix))?);
+        }
+        let symbols_path = with_suffix(".symbols");
+        let have_symbols = symbols_path.exists();
+        if have_symbols {
+            files.push(open(&symbols_path)?);
+        }
+        let (mc, mut offs) = util::memmap_concat(&files).or_else(|e| err(ErrorKind::Other, format!("mmap failed: {}", e)))?;
+        let symbols_off = if have_symbols { offs.pop() } else { None };
+        DyldCache::new_split(mc, &offs[1..], symbols_off, inner_sects, unslide)
+    }
+    // for 64-bit entries, `off` is relative to the cache's address rather than the file
     pub fn get_ls_entry_for_offset(&self, off: u64) -> Option<::DscTabs> {
-        let entry_size = size_of::<dyld_cache_local_symbols_entry>();
         if let Some(ref ls) = self.local_symbols {
+            let entry_size = if ls.entries_64 {
+                size_of::<dyld_cache_local_symbols_entry_64>()
+            } else {
+                size_of::<dyld_cache_local_symbols_entry>()
+            };
             let entries = ls.entries.get();
             for entry_slice in entries.chunks(entry_size) {
-                let entry: dyld_cache_local_symbols_entry = util::copy_from_slice(entry_slice, self.eb.endian);
-                if entry.dylibOffset as u64 == off {
-                    if entry.nlistStartIndex > ls.nlist_count || entry.nlistCount > ls.nlist_count - entry.nlistStartIndex {
+                let (dylib_offset, start, count) = if ls.entries_64 {
+                    let entry: dyld_cache_local_symbols_entry_64 = util::copy_from_slice(entry_slice, self.eb.endian);
+                    (entry.dylibOffset, entry.nlistStartIndex, entry.nlistCount)
+                } else {
+                    let entry: dyld_cache_local_symbols_entry = util::copy_from_slice(entry_slice, self.eb.endian);
+                    (entry.dylibOffset as u64, entry.nlistStartIndex, entry.nlistCount)
+                };
+                if dylib_offset == off {
+                    if start > ls.nlist_count || count > ls.nlist_count - start {
                         errln!("warning: shared cache local symbols entry out of range");
                         return None;
                     } else {
-                        return Some(::DscTabs { symtab: ls.symtab.clone(), strtab: ls.strtab.clone(), start: entry.nlistStartIndex, count: entry.nlistCount });
+                        return Some(::DscTabs { symtab: ls.symtab.clone(), strtab: ls.strtab.clone(), start: start, count: count });
                     }
                 }
             }
@@ -558,8 +980,13 @@ impl DyldCache {
         let mut mo = MachO::new(buf, true, Some(MachODCInfo {
             hdr_offset: off as usize,
             have_images_text_offset: self.have_images_text_offset,
+            split_segments: if self.subcaches.is_empty() { Vec::new() } else { self.eb.segments.clone() },
         }))?;
-        mo.dsc_tabs = self.get_ls_entry_for_offset(off);
+        let ls_off = match self.local_symbols {
+            Some(LocalSymbols { entries_64: true, .. }) => ii.address.wrapping_sub(self.eb.segments[0].vmaddr.0),
+            _ => off,
+        };
+        mo.dsc_tabs = self.get_ls_entry_for_offset(ls_off);
         if fix_data {
             let _sw2 = util::stopwatch("DyldCache::load_single_image fix_data");
             for seg in &mo.eb.segments {
@@ -573,7 +1000,24 @@ impl DyldCache {
             return err(BadData, "no data segment");
         });
         let (data_addr, data_size) = (data_seg.vmaddr, data_seg.vmsize);
-        Ok(Some(SlideInfo::new(blob, self.eb.endian, self.eb.pointer_size == 8, data_addr, data_size)?))
+        Ok(Some(SlideInfo::new(blob, self.eb.endian, self.eb.pointer_size == 8, 1, data_addr, data_size)?))
+    }
+    // `file` is the (sub)cache containing `mws`, whose first mapping is segment `first_seg_idx`
+    fn make_mapping_slide_info(&self, file: &Mem<u8>, mws: &Mem<u8>, first_seg_idx: usize) -> ExecResult<Vec<SlideInfo>> {
+        let so = size_of::<dyld_cache_mapping_and_slide_info>();
+        let mut infos = Vec::new();
+        for (i, mi_buf) in mws.get().chunks(so).enumerate() {
+            let mi: dyld_cache_mapping_and_slide_info = util::copy_from_slice(mi_buf, self.eb.endian);
+            if mi.slideInfoFileSize == 0 { continue; }
+            // same order as the plain mapping info
+            let seg_idx = first_seg_idx + i;
+            let seg = some_or!(self.eb.segments.get(seg_idx), {
+                return err(BadData, "more mappings with slide info than mappings");
+            });
+            let blob = file_array(file, "slide info", mi.slideInfoFileOffset, mi.slideInfoFileSize, 1);
+            infos.push(SlideInfo::new(blob, self.eb.endian, self.eb.pointer_size == 8, seg_idx, seg.vmaddr, seg.vmsize)?);
+        }
+        Ok(infos)
     }
     pub fn auto_unslide(&mut self) {
         let slide = {
@@ -616,11 +1060,8 @@ impl DyldCache {
         }
     }
     pub fn fix_data(&self, range: Option<(VMA, u64)>) -> ExecResult<()> {
-        match self.slide_info {
-            Some(SlideInfo::V2(ref v2)) => {
-                let _ = v2.save_rebase_list(&self.eb, range);
-            },
-            _ => (),
+        if let Some(ref slide_info) = self.slide_info {
+            let _ = slide_info.save_rebase_list(&self.eb, range);
         }
         Ok(())
     }
@@ -693,12 +1134,12 @@ impl Exec for DyldCache {
             Some(SlideInfo::V1(ref v1)) => {
                 let mut ret = Vec::new();
                 v1.iter(None, |addr| {
-                    ret.push(Reloc { address: addr, kind: RelocKind::_32Bit, base: None, target: RelocTarget::ThisImageSlide });
+                    ret.push(Reloc { address: addr, kind: RelocKind::_32Bit, addend: None, target: RelocTarget::ThisImageSlide, auth: None });
                 });
                 ret
             },
-            Some(SlideInfo::V2(ref v2)) => {
-                v2.save_rebase_list(&self.eb, None).iter().flat_map(|slab| {
+            Some(ref slide_info) => {
+                slide_info.save_rebase_list(&self.eb, None).into_iter().flatten().flat_map(|slab| {
                     let relocs = slab.get().unwrap();
                     relocs.iter()
                 }).cloned().collect()
@@ -858,3 +1299,92 @@ impl ImageCache {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exec::Prot;
+
+    const DATA: VMA = VMA(0x180004000);
+    const VALUE_ADD: u64 = 0x180000000;
+
+    // one data page at DATA holding `ptrs`, chained from the first
+    fn slide(v5: bool, ptrs: &[(usize, u64)]) -> (SlideInfoV3, ExecBase) {
+        let mut blob = Vec::new();
+        for &w in &[if v5 { 5u32 } else { 3 }, 0x1000, 1, 0] {
+            blob.extend_from_slice(&w.to_le_bytes());
+        }
+        blob.extend_from_slice(&VALUE_ADD.to_le_bytes());
+        blob.extend_from_slice(&(ptrs[0].0 as u16).to_le_bytes());
+        let mut data = vec![0u8; 0x1000];
+        for &(off, raw) in ptrs {
+            data[off..off + 8].copy_from_slice(&raw.to_le_bytes());
+        }
+        let eb = ExecBase {
+            arch: arch::AArch64,
+            pointer_size: 8,
+            endian: LittleEndian,
+            segments: vec![Segment {
+                vmaddr: DATA,
+                vmsize: 0x1000,
+                filesize: 0x1000,
+                prot: Prot { r: true, w: true, x: false },
+                data: Some(Mem::with_vec(data)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        (SlideInfoV3::new(Mem::with_vec(blob), LittleEndian, true, v5, 0).unwrap(), eb)
+    }
+
+    fn walk(si: &SlideInfoV3, eb: &ExecBase) -> Vec<(VMA, u64, Option<PtrAuth>)> {
+        let data = eb.segments[0].data.as_ref().unwrap().get();
+        si.save_rebase_list(eb, None)[0].get().unwrap().iter().map(|r| {
+            let off = (r.address - DATA) as usize;
+            let target: u64 = util::copy_from_slice(&data[off..off + 8], LittleEndian);
+            (r.address, target, r.auth)
+        }).collect()
+    }
+
+    const V3_PLAIN: u64 = 0x12 << 43 | 0x1_2345_6789;
+    const V3_AUTH: u64 = 1 << 63 | 2 << 49 | 1 << 48 | 0x1234 << 32 | 0x5678;
+    const V5_PLAIN: u64 = 0x12 << 34 | 0x1234;
+    const V5_AUTH: u64 = 1 << 63 | 1 << 51 | 1 << 50 | 0xbeef << 34 | 0x5678;
+
+    #[test]
+    fn test_decode() {
+        let (v3, _) = slide(false, &[(0, 0)]);
+        assert_eq!(v3.decode(V3_PLAIN), (0x1200_0001_2345_6789, None));
+        assert_eq!(v3.decode(V3_AUTH),
+                   (VALUE_ADD + 0x5678, Some(PtrAuth { key: 2, addr_div: true, diversity: 0x1234 })));
+        let (v5, _) = slide(true, &[(0, 0)]);
+        assert_eq!(v5.decode(V5_PLAIN), (0x1200_0001_8000_1234, None));
+        assert_eq!(v5.decode(V5_AUTH),
+                   (VALUE_ADD + 0x5678, Some(PtrAuth { key: 2, addr_div: true, diversity: 0xbeef })));
+        assert_eq!(v5.decode(V5_AUTH & !(1 << 51)).1.unwrap().key, 0);
+    }
+
+    #[test]
+    fn test_walk_v3() {
+        let (si, eb) = slide(false, &[(0x10, V3_PLAIN | 2 << 51),
+                                      (0x20, V3_AUTH | 3 << 51),
+                                      (0x38, V3_PLAIN)]);
+        let auth = Some(PtrAuth { key: 2, addr_div: true, diversity: 0x1234 });
+        assert_eq!(walk(&si, &eb), vec![(DATA + 0x10, 0x1200_0001_2345_6789, None),
+                                        (DATA + 0x20, VALUE_ADD + 0x5678, auth),
+                                        (DATA + 0x38, 0x1200_0001_2345_6789, None)]);
+    }
+
+    #[test]
+    fn test_walk_v5() {
+        // keyIsData sits where v3 keeps the low bit of next, so this chain only lines up if
+        // the stride is read from bit 52
+        let (si, eb) = slide(true, &[(0x10, V5_PLAIN | 2 << 52),
+                                     (0x20, V5_AUTH | 3 << 52),
+                                     (0x38, V5_PLAIN)]);
+        let auth = Some(PtrAuth { key: 2, addr_div: true, diversity: 0xbeef });
+        assert_eq!(walk(&si, &eb), vec![(DATA + 0x10, 0x1200_0001_8000_1234, None),
+                                        (DATA + 0x20, VALUE_ADD + 0x5678, auth),
+                                        (DATA + 0x38, 0x1200_0001_8000_1234, None)]);
+    }
+}
@@ -15,15 +15,17 @@ use std::mem::{replace, size_of, transmute};
 use std::str::FromStr;
 use std::cmp::max;
 use macho_bind::*;
-use exec::{arch, VMA, SymbolValue, ByteSliceIterator, DepLib, SourceLib, ErrorKind, err, SymbolSource, Symbol};
+use exec::{arch, VMA, SymbolValue, ByteSliceIterator, DepLib, SourceLib, ErrorKind, err, SymbolSource, Symbol, SymbolKind, Reloc};
 use std::{u64, u32, usize};
 use vec_map::VecMap;
 use std::collections::{HashSet};
 use std::borrow::Cow;
 use std::any::Any;
-use util::{VecStrExt, Mem, Swap, SliceExt, OptionExt, copy_memory, into_cow, IntStuff, Endian, ByteString, ByteStr, FieldLens, Ext, Narrow, CheckAdd, CheckSub, TrivialState, ReadCell, stopwatch, vec_extend_from_slice, fast_slice_to_owned};
+use util::{VecStrExt, Mem, Swap, SliceExt, OptionExt, copy_memory, into_cow, IntStuff, Endian, ByteString, ByteStr, FieldLens, Ext, Narrow, CheckAdd, CheckSub, TrivialState, ReadCell, Lazy, stopwatch, vec_extend_from_slice, fast_slice_to_owned};
 
 pub mod dyldcache;
+pub mod chained;
+mod reloc;
 use dyldcache::{DyldCache, ImageCache, SlideInfo};
 
 pub const VM_PROT_WRITE: u32 = 2;
@@ -178,6 +180,8 @@ impl Swap for x_nlist_64 {
 }
 
 pub const EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE: u32 = 2;
+pub const LC_DYLD_EXPORTS_TRIE: u32 = 0x33 | LC_REQ_DYLD;
+pub const LC_DYLD_CHAINED_FIXUPS: u32 = 0x34 | LC_REQ_DYLD;
 
 pub fn u32_to_prot(ip: u32) -> exec::Prot {
     exec::Prot {
@@ -363,6 +367,8 @@ pub struct SectPrivate {
     pub flags: u32,
     pub reserved1: u32,
     pub reserved2: u32,
+    pub reloff: u32,
+    pub nreloc: u32,
 }
 
 #[derive(Default, Clone)]
@@ -396,6 +402,9 @@ pub struct MachO {
     pub dyld_weak_bind: Mem<u8>,
     pub dyld_lazy_bind: Mem<u8>,
     pub dyld_export: Mem<u8>,
+    // newer replacements for dyld_info
+    pub dyld_chained_fixups: Mem<u8>,
+    pub dyld_exports_trie: Mem<u8>,
     // linkedit_data_commands
     pub segment_split_info: Mem<u8>,
     pub function_starts: Mem<u8>,
@@ -404,7 +413,10 @@ pub struct MachO {
     pub dylib_code_sign_drs: Mem<u8>,
     pub code_signature: Mem<u8>,
 
-    _linkedit_bits: Option<[LinkeditBit; 22]>,
+    _linkedit_bits: Option<[LinkeditBit; 24]>,
+    nlist_sym_cache: Lazy<Vec<Symbol<'static>>>,
+    bind_sym_cache: Lazy<Vec<Symbol<'static>>>,
+    chained_import_cache: Lazy<Vec<Symbol<'static>>>,
 
     // from dyld cache
     pub dc_info: MachODCInfo,
@@ -443,7 +455,7 @@ macro_rules! lbit {
     ($self_field:ident, $cmd_id:ident, $cmd_type:ty, $off_field:ident, $size_field:ident, $divi:expr) => { lbit!($self_field, $cmd_id, $cmd_type, $off_field, $size_field, $divi, false) }
 }
 
-fn make_linkedit_bits(is64: bool) -> [LinkeditBit; 22] {
+fn make_linkedit_bits(is64: bool) -> [LinkeditBit; 24] {
     let nlist_size = if is64 { size_of::<nlist_64>() } else { size_of::<nlist>() };
     [
         // section relocations here?
@@ -452,6 +464,8 @@ fn make_linkedit_bits(is64: bool) -> [LinkeditBit; 22] {
         lbit!(dyld_weak_bind, LC_DYLD_INFO, dyld_info_command, weak_bind_off, weak_bind_size, 1),
         lbit!(dyld_lazy_bind, LC_DYLD_INFO, dyld_info_command, lazy_bind_off, lazy_bind_size, 1),
         lbit!(dyld_export, LC_DYLD_INFO, dyld_info_command, export_off, export_size, 1),
+        lbit!(dyld_chained_fixups, LC_DYLD_CHAINED_FIXUPS, linkedit_data_command, dataoff, datasize, 1),
+        lbit!(dyld_exports_trie, LC_DYLD_EXPORTS_TRIE, linkedit_data_command, dataoff, datasize, 1),
 
         lbit!(locrel, LC_DYSYMTAB, dysymtab_command, locreloff, nlocrel, size_of::<relocation_info>()),
         lbit!(segment_split_info, LC_SEGMENT_SPLIT_INFO, linkedit_data_command, dataoff, datasize, 1),
@@ -509,21 +523,8 @@ impl exec::Exec for MachO {
                 out
             },
             SymbolSource::Imported => {
-                let mut out = Vec::new();
-                self.parse_each_dyld_bind(&mut |state: &ParseDyldBindState<'a>| {
-                    if state.already_bound_this_symbol { return true; }
-                    out.push(Symbol {
-                        name: some_or!(state.symbol, { return true; }).into(),
-                        is_public: true,
-                        // XXX what about BIND_SYMBOL_FLAGS_NON_WEAK_DEFINITION?
-                        is_weak: state.which == WhichBind::WeakBind ||
-                                 state.flags & BIND_SYMBOL_FLAGS_WEAK_IMPORT != 0,
-                        val: SymbolValue::Undefined(state.source_dylib),
-                        size: None,
-                        private: 0,
-                    });
-                    true
-                });
+                let mut out = self.get_dyld_bind_imports();
+                out.extend(self.parse_chained_imports());
                 out
             },
             SymbolSource::Exported => self.get_exported_symbol_list(None),
@@ -555,6 +556,19 @@ impl exec::Exec for MachO {
         res
     }
 
+    fn get_reloc_list<'a>(&'a self, specific: Option<&'a dyn Any>) -> Vec<Reloc<'a>> {
+        assert!(specific.is_none());
+        self.get_relocs()
+    }
+
+    fn get_function_starts(&self) -> Vec<VMA> {
+        self.parse_function_starts()
+    }
+
+    fn get_stub_list(&self) -> Vec<(&ByteStr, VMA)> {
+        self.stub_name_list()
+    }
+
     fn get_dep_libs(&self) -> Cow<[DepLib]> {
         let dls = self.load_dylib.iter().enumerate().map(|(i, ld)| DepLib {
             path: (&*ld.path).into(),
@@ -577,6 +591,12 @@ impl exec::Exec for MachO {
                 ld.compatibility_version)
     }
 
+    fn write_back(&mut self) -> exec::ExecResult<Mem<u8>> {
+        self.reallocate()?;
+        self.rewhole();
+        Ok(self.eb.whole_buf.clone().unwrap())
+    }
+
     fn as_any(&self) -> &dyn std::any::Any { self as &dyn std::any::Any }
 }
 
@@ -701,18 +721,21 @@ pub enum GuessBrokenCacheSlideResult {
 }
 
 // info about the dyld cache containing this macho
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Clone, Debug)]
 pub struct MachODCInfo {
     hdr_offset: usize,
     have_images_text_offset: bool,
+    // for split caches, whose load commands have offsets into whichever subcache has the segment,
+    // the cache's segments, to go by address instead
+    split_segments: Vec<exec::Segment>,
 }
 
 impl MachO {
     pub fn new(mc: Mem<u8>, do_lcs: bool, dc_info: Option<MachODCInfo>) -> exec::ExecResult<MachO> {
         let mut me: MachO = Default::default();
         let dc_info = dc_info.unwrap_or(Default::default());
-        me.dc_info = dc_info;
         let hdr_offset = dc_info.hdr_offset;
+        me.dc_info = dc_info;
         let mut lc_off = hdr_offset.checked_add(size_of::<mach_header>()).ok_or_truncated()?;
         {
             let buf = mc.get();
@@ -788,6 +811,10 @@ impl MachO {
         self.nlist_size = if self.is64 { size_of::<nlist_64>() } else { size_of::<nlist>() };
         let end = self.eb.endian;
         let hdr_offset = self.dc_info.hdr_offset as u64;
+        let split_segments = self.dc_info.split_segments.clone();
+        let is_split = !split_segments.is_empty();
+        // (subcache offset, whole buffer offset) of __LINKEDIT, for split caches
+        let mut linkedit_offs: Option<(u64, u64)> = None;
         let whole = mc.get();
         let mut segi: usize = 0;
         for lci in 0..self.mh.ncmds {
@@ -818,9 +845,18 @@ impl MachO {
                         // cache, the offset isn't even consistent between __TEXT's fileoff and
                         // __text's.  So just do it based on the address.
                         fileoff = hdr_offset;
+                    } else if is_split && sc.filesize != 0 {
+                        fileoff = some_or!(exec::addr_to_off(&split_segments, VMA(sc.vmaddr as u64), 0), {
+                            errln!("warning: split cache segment {} not in any subcache", name);
+                            fileoff
+                        });
+                        if &name == "__LINKEDIT" {
+                            linkedit_offs = Some((sc.fileoff as u64, fileoff));
+                        }
                     }
                     //let was_0 = sc.fileoff == 0;
                     //let fileoff = if was_0 { hdr_offset as u64 } else { sc.fileoff as u64 };
+                    let seg_fileoff = fileoff;
                     let data: Option<Mem<u8>> = mc.slice(fileoff as usize, (fileoff + (sc.filesize as u64)) as usize);
                     let mut seg = exec::Segment {
                         vmaddr: VMA(sc.vmaddr as u64),
@@ -838,8 +874,8 @@ impl MachO {
                     for secti in 0..sc.nsects {
                         let s: section_x = util::copy_from_slice(&lc_buf[off..off + size_of::<section_x>()], end);
                         let mut fileoff = s.offset as u64;
-                        if is_cache_text {
-                            fileoff = some_or!((s.addr as u64).check_sub(sc.vmaddr as u64).check_add(hdr_offset), {
+                        if is_cache_text || (is_split && s.offset != 0) {
+                            fileoff = some_or!((s.addr as u64).check_sub(sc.vmaddr as u64).check_add(seg_fileoff), {
                                 errln!("warning: integer overflow in shared cache library __TEXT offset recalculation");
                                 fileoff
                             });
@@ -863,6 +899,8 @@ impl MachO {
                             flags: s.flags,
                             reserved1: s.reserved1,
                             reserved2: s.reserved2,
+                            reloff: s.reloff,
+                            nreloc: s.nreloc,
                         });
                         off += size_of::<section_x>();
                     }
@@ -874,7 +912,8 @@ impl MachO {
                 LC_SEGMENT_64 => do_segment(true, &mut self.eb.segments, &mut self.eb.sections, &mut self.sect_private),
                 LC_DYLD_INFO | LC_DYLD_INFO_ONLY | LC_SYMTAB | LC_DYSYMTAB |
                 LC_FUNCTION_STARTS | LC_DATA_IN_CODE | LC_DYLIB_CODE_SIGN_DRS |
-                LC_SEGMENT_SPLIT_INFO | LC_LINKER_OPTIMIZATION_HINT | LC_CODE_SIGNATURE => {
+                LC_SEGMENT_SPLIT_INFO | LC_LINKER_OPTIMIZATION_HINT | LC_CODE_SIGNATURE |
+                LC_DYLD_CHAINED_FIXUPS | LC_DYLD_EXPORTS_TRIE => {
                     for fb in self.linkedit_bits() {
                         if lc.cmd == fb.cmd_id || (lc.cmd == LC_DYLD_INFO_ONLY && fb.cmd_id == LC_DYLD_INFO) {
                             let mcref: &mut Mem<u8> = unsafe { fb.self_field.get_mut_unsafe(self_) };
@@ -903,8 +942,12 @@ impl MachO {
                                     _ => ignore = true,
                                 }
                             }
+                            let mut off: u64 = off.ext();
+                            if let (false, Some((orig, new))) = (fb.is_symtab, linkedit_offs) {
+                                off = off.wrapping_sub(orig).wrapping_add(new);
+                            }
                             if !ignore {
-                                *mcref = file_array(buf, fb.name, off.ext(), count.ext(), fb.elm_size);
+                                *mcref = file_array(buf, fb.name, off, count.ext(), fb.elm_size);
                             }
                         }
                     }
@@ -1027,6 +1070,8 @@ impl MachO {
                     is_weak: weak,
                     val: val,
                     size: None,
+                    kind: SymbolKind::Unknown,
+                    attrs: nl.n_type as u32 | (nl.n_desc as u32) << 8,
                     private: off,
                 })
             }
@@ -1234,6 +1279,8 @@ impl MachO {
             (LC_LINKER_OPTIMIZATION_HINT, size_of::<linkedit_data_command>()),
             // for consistency, code_signature is here, but reallocate() nukes it
             (LC_CODE_SIGNATURE, size_of::<linkedit_data_command>()),
+            (LC_DYLD_CHAINED_FIXUPS, size_of::<linkedit_data_command>()),
+            (LC_DYLD_EXPORTS_TRIE, size_of::<linkedit_data_command>()),
         ].iter().map(|&(cmd, cmdsize)| {
             let mut buf: Vec<u8> = Vec::new();
             buf.resize(cmdsize, 0);
@@ -1285,6 +1332,8 @@ impl MachO {
                         LC_DATA_IN_CODE => Some(6),
                         LC_LINKER_OPTIMIZATION_HINT => Some(7),
                         LC_CODE_SIGNATURE => Some(8),
+                        LC_DYLD_CHAINED_FIXUPS => Some(9),
+                        LC_DYLD_EXPORTS_TRIE => Some(10),
                         _ => None
                     } {
                         if let Some(new_cmd) = bit_cmds[idx].take() {
@@ -1389,6 +1438,27 @@ impl MachO {
         self.parse_dyld_bind(self.dyld_lazy_bind.get(), WhichBind::LazyBind, cb);
     }
 
+    pub fn get_dyld_bind_imports<'a>(&'a self) -> Vec<Symbol<'a>> {
+        let mut out = Vec::new();
+        self.parse_each_dyld_bind(&mut |state: &ParseDyldBindState<'a>| {
+            if state.already_bound_this_symbol { return true; }
+            out.push(Symbol {
+                name: some_or!(state.symbol, { return true; }).into(),
+                is_public: true,
+                // XXX what about BIND_SYMBOL_FLAGS_NON_WEAK_DEFINITION?
+                is_weak: state.which == WhichBind::WeakBind ||
+                         state.flags & BIND_SYMBOL_FLAGS_WEAK_IMPORT != 0,
+                val: SymbolValue::Undefined(state.source_dylib),
+                size: None,
+                kind: SymbolKind::Unknown,
+                attrs: 0,
+                private: 0,
+            });
+            true
+        });
+        out
+    }
+
     fn parse_dyld_bind<'a>(&'a self, mut slice: &'a [ReadCell<u8>], which: WhichBind, cb: &mut dyn FnMut(&ParseDyldBindState<'a>) -> bool) {
         let pointer_size = self.eb.pointer_size as u64;
         let leb = |slice_: &mut &[ReadCell<u8>], signed| -> Option<u64> {
@@ -1519,6 +1589,70 @@ impl MachO {
             }
         }
     }
+    pub fn parse_dyld_rebase(&self, cb: &mut dyn FnMut(VMA, u8) -> bool) {
+        let pointer_size = self.eb.pointer_size as u64;
+        let mut slice = self.dyld_rebase.get();
+        macro_rules! leb { () => {
+            if let Some(num) = exec::read_leb128_inner_noisy(&mut ByteSliceIterator(&mut slice), false, "parse_dyld_rebase") { num } else { return }
+        } }
+        let mut typ: u8 = 0;
+        let mut seg: Option<&exec::Segment> = None;
+        let mut seg_off: u64 = 0;
+        macro_rules! do_rebase { ($advance:expr) => {
+            {
+                let seg = some_or!(seg, {
+                    errln!("parse_dyld_rebase: rebase before REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB");
+                    return;
+                });
+                if seg_off >= seg.vmsize {
+                    errln!("warning: parse_dyld_rebase: going out of range of segment (off={:x}, size={:x}), addr={}",
+                           seg_off, seg.vmsize, seg.vmaddr);
+                    return;
+                }
+                if !cb(seg.vmaddr.wrapping_add(seg_off), typ) { return; }
+                seg_off = seg_off.wrapping_add($advance);
+            }
+        } }
+        while !slice.is_empty() {
+            let byte = slice[0].get();
+            slice = &slice[1..];
+            let immediate = byte & (REBASE_IMMEDIATE_MASK as u8);
+            let opcode = byte & (REBASE_OPCODE_MASK as u8);
+            match opcode as u32 {
+                REBASE_OPCODE_DONE => break,
+                REBASE_OPCODE_SET_TYPE_IMM => typ = immediate,
+                REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB => {
+                    seg_off = leb!();
+                    seg = self.eb.segments.get(immediate as usize);
+                    if seg.is_none() {
+                        errln!("parse_dyld_rebase: REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB: bad segment index {}", immediate);
+                    }
+                },
+                REBASE_OPCODE_ADD_ADDR_ULEB => seg_off = seg_off.wrapping_add(leb!()),
+                REBASE_OPCODE_ADD_ADDR_IMM_SCALED => seg_off = seg_off.wrapping_add((immediate as u64) * pointer_size),
+                REBASE_OPCODE_DO_REBASE_IMM_TIMES => {
+                    for _ in 0..immediate { do_rebase!(pointer_size); }
+                },
+                REBASE_OPCODE_DO_REBASE_ULEB_TIMES => {
+                    let count = leb!();
+                    for _ in 0..count { do_rebase!(pointer_size); }
+                },
+                REBASE_OPCODE_DO_REBASE_ADD_ADDR_ULEB => {
+                    let add = leb!();
+                    do_rebase!(add.wrapping_add(pointer_size));
+                },
+                REBASE_OPCODE_DO_REBASE_ULEB_TIMES_SKIPPING_ULEB => {
+                    let count = leb!();
+                    let skip = leb!().wrapping_add(pointer_size);
+                    for _ in 0..count { do_rebase!(skip); }
+                },
+                _ => {
+                    errln!("parse_dyld_rebase: unknown rebase opcode (byte=0x{:x})", byte);
+                    break;
+                }
+            }
+        }
+    }
     fn parse_dyld_export<'a>(&'a self, dyld_export: &'a [ReadCell<u8>], search_for: Option<&'a ByteStr>, cb: &mut dyn for<'b> FnMut(&'b ParseDyldExportState<'b>) -> bool) {
         if dyld_export.is_empty() { return; }
         enum State<'x> {
@@ -1689,7 +1823,16 @@ impl MachO {
                                 result = GuessBrokenCacheSlideResult::Inconsistent;
                                 return false;
                             }
-                        }
+                        },
+                        // decoded by fix_data, which leaves unslid pointers behind
+                        Some(_) => {
+                            if broken_addr == true_addr {
+                                0
+                            } else {
+                                result = GuessBrokenCacheSlideResult::Inconsistent;
+                                return false;
+                            }
+                        },
                     };
                     match result {
                         GuessBrokenCacheSlideResult::GotNoBindSelf => result = GuessBrokenCacheSlideResult::Guess(this_guess),
@@ -1714,9 +1857,13 @@ impl MachO {
         });
         result
     }
+    // LC_DYLD_EXPORTS_TRIE has the same format as the LC_DYLD_INFO one
+    pub fn export_trie(&self) -> &[ReadCell<u8>] {
+        if self.dyld_export.len() != 0 { self.dyld_export.get() } else { self.dyld_exports_trie.get() }
+    }
     pub fn get_exported_symbol_list(&self, search_for: Option<&ByteStr>) -> Vec<Symbol<'static>> {
         let mut out = Vec::new();
-        self.parse_dyld_export(self.dyld_export.get(), search_for, &mut |state: &ParseDyldExportState| {
+        self.parse_dyld_export(self.export_trie(), search_for, &mut |state: &ParseDyldExportState| {
             out.push(Symbol {
                 name: state.name.to_owned().into(),
                 is_public: true,
@@ -1733,6 +1880,8 @@ impl MachO {
                     _ => panic!("muri")
                 } },
                 size: None,
+                kind: SymbolKind::Unknown,
+                attrs: 0,
                 private: state.offset,
             });
             true
@@ -1756,6 +1905,50 @@ impl MachO {
         }
         out
     }
+    pub fn stub_name_list(&self) -> Vec<(&ByteStr, VMA)> {
+        let mut res = Vec::new();
+        let indirectsym = self.indirectsym.get();
+        let indirectsym_count = indirectsym.len() / 4;
+        let symtab = self.symtab.get();
+        let strtab = self.strtab.get();
+        let end = self.eb.endian;
+        let nlist_size = self.nlist_size;
+        for sect in &self.eb.sections {
+            let sp = &self.sect_private[sect.private];
+            if sp.flags & SECTION_TYPE != S_SYMBOL_STUBS { continue; }
+            let (ind_idx, stub_size) = (sp.reserved1 as usize, sp.reserved2 as usize);
+            if stub_size == 0 {
+                errln!("warning: stub_name_list: zero stub size (reserved2) for section {:?}", sect.name);
+                continue;
+            }
+            let stub_count = sect.filesize / stub_size.ext();
+            if ind_idx > indirectsym_count {
+                errln!("warning: stub_name_list: reserved1 ({}) > stub count ({}) for section {:?}",
+                       ind_idx, stub_count, sect.name);
+                continue;
+            }
+            let stub_count = if stub_count > (indirectsym_count - ind_idx).ext() {
+                errln!("warning: stub_name_list: reserved1 ({}) + stub count ({}) goes off end of indirect table \
+                        for section {:?}", ind_idx, stub_count, sect.name);
+                indirectsym_count - ind_idx
+            } else { stub_count as usize };
+            let mut stub_addr = sect.vmaddr;
+            for indirect_buf in indirectsym[ind_idx * 4 .. (ind_idx + stub_count) * 4].chunks(4) {
+                let sym_idx: u32 = util::copy_from_slice(indirect_buf, end);
+                let off = (sym_idx as usize).saturating_mul(nlist_size);
+                let sa = stub_addr;
+                stub_addr = stub_addr.wrapping_add(stub_size.ext());
+                let nlist_buf = some_or!(symtab.slice_opt(off, off + nlist_size), {
+                    errln!("warning: stub_name_list: bad symbol table index {}", sym_idx);
+                    continue;
+                });
+                let nl = copy_nlist_from_slice(nlist_buf, end);
+                let name = strx_to_name(strtab, nl.n_strx.ext());
+                res.push((name, sa));
+            }
+        }
+        res
+    }
 }
 
 pub struct MachOProber;
@@ -1875,3 +2068,113 @@ impl exec::ExecProber for FatMachOProber {
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    pub const BASE: u64 = 0x100000000;
+
+    pub fn put(buf: &mut Vec<u8>, off: usize, bytes: &[u8]) {
+        if buf.len() < off + bytes.len() { buf.resize(off + bytes.len(), 0); }
+        buf[off..off + bytes.len()].copy_from_slice(bytes);
+    }
+    pub fn put_words(buf: &mut Vec<u8>, off: usize, words: &[u32]) {
+        for (i, w) in words.iter().enumerate() {
+            put(buf, off + 4 * i, &w.to_le_bytes());
+        }
+    }
+
+    // A little-endian arm64 executable whose __TEXT, __DATA and __LINKEDIT each map a page, at
+    // file offsets 0, 0x1000 and 0x2000 plus BASE.  `sects` are (segment, name, file offset, size,
+    // flags, reserved1, reserved2); `lcs` are the other load commands as (cmd, words after
+    // cmdsize); `contents` are (file offset, bytes).
+    pub fn build_macho(sects: &[(&str, &str, u32, u32, u32, u32, u32)],
+                       lcs: &[(u32, &[u32])],
+                       contents: &[(usize, &[u8])]) -> MachO {
+        let mut buf = vec![0u8; 0x3000];
+        let mut lc_off = 32;
+        let mut ncmds = 0;
+        for (i, &seg) in ["__TEXT", "__DATA", "__LINKEDIT"].iter().enumerate() {
+            let seg_sects: Vec<_> = sects.iter().filter(|s| s.0 == seg).collect();
+            let cmdsize = 72 + 80 * seg_sects.len();
+            put_words(&mut buf, lc_off, &[LC_SEGMENT_64, cmdsize as u32]);
+            put(&mut buf, lc_off + 8, seg.as_bytes());
+            let off = 0x1000 * i as u64;
+            for (j, &val) in [BASE + off, 0x1000, off, 0x1000].iter().enumerate() {
+                put(&mut buf, lc_off + 24 + 8 * j, &val.to_le_bytes());
+            }
+            let prot = if i == 1 { 3 } else if i == 0 { 5 } else { 1 };
+            put_words(&mut buf, lc_off + 56, &[prot, prot, seg_sects.len() as u32]);
+            for (j, &&(_, name, offset, size, flags, reserved1, reserved2)) in seg_sects.iter().enumerate() {
+                let so = lc_off + 72 + 80 * j;
+                put(&mut buf, so, name.as_bytes());
+                put(&mut buf, so + 16, seg.as_bytes());
+                put(&mut buf, so + 32, &(BASE + offset as u64).to_le_bytes());
+                put(&mut buf, so + 40, &(size as u64).to_le_bytes());
+                put_words(&mut buf, so + 48, &[offset]);
+                put_words(&mut buf, so + 64, &[flags, reserved1, reserved2]);
+            }
+            lc_off += cmdsize;
+            ncmds += 1;
+        }
+        for &(cmd, words) in lcs {
+            let cmdsize = 8 + 4 * words.len();
+            put_words(&mut buf, lc_off, &[cmd, cmdsize as u32]);
+            put_words(&mut buf, lc_off + 8, words);
+            lc_off += cmdsize;
+            ncmds += 1;
+        }
+        put_words(&mut buf, 0, &[0xfeedfacf, CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL, MH_EXECUTE,
+                                 ncmds, lc_off as u32 - 32, 0, 0]);
+        for &(off, bytes) in contents {
+            put(&mut buf, off, bytes);
+        }
+        MachO::new(Mem::with_vec(buf), true, None).unwrap()
+    }
+
+    fn bytes(mem: &Mem<u8>) -> Vec<u8> {
+        mem.get().iter().map(|c| c.get()).collect()
+    }
+
+    #[test]
+    fn test_write_back() {
+        let mut mo = build_macho(&[("__DATA", "__data", 0x1000, 0x10, 0, 0, 0)], &[], &[(0x1000, b"hello")]);
+        let orig = bytes(mo.eb.whole_buf.as_ref().unwrap());
+        assert_eq!(bytes(&mo.eb.write_back_in_place().unwrap()), orig);
+        // patch __DATA, in a copy as a SegmentWriter would
+        let mut data = orig[0x1000..0x2000].to_vec();
+        data[..5].copy_from_slice(b"HELLO");
+        mo.eb.segments[1].data = Some(Mem::with_vec(data));
+        let mut patched = orig.clone();
+        patched[0x1000..0x1005].copy_from_slice(b"HELLO");
+        assert_eq!(bytes(&mo.eb.write_back_in_place().unwrap()), patched);
+        // the full write back lays the file out again, which should come back the same way
+        let out = exec::Exec::write_back(&mut mo).unwrap();
+        let mo2 = MachO::new(out, true, None).unwrap();
+        let names: Vec<_> = mo2.eb.segments.iter().map(|seg| (seg.name.as_ref().unwrap().to_string(), seg.vmaddr.0 - BASE)).collect();
+        assert_eq!(names, vec![("__TEXT".to_owned(), 0), ("__DATA".to_owned(), 0x1000), ("__LINKEDIT".to_owned(), 0x2000)]);
+        assert_eq!(&bytes(mo2.eb.segments[1].data.as_ref().unwrap())[..6], b"HELLO\0");
+        let sect = &mo2.eb.sections[0];
+        assert_eq!((sect.vmaddr.0 - BASE, sect.fileoff), (0x1000, mo2.eb.segments[1].fileoff));
+    }
+
+    #[test]
+    fn test_stub_name_list() {
+        let mut linkedit = Vec::new();
+        // two nlist_64s, their names, and the indirect symbol table
+        put_words(&mut linkedit, 0, &[1, 0xf, 0, 0, 6, 0xf, 0, 0]);
+        put(&mut linkedit, 0x100, b"\0_foo\0_bar\0");
+        put_words(&mut linkedit, 0x200, &[0, 1]);
+        let mut dysymtab = [0u32; 18];
+        dysymtab[12] = 0x2200;
+        dysymtab[13] = 2;
+        // the second stubs section has no stub size, so it's skipped rather than divided by
+        let mo = build_macho(&[("__TEXT", "__stubs", 0x800, 24, S_SYMBOL_STUBS, 0, 12),
+                               ("__TEXT", "__auth_stubs", 0x900, 24, S_SYMBOL_STUBS, 0, 0)],
+                             &[(LC_SYMTAB, &[0x2000, 2, 0x2100, 11]), (LC_DYSYMTAB, &dysymtab)],
+                             &[(0x2000, &linkedit)]);
+        let stubs: Vec<_> = mo.stub_name_list().iter().map(|&(name, addr)| (name.to_string(), addr.0 - BASE)).collect();
+        assert_eq!(stubs, vec![("_foo".to_owned(), 0x800), ("_bar".to_owned(), 0x80c)]);
+    }
+}
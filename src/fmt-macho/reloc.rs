@@ -0,0 +1,253 @@
+use macho_bind::*;
+use exec::{arch, VMA, Reloc, RelocKind, RelocTarget, Symbol};
+use util::{self, ReadCell, Ext};
+use std::mem::size_of;
+use {MachO, ParseDyldBindState, file_array};
+use chained::ChainedFixup;
+
+// from mach-o/{x86_64,arm,arm64}/reloc.h, which we don't have a copy of
+const X86_64_RELOC_UNSIGNED: u32 = 0;
+const X86_64_RELOC_SIGNED: u32 = 1;
+const X86_64_RELOC_BRANCH: u32 = 2;
+const X86_64_RELOC_GOT_LOAD: u32 = 3;
+const X86_64_RELOC_GOT: u32 = 4;
+const X86_64_RELOC_SIGNED_1: u32 = 6;
+const X86_64_RELOC_SIGNED_2: u32 = 7;
+const X86_64_RELOC_SIGNED_4: u32 = 8;
+const ARM_RELOC_VANILLA: u32 = 0;
+const ARM_RELOC_BR24: u32 = 5;
+const ARM_THUMB_RELOC_BR22: u32 = 6;
+const ARM64_RELOC_UNSIGNED: u32 = 0;
+const ARM64_RELOC_BRANCH26: u32 = 2;
+const ARM64_RELOC_PAGE21: u32 = 3;
+const ARM64_RELOC_PAGEOFF12: u32 = 4;
+const ARM64_RELOC_GOT_LOAD_PAGE21: u32 = 5;
+const ARM64_RELOC_GOT_LOAD_PAGEOFF12: u32 = 6;
+const ARM64_RELOC_POINTER_TO_GOT: u32 = 7;
+const ARM64_RELOC_ADDEND: u32 = 10;
+const GENERIC_RELOC_VANILLA: u32 = 0;
+const GENERIC_RELOC_PAIR: u32 = 1; // same as ARM_RELOC_PAIR
+
+// REBASE_TYPE_* and BIND_TYPE_* have the same values
+fn opcode_reloc_kind(typ: u8) -> RelocKind {
+    match typ as u32 {
+        REBASE_TYPE_POINTER => RelocKind::Pointer,
+        REBASE_TYPE_TEXT_ABSOLUTE32 => RelocKind::_32Bit,
+        REBASE_TYPE_TEXT_PCREL32 => RelocKind::_32BitPCRel,
+        _ => RelocKind::Other(typ.ext()),
+    }
+}
+
+impl MachO {
+    fn get_nlist_syms(&self) -> &[Symbol<'static>] {
+        self.nlist_sym_cache.get(|| {
+            let mut out = Vec::new();
+            if self.nlist_size != 0 {
+                self.push_nlist_symbols(self.symtab.get(), self.strtab.get(), 0, self.symtab.len() / self.nlist_size, false, &mut out);
+            }
+            out.into_iter().map(Symbol::into_owned).collect()
+        })
+    }
+    fn get_bind_syms(&self) -> &[Symbol<'static>] {
+        self.bind_sym_cache.get(|| {
+            self.get_dyld_bind_imports().into_iter().map(Symbol::into_owned).collect()
+        })
+    }
+    fn get_chained_import_syms(&self) -> &[Symbol<'static>] {
+        self.chained_import_cache.get(|| {
+            self.parse_chained_imports().into_iter().map(Symbol::into_owned).collect()
+        })
+    }
+
+    // PAGEOFF12 is used for both add and load/store; the scale comes from the instruction
+    fn arm64_pageoff_kind(&self, addr: VMA) -> RelocKind {
+        let data = some_or!(self.eb.get_sane(addr, 4), {
+            return RelocKind::Arm64AddLo12;
+        });
+        let insn: u32 = util::copy_from_slice(data, self.eb.endian);
+        if insn & 0x3b000000 != 0x39000000 {
+            return RelocKind::Arm64AddLo12;
+        }
+        let mut size = (insn >> 30) as u8;
+        if insn & 0x04800000 == 0x04800000 { size = 4; } // 128-bit SIMD
+        RelocKind::Arm64LdstLo12(size)
+    }
+
+    fn classic_reloc_kind(&self, typ: u32, pcrel: bool, length: u32, addr: VMA) -> RelocKind {
+        let plain = || match (length, pcrel) {
+            (2, false) => RelocKind::_32Bit,
+            (2, true) => RelocKind::_32BitPCRel,
+            (3, false) => RelocKind::_64Bit,
+            _ => RelocKind::Other(typ),
+        };
+        match self.eb.arch {
+            arch::X86_64 => match typ {
+                X86_64_RELOC_UNSIGNED | X86_64_RELOC_SIGNED | X86_64_RELOC_BRANCH |
+                X86_64_RELOC_SIGNED_1 | X86_64_RELOC_SIGNED_2 | X86_64_RELOC_SIGNED_4 => plain(),
+                X86_64_RELOC_GOT_LOAD | X86_64_RELOC_GOT if pcrel => RelocKind::_32BitGOTPCRel,
+                _ => RelocKind::Other(typ),
+            },
+            arch::AArch64 => match typ {
+                ARM64_RELOC_UNSIGNED => plain(),
+                ARM64_RELOC_BRANCH26 => RelocKind::Arm64Br26,
+                ARM64_RELOC_PAGE21 => RelocKind::Arm64Adrp,
+                ARM64_RELOC_PAGEOFF12 => self.arm64_pageoff_kind(addr),
+                ARM64_RELOC_GOT_LOAD_PAGE21 => RelocKind::Arm64AdrpGot,
+                ARM64_RELOC_GOT_LOAD_PAGEOFF12 => RelocKind::Arm64Ld64GotLo12,
+                ARM64_RELOC_POINTER_TO_GOT if pcrel => RelocKind::_32BitGOTPCRel,
+                _ => RelocKind::Other(typ),
+            },
+            arch::ARM => match typ {
+                ARM_RELOC_VANILLA => plain(),
+                ARM_RELOC_BR24 => RelocKind::ArmBr24,
+                ARM_THUMB_RELOC_BR22 => RelocKind::ThumbBr22,
+                _ => RelocKind::Other(typ),
+            },
+            _ => match typ {
+                GENERIC_RELOC_VANILLA => plain(),
+                _ => RelocKind::Other(typ),
+            },
+        }
+    }
+
+    // relocation_info entries, with r_address relative to `base`
+    fn push_classic_relocs<'a>(&self, data: &[ReadCell<u8>], base: VMA, syms: &'a [Symbol<'static>], out: &mut Vec<Reloc<'a>>) {
+        let end = self.eb.endian;
+        let arch = self.eb.arch;
+        let have_scattered = arch != arch::X86_64 && arch != arch::AArch64;
+        let is_obj = self.mh.filetype == MH_OBJECT;
+        let size = size_of::<relocation_info>();
+        let mut addend: Option<i64> = None;
+        let mut off = 0;
+        while off + size <= data.len() {
+            let ri: relocation_info = util::copy_from_slice(&data[off..off + size], end);
+            off += size;
+            if have_scattered && (ri.r_address as u32) & R_SCATTERED != 0 {
+                let sri: scattered_relocation_info = util::copy_from_slice(&data[off - size..off], end);
+                let typ = sri.r_type();
+                // the second half of a *DIFF pair
+                if typ == GENERIC_RELOC_PAIR { continue; }
+                let address = base.wrapping_add(sri.r_address().ext());
+                out.push(Reloc {
+                    address: address,
+                    kind: self.classic_reloc_kind(typ, sri.r_pcrel() != 0, sri.r_length(), address),
+                    addend: None,
+                    target: RelocTarget::ThisImageSlide,
+                    auth: None,
+                });
+                continue;
+            }
+            let typ = ri.r_type();
+            if arch == arch::AArch64 && typ == ARM64_RELOC_ADDEND {
+                // applies to the next entry; r_symbolnum is a signed 24-bit value
+                addend = Some(((ri.r_symbolnum() << 8) as i32 >> 8) as i64);
+                continue;
+            }
+            if have_scattered && typ == GENERIC_RELOC_PAIR { continue; }
+            let address = base.wrapping_add(ri.r_address as u32 as u64);
+            let target = if ri.r_extern() != 0 {
+                let symoff = (ri.r_symbolnum() as usize) * self.nlist_size;
+                match syms.binary_search_by_key(&symoff, |sym| sym.private) {
+                    Ok(i) => RelocTarget::Import(&syms[i]),
+                    Err(_) => {
+                        errln!("warning: relocation at {} has bad symbol index {}", address, ri.r_symbolnum());
+                        continue;
+                    },
+                }
+            } else {
+                if !is_obj && ri.r_symbolnum() == R_ABS { continue; }
+                RelocTarget::ThisImageSlide
+            };
+            out.push(Reloc {
+                address: address,
+                kind: self.classic_reloc_kind(typ, ri.r_pcrel() != 0, ri.r_length(), address),
+                addend: addend.take(),
+                target: target,
+                auth: None,
+            });
+        }
+    }
+
+    // dyld uses the first writable segment for x86_64 and the first segment otherwise
+    fn classic_reloc_base(&self) -> VMA {
+        let segs = &self.eb.segments;
+        let seg = if self.eb.arch == arch::X86_64 {
+            segs.iter().find(|seg| seg.prot.w)
+        } else {
+            segs.first()
+        };
+        seg.map_or(VMA(0), |seg| seg.vmaddr)
+    }
+
+    pub fn get_relocs(&self) -> Vec<Reloc> {
+        let mut out = Vec::new();
+        self.parse_dyld_rebase(&mut |addr, typ| {
+            out.push(Reloc {
+                address: addr,
+                kind: opcode_reloc_kind(typ),
+                addend: None,
+                target: RelocTarget::ThisImageSlide,
+                auth: None,
+            });
+            true
+        });
+
+        // one symbol per run of binds, in the same order as get_dyld_bind_imports
+        let bind_syms = self.get_bind_syms();
+        let mut sym_count = 0;
+        self.parse_each_dyld_bind(&mut |state: &ParseDyldBindState| {
+            if state.symbol.is_none() { return true; }
+            if !state.already_bound_this_symbol { sym_count += 1; }
+            let sym = some_or!(bind_syms.get(sym_count - 1), { return true; });
+            let seg_off = some_or!(state.seg_off, { return true; });
+            out.push(Reloc {
+                address: state.seg.unwrap().vmaddr.wrapping_add(seg_off),
+                kind: opcode_reloc_kind(state.typ),
+                addend: Some(state.addend),
+                target: RelocTarget::Import(sym),
+                auth: None,
+            });
+            true
+        });
+
+        // chained rebases store the target in place of the pointer, so it goes in the addend
+        let chained_syms = self.get_chained_import_syms();
+        self.parse_chained_fixups(&mut |addr, fixup, auth| {
+            let (target, addend) = match fixup {
+                ChainedFixup::Rebase(VMA(target)) => (RelocTarget::ThisImageSlide, target as i64),
+                ChainedFixup::Bind { ordinal, addend } => {
+                    let sym = some_or!(chained_syms.get(ordinal), {
+                        errln!("warning: chained bind at {} has bad import ordinal {}", addr, ordinal);
+                        return true;
+                    });
+                    (RelocTarget::Import(sym), addend)
+                },
+            };
+            out.push(Reloc {
+                address: addr,
+                kind: RelocKind::Pointer,
+                addend: Some(addend),
+                target: target,
+                auth: auth,
+            });
+            true
+        });
+
+        let nlist_syms = self.get_nlist_syms();
+        if self.extrel.len() != 0 || self.locrel.len() != 0 {
+            let base = self.classic_reloc_base();
+            self.push_classic_relocs(self.extrel.get(), base, nlist_syms, &mut out);
+            self.push_classic_relocs(self.locrel.get(), base, nlist_syms, &mut out);
+        }
+        if self.mh.filetype == MH_OBJECT {
+            let whole = self.eb.whole_buf.as_ref().unwrap();
+            for sect in &self.eb.sections {
+                let sp = &self.sect_private[sect.private];
+                if sp.nreloc == 0 { continue; }
+                let relocs = file_array(whole, "section relocations", sp.reloff.ext(), sp.nreloc.ext(), size_of::<relocation_info>());
+                self.push_classic_relocs(relocs.get(), sect.vmaddr, nlist_syms, &mut out);
+            }
+        }
+        out
+    }
+}
@@ -21,6 +21,7 @@ extern crate dis_generated_jump_dis;
 use dis_generated_jump_dis::AArch64Handler;
 extern crate dis_simple_trawl;
 use dis_simple_trawl::CodeMap;
+use dis_simple_trawl::functions::DEFAULT_NORETURN_NAMES;
 
 struct ReaggregatedSyms {
     localsym: Vec<u8>,
@@ -37,8 +38,7 @@ trait MachODscExtraction {
     fn sect_bounds_named(&self, sectname: &str) -> (VMA, u64);
     fn fix_objc_from_cache<'dc>(&mut self, dc: &'dc DyldCache);
     fn check_no_other_lib_refs<'a>(&'a self, dc: &'a DyldCache);
-    fn guess_text_relocs(&self, stack_chk_fail: Option<VMA>) -> Vec<(VMA, RelocKind, VMA)>;
-    fn stub_name_list(&self) -> Vec<(&ByteStr, VMA)>;
+    fn guess_text_relocs(&self, noreturn: &[VMA]) -> Vec<(VMA, RelocKind, VMA)>;
     fn fix_text_relocs_from_cache(&mut self, ic: &ImageCache, dc: &DyldCache);
     fn backwards_reexport_map<'a>(&'a self, ic: &'a ImageCache) -> HashMap<ByteString, &'a ByteStr, Fnv>;
     fn reconstruct_rebase(&self, dc: &DyldCache) -> Vec<u8>;
@@ -586,7 +586,7 @@ impl MachODscExtraction for MachO {
         }
     }
     // currently for cache extraction on arm64 only
-    fn guess_text_relocs(&self, stack_chk_fail: Option<VMA>) -> Vec<(VMA, RelocKind, VMA)> {
+    fn guess_text_relocs(&self, noreturn: &[VMA]) -> Vec<(VMA, RelocKind, VMA)> {
         let _sw = stopwatch("guess_text_relocs");
         let mut relocs = Vec::new();
         if self.eb.arch != arch::AArch64 {
@@ -610,9 +610,10 @@ impl MachODscExtraction for MachO {
             let grain_shift: u8 = 2; // xxx
             let start_addr = sect.vmaddr;
             let mut codemap = CodeMap::new(start_addr, grain_shift, util::downgrade(sectdata), end, &self.eb.segments);
-            if let Some(stack_chk_fail) = stack_chk_fail {
-                codemap.mark_noreturn_addr(stack_chk_fail);
+            for &addr in noreturn {
+                codemap.mark_noreturn_addr(addr);
             }
+            codemap.set_infer_noreturn(true);
             {
                 // TODO sort? only useful if there are many sections like this
                 for group in &[self.localsym.get(), self.extdefsym.get()] {
@@ -651,46 +652,6 @@ impl MachODscExtraction for MachO {
         }
         relocs
     }
-    fn stub_name_list(&self) -> Vec<(&ByteStr, VMA)> {
-        let mut res = Vec::new();
-        let indirectsym = self.indirectsym.get();
-        let indirectsym_count = indirectsym.len() / 4;
-        let symtab = self.symtab.get();
-        let strtab = self.strtab.get();
-        let end = self.eb.endian;
-        let nlist_size = self.nlist_size;
-        for sect in &self.eb.sections {
-            let sp = &self.sect_private[sect.private];
-            if sp.flags & SECTION_TYPE != S_SYMBOL_STUBS { continue; }
-            let (ind_idx, stub_size) = (sp.reserved1 as usize, sp.reserved2 as usize);
-            let stub_count = sect.filesize / stub_size.ext();
-            if ind_idx > indirectsym_count {
-                errln!("warning: stub_name_list: reserved1 ({}) > stub count ({}) for section {:?}",
-                       ind_idx, stub_count, sect.name);
-                continue;
-            }
-            let stub_count = if stub_count > (indirectsym_count - ind_idx).ext() {
-                errln!("warning: stub_name_list: reserved1 ({}) + stub count ({}) goes off end of indirect table \
-                        for section {:?}", ind_idx, stub_count, sect.name);
-                indirectsym_count - ind_idx
-            } else { stub_count as usize };
-            let mut stub_addr = sect.vmaddr;
-            for indirect_buf in indirectsym[ind_idx * 4 .. (ind_idx + stub_count) * 4].chunks(4) {
-                let sym_idx: u32 = util::copy_from_slice(indirect_buf, end);
-                let off = (sym_idx as usize).saturating_mul(nlist_size);
-                let sa = stub_addr;
-                stub_addr = stub_addr.wrapping_add(stub_size.ext());
-                let nlist_buf = some_or!(symtab.slice_opt(off, off + nlist_size), {
-                    errln!("warning: stub_name_list: bad symbol table index {}", sym_idx);
-                    continue;
-                });
-                let nl = copy_nlist_from_slice(nlist_buf, end);
-                let name = strx_to_name(strtab, nl.n_strx.ext());
-                res.push((name, sa));
-            }
-        }
-        res
-    }
     fn fix_text_relocs_from_cache(&mut self, ic: &ImageCache, dc: &DyldCache) {
         let _sw = stopwatch("fix_text_relocs_from_cache");
         let pointer_size = self.eb.pointer_size;
@@ -703,8 +664,7 @@ impl MachODscExtraction for MachO {
             my_stubs_by_name.insert(name, stub_addr);
         }
 
-        let __stack_chk_fail = ic_get_known_addrs(ic).__stack_chk_fail;
-        let guess = self.guess_text_relocs(__stack_chk_fail);
+        let guess = self.guess_text_relocs(&ic_get_known_addrs(ic).noreturn);
         if guess.len() == 0 { return; }
 
         let mut target_cache: HashMap<VMA, Option<VMA>, _> = util::new_fnv_hashmap();
@@ -933,30 +893,40 @@ fn ice_get_addr_syms(this: &ImageCacheEntry) -> &Vec<exec::Symbol<'static>> {
     any.downcast_ref().unwrap()
 }
 
+// Where the cache's copies of DEFAULT_NORETURN_NAMES live; calls to them don't fall through.
+static NORETURN_LIBS: &[&str] = &[
+    "/usr/lib/system/libsystem_c.dylib",
+    "/usr/lib/system/libsystem_kernel.dylib",
+    "/usr/lib/system/libsystem_platform.dylib",
+    "/usr/lib/system/libsystem_pthread.dylib",
+    "/usr/lib/system/libunwind.dylib",
+    "/usr/lib/libobjc.A.dylib",
+    "/usr/lib/libc++abi.dylib",
+];
+
 #[derive(Default)]
 struct KnownAddrs {
-    __stack_chk_fail: Option<VMA>,
+    noreturn: Vec<VMA>,
 }
 
 fn ic_get_known_addrs(this: &ImageCache) -> &KnownAddrs {
     let any = this.known_addrs.get(|| {
         let mut ka = Box::new(KnownAddrs::default());
-        scope! { 'foo: {
-            let libsystem_c = some_or!(this.lookup_path(ByteStr::from_str("/usr/lib/system/libsystem_c.dylib")).and_then(|ice| ice.mo.as_ref().ok()), {
-                errln!("ic_get_known_addrs: no libsystem_c.dylib so won't find __stack_chk_fail");
-                break 'foo;
-            });
-            let lex = libsystem_c.lookup_export(ByteStr::from_str("___stack_chk_fail"), None);
-            let sym = some_or!(lex.first(), {
-                errln!("ic_get_known_addrs: __stack_chk_fail symbol not found in libsystem_c.dylib");
-                break 'foo;
+        for &path in NORETURN_LIBS {
+            let mo = some_or!(this.lookup_path(ByteStr::from_str(path)).and_then(|ice| ice.mo.as_ref().ok()), {
+                errln!("ic_get_known_addrs: no {} so won't find noreturn functions in it", path);
+                continue;
             });
-            if let SymbolValue::Addr(vma) = sym.val {
-                ka.__stack_chk_fail = Some(vma);
-            } else {
-                errln!("ic_get_known_addrs: __stack_chk_fail symbol not found in libsystem_c.dylib");
+            for name in DEFAULT_NORETURN_NAMES {
+                let lex = mo.lookup_export(ByteStr::from_str(&format!("_{}", name)), None);
+                if let Some(&SymbolValue::Addr(vma)) = lex.first().map(|sym| &sym.val) {
+                    ka.noreturn.push(vma);
+                }
             }
-        } }
+        }
+        if ka.noreturn.is_empty() {
+            errln!("ic_get_known_addrs: found no noreturn functions");
+        }
         ka
     });
     any.downcast_ref().unwrap()
@@ -1042,7 +1012,6 @@ pub fn extract_as_necessary(mo: &mut MachO, dc: Option<&DyldCache>, image_cache:
         mo.fix_objc_from_cache(dc);
         mo.check_no_other_lib_refs(dc);
     }
-    mo.reallocate()?;
-    mo.rewhole();
+    mo.write_back()?;
     Ok(())
 }
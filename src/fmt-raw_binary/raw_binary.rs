@@ -11,6 +11,10 @@ impl exec::Exec for RawBinary {
         &self.eb
     }
 
+    fn write_back(&mut self) -> exec::ExecResult<util::Mem<u8>> {
+        self.eb.write_back_in_place()
+    }
+
     fn as_any(&self) -> &std::any::Any { self as &std::any::Any }
 }
 
@@ -41,15 +41,49 @@ macro_rules! display_as_debug{($ty:ty) => (
 
 #[macro_export]
 macro_rules! offset_of{($ty:ty, $field:ident) => (
-    unsafe { (&(*(0 as *const $ty)).$field) as *const _ as usize }
+    unsafe {
+        // not through a null pointer, which debug builds now check for
+        let outer = ::std::mem::MaybeUninit::<$ty>::uninit();
+        let base = outer.as_ptr();
+        ::std::ptr::addr_of!((*base).$field) as usize - base as usize
+    }
 )}
 
 #[macro_export]
 macro_rules! errln {($($a:tt)*) => {{
     use ::std::io::Write;
-    writeln!(::std::io::stderr(), $($a)*).unwrap();
+    let msg = format!($($a)*);
+    if !$crate::errln_captured(&msg) {
+        writeln!(::std::io::stderr(), "{}", msg).unwrap();
+    }
 }}}
 
+thread_local!(static ERRLN_CAPTURE: ::std::cell::RefCell<Option<Vec<String>>> = ::std::cell::RefCell::new(None));
+
+#[doc(hidden)]
+pub fn errln_captured(msg: &str) -> bool {
+    ERRLN_CAPTURE.with(|cap| {
+        if let Some(ref mut v) = *cap.borrow_mut() { v.push(msg.to_owned()); true } else { false }
+    })
+}
+
+// run f with this thread's errln!s collected instead of printed
+pub fn capture_errln<R, F: FnOnce() -> R>(f: F) -> (R, Vec<String>) {
+    let old = ERRLN_CAPTURE.with(|cap| cap.borrow_mut().replace(Vec::new()));
+    // put the old state back even if f panics
+    struct Restore(Option<Vec<String>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let old = self.0.take();
+            ERRLN_CAPTURE.with(|cap| *cap.borrow_mut() = old);
+        }
+    }
+    let _restore = Restore(old);
+    let res = f();
+    let msgs = ERRLN_CAPTURE.with(|cap| cap.borrow_mut().take().unwrap());
+    (res, msgs)
+}
+
 #[macro_export]
 macro_rules! some_or {($opt:expr, $els:stmt) => {
     if let Some(xxx) = $opt { xxx } else { $els }
@@ -100,7 +134,11 @@ macro_rules! impl_check_x_option {($trait_:ident, $meth:ident, $T:ty, $U:ty) =>
 
 #[macro_export]
 macro_rules! field_lens { ($ty:ty, $field:ident) => {
-    unsafe { util::__field_lens::<$ty, _>(&(*(0 as *const $ty)).$field as *const _) }
+    unsafe {
+        let outer = ::std::mem::MaybeUninit::<$ty>::uninit();
+        let base = outer.as_ptr();
+        util::__field_lens::<$ty, _>(base, ::std::ptr::addr_of!((*base).$field))
+    }
 } }
 
 #[macro_export]
@@ -170,6 +208,16 @@ fn test_bitflags() {
 
 }
 
+#[test]
+fn test_capture_errln() {
+    let ((), outer) = capture_errln(|| {
+        errln!("a {}", 1);
+        let ((), inner) = capture_errln(|| errln!("b"));
+        assert_eq!(inner, vec!["b".to_owned()]);
+    });
+    assert_eq!(outer, vec!["a 1".to_owned()]);
+}
+
 #[test]
 fn test_scope() {
     scope! { 'foo: { break 'foo; } }
@@ -0,0 +1,253 @@
+// Just enough JSON for manifests and machine-readable output: objects keep their key order, and
+// numbers are kept as their text so 64-bit addresses don't get mangled.
+
+use std::fmt::{self, Display, Formatter, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn num<N: Display>(n: N) -> Json {
+        Json::Number(n.to_string())
+    }
+    pub fn str<S: Into<String>>(s: S) -> Json {
+        Json::Str(s.into())
+    }
+    pub fn opt<T, F: FnOnce(T) -> Json>(o: Option<T>, f: F) -> Json {
+        o.map_or(Json::Null, f)
+    }
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        if let Json::Object(ref fields) = *self {
+            fields.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v)
+        } else { None }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        if let Json::Str(ref s) = *self { Some(s) } else { None }
+    }
+    pub fn as_array(&self) -> Option<&[Json]> {
+        if let Json::Array(ref a) = *self { Some(a) } else { None }
+    }
+    pub fn parse(s: &str) -> Result<Json, String> {
+        let mut it = s.chars().peekable();
+        let val = parse_value(&mut it)?;
+        skip_ws(&mut it);
+        if it.next().is_some() { return Err("trailing garbage".to_owned()); }
+        Ok(val)
+    }
+}
+
+fn write_str(fmt: &mut Formatter, s: &str) -> fmt::Result {
+    fmt.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => fmt.write_str("\\\"")?,
+            '\\' => fmt.write_str("\\\\")?,
+            '\n' => fmt.write_str("\\n")?,
+            '\r' => fmt.write_str("\\r")?,
+            '\t' => fmt.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(fmt, "\\u{:04x}", c as u32)?,
+            c => fmt.write_char(c)?,
+        }
+    }
+    fmt.write_char('"')
+}
+
+// one line; `{:#}` puts each array element and object field on its own line, indented two spaces
+// per level of nesting
+impl Display for Json {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        let indent = if fmt.alternate() { Some(0) } else { None };
+        self.write(fmt, indent)
+    }
+}
+
+impl Json {
+    fn write(&self, fmt: &mut Formatter, indent: Option<usize>) -> fmt::Result {
+        let newline = |fmt: &mut Formatter, level: usize| -> fmt::Result {
+            fmt.write_char('\n')?;
+            for _ in 0..level { fmt.write_str("  ")?; }
+            Ok(())
+        };
+        let inner = indent.map(|level| level + 1);
+        match *self {
+            Json::Null => fmt.write_str("null"),
+            Json::Bool(b) => write!(fmt, "{}", b),
+            Json::Number(ref n) => fmt.write_str(n),
+            Json::Str(ref s) => write_str(fmt, s),
+            Json::Array(ref a) => {
+                fmt.write_char('[')?;
+                for (i, v) in a.iter().enumerate() {
+                    if i != 0 { fmt.write_char(',')?; }
+                    if let Some(level) = inner { newline(fmt, level)?; }
+                    v.write(fmt, inner)?;
+                }
+                if let (Some(level), false) = (indent, a.is_empty()) { newline(fmt, level)?; }
+                fmt.write_char(']')
+            },
+            Json::Object(ref fields) => {
+                fmt.write_char('{')?;
+                for (i, &(ref k, ref v)) in fields.iter().enumerate() {
+                    if i != 0 { fmt.write_char(',')?; }
+                    if let Some(level) = inner { newline(fmt, level)?; }
+                    write_str(fmt, k)?;
+                    fmt.write_str(if indent.is_some() { ": " } else { ":" })?;
+                    v.write(fmt, inner)?;
+                }
+                if let (Some(level), false) = (indent, fields.is_empty()) { newline(fmt, level)?; }
+                fmt.write_char('}')
+            },
+        }
+    }
+}
+
+type It<'a> = Peekable<Chars<'a>>;
+
+fn skip_ws(it: &mut It) {
+    while let Some(&c) = it.peek() {
+        if !c.is_whitespace() { break; }
+        it.next();
+    }
+}
+
+fn expect(it: &mut It, word: &str) -> Result<(), String> {
+    for c in word.chars() {
+        if it.next() != Some(c) { return Err(format!("expected '{}'", word)); }
+    }
+    Ok(())
+}
+
+fn parse_string(it: &mut It) -> Result<String, String> {
+    expect(it, "\"")?;
+    let mut s = String::new();
+    loop {
+        match it.next() {
+            None => return Err("unterminated string".to_owned()),
+            Some('"') => return Ok(s),
+            Some('\\') => {
+                let c = match it.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('b') => '\x08',
+                    Some('f') => '\x0c',
+                    Some('u') => {
+                        let hex: String = it.by_ref().take(4).collect();
+                        let val = u32::from_str_radix(&hex, 16).map_err(|_| "bad \\u escape".to_owned())?;
+                        // surrogate pairs aren't worth it; we never write them
+                        some_or!(::std::char::from_u32(val), { return Err("surrogate in \\u escape".to_owned()); })
+                    },
+                    Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => c,
+                    _ => return Err("bad escape".to_owned()),
+                };
+                s.push(c);
+            },
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+fn parse_value(it: &mut It) -> Result<Json, String> {
+    skip_ws(it);
+    let c = some_or!(it.peek().cloned(), { return Err("unexpected end".to_owned()); });
+    match c {
+        'n' => { expect(it, "null")?; Ok(Json::Null) },
+        't' => { expect(it, "true")?; Ok(Json::Bool(true)) },
+        'f' => { expect(it, "false")?; Ok(Json::Bool(false)) },
+        '"' => Ok(Json::Str(parse_string(it)?)),
+        '[' => {
+            it.next();
+            let mut a = Vec::new();
+            skip_ws(it);
+            if it.peek() == Some(&']') { it.next(); return Ok(Json::Array(a)); }
+            loop {
+                a.push(parse_value(it)?);
+                skip_ws(it);
+                match it.next() {
+                    Some(',') => (),
+                    Some(']') => return Ok(Json::Array(a)),
+                    _ => return Err("expected ',' or ']'".to_owned()),
+                }
+            }
+        },
+        '{' => {
+            it.next();
+            let mut fields = Vec::new();
+            skip_ws(it);
+            if it.peek() == Some(&'}') { it.next(); return Ok(Json::Object(fields)); }
+            loop {
+                skip_ws(it);
+                let k = parse_string(it)?;
+                skip_ws(it);
+                expect(it, ":")?;
+                fields.push((k, parse_value(it)?));
+                skip_ws(it);
+                match it.next() {
+                    Some(',') => (),
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("expected ',' or '}'".to_owned()),
+                }
+            }
+        },
+        '-' | '0'..='9' => {
+            let mut n = String::new();
+            while let Some(&c) = it.peek() {
+                if !(c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') { break; }
+                n.push(c);
+                it.next();
+            }
+            Ok(Json::Number(n))
+        },
+        _ => Err(format!("unexpected '{}'", c)),
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let val = Json::Object(vec![
+        ("path".to_owned(), Json::str("/usr/lib/\"weird\"\n.dylib")),
+        ("addr".to_owned(), Json::num(0xffff_ffff_ffff_fff0u64)),
+        ("warnings".to_owned(), Json::Array(vec![Json::str("a"), Json::Null, Json::Bool(false)])),
+        ("empty".to_owned(), Json::Object(vec![])),
+    ]);
+    assert_eq!(Json::parse(&format!("{}", val)), Ok(val.clone()));
+    assert_eq!(Json::parse(&format!("{:#}", val)), Ok(val.clone()));
+    assert_eq!(val.get("addr"), Some(&Json::Number("18446744073709551600".to_owned())));
+    assert!(Json::parse("[1,").is_err());
+}
+
+#[test]
+fn test_pretty() {
+    let val = Json::Object(vec![
+        ("name".to_owned(), Json::str("f")),
+        ("blocks".to_owned(), Json::Array(vec![
+            Json::Object(vec![("start".to_owned(), Json::num(1)), ("edges".to_owned(), Json::Array(vec![]))]),
+            Json::Array(vec![Json::Null, Json::Bool(true)]),
+        ])),
+        ("empty".to_owned(), Json::Object(vec![])),
+    ]);
+    assert_eq!(format!("{}", val), r#"{"name":"f","blocks":[{"start":1,"edges":[]},[null,true]],"empty":{}}"#);
+    assert_eq!(format!("{:#}", val), r#"{
+  "name": "f",
+  "blocks": [
+    {
+      "start": 1,
+      "edges": []
+    },
+    [
+      null,
+      true
+    ]
+  ],
+  "empty": {}
+}"#);
+    assert_eq!(format!("{:#}", Json::num(5)), "5");
+}
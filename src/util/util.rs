@@ -30,8 +30,6 @@ use std::iter::FromIterator;
 
 extern crate fnv;
 use fnv::FnvHasher;
-extern crate nodrop;
-use nodrop::NoDrop;
 
 //use std::ty::Unsafe;
 
@@ -54,6 +52,8 @@ pub use trivial_hasher::*;
 mod small_vector;
 pub use small_vector::SmallVector;
 
+pub mod json;
+
 pub struct ReadCell<T: Copy> {
     pub value: UnsafeCell<T>
 }
@@ -124,34 +124,30 @@ impl_rwsp!(T, &'a mut [T]);
 #[inline]
 pub fn copy_from_slice<'a, T: Copy + Swap, S: ?Sized + ROSlicePtr<u8>>(slice: &S, end: Endian) -> T {
     assert_eq!(slice.len(), size_of::<T>());
-    unsafe {
-        let mut t : T = MaybeUninit::uninit().assume_init();
-        copy(transmute(slice.as_ptr()), &mut t, 1);
-        t.bswap_from(end);
-        t
-    }
+    // the data needn't be aligned for T
+    let mut t: T = unsafe { ptr::read_unaligned(slice.as_ptr() as *const T) };
+    t.bswap_from(end);
+    t
 }
 
 #[inline]
 pub fn copy_to_slice<'a, T: Copy + Swap, S: RWSlicePtr<'a, u8>>(slice: S, t: &T, end: Endian) {
     assert_eq!(slice.len(), size_of::<T>());
-    unsafe {
-        let stp: *mut T = transmute(slice.as_mut_ptr());
-        copy(t, stp, 1);
-        (*stp).bswap_from(end);
-    }
+    let mut t = *t;
+    t.bswap_from(end);
+    unsafe { ptr::write_unaligned(slice.as_mut_ptr() as *mut T, t); }
 }
 
 pub fn copy_to_vec<T: Copy + Swap>(vec: &mut Vec<u8>, t: &T, end: Endian) {
     let size = size_of::<T>();
     let off = vec.len();
     assert!(off <= !0usize - size);
+    let mut t = *t;
+    t.bswap_from(end);
     unsafe {
         vec.reserve(size);
+        ptr::write_unaligned(vec.as_mut_ptr().offset(off as isize) as *mut T, t);
         vec.set_len(off + size);
-        let stp: *mut T = transmute(vec.as_mut_ptr().offset(off as isize));
-        copy(t, stp, 1);
-        (*stp).bswap_from(end);
     }
 }
 
@@ -792,6 +788,18 @@ enum MemoryContainer {
     Empty,
     MemoryMap(Mmap),
     BoxedSlice(Box<[u8]>),
+    ConcatMap(ConcatMap),
+}
+
+// a reservation with several files mapped into it; see memmap_concat
+struct ConcatMap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+impl Drop for ConcatMap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len); }
+    }
 }
 
 #[derive(Clone)]
@@ -847,7 +855,8 @@ impl<T: Swap> Mem<T> {
         // todo
         Mem {
             mc: Arc::new(MemoryContainer::Empty),
-            ptr: 0 as *const T,
+            // not null, since get() makes a slice out of it
+            ptr: std::ptr::NonNull::dangling().as_ptr(),
             len: 0,
         }
     }
@@ -931,6 +940,42 @@ pub fn memmap(fil: &File) -> io::Result<Mem<u8>> {
     Ok(Mem::with_mm(Mmap::open(fil, memmap::Protection::ReadCopy)?))
 }
 
+// Maps the files back to back (each starting on a page boundary), so that something split into
+// several files can be treated as one big one.  Returns the start offset of each file.
+#[cfg(unix)]
+pub fn memmap_concat(fils: &[File]) -> io::Result<(Mem<u8>, Vec<usize>)> {
+    use std::os::unix::io::AsRawFd;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut offsets = Vec::with_capacity(fils.len());
+    let mut lens = Vec::with_capacity(fils.len());
+    let mut total: usize = 0;
+    for fil in fils {
+        let len = fil.metadata()?.len() as usize;
+        offsets.push(total);
+        lens.push(len);
+        total = some_or!(total.checked_add(len.align_up_to(page_size)), {
+            return Err(io::Error::new(io::ErrorKind::Other, "memmap_concat: too big"));
+        });
+    }
+    let cm = unsafe {
+        let ptr = libc::mmap(ptr::null_mut(), max(total, page_size), libc::PROT_NONE,
+                             libc::MAP_PRIVATE | libc::MAP_ANON, -1, 0);
+        if ptr == libc::MAP_FAILED { return Err(io::Error::last_os_error()); }
+        ConcatMap { ptr: ptr, len: max(total, page_size) }
+    };
+    for ((fil, &off), &len) in fils.iter().zip(&offsets).zip(&lens) {
+        if len == 0 { continue; }
+        let ptr = unsafe {
+            libc::mmap((cm.ptr as *mut u8).offset(off as isize) as *mut libc::c_void, len,
+                       libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_FIXED,
+                       fil.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED { return Err(io::Error::last_os_error()); }
+    }
+    let ptr = cm.ptr as *const u8;
+    Ok((Mem { mc: Arc::new(MemoryContainer::ConcatMap(cm)), ptr: ptr, len: total }, offsets))
+}
+
 pub fn do_getopts(args: &[String], min_expected_free: usize, max_expected_free: usize, optgrps: &mut Vec<getopts::OptGroup>) -> Option<getopts::Matches> {
     if let Ok(m) = getopts::getopts(args, &optgrps) {
         if m.free.len() >= min_expected_free &&
@@ -1139,14 +1184,14 @@ pub fn into_cow<'a, T: ?Sized + ToOwned, S: Into<Cow<'a, T>>>(s: S) -> Cow<'a, T
 
 pub struct Lazy<T> {
     mtx: Mutex<()>,
-    val: UnsafeCell<NoDrop<T>>,
+    val: UnsafeCell<MaybeUninit<T>>,
     is_valid: AtomicBool,
 }
 impl<T> Lazy<T> {
     pub fn new() -> Lazy<T> {
         Lazy {
             mtx: Mutex::new(()),
-            val: unsafe { UnsafeCell::new(MaybeUninit::uninit().assume_init()) },
+            val: UnsafeCell::new(MaybeUninit::uninit()),
             is_valid: AtomicBool::new(false),
         }
     }
@@ -1154,18 +1199,28 @@ impl<T> Lazy<T> {
         unsafe {
             if !self.is_valid.load(Ordering::Acquire) {
                 let _guard = self.mtx.lock().unwrap();
-                ptr::write(&mut **self.val.get(), f());
-                self.is_valid.store(true, Ordering::Release);
+                // someone else may have filled it in while we waited
+                if !self.is_valid.load(Ordering::Acquire) {
+                    ptr::write((*self.val.get()).as_mut_ptr(), f());
+                    self.is_valid.store(true, Ordering::Release);
+                }
             }
-            &*self.val.get()
+            &*(*self.val.get()).as_ptr()
         }
     }
 }
 unsafe impl<T> Sync for Lazy<T> {}
+impl<T> Default for Lazy<T> {
+    fn default() -> Self { Lazy::new() }
+}
+// Lazy is used for caches, so a clone just starts out empty.
+impl<T> Clone for Lazy<T> {
+    fn clone(&self) -> Self { Lazy::new() }
+}
 impl<T> Drop for Lazy<T> {
     fn drop(&mut self) {
         if self.is_valid.load(Ordering::Acquire) {
-            unsafe { ptr::read(&mut **self.val.get()); }
+            unsafe { ptr::drop_in_place((*self.val.get()).as_mut_ptr()); }
         }
     }
 }
@@ -1211,8 +1266,8 @@ impl<O, I> Clone for FieldLens<O, I> {
 }
 impl<O, I> Copy for FieldLens<O, I> {}
 
-pub unsafe fn __field_lens<Outer, Inner>(offset: *const Inner) -> FieldLens<Outer, Inner> {
-    FieldLens { offset: offset as usize, lol: PhantomData }
+pub unsafe fn __field_lens<Outer, Inner>(outer: *const Outer, inner: *const Inner) -> FieldLens<Outer, Inner> {
+    FieldLens { offset: inner as usize - outer as usize, lol: PhantomData }
 }
 
 impl<Outer, Inner> FieldLens<Outer, Inner> {
@@ -1272,8 +1327,10 @@ impl<'a> Drop for Stopwatch<'a> {
         if unsafe { ENABLE_STOPWATCH } {
             let duration = Instant::now() - self.start_time.unwrap();
             let duration_f = (duration.as_secs() as f64 * 1000.0) + (duration.subsec_nanos() as f64 * 0.000001);
-            errln!("{blank:spaces$}{desc}: {duration:?}ms", blank="", spaces=self.indent,
-                   desc=self.desc, duration=duration_f);
+            // not errln!, so timings don't end up in captured warnings
+            use std::io::Write;
+            writeln!(io::stderr(), "{blank:spaces$}{desc}: {duration:?}ms", blank="", spaces=self.indent,
+                     desc=self.desc, duration=duration_f).unwrap();
             STOPWATCH_INDENT.with(|cell| cell.set(self.indent));
         }
     }
@@ -1505,3 +1562,56 @@ pub fn unlikely(b: bool) -> bool { unsafe { std::intrinsics::unlikely(b) } }
 pub fn likely(b: bool) -> bool { b }
 #[cfg(not(feature = "nightly"))] #[inline(always)]
 pub fn unlikely(b: bool) -> bool { b }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn test_empty_mem() {
+        // get() makes a slice, which mustn't come from a null pointer even when empty
+        let mem: Mem<u8> = Mem::empty();
+        assert!(mem.get().is_empty());
+        assert!(Mem::<u8>::default().get().is_empty());
+        assert!(mem.slice(0, 0).unwrap().get().is_empty());
+    }
+
+    #[test]
+    fn test_lazy_init_and_drop() {
+        let rc = Rc::new(());
+        {
+            let lazy: Lazy<Rc<()>> = Lazy::new();
+            assert_eq!(**lazy.get(|| rc.clone()), ());
+            lazy.get(|| panic!("initialized twice"));
+            assert_eq!(Rc::strong_count(&rc), 2);
+            // caches clone empty
+            lazy.clone().get(|| rc.clone());
+            assert_eq!(Rc::strong_count(&rc), 2);
+        }
+        assert_eq!(Rc::strong_count(&rc), 1);
+        // and an unused one has nothing to drop
+        drop(Lazy::<Rc<()>>::new());
+    }
+
+    #[test]
+    fn test_lazy_race() {
+        let lazy: Arc<Lazy<Vec<usize>>> = Arc::new(Lazy::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8).map(|i| {
+            let (lazy, calls) = (lazy.clone(), calls.clone());
+            thread::spawn(move || {
+                let val = lazy.get(|| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(std::time::Duration::from_millis(10));
+                    vec![i; 100]
+                });
+                assert!(val.iter().all(|&x| x == val[0]));
+            })
+        }).collect();
+        for thread in threads { thread.join().unwrap(); }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -10,6 +10,9 @@ use std::fs::File;
 use std::io::Write;
 use std::sync::Arc;
 use std::sync::mpsc::channel;
+use std::hash::{BuildHasher, Hasher};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 
 #[macro_use]
 extern crate macros;
@@ -19,25 +22,164 @@ extern crate fmt_macho_dsc_extraction as macho_dsc_extraction;
 extern crate exec;
 use macho::dyldcache::{DyldCache, ImageInfo, ImageCache};
 use util::{ByteString, ByteStr};
+use util::json::Json;
 use exec::arch;
 
 extern crate threadpool;
 use threadpool::ThreadPool;
 extern crate num_cpus;
 
+fn hash_output(buf: &[u8]) -> u64 {
+    let mut hasher = util::Fnv::default().build_hasher();
+    hasher.write(buf);
+    hasher.finish()
+}
+
+// returns the hash of what was written
 fn extract_one(dc: &DyldCache, ii: &ImageInfo, outpath: &Path, image_cache: Option<&ImageCache>,
-               minimal_processing: bool) {
-    let mut macho = match dc.load_single_image(ii, /*fix_data*/ true) {
-        Ok(m) => m,
-        Err(e) => { errln!("for '{}', parse Mach-O fail: {}", ii.path, e); return },
+               minimal_processing: bool) -> Result<u64, String> {
+    let mut macho = dc.load_single_image(ii, /*fix_data*/ true).map_err(|e| format!("parse Mach-O fail: {}", e))?;
+    macho_dsc_extraction::extract_as_necessary(&mut macho, Some(dc), image_cache, minimal_processing)
+        .map_err(|e| format!("extract fail: {}", e))?;
+    let buf = unsafe { macho.eb.whole_buf.as_ref().unwrap().get_plain_slice() };
+    File::create(outpath).and_then(|mut fp| fp.write_all(buf))
+        .map_err(|e| format!("write {:?} fail: {}", outpath, e))?;
+    Ok(hash_output(buf))
+}
+
+// for --all: also collects the errln!s from extraction, and turns panics into errors
+fn extract_one_captured(dc: &DyldCache, ii: &ImageInfo, outpath: &Path, image_cache: Option<&ImageCache>,
+                        minimal_processing: bool) -> (Result<u64, String>, Vec<String>) {
+    macros::capture_errln(|| {
+        match catch_unwind(AssertUnwindSafe(|| extract_one(dc, ii, outpath, image_cache, minimal_processing))) {
+            Ok(res) => res,
+            Err(payload) => Err(if let Some(s) = payload.downcast_ref::<&str>() {
+                format!("panic: {}", s)
+            } else if let Some(s) = payload.downcast_ref::<String>() {
+                format!("panic: {}", s)
+            } else {
+                "panic".to_owned()
+            }),
+        }
+    })
+}
+
+struct ManifestEntry {
+    path: String,
+    output: String,
+    status: String, // "ok", "failed" or "symlink"
+    symlink_to: Option<String>,
+    error: Option<String>,
+    warnings: Vec<String>,
+    hash: Option<u64>,
+}
+
+impl ManifestEntry {
+    fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("path".to_owned(), Json::str(&*self.path)),
+            ("output".to_owned(), Json::str(&*self.output)),
+            ("status".to_owned(), Json::str(&*self.status)),
+            ("symlink_to".to_owned(), Json::opt(self.symlink_to.as_ref(), |s| Json::str(&**s))),
+            ("error".to_owned(), Json::opt(self.error.as_ref(), |s| Json::str(&**s))),
+            ("warnings".to_owned(), Json::Array(self.warnings.iter().map(|w| Json::str(&**w)).collect())),
+            ("hash".to_owned(), Json::opt(self.hash, |h| Json::str(format!("{:016x}", h)))),
+        ])
+    }
+    fn from_json(j: &Json) -> Option<ManifestEntry> {
+        let get_str = |key| j.get(key).and_then(Json::as_str).map(|s| s.to_owned());
+        Some(ManifestEntry {
+            path: get_str("path")?,
+            output: get_str("output")?,
+            status: get_str("status")?,
+            symlink_to: get_str("symlink_to"),
+            error: get_str("error"),
+            warnings: j.get("warnings").and_then(Json::as_array).unwrap_or(&[])
+                       .iter().filter_map(|w| w.as_str().map(|s| s.to_owned())).collect(),
+            hash: get_str("hash").and_then(|h| u64::from_str_radix(&h, 16).ok()),
+        })
+    }
+}
+
+fn uuid_str(dc: &DyldCache) -> Option<String> {
+    dc.uuid.map(|uuid| uuid.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// written to a temporary file and renamed, so an interrupted run leaves the last complete one
+fn write_manifest(manifest_path: &Path, dc: &DyldCache, dc_path: &OsStr, entries: &[Option<ManifestEntry>]) {
+    let j = Json::Object(vec![
+        ("cache".to_owned(), Json::str(dc_path.to_string_lossy().into_owned())),
+        ("cache_uuid".to_owned(), Json::opt(uuid_str(dc), Json::Str)),
+        ("images".to_owned(), Json::Array(entries.iter().filter_map(|e| e.as_ref().map(ManifestEntry::to_json)).collect())),
+    ]);
+    let mut tmp_path = manifest_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let res = File::create(&tmp_path).and_then(|mut fp| writeln!(fp, "{:#}", j))
+        .and_then(|()| std::fs::rename(&tmp_path, manifest_path));
+    if let Err(e) = res {
+        errln!("write manifest {:?} fail: {}", manifest_path, e);
+    }
+}
+
+// the ok entries from a previous run of the same cache, by image path
+fn read_manifest(manifest_path: &Path, dc: &DyldCache) -> HashMap<String, ManifestEntry> {
+    let mut out = HashMap::new();
+    let text = match std::fs::read_to_string(manifest_path) {
+        Ok(text) => text,
+        Err(e) => {
+            errln!("warning: can't read manifest {:?} ({}); extracting everything", manifest_path, e);
+            return out;
+        },
     };
-    match macho_dsc_extraction::extract_as_necessary(&mut macho, Some(dc), image_cache, minimal_processing) {
-        Ok(()) => (),
-        Err(e) => { errln!("for '{}', extract fail: {}", ii.path, e); return },
+    let j = ok_or!(Json::parse(&text), e, {
+        errln!("warning: bad manifest {:?} ({}); extracting everything", manifest_path, e);
+        return out;
+    });
+    if j.get("cache_uuid").and_then(Json::as_str).map(|s| s.to_owned()) != uuid_str(dc) {
+        errln!("warning: manifest {:?} is for a different cache; extracting everything", manifest_path);
+        return out;
+    }
+    for e in j.get("images").and_then(Json::as_array).unwrap_or(&[]) {
+        let entry = some_or!(ManifestEntry::from_json(e), continue);
+        if entry.status == "ok" {
+            out.insert(entry.path.clone(), entry);
+        }
+    }
+    out
+}
+
+fn output_unchanged(entry: &ManifestEntry, output_path: &Path) -> bool {
+    if Path::new(&entry.output) != output_path { return false; }
+    let expected = some_or!(entry.hash, return false);
+    // a symlink would mean it was an alias last time
+    match std::fs::symlink_metadata(output_path) {
+        Ok(ref md) if md.file_type().is_file() => (),
+        _ => return false,
+    }
+    std::fs::read(output_path).map(|buf| hash_output(&buf) == expected).unwrap_or(false)
+}
+
+// reports what went wrong, since in --all mode it was captured
+fn finish_entry(ii: &ImageInfo, output_path: &Path, res: Result<u64, String>, warnings: Vec<String>) -> ManifestEntry {
+    for w in &warnings {
+        errln!("for '{}': {}", ii.path, w);
+    }
+    let (status, error, hash) = match res {
+        Ok(hash) => ("ok", None, Some(hash)),
+        Err(e) => {
+            errln!("for '{}', {}", ii.path, e);
+            ("failed", Some(e), None)
+        },
+    };
+    ManifestEntry {
+        path: ii.path.to_string(),
+        output: output_path.to_string_lossy().into_owned(),
+        status: status.to_owned(),
+        symlink_to: None,
+        error: error,
+        warnings: warnings,
+        hash: hash,
     }
-    let mut fp = File::create(outpath).unwrap();
-    let buf = unsafe { macho.eb.whole_buf.as_ref().unwrap().get_plain_slice() };
-    fp.write_all(buf).unwrap();
 }
 
 #[cfg(unix)]
@@ -52,7 +194,10 @@ fn bstr_to_path(a: &ByteStr) -> Result<&Path, std::str::Utf8Error> {
 fn usage() -> ! {
     println!(
     "Yet Another Shared Cache Extractor
-    Usage: yasce <cache> [basename | full path | --all | blank to list files] [-o dir/file] [-v] [-m/--minimal-processing]"
+    Usage: yasce <cache> [basename | full path | --all | blank to list files] [-o dir/file] [-v] [-m/--minimal-processing]
+                 [--manifest file.json [--resume]]
+    --manifest: with --all, write a JSON list of each image's output, symlink target, warnings and output hash
+    --resume: skip images whose output still matches the hash in the existing manifest"
     );
     util::exit();
 }
@@ -65,11 +210,15 @@ fn main() {
     let mut verbose = false;
     let mut extract_all = false;
     let mut minimal_processing = false;
+    let mut manifest_path = None;
+    let mut resume = false;
     let dash_o = OsStr::new("-o");
     let dash_v = OsStr::new("-v");
     let dash_m = OsStr::new("-m");
     let dash_dash_all = OsStr::new("--all");
     let dash_dash_minimal_processing = OsStr::new("--minimal-processing");
+    let dash_dash_manifest = OsStr::new("--manifest");
+    let dash_dash_resume = OsStr::new("--resume");
     let dash_dash = OsStr::new("--");
     while let Some(arg) = args_it.next() {
         if arg == dash_o {
@@ -84,6 +233,14 @@ fn main() {
             extract_all = true;
         } else if arg == dash_m || arg == dash_dash_minimal_processing {
             minimal_processing = true;
+        } else if arg == dash_dash_manifest {
+            if manifest_path.is_some() {
+                errln!("multiple --manifest specified");
+                usage();
+            }
+            manifest_path = Some(PathBuf::from(args_it.next().unwrap_or_else(|| usage())));
+        } else if arg == dash_dash_resume {
+            resume = true;
         } else if arg == dash_dash {
             while let Some(arg) = args_it.next() {
                 base_args.push(arg);
@@ -94,16 +251,19 @@ fn main() {
     }
     let argc = base_args.len();
     if argc != 2 && argc != 3 { usage(); }
+    if (manifest_path.is_some() || resume) && !extract_all {
+        errln!("--manifest and --resume only make sense with --all");
+        usage();
+    }
+    if resume && manifest_path.is_none() {
+        errln!("--resume needs --manifest");
+        usage();
+    }
     let dc_path = &base_args[1];
     let filename = base_args.get(2);
 
-    let fp = File::open(&Path::new(&dc_path)).unwrap_or_else(|e| {
-        errln!("open {:?} failed: {}", dc_path, e);
-        util::exit();
-    });
-
-    let dc_buf = util::memmap(&fp).unwrap();
-    let dc = DyldCache::new(dc_buf, false, true).unwrap_or_else(|e| {
+    // also picks up any subcaches and .symbols file next to it
+    let dc = DyldCache::open(&Path::new(&dc_path), false, true).unwrap_or_else(|e| {
         errln!("parse dyld cache format fail: {}", e);
         util::exit();
     });
@@ -127,8 +287,10 @@ fn main() {
                     errln!("warning: only extracted the first of multiple files with path '{}'", ii.path);
                     return;
                 }
-                extract_one(&dc, ii, get_output_path(ii, &output_name),
-                            (*image_cache).as_ref(), minimal_processing);
+                if let Err(e) = extract_one(&dc, ii, get_output_path(ii, &output_name),
+                                            (*image_cache).as_ref(), minimal_processing) {
+                    errln!("for '{}', {}", ii.path, e);
+                }
                 which_extracted = Some(&ii.path);
             }
         }
@@ -144,8 +306,10 @@ fn main() {
                     errln!("    {}", ii.path);
                     continue;
                 }
-                extract_one(&dc, ii, get_output_path(ii, &output_name),
-                            (*image_cache).as_ref(), minimal_processing);
+                if let Err(e) = extract_one(&dc, ii, get_output_path(ii, &output_name),
+                                            (*image_cache).as_ref(), minimal_processing) {
+                    errln!("for '{}', {}", ii.path, e);
+                }
                 which_extracted = Some(&ii.path);
             }
         }
@@ -170,6 +334,11 @@ fn main() {
 
         let xdc = Arc::new(dc);
         let canon = xdc.make_canonical_path_map();
+        let mut previous = if resume {
+            read_manifest(manifest_path.as_ref().unwrap(), &xdc)
+        } else { HashMap::new() };
+        let mut entries: Vec<Option<ManifestEntry>> = (0..canon.len()).map(|_| None).collect();
+        let mut skipped = 0;
         let mut wait_count = 0;
         let stuff = if verbose { None } else {
             let threads = num_cpus::get();
@@ -208,6 +377,22 @@ fn main() {
                     let (target_path, _) = get_output_path(dot_dots, target);
                     let _ = std::fs::remove_file(&output_path);
                     symlink(&target_path, &output_path).unwrap();
+                    entries[i] = Some(ManifestEntry {
+                        path: ii.path.to_string(),
+                        output: output_path.to_string_lossy().into_owned(),
+                        status: "symlink".to_owned(),
+                        symlink_to: Some(target_path.to_string_lossy().into_owned()),
+                        error: None,
+                        warnings: Vec::new(),
+                        hash: None,
+                    });
+                    continue;
+                }
+            }
+            if let Some(prev) = previous.remove(&ii.path.to_string()) {
+                if output_unchanged(&prev, &output_path) {
+                    entries[i] = Some(prev);
+                    skipped += 1;
                     continue;
                 }
             }
@@ -218,25 +403,42 @@ fn main() {
                 wait_count += 1;
                 pool.execute(move || {
                     let ii = &xdc_.image_info[i];
-                    extract_one(&xdc_, ii, &output_path,
-                                (*image_cache_).as_ref(), minimal_processing);
-                    tx_.send(()).unwrap();
+                    let (res, warnings) = extract_one_captured(&xdc_, ii, &output_path,
+                                                               (*image_cache_).as_ref(), minimal_processing);
+                    tx_.send((i, output_path, res, warnings)).unwrap();
                 });
             } else {
                 println!("-> {}", ii.path);
-                extract_one(&xdc, ii, &output_path,
-                            (*image_cache).as_ref(), minimal_processing);
+                let (res, warnings) = extract_one_captured(&xdc, ii, &output_path,
+                                                           (*image_cache).as_ref(), minimal_processing);
+                entries[i] = Some(finish_entry(ii, &output_path, res, warnings));
             }
         }
+        if skipped != 0 {
+            println!("skipped {} unchanged", skipped);
+        }
         if let Some((_, _, ref rx)) = stuff {
             print!("{}", format!("0/{} ", wait_count));
-            for i in 0..wait_count {
-                rx.recv().unwrap();
-                let text = format!("\x1b[1K\x1b[999D{}/{} ", i, wait_count);
+            for done in 1..wait_count+1 {
+                let (i, output_path, res, warnings) = rx.recv().unwrap();
+                if res.is_err() || !warnings.is_empty() {
+                    print!("\x1b[1K\x1b[999D");
+                    std::io::stdout().flush().unwrap();
+                }
+                entries[i] = Some(finish_entry(&xdc.image_info[i], &output_path, res, warnings));
+                if done % 64 == 0 {
+                    if let Some(ref manifest_path) = manifest_path {
+                        write_manifest(manifest_path, &xdc, dc_path, &entries);
+                    }
+                }
+                let text = format!("\x1b[1K\x1b[999D{}/{} ", done, wait_count);
                 print!("{}", text);
             }
             println!("");
         }
+        if let Some(ref manifest_path) = manifest_path {
+            write_manifest(manifest_path, &xdc, dc_path, &entries);
+        }
     } else {
         // just list
         let canon = dc.make_canonical_path_map();